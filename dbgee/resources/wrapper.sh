@@ -1,4 +1,7 @@
 #!/bin/sh
 # a wrapper script generated by dbgee
+# backup-suffix: %backup_suffix%
 
+%bypass_conditions%
+%unset_once%
 exec %run_cmd%
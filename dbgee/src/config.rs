@@ -0,0 +1,305 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use strum::VariantNames;
+use toml::Value;
+
+use crate::DebuggerOptValues;
+
+/// File name of the config file. dbgee looks it up from the current directory up to the root directory.
+const CONFIG_FILE_NAME: &str = ".dbgee.toml";
+
+/// Hook conditions defined as a named profile in the `[hook_profiles.<name>]` table of the config file.
+/// Relative paths in a profile are relative to the directory of the config file.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HookProfile {
+    pub hook_executable: Option<PathBuf>,
+    pub hook_source: Option<Vec<String>>,
+    pub hook_source_dir: Option<PathBuf>,
+    pub hook_library: Option<String>,
+    pub hook_source_exclude: Vec<String>,
+    pub hook_build_id: Option<String>,
+}
+
+/// Loads the hook profile `name` from the config file which is the nearest to the current directory
+pub fn load_hook_profile(name: &str) -> Result<HookProfile> {
+    let current_dir = env::current_dir().context("Failed to get the current directory")?;
+    let config_path = find_config_file(&current_dir).ok_or_else(|| {
+        anyhow!(
+            "--hook-profile is given, but {} is not found in {:?} or its ancestors.",
+            CONFIG_FILE_NAME,
+            current_dir
+        )
+    })?;
+    let config = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read {:?}", config_path))?;
+    parse_hook_profile(&config, name, config_path.parent().unwrap_or(&current_dir))
+        .with_context(|| format!("Failed to load the hook profile from {:?}", config_path))
+}
+
+/// Rule in the `[[detect]]` array of the config file, which chooses `debugger` for the debuggees
+/// whose output of `file` contains `file_output`, e.g. for in-house formats which `file` reports generically.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DetectRule {
+    pub file_output: String,
+    pub debugger: DebuggerOptValues,
+}
+
+/// Loads the detection rules from the config file which is the nearest to the current directory.
+/// There are no rules without the config file.
+pub fn load_detect_rules() -> Result<Vec<DetectRule>> {
+    let current_dir = env::current_dir().context("Failed to get the current directory")?;
+    let config_path = match find_config_file(&current_dir) {
+        Some(config_path) => config_path,
+        None => return Ok(vec![]),
+    };
+    let config = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read {:?}", config_path))?;
+    parse_detect_rules(&config)
+        .with_context(|| format!("Failed to load the detect rules from {:?}", config_path))
+}
+
+fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn parse_hook_profile(config: &str, name: &str, base_dir: &Path) -> Result<HookProfile> {
+    let config: Value = config.parse().context("Failed to parse the config file")?;
+    let profile = config
+        .get("hook_profiles")
+        .and_then(|profiles| profiles.get(name))
+        .and_then(Value::as_table)
+        .ok_or_else(|| anyhow!("hook profile '{}' is not defined", name))?;
+
+    let mut hook_profile = HookProfile::default();
+    for (key, value) in profile {
+        match key.as_str() {
+            "hook_executable" => {
+                hook_profile.hook_executable = Some(base_dir.join(value_as_str(key, value)?))
+            }
+            "hook_source" => {
+                hook_profile.hook_source = Some(
+                    value_as_strs(key, value)?
+                        .into_iter()
+                        .map(|source| match source.strip_prefix('@') {
+                            // `@<file>` is a list file of sources
+                            Some(list_file) => format!("@{}", base_dir.join(list_file).display()),
+                            None => base_dir.join(source).display().to_string(),
+                        })
+                        .collect(),
+                );
+            }
+            "hook_source_dir" => {
+                hook_profile.hook_source_dir = Some(base_dir.join(value_as_str(key, value)?))
+            }
+            "hook_library" => {
+                let library = value_as_str(key, value)?;
+                // A file name such as `libfoo.so` is matched as it is, unlike a path
+                hook_profile.hook_library = Some(if library.contains('/') {
+                    base_dir.join(library).display().to_string()
+                } else {
+                    library.to_owned()
+                });
+            }
+            "hook_source_exclude" => {
+                hook_profile.hook_source_exclude = value_as_strs(key, value)?
+                    .into_iter()
+                    .map(|exclude| {
+                        // A glob matches the whole path of a source file as `--hook-source-exclude` does
+                        if exclude.contains(['*', '?', '[']) {
+                            exclude.to_owned()
+                        } else {
+                            base_dir.join(exclude).display().to_string()
+                        }
+                    })
+                    .collect();
+            }
+            "hook_build_id" => {
+                hook_profile.hook_build_id = Some(value_as_str(key, value)?.to_owned())
+            }
+            other => bail!(
+                "unknown key '{}' in hook profile '{}'. Supported keys are hook_executable, hook_source, hook_source_dir, hook_source_exclude, hook_build_id and hook_library.",
+                other,
+                name
+            ),
+        }
+    }
+    Ok(hook_profile)
+}
+
+fn parse_detect_rules(config: &str) -> Result<Vec<DetectRule>> {
+    let config: Value = config.parse().context("Failed to parse the config file")?;
+    let rules = match config.get("detect") {
+        Some(Value::Array(rules)) => rules,
+        Some(other) => bail!("'detect' must be an array of tables, but it is {}", other),
+        None => return Ok(vec![]),
+    };
+    rules
+        .iter()
+        .map(|rule| {
+            let get = |key: &str| -> Result<&str> {
+                let value = rule
+                    .get(key)
+                    .ok_or_else(|| anyhow!("'{}' is missing in a detect rule", key))?;
+                value_as_str(key, value)
+            };
+            let debugger = get("debugger")?;
+            Ok(DetectRule {
+                file_output: get("file_output")?.to_owned(),
+                debugger: DebuggerOptValues::from_str(debugger).map_err(|_| {
+                    anyhow!(
+                        "unknown debugger '{}' in a detect rule. Choose one of {}.",
+                        debugger,
+                        DebuggerOptValues::VARIANTS.join(", ")
+                    )
+                })?,
+            })
+        })
+        .collect()
+}
+
+fn value_as_str<'a>(key: &str, value: &'a Value) -> Result<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| anyhow!("'{}' must be a string, but it is {}", key, value))
+}
+
+/// Returns the strings of `value`, which is either an array of strings or a string
+fn value_as_strs<'a>(key: &str, value: &'a Value) -> Result<Vec<&'a str>> {
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|value| value_as_str(key, value))
+            .collect(),
+        other => Ok(vec![value_as_str(key, other)?]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hook_profile() {
+        let config = r#"
+            [hook_profiles.worker]
+            hook_source_dir = "src"
+            hook_source = ["src/worker.c", "@changed.txt", "/abs/main.c"]
+            hook_source_exclude = ["src/vendor", "**/third_party"]
+
+            [hook_profiles.server]
+            hook_executable = "/usr/local/bin/server"
+            hook_build_id = "3f2a9c"
+
+            [hook_profiles.plugin]
+            hook_library = "libplugin.so"
+
+            [hook_profiles.local_plugin]
+            hook_library = "build/libplugin.so"
+        "#;
+        let base_dir = Path::new("/project");
+        assert_eq!(
+            HookProfile {
+                hook_executable: None,
+                hook_source: Some(vec![
+                    "/project/src/worker.c".to_owned(),
+                    "@/project/changed.txt".to_owned(),
+                    "/abs/main.c".to_owned()
+                ]),
+                hook_source_dir: Some(PathBuf::from("/project/src")),
+                hook_library: None,
+                hook_source_exclude: vec![
+                    "/project/src/vendor".to_owned(),
+                    "**/third_party".to_owned()
+                ],
+                hook_build_id: None,
+            },
+            parse_hook_profile(config, "worker", base_dir).unwrap()
+        );
+        assert_eq!(
+            Some(PathBuf::from("/usr/local/bin/server")),
+            parse_hook_profile(config, "server", base_dir)
+                .unwrap()
+                .hook_executable
+        );
+        assert_eq!(
+            Some("3f2a9c".to_owned()),
+            parse_hook_profile(config, "server", base_dir)
+                .unwrap()
+                .hook_build_id
+        );
+        assert_eq!(
+            Some("libplugin.so".to_owned()),
+            parse_hook_profile(config, "plugin", base_dir)
+                .unwrap()
+                .hook_library
+        );
+        assert_eq!(
+            Some("/project/build/libplugin.so".to_owned()),
+            parse_hook_profile(config, "local_plugin", base_dir)
+                .unwrap()
+                .hook_library
+        );
+        assert!(parse_hook_profile(config, "nonexistent", base_dir).is_err());
+        assert!(parse_hook_profile(
+            "[hook_profiles.worker]\nhook_name = \"worker\"",
+            "worker",
+            base_dir
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_detect_rules() {
+        let config = r#"
+            [[detect]]
+            file_output = "ACME launcher"
+            debugger = "gdb"
+
+            [[detect]]
+            file_output = "ACME bytecode"
+            debugger = "stop-and-write-pid"
+        "#;
+        assert_eq!(
+            vec![
+                DetectRule {
+                    file_output: "ACME launcher".to_owned(),
+                    debugger: DebuggerOptValues::Gdb,
+                },
+                DetectRule {
+                    file_output: "ACME bytecode".to_owned(),
+                    debugger: DebuggerOptValues::StopAndWritePid,
+                },
+            ],
+            parse_detect_rules(config).unwrap()
+        );
+        assert_eq!(
+            Vec::<DetectRule>::new(),
+            parse_detect_rules("[hook_profiles.worker]").unwrap()
+        );
+        assert!(
+            parse_detect_rules("[[detect]]\nfile_output = \"ACME\"\ndebugger = \"vim\"").is_err()
+        );
+        assert!(parse_detect_rules("[[detect]]\ndebugger = \"gdb\"").is_err());
+    }
+
+    #[test]
+    fn test_find_config_file() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let sub_dir = tempdir.path().join("a/b");
+        fs::create_dir_all(&sub_dir).unwrap();
+        assert_eq!(
+            None,
+            find_config_file(&sub_dir).filter(|path| path.starts_with(tempdir.path()))
+        );
+
+        let config_path = tempdir.path().join("a").join(CONFIG_FILE_NAME);
+        fs::write(&config_path, "").unwrap();
+        assert_eq!(Some(config_path), find_config_file(&sub_dir));
+    }
+}
@@ -1,23 +1,36 @@
 use crate::{
+    debugger_terminal::escape_json_string,
     file_helper::{
-        command_exists, get_abspath, get_cached_command_output, get_cached_file_output,
-        get_valid_executable_path,
+        command_exists, find_command_path, find_free_port, get_abspath, get_cached_command_output,
+        get_cached_file_output, get_valid_executable_path, log_commandline, read_shebang,
+        LoggedCommand,
     },
     DebuggerTerminal,
 };
-use crate::{Opts, SETOPTS_POSITIONAL_ARGS};
+use crate::{
+    os::{
+        create_cpu_limit_cgroup, enter_cgroup, find_missing_comp_dirs, get_process_state,
+        get_ptrace_scope, get_tracer_pid, is_port_listening, is_zig_binary,
+        set_parent_death_signal,
+    },
+    session, Opts, SETOPTS_POSITIONAL_ARGS, SETOPTS_SET_ONLY_ARGS, SYSTEMDOPTS_ONLY_ARGS,
+};
 
 use std::ffi::CString;
-use std::io::Write;
-use std::path::Path;
-use std::process::Command;
-use std::{collections::HashMap, fs::File};
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{collections::HashMap, collections::VecDeque, fs::File};
 use std::{env, fs};
 use std::{
     io::{BufRead, BufReader},
     str,
 };
-use std::{path::PathBuf, str::FromStr};
+use sysinfo::{ProcessExt, SystemExt};
 
 use anyhow::{anyhow, bail, Context, Result};
 use nix::sys::{ptrace, signal, wait};
@@ -30,6 +43,9 @@ use structopt::StructOpt;
 use strum::{Display, EnumString};
 
 pub trait Debugger {
+    /// Name of the debugger as `--debugger` calls it, such as "gdb" and "debugpy", or "lldb-dap" for `--dap`
+    fn name(&self) -> &str;
+
     /// Runs and Attaches to the debuggee.
     ///
     /// # Arguments
@@ -51,13 +67,15 @@ pub trait Debugger {
     ///
     /// * `debuggee` - Path to the debuggee file
     /// * `start_cmd` - If `len()` > 0, spawn `start_cmd` after wrapping the debuggee file.
-    ///    After `start_cmd` completes, `debuggee` will be automatically restored.
+    ///   After `start_cmd` completes, `debuggee` will be automatically restored.
+    /// * `wrapper_options` - Options to customize the wrapper script
     /// * `terminal` - Terminal where debugger launches
     ///
     fn set(
         &mut self,
         debuggee: &str,
         start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
         terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()>;
 
@@ -87,6 +105,12 @@ pub trait Debugger {
         unimplemented!()
     }
 
+    /// Returns the pid of the debuggee, given `launched_pid` which `run` returned.
+    /// It differs from `launched_pid` for debuggers which launch the debuggee as their child, such as dlv.
+    fn debuggee_pid(&self, launched_pid: Pid) -> Pid {
+        launched_pid
+    }
+
     fn build_attach_commandline(&self) -> Result<Vec<String>>;
     fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>>;
     // Note that a debugger could support debuggee even if is_surely_supported_debuggee == false
@@ -94,43 +118,854 @@ pub trait Debugger {
     fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool>;
 }
 
+/// Launches the processes of debuggees and debugger servers.
+///
+/// Debuggers take it by `build` or `new` so that unit tests can substitute a fake which doesn't launch processes.
+pub trait ProcessLauncher {
+    /// Launches the debuggee of `commandline`, and stops it right after exec by `stop_method`
+    fn run_and_stop(
+        &self,
+        commandline: &[&str],
+        stop_method: StopMethod,
+        options: &DebuggerOptions,
+    ) -> Result<Pid>;
+
+    /// Launches the debugger server of `commandline`, and waits for it to get ready.
+    /// The server is ready when it listens on `listen_port`, where the client connects to,
+    /// or when it writes a line which matches `--ready-regex` if given.
+    ///
+    /// This is not a per-backend method of `Debugger`, because every server which dbgee launches, i.e. dlv,
+    /// debugpy, the JDWP agent, the node inspector, rdbg and lldb-dap, gets ready by listening on the port.
+    /// Their ready lines differ among their versions, and dlv and the JDWP agent print them to the stdout,
+    /// which the debuggee shares.
+    fn launch_server(
+        &self,
+        commandline: &[&str],
+        listen_port: u16,
+        options: &DebuggerOptions,
+    ) -> Result<Pid>;
+}
+
+/// `ProcessLauncher` which actually launches processes
+pub struct SystemProcessLauncher;
+
+impl ProcessLauncher for SystemProcessLauncher {
+    fn run_and_stop(
+        &self,
+        commandline: &[&str],
+        stop_method: StopMethod,
+        options: &DebuggerOptions,
+    ) -> Result<Pid> {
+        if options.no_sigint_ignore {
+            // Ctrl+C kills dbgee by the default disposition. The stopped debuggee is left as it is
+            // unless the debugger has attached to it
+            return fork_exec_stop(commandline, options, stop_method);
+        }
+        // To wait for the child process, not being signalled by Ctrl+C
+        ignore_sigint()?;
+        let debuggee_pid = fork_exec_stop(commandline, options, stop_method)?;
+        // Sleeping childs don't respond to SIGINT/SIGTERM. Kill them by SIGKILL for ergonomics
+        kill9_child_by_sigint(debuggee_pid)?;
+        Ok(debuggee_pid)
+    }
+
+    fn launch_server(
+        &self,
+        commandline: &[&str],
+        listen_port: u16,
+        options: &DebuggerOptions,
+    ) -> Result<Pid> {
+        // The server would fail to listen, while dbgee would take the other process for the server
+        if is_port_listening(listen_port)? {
+            bail!(
+                "{} cannot listen on the port: {}",
+                commandline[0],
+                build_port_in_use_message(listen_port)
+            );
+        }
+        let (stderr_relay, stderr_writer) = ServerOutputRelay::new(nix::libc::STDERR_FILENO)?;
+        let mut command = Command::new(commandline[0]);
+        command.args(&commandline[1..]).stderr(stderr_writer);
+        let mut relays = vec![stderr_relay];
+        // The stdout is captured only to find the ready line, since the debuggee of dlv shares it.
+        // A terminal is relayed through a pseudo terminal, not to change what the debuggee writes to.
+        if options.server_ready_regex.is_some() {
+            let stdout = nix::libc::STDOUT_FILENO;
+            let (stdout_relay, stdout_writer) = if unistd::isatty(stdout).unwrap_or(false) {
+                ServerOutputRelay::new_pty(stdout)?
+            } else {
+                ServerOutputRelay::new(stdout)?
+            };
+            command.stdout(stdout_writer);
+            relays.push(stdout_relay);
+        }
+        let spawned = command.logged().spawn();
+        // Close the write ends of dbgee, so that the relays end when the server closes them
+        drop(command);
+        let mut server = spawned.with_context(|| format!("failed to launch {}", commandline[0]))?;
+        let server_pid = Pid::from_raw(server.id() as i32);
+        // To wait for the child process, not being signalled by Ctrl+C.
+        // Ignore SIGINT after Command::spawn because spawn inherits the parent's signal handlers.
+        // This makes some gap between the timing when the debugger launched and the timing when the host started to ignore SIGINT,
+        // but I'm doing this just due to laziness
+        if !options.no_sigint_ignore {
+            ignore_sigint()?;
+        }
+
+        let ready_regex = options.server_ready_regex.as_ref();
+        let timeout = options.server_timeout.unwrap_or(SERVER_LISTEN_TIMEOUT);
+        match ready_regex {
+            Some(ready_regex) => {
+                wait_until_output_matches(&mut server, ready_regex, &mut relays, timeout)
+            }
+            None => wait_until_listening(&mut server, listen_port, &mut relays, timeout),
+        }
+        .map_err(|e| {
+            e.context(build_server_not_ready_hint(
+                listen_port,
+                ready_regex,
+                &relays[0].last_lines(),
+            ))
+        })
+        .with_context(|| format!("{} didn't get ready", commandline[0]))?;
+        for relay in relays {
+            relay
+                .hand_over()
+                .map_err(|e| kill_launched_process(server_pid, e))?;
+        }
+        Ok(server_pid)
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, EnumString, Display)]
 #[strum(serialize_all = "camelCase")]
 pub enum AttachInformationKey {
     DebuggerTypeHint,
     Pid,
     DebuggerPort,
+    DebuggerHost,
     ProgramName,
+    /// Name of the debuggee to show in VSCode, which is the basename of `ProgramName` unless given
+    DebuggeeName,
+    /// JSON object which maps the paths of sources at build time to the ones on this machine
+    SourceMap,
+    SymbolSearchPath,
+    /// Working directory of the debuggee, such as the one of the hooked process
+    DebuggeeCwd,
+    /// JSON object of the environment variables of the debuggee, given only when asked
+    DebuggeeEnv,
+    /// Path to the core dump of the debuggee captured by `--capture-core`
+    CorePath,
+}
+
+/// Options to customize the wrapper script which `set` puts in place of the debuggee.
+#[derive(Debug, Clone)]
+pub struct WrapperOptions {
+    /// Suffix of the file name to which the original debuggee is moved
+    pub backup_suffix: String,
+    /// Re-wrap the debuggee even if it is already wrapped
+    pub force: bool,
+    /// Wrap the debuggee even if it is running, only warning that the running instances keep running the original
+    pub allow_running: bool,
+    /// Attach the debugger only when this environment variable is set
+    pub activate_env: Option<ActivationEnv>,
+    /// Attach the debugger only when the stdin of the debuggee is a terminal
+    pub only_interactive: bool,
+    /// Wrap the debuggee even if it has other hardlinks, which keep pointing to the original debuggee
+    pub allow_hardlinked: bool,
+    /// Restore the original debuggee when the wrapper launches the debugger for the first time
+    pub once: bool,
+    /// Arguments which the wrapper always passes to the debuggee before its own arguments
+    pub debuggee_args: Vec<String>,
+}
+
+impl Default for WrapperOptions {
+    fn default() -> Self {
+        WrapperOptions {
+            backup_suffix: DEFAULT_BACKUP_SUFFIX.to_owned(),
+            force: false,
+            allow_running: false,
+            activate_env: None,
+            only_interactive: false,
+            allow_hardlinked: false,
+            once: false,
+            debuggee_args: vec![],
+        }
+    }
+}
+
+/// Environment variable which activates the wrapper script, given as `VAR` or `VAR=VAL`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivationEnv {
+    pub name: String,
+    /// If None, any non-empty value activates the wrapper script.
+    pub value: Option<String>,
+}
+
+impl FromStr for ActivationEnv {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        static ENV_NAME_RE: Lazy<Regex> =
+            Lazy::new(|| Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap());
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_owned())),
+            None => (s, None),
+        };
+        if !ENV_NAME_RE.is_match(name) {
+            bail!("invalid environment variable name '{}'", name);
+        }
+        Ok(ActivationEnv {
+            name: name.to_owned(),
+            value,
+        })
+    }
+}
+
+impl ActivationEnv {
+    /// Builds the shell condition which is true when the wrapper script is NOT activated.
+    fn build_inactive_condition(&self) -> String {
+        match self.value {
+            Some(ref value) => format!(
+                "[ \"${{{}:-}}\" != '{}' ]",
+                self.name,
+                escape_single_quote(value)
+            ),
+            None => format!("[ -z \"${{{}:-}}\" ]", self.name),
+        }
+    }
+}
+
+pub const DEFAULT_BACKUP_SUFFIX: &str = "-original";
+
+/// Options to customize how debuggers are launched.
+#[derive(Debug, Clone, Default)]
+pub struct DebuggerOptions {
+    /// Launch the debugger with sudo
+    pub sudo: bool,
+    /// Launch the debugger as this user by `sudo -u`
+    pub debugger_user: Option<String>,
+    /// Debug child processes as well. Effective for debugpy
+    pub follow_subprocess: bool,
+    /// Capture a core dump of the debuggee to this path before the debugger attaches. Effective for gdb and lldb
+    pub capture_core: Option<PathBuf>,
+    /// Let the debuggee continue after capturing the core dump, instead of attaching the debugger
+    pub capture_core_only: bool,
+    /// Directories to search for the sources of the debuggee. Effective for gdb and lldb
+    pub source_dirs: Vec<PathBuf>,
+    /// Arguments appended verbatim to the command line of the debugger. Effective for the debuggers with
+    /// command line clients
+    pub debugger_args: Vec<String>,
+    /// Don't ignore SIGINT while the debugger runs, so that Ctrl+C kills dbgee and the debuggee
+    pub no_sigint_ignore: bool,
+    /// Path to the gdb or lldb executable to use instead of the one in PATH
+    pub debugger_path: Option<String>,
+    /// Path to the dlv executable to use instead of the one in PATH
+    pub dlv_path: Option<String>,
+    /// Files to redirect the stdin, stdout and stderr of the debuggee to. Effective for gdb, lldb and stop-and-write-pid
+    pub debuggee_stdio: DebuggeeStdio,
+    /// Debug native debuggees via lldb-dap, the DAP server of lldb, instead of the interactive debuggers
+    pub dap: bool,
+    /// How to keep the debuggee stopped until a debugger attaches. Effective for stop-and-write-pid
+    pub stop_method: StopMethod,
+    /// Maps the paths of sources at build time to the ones on this machine. Effective for gdb, lldb and VSCode
+    pub source_maps: Vec<SourceMap>,
+    /// Directory to search for the separate debug symbols of the debuggee. Effective for gdb, lldb and VSCode
+    pub symbols_dir: Option<PathBuf>,
+    /// Shell command to run right before the debugger opens. The attach fails if it fails
+    pub pre_attach_hook: Option<String>,
+    /// Limits of the resources of the debuggee. Effective for gdb, lldb and stop-and-write-pid
+    pub resource_limits: ResourceLimits,
+    /// Working directory which the debugger changes to, such as the one of the hooked process. Effective for gdb and lldb
+    pub debugger_cwd: Option<PathBuf>,
+    /// Working directory of the debuggee, such as the one of the hooked process, to report in the attach information
+    pub debuggee_cwd: Option<PathBuf>,
+    /// `KEY=VALUE`s of the environment variables of the debuggee to report in the attach information.
+    /// Given only when asked, since they may contain secrets
+    pub debuggee_env: Option<Vec<String>>,
+    /// Signal which the debuggee receives when dbgee exits. Effective for gdb, lldb and stop-and-write-pid
+    pub parent_death_signal: Option<signal::Signal>,
+    /// Name of the debuggee to show instead of the basename of its path. Effective for VSCode
+    pub debuggee_name: Option<String>,
+    /// Start `record full` of gdb right after attaching, to execute the debuggee in reverse. Effective for gdb
+    pub record: bool,
+    /// Regex of the line of the stdout or stderr of a debugger server which tells that the server is ready,
+    /// instead of listening on the port. Effective for dlv, debugpy, lldb-dap and the JVM of jdb
+    pub server_ready_regex: Option<Regex>,
+    /// Command line of the debugger given by the user, where `{pid}` and `{program}` are replaced with the pid and
+    /// the path of the debuggee. Replaces the command line of gdb
+    pub debugger_command_template: Option<String>,
+    /// Port which debugger servers listen on instead of the default. $DBGEE_PORT overrides it. Effective for dlv, debugpy,
+    /// lldb-dap and the JVM of jdb
+    pub listen_port: Option<u16>,
+    /// How long to wait for a debugger server to get ready instead of the default. Effective for dlv, debugpy,
+    /// lldb-dap and the JVM of jdb
+    pub server_timeout: Option<Duration>,
+}
+
+/// Limits of the resources of the debuggee, which are applied before exec
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Maximum size of the virtual memory in bytes, applied by RLIMIT_AS
+    pub memory_bytes: Option<u64>,
+    /// Maximum CPU usage in percent of one CPU, applied by a cgroup on Linux
+    pub cpu_percent: Option<u32>,
+}
+
+/// Files which the standard streams of the debuggee are redirected to, applied before exec
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebuggeeStdio {
+    /// File to read as stdin
+    pub stdin: Option<PathBuf>,
+    /// File to append stdout to
+    pub stdout: Option<PathBuf>,
+    /// File to append stderr to
+    pub stderr: Option<PathBuf>,
+}
+
+impl DebuggeeStdio {
+    /// Opens the files, and returns them with the file descriptors to replace
+    fn open(&self) -> Result<Vec<(fs::File, RawFd)>> {
+        let mut files = vec![];
+        if let Some(ref path) = self.stdin {
+            let file = fs::File::open(path)
+                .with_context(|| format!("Failed to open {:?} for the debuggee's stdin", path))?;
+            files.push((file, nix::libc::STDIN_FILENO));
+        }
+        for (path, fd) in [
+            (&self.stdout, nix::libc::STDOUT_FILENO),
+            (&self.stderr, nix::libc::STDERR_FILENO),
+        ] {
+            if let Some(path) = path {
+                let file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| {
+                        format!("Failed to open {:?} for the debuggee's output", path)
+                    })?;
+                files.push((file, fd));
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// A mapping from a source path prefix at build time to the one on this machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    pub from: String,
+    pub to: String,
+}
+
+/// How the debuggee is kept stopped until a debugger attaches to it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum StopMethod {
+    /// Stop the debuggee by SIGSTOP, and detach from it
+    #[default]
+    Sigstop,
+    /// Keep the debuggee stopped under ptrace of dbgee, and hand it over to the debugger when its hold file is removed
+    PtraceHold,
+}
+
+impl DebuggerOptions {
+    /// Returns the command to launch gdb or lldb, which is `debugger_name` unless `debugger_path` is given
+    fn debugger_command(&self, debugger_name: &str) -> String {
+        self.debugger_path
+            .clone()
+            .unwrap_or_else(|| debugger_name.to_owned())
+    }
+
+    /// Builds the JSON object of `source_maps` for the attach information of VSCode
+    fn build_source_map_json(&self) -> String {
+        format!(
+            "{{{}}}",
+            self.source_maps
+                .iter()
+                .map(|map| format!(
+                    r#""{}": "{}""#,
+                    escape_json_string(&map.from),
+                    escape_json_string(&map.to)
+                ))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+
+    /// Adds `source_maps` and `symbols_dir` to the attach information for VSCode
+    fn insert_source_information(&self, info: &mut HashMap<AttachInformationKey, String>) {
+        if !self.source_maps.is_empty() {
+            info.insert(
+                AttachInformationKey::SourceMap,
+                self.build_source_map_json(),
+            );
+        }
+        if let Some(ref symbols_dir) = self.symbols_dir {
+            info.insert(
+                AttachInformationKey::SymbolSearchPath,
+                symbols_dir.display().to_string(),
+            );
+        }
+    }
+
+    /// Adds `debuggee_cwd` and `debuggee_env` to the attach information
+    fn insert_process_context(&self, info: &mut HashMap<AttachInformationKey, String>) {
+        if let Some(ref cwd) = self.debuggee_cwd {
+            info.insert(AttachInformationKey::DebuggeeCwd, cwd.display().to_string());
+        }
+        if let Some(ref env) = self.debuggee_env {
+            info.insert(AttachInformationKey::DebuggeeEnv, Self::build_env_json(env));
+        }
+    }
+
+    /// Builds the JSON object of `KEY=VALUE`s of environment variables
+    fn build_env_json(env: &[String]) -> String {
+        format!(
+            "{{{}}}",
+            env.iter()
+                .map(|var| {
+                    let (key, val) = var.split_once('=').unwrap_or((var, ""));
+                    format!(
+                        r#""{}": "{}""#,
+                        escape_json_string(key),
+                        escape_json_string(val)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+
+    /// Adds the name of the debuggee at `debuggee_path` to the attach information for VSCode
+    fn insert_debuggee_name(
+        &self,
+        info: &mut HashMap<AttachInformationKey, String>,
+        debuggee_path: &str,
+    ) {
+        let name = self.debuggee_name.clone().unwrap_or_else(|| {
+            Path::new(debuggee_path).file_name().map_or_else(
+                || debuggee_path.to_owned(),
+                |name| name.to_string_lossy().into_owned(),
+            )
+        });
+        info.insert(AttachInformationKey::DebuggeeName, name);
+    }
+
+    /// Runs `pre_attach_hook` for the process of `pid` if it is given
+    fn run_pre_attach_hook(&self, pid: Pid) -> Result<()> {
+        match self.pre_attach_hook {
+            Some(ref hook) => run_attach_hook("--pre-attach-hook", hook, pid),
+            None => Ok(()),
+        }
+    }
+
+    /// Bails if `command` is neither an explicitly given path nor in PATH
+    fn bail_if_command_missing(command: &str, explicit_path: &Option<String>) -> Result<()> {
+        // A command given by its path is checked when it's given
+        if explicit_path.is_none() && !command.contains('/') && !command_exists(command) {
+            bail!("'{}' is not in PATH. Did you install {}?", command, command)
+        }
+        Ok(())
+    }
+
+    /// Wraps the command line to launch a debugger according to the options
+    fn wrap_commandline(&self, commandline: Vec<String>) -> Vec<String> {
+        let sudo: Vec<String> = match (&self.debugger_user, self.sudo) {
+            (Some(user), _) => vec!["sudo".to_owned(), "-u".to_owned(), user.clone()],
+            (None, true) => vec!["sudo".to_owned()],
+            (None, false) => return commandline,
+        };
+        sudo.into_iter().chain(commandline).collect()
+    }
+
+    /// Warns that the debugger running as another user may fail to attach due to ptrace restrictions
+    fn show_debugger_user_warning(&self) {
+        let user = match self.debugger_user {
+            Some(ref user) => user,
+            None => return,
+        };
+        if let Some(ptrace_scope) = get_ptrace_scope().filter(|scope| *scope > 0) {
+            log::warn!(
+                "kernel.yama.ptrace_scope is {}. The debugger running as '{}' may fail to attach \
+                unless it is root or has CAP_SYS_PTRACE. The debuggee must be owned by '{}' as well.",
+                ptrace_scope,
+                user,
+                user
+            );
+        }
+    }
 }
 
 pub struct GdbDebugger;
 
 impl GdbDebugger {
-    pub fn build() -> Result<GdbCompatibleDebugger> {
-        let command_builder = |pid: Pid, _name: String| {
-            Ok(vec![
-                "gdb".to_owned(),
+    pub fn build(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<GdbCompatibleDebugger> {
+        if options.record && !Self::can_record_natively() {
+            bail!(
+                "--record is not supported on this platform, since `record full` of gdb supports only \
+                native processes on Linux of x86, x86-64, ARM, AArch64, PowerPC64 and s390x. Use rr if available"
+            );
+        }
+        let source_dirs = options.source_dirs.clone();
+        let source_map_args = Self::build_source_map_args(options);
+        let cwd_args = Self::build_cwd_args(options);
+        let record_args = Self::build_record_args(options);
+        let gdb = options.debugger_command("gdb");
+        let command_builder = move |pid: Pid, name: String| {
+            let mut commandline = vec![
+                gdb.clone(),
                 "-tui".to_owned(),
                 "-p".to_owned(),
                 pid.as_raw().to_string(),
-            ])
+            ];
+            commandline.extend(Self::build_source_dir_args(&source_dirs, &name));
+            commandline.extend(source_map_args.iter().cloned());
+            commandline.extend(cwd_args.iter().cloned());
+            commandline.extend(record_args.iter().cloned());
+            Ok(commandline)
         };
-        GdbCompatibleDebugger::new("gdb", Box::new(command_builder))
+        GdbCompatibleDebugger::new("gdb", Box::new(command_builder), options.clone(), launcher)
+    }
+
+    /// Builds the arguments to add `source_dirs` to the source search paths of gdb
+    fn build_source_dir_args(source_dirs: &[PathBuf], debuggee: &str) -> Vec<String> {
+        suggest_source_dir_if_missing(source_dirs, debuggee);
+        source_dirs
+            .iter()
+            .flat_map(|source_dir| {
+                vec![
+                    "-ex".to_owned(),
+                    format!("directory {}", source_dir.display()),
+                ]
+            })
+            .collect()
+    }
+
+    /// Builds the arguments to apply `source_maps` and `symbols_dir` of `options` by gdb
+    fn build_source_map_args(options: &DebuggerOptions) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(ref symbols_dir) = options.symbols_dir {
+            // `-iex` runs before gdb loads the debuggee. Keep gdb's default directory as well
+            args.push("-iex".to_owned());
+            args.push(format!(
+                "set debug-file-directory {}:/usr/lib/debug",
+                symbols_dir.display()
+            ));
+        }
+        for map in &options.source_maps {
+            args.push("-ex".to_owned());
+            args.push(format!(
+                "set substitute-path {} {}",
+                quote_debugger_arg(&map.from),
+                quote_debugger_arg(&map.to)
+            ));
+        }
+        args
+    }
+
+    /// Builds the arguments to change the working directory of gdb to `debugger_cwd` of `options`
+    fn build_cwd_args(options: &DebuggerOptions) -> Vec<String> {
+        match options.debugger_cwd {
+            Some(ref cwd) => vec!["-ex".to_owned(), format!("cd {}", cwd.display())],
+            None => vec![],
+        }
+    }
+
+    /// Returns if `record full` of gdb supports the native processes of the platform dbgee is built for
+    fn can_record_natively() -> bool {
+        cfg!(all(
+            target_os = "linux",
+            any(
+                target_arch = "x86",
+                target_arch = "x86_64",
+                target_arch = "arm",
+                target_arch = "aarch64",
+                target_arch = "powerpc64",
+                target_arch = "s390x"
+            )
+        ))
+    }
+
+    /// Builds the arguments to start recording the execution of the debuggee for reverse debugging.
+    /// gdb only prints the error of `record full` when it cannot record the debuggee, so a hint is echoed before it.
+    fn build_record_args(options: &DebuggerOptions) -> Vec<String> {
+        if options.record {
+            vec![
+                "-ex".to_owned(),
+                "echo [dbgee] Starting `record full` for --record. \
+                If gdb reports an error right below, gdb cannot record this debuggee and it runs without recording.\\n"
+                    .to_owned(),
+                "-ex".to_owned(),
+                "record full".to_owned(),
+            ]
+        } else {
+            vec![]
+        }
+    }
+
+    /// Builds the command line to run the debuggee under gdb in batch mode, which runs `on_crash_commands`
+    /// when the debuggee crashes.
+    ///
+    /// gdb exits with the exit code of the debuggee, or 128 + signal number if the debuggee crashes.
+    pub fn build_on_crash_commandline(
+        options: &DebuggerOptions,
+        debuggee: &str,
+        args: &[String],
+        on_crash_commands: &[String],
+    ) -> Result<Vec<String>> {
+        DebuggerOptions::bail_if_command_missing("gdb", &options.debugger_path)?;
+        let mut commandline = vec![options.debugger_command("gdb"), "--batch".to_owned()];
+        commandline.extend(Self::build_source_dir_args(&options.source_dirs, debuggee));
+        commandline.extend(Self::build_source_map_args(options));
+        let commands = std::iter::once("run")
+            .chain(on_crash_commands.iter().map(String::as_str))
+            // $_exitcode is void if the debuggee is stopped by a signal
+            .chain(std::iter::once(
+                "quit $_isvoid($_exitcode) ? 128 + $_siginfo.si_signo : $_exitcode",
+            ));
+        for command in commands {
+            commandline.push("-ex".to_owned());
+            commandline.push(command.to_owned());
+        }
+        commandline.push("--args".to_owned());
+        commandline.push(debuggee.to_owned());
+        commandline.extend(args.iter().cloned());
+        Ok(options.wrap_commandline(commandline))
+    }
+
+    /// Builds the command line to attach gdb to `pid` in batch mode, print the backtraces of all
+    /// the threads, and detach.
+    pub fn build_backtrace_commandline(options: &DebuggerOptions, pid: Pid) -> Result<Vec<String>> {
+        DebuggerOptions::bail_if_command_missing("gdb", &options.debugger_path)?;
+        let commandline = vec![
+            options.debugger_command("gdb"),
+            "--batch".to_owned(),
+            "-p".to_owned(),
+            pid.as_raw().to_string(),
+            "-ex".to_owned(),
+            "thread apply all bt".to_owned(),
+        ];
+        Ok(options.wrap_commandline(commandline))
     }
 }
 
 pub struct LldbDebugger;
 
 impl LldbDebugger {
-    pub fn build() -> Result<GdbCompatibleDebugger> {
-        let command_builder = |pid: Pid, _name: String| {
-            Ok(vec![
-                "lldb".to_owned(),
-                "-p".to_owned(),
-                pid.as_raw().to_string(),
-            ])
+    pub fn build(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<GdbCompatibleDebugger> {
+        if options.record {
+            bail!("--record is supported only by gdb, since lldb cannot record the execution of processes");
+        }
+        let source_dirs = options.source_dirs.clone();
+        let source_map_args = Self::build_source_map_args(options);
+        let cwd_args = Self::build_cwd_args(options);
+        let lldb = options.debugger_command("lldb");
+        let command_builder = move |pid: Pid, name: String| {
+            let mut commandline = vec![lldb.clone(), "-p".to_owned(), pid.as_raw().to_string()];
+            commandline.extend(Self::build_source_dir_args(&source_dirs, &name));
+            commandline.extend(source_map_args.iter().cloned());
+            commandline.extend(cwd_args.iter().cloned());
+            if is_zig_binary(&name) {
+                commandline.extend(Self::build_zig_args());
+            }
+            Ok(commandline)
         };
-        GdbCompatibleDebugger::new("lldb", Box::new(command_builder))
+        GdbCompatibleDebugger::new("lldb", Box::new(command_builder), options.clone(), launcher)
+    }
+
+    /// Builds the arguments to find sources in `source_dirs` by lldb.
+    ///
+    /// lldb doesn't have source search paths. Instead, map the comp_dirs of the debuggee which don't exist
+    /// on this machine to `source_dirs`. If there are none, map the sources recorded as relative paths,
+    /// which lldb resolves against ".", to `source_dirs`.
+    fn build_source_dir_args(source_dirs: &[PathBuf], debuggee: &str) -> Vec<String> {
+        if source_dirs.is_empty() {
+            return vec![];
+        }
+        let missing_comp_dirs = find_missing_comp_dirs(debuggee);
+        if missing_comp_dirs.is_empty() {
+            return source_dirs
+                .iter()
+                .flat_map(|source_dir| {
+                    vec![
+                        "-o".to_owned(),
+                        format!(
+                            "settings append target.source-map . {}",
+                            quote_debugger_arg(&source_dir.display().to_string())
+                        ),
+                    ]
+                })
+                .collect();
+        }
+        missing_comp_dirs
+            .iter()
+            .flat_map(|comp_dir| {
+                source_dirs.iter().map(move |source_dir| {
+                    format!(
+                        "settings append target.source-map {} {}",
+                        quote_debugger_arg(&comp_dir.display().to_string()),
+                        quote_debugger_arg(&source_dir.display().to_string())
+                    )
+                })
+            })
+            .flat_map(|command| vec!["-o".to_owned(), command])
+            .collect()
+    }
+
+    /// Builds the arguments for the debuggees compiled by Zig.
+    /// lldb has no Zig support, and its default `step-avoid-regexp` is for the C++ std, `^std::`.
+    /// Let `step` skip the Zig standard library, whose functions are named like `std.debug.print`.
+    fn build_zig_args() -> Vec<String> {
+        vec![
+            "-o".to_owned(),
+            r"settings set target.process.thread.step-avoid-regexp ^(std|builtin)\.".to_owned(),
+        ]
+    }
+
+    /// Builds the arguments to apply `source_maps` and `symbols_dir` of `options` by lldb
+    fn build_source_map_args(options: &DebuggerOptions) -> Vec<String> {
+        let mut args = vec![];
+        if let Some(ref symbols_dir) = options.symbols_dir {
+            // `-O` runs before lldb loads the debuggee
+            args.push("-O".to_owned());
+            args.push(format!(
+                "settings append target.debug-file-search-paths {}",
+                symbols_dir.display()
+            ));
+        }
+        for map in &options.source_maps {
+            args.push("-o".to_owned());
+            args.push(format!(
+                "settings append target.source-map {} {}",
+                quote_debugger_arg(&map.from),
+                quote_debugger_arg(&map.to)
+            ));
+        }
+        args
+    }
+
+    /// Builds the arguments to change the working directory of lldb to `debugger_cwd` of `options`.
+    /// `target.run-cwd` is the working directory of the processes which lldb launches, like `cd` of gdb.
+    /// It doesn't change the directory where lldb resolves relative paths such as the ones to the sources,
+    /// which `platform settings -w` does.
+    fn build_cwd_args(options: &DebuggerOptions) -> Vec<String> {
+        match options.debugger_cwd {
+            Some(ref cwd) => {
+                let cwd = quote_debugger_arg(&cwd.display().to_string());
+                vec![
+                    "-o".to_owned(),
+                    format!("settings set target.run-cwd {}", cwd),
+                    "-o".to_owned(),
+                    format!("platform settings -w {}", cwd),
+                ]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Builds the command line to run the debuggee under lldb in batch mode, which runs `on_crash_commands`
+    /// when the debuggee crashes.
+    ///
+    /// lldb exits with the exit code of the debuggee, or 128 + signal number if the debuggee crashes.
+    pub fn build_on_crash_commandline(
+        options: &DebuggerOptions,
+        debuggee: &str,
+        args: &[String],
+        on_crash_commands: &[String],
+    ) -> Result<Vec<String>> {
+        DebuggerOptions::bail_if_command_missing("lldb", &options.debugger_path)?;
+        let mut commandline = vec![options.debugger_command("lldb"), "--batch".to_owned()];
+        commandline.extend(Self::build_source_dir_args(&options.source_dirs, debuggee));
+        commandline.extend(Self::build_source_map_args(options));
+        if is_zig_binary(debuggee) {
+            commandline.extend(Self::build_zig_args());
+        }
+        commandline.extend(vec![
+            "-o".to_owned(),
+            "run".to_owned(),
+            // lldb skips the rest of `-o` commands and runs `-k` commands if the debuggee crashes
+            "-o".to_owned(),
+            "script import os; os._exit(lldb.process.GetExitStatus())".to_owned(),
+        ]);
+        let on_crash_commands =
+            on_crash_commands
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once(
+                    "script import os; os._exit(128 + lldb.thread.GetStopReasonDataAtIndex(0))",
+                ));
+        for command in on_crash_commands {
+            commandline.push("-k".to_owned());
+            commandline.push(command.to_owned());
+        }
+        commandline.push("--".to_owned());
+        commandline.push(debuggee.to_owned());
+        commandline.extend(args.iter().cloned());
+        Ok(options.wrap_commandline(commandline))
+    }
+}
+
+/// Debugger given by `--debugger-command`, such as `cgdb -p {pid}` or a wrapper script of gdb
+pub struct CustomDebugger;
+
+impl CustomDebugger {
+    pub fn build(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<GdbCompatibleDebugger> {
+        let template = options
+            .debugger_command_template
+            .clone()
+            .ok_or_else(|| anyhow!("[BUG] CustomDebugger is built without the template"))?;
+        if options.capture_core.is_some() || options.record {
+            bail!("--capture-core and --record are not supported with --debugger-command");
+        }
+        let debugger_name = template
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| anyhow!("--debugger-command should not be empty"))?
+            .to_owned();
+        let command_builder =
+            move |pid: Pid, program: String| Ok(Self::build_commandline(&template, pid, &program));
+        GdbCompatibleDebugger::new(
+            &debugger_name,
+            Box::new(command_builder),
+            options.clone(),
+            launcher,
+        )
+    }
+
+    /// Splits `template` by whitespace, and replaces `{pid}` and `{program}` in each argument
+    fn build_commandline(template: &str, pid: Pid, program: &str) -> Vec<String> {
+        template
+            .split_whitespace()
+            .map(|arg| {
+                arg.replace("{pid}", &pid.as_raw().to_string())
+                    .replace("{program}", program)
+            })
+            .collect()
+    }
+}
+
+/// Suggest `--source-dir` if the debuggee was built in directories which don't exist on this machine
+fn suggest_source_dir_if_missing(source_dirs: &[PathBuf], debuggee: &str) {
+    if !source_dirs.is_empty() {
+        return;
+    }
+    let missing_comp_dirs = find_missing_comp_dirs(debuggee);
+    if let Some(comp_dir) = missing_comp_dirs.first() {
+        log::info!(
+            "The debuggee was built in {:?}, which doesn't exist on this machine. \
+            Give --source-dir if the debugger cannot find the sources.",
+            comp_dir
+        );
     }
 }
 
@@ -139,30 +974,111 @@ pub struct GdbCompatibleDebugger {
     debuggee_pid: Option<Pid>,
     debuggee_path: Option<String>,
     commandline_builder: Box<dyn Fn(Pid, String) -> Result<Vec<String>>>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
 }
 
 impl GdbCompatibleDebugger {
     pub fn new(
         debugger_name: &str,
         command_builder: Box<dyn Fn(Pid, String) -> Result<Vec<String>>>,
+        options: DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
     ) -> Result<GdbCompatibleDebugger> {
-        if !command_exists(debugger_name) {
-            bail!(
-                "'{}' is not in PATH. Did you install {}?",
-                debugger_name,
-                debugger_name
-            )
-        }
+        DebuggerOptions::bail_if_command_missing(debugger_name, &options.debugger_path)?;
         Ok(GdbCompatibleDebugger {
             debugger_name: debugger_name.to_owned(),
             debuggee_pid: None,
             debuggee_path: None,
             commandline_builder: command_builder,
+            options,
+            launcher,
         })
     }
+
+    /// Returns the options of `dbgee run` for `set`, which pin this debugger and its absolute path.
+    /// The wrapper may be launched with another PATH, e.g. by cron or systemd.
+    fn build_resolved_run_args(&self) -> Vec<(&'static str, String)> {
+        if self.options.debugger_command_template.is_some() {
+            // `--debugger-command` is passed to `dbgee run` as it is
+            return vec![];
+        }
+        let mut run_args = vec![("debugger", self.debugger_name.clone())];
+        if self.options.debugger_path.is_none() {
+            run_args
+                .extend(find_command_path(&self.debugger_name).map(|path| ("debugger-path", path)));
+        }
+        run_args
+    }
+
+    /// Capture a core dump of the stopped debuggee to `core_path` by the debugger, keeping the debuggee stopped.
+    fn capture_core(&self, pid: Pid, core_path: &Path) -> Result<()> {
+        let commandline = self.build_capture_core_commandline(pid, core_path)?;
+        let status = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .logged()
+            .stdout(std::process::Stdio::null())
+            .status()
+            .with_context(|| format!("Failed to launch {}", self.debugger_name))?;
+        if !status.success() {
+            bail!("{} failed to capture a core dump", self.debugger_name);
+        }
+        // The debuggee may be continued when the debugger detaches from it
+        signal::kill(pid, signal::SIGSTOP).context("Failed to stop the debuggee again")?;
+        log::info!(
+            "Captured a core dump of the debuggee: {}",
+            core_path.display()
+        );
+        Ok(())
+    }
+
+    /// Lets the stopped debuggee continue without the debugger, for `--capture-core-only`
+    fn continue_without_attaching(&self, pid: Pid) -> Result<()> {
+        log::info!("Letting the debuggee continue without attaching the debugger");
+        signal::kill(pid, signal::SIGCONT).context("Failed to continue the debuggee")
+    }
+
+    /// Runs the preparations which the options ask for before the debugger attaches to the stopped debuggee
+    fn prepare_attach(&self, pid: Pid) -> Result<()> {
+        if let Some(ref core_path) = self.options.capture_core {
+            self.capture_core(pid, core_path)?;
+        }
+        Ok(())
+    }
+
+    fn build_capture_core_commandline(&self, pid: Pid, core_path: &Path) -> Result<Vec<String>> {
+        let core_path = core_path
+            .to_str()
+            .ok_or_else(|| anyhow!("the core path should be a valid UTF-8 path"))?;
+        let pid = pid.as_raw().to_string();
+        let commandline = match self.debugger_name.as_str() {
+            "gdb" => vec![
+                self.options.debugger_command("gdb"),
+                "--batch".to_owned(),
+                "-p".to_owned(),
+                pid,
+                "-ex".to_owned(),
+                format!("generate-core-file {}", core_path),
+            ],
+            "lldb" => vec![
+                self.options.debugger_command("lldb"),
+                "--batch".to_owned(),
+                "-p".to_owned(),
+                pid,
+                "-o".to_owned(),
+                format!("process save-core {}", core_path),
+            ],
+            other => bail!("[BUG] capturing a core dump is not supported by {}", other),
+        };
+        Ok(self.options.wrap_commandline(commandline))
+    }
 }
 
 impl Debugger for GdbCompatibleDebugger {
+    fn name(&self) -> &str {
+        &self.debugger_name
+    }
+
     fn run(
         &mut self,
         debuggee: &str,
@@ -170,9 +1086,22 @@ impl Debugger for GdbCompatibleDebugger {
         terminal: &mut dyn DebuggerTerminal,
     ) -> Result<Pid> {
         let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
-        let debuggee_pid = run_and_stop_dbgee(&debuggee_abspath, args.into_iter())?;
+        let debuggee_cmd: Vec<&str> = std::iter::once(debuggee_abspath.as_str())
+            .chain(args)
+            .collect();
+        let debuggee_pid =
+            self.launcher
+                .run_and_stop(&debuggee_cmd, StopMethod::Sigstop, &self.options)?;
         self.debuggee_pid = Some(debuggee_pid);
         self.debuggee_path = Some(debuggee_abspath);
+        self.prepare_attach(debuggee_pid)?;
+        if self.options.capture_core_only {
+            self.continue_without_attaching(debuggee_pid)?;
+            return Ok(debuggee_pid);
+        }
+        self.options
+            .run_pre_attach_hook(debuggee_pid)
+            .map_err(|e| kill_launched_process(debuggee_pid, e))?;
         terminal.open(self)?;
         Ok(debuggee_pid)
     }
@@ -181,9 +1110,15 @@ impl Debugger for GdbCompatibleDebugger {
         &mut self,
         debuggee: &str,
         start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
-        set_to_exec_dgeee(debuggee, start_cmd)
+        set_to_exec_dgeee(
+            debuggee,
+            start_cmd,
+            wrapper_options,
+            &self.build_resolved_run_args(),
+        )
     }
 
     fn unset(&mut self, debuggee: &str) -> Result<()> {
@@ -199,21 +1134,35 @@ impl Debugger for GdbCompatibleDebugger {
         let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
         self.debuggee_pid = Some(pid);
         self.debuggee_path = Some(debuggee_abspath);
+        self.prepare_attach(pid)?;
+        if self.options.capture_core_only {
+            return self.continue_without_attaching(pid);
+        }
+        self.options.run_pre_attach_hook(pid)?;
         terminal.open(self)?;
         Ok(())
     }
 
     fn build_attach_commandline(&self) -> Result<Vec<String>> {
-        (self.commandline_builder)(
+        let mut commandline = (self.commandline_builder)(
             self.debuggee_pid
                 .ok_or_else(|| anyhow!("[BUG] uninitialized GdbCompatibleDebugger"))?,
             self.debuggee_path
                 .clone()
                 .ok_or_else(|| anyhow!("[BUG] uninitialized GdbCompatibleDebugger"))?,
-        )
+        )?;
+        commandline.extend(self.options.debugger_args.iter().cloned());
+        if cfg!(target_os = "macos") && !self.options.sudo {
+            show_macos_debugging_permission_warning();
+        }
+        self.options.show_debugger_user_warning();
+        Ok(self.options.wrap_commandline(commandline))
     }
 
     fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        if self.options.debugger_command_template.is_some() {
+            bail!("--debugger-command runs in a terminal such as tmux. VSCode is not supported");
+        }
         let mut info = HashMap::new();
         info.insert(
             AttachInformationKey::DebuggerTypeHint,
@@ -229,15 +1178,23 @@ impl Debugger for GdbCompatibleDebugger {
                 ))?
             ),
         );
-        info.insert(
-            AttachInformationKey::ProgramName,
-            self.debuggee_path.clone().ok_or_else(|| {
-                anyhow!(
-                    "[BUG] uninitialized GdbCompatibleDebugger: {}",
-                    self.debugger_name
-                )
-            })?,
-        );
+        let debuggee_path = self.debuggee_path.clone().ok_or_else(|| {
+            anyhow!(
+                "[BUG] uninitialized GdbCompatibleDebugger: {}",
+                self.debugger_name
+            )
+        })?;
+        self.options.insert_debuggee_name(&mut info, &debuggee_path);
+        info.insert(AttachInformationKey::ProgramName, debuggee_path);
+        self.options.insert_source_information(&mut info);
+        self.options.insert_process_context(&mut info);
+        // The core dump is captured before the debugger attaches
+        if let Some(ref core_path) = self.options.capture_core {
+            info.insert(
+                AttachInformationKey::CorePath,
+                core_path.display().to_string(),
+            );
+        }
         Ok(info)
     }
 
@@ -247,26 +1204,56 @@ impl Debugger for GdbCompatibleDebugger {
             return Ok(true);
         }
         if file_output.contains("shell") && check_if_wrapped(debuggee) {
-            return self.is_debuggee_surely_supported(&get_debuggee_backup_name(debuggee));
+            return self.is_debuggee_surely_supported(&get_backup_path_of_wrapper(debuggee));
         }
         Ok(false)
     }
 }
 
-pub struct DelveDebugger {
-    port: Option<i32>,
+/// Records the debuggee by `rr record`, and replays the recording by `rr replay`.
+/// rr replays only finished recordings, so the debugger terminal opens after the debuggee exits.
+/// The recording is written to its own trace directory, which is kept after dbgee exits to replay it again.
+pub struct RrDebugger {
+    options: DebuggerOptions,
+    trace_dir: Option<PathBuf>,
 }
 
-impl DelveDebugger {
-    pub fn new() -> Result<DelveDebugger> {
-        if !command_exists("dlv") {
-            bail!("'dlv' is not in PATH. Did you install delve?")
+impl RrDebugger {
+    pub fn new(options: &DebuggerOptions) -> Result<RrDebugger> {
+        if !cfg!(target_os = "linux") {
+            bail!("rr is supported only on Linux");
         }
-        Ok(DelveDebugger { port: None })
+        if options.sudo || options.debugger_user.is_some() {
+            bail!(
+                "rr doesn't support --sudo-debugger and --debugger-user, since it records the debuggee \
+                as the user running dbgee and replays the recording of that user. Run dbgee itself by sudo instead"
+            );
+        }
+        DebuggerOptions::bail_if_command_missing("rr", &options.debugger_path)?;
+        Ok(RrDebugger {
+            options: options.clone(),
+            trace_dir: None,
+        })
+    }
+
+    fn build_record_commandline(&self, trace_dir: &Path, debuggee_cmd: &[&str]) -> Vec<String> {
+        vec![
+            self.options.debugger_command("rr"),
+            "record".to_owned(),
+            "-o".to_owned(),
+            trace_dir.to_string_lossy().into_owned(),
+        ]
+        .into_iter()
+        .chain(debuggee_cmd.iter().map(|arg| (*arg).to_owned()))
+        .collect()
     }
 }
 
-impl Debugger for DelveDebugger {
+impl Debugger for RrDebugger {
+    fn name(&self) -> &str {
+        "rr"
+    }
+
     fn run(
         &mut self,
         debuggee: &str,
@@ -274,39 +1261,278 @@ impl Debugger for DelveDebugger {
         terminal: &mut dyn DebuggerTerminal,
     ) -> Result<Pid> {
         let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
-        self.port = Some(5679);
-        let debugger_args: Vec<&str> = vec![
-            "exec",
-            "--headless",
-            "--log-dest",
-            "/dev/null",
-            "--api-version=2",
-            "--listen",
-            "localhost:5679",
-            &debuggee_abspath,
-            "--",
-        ]
-        .into_iter()
-        .chain(args.into_iter())
-        .collect();
-
-        if cfg!(target_os = "macos") {
-            show_macos_dlv_log_warning();
+        let debuggee_cmd: Vec<&str> = std::iter::once(debuggee_abspath.as_str())
+            .chain(args)
+            .collect();
+        // `rr record -o` requires the trace directory not to exist
+        let trace_dir = tempfile::Builder::new()
+            .prefix("dbgee-rr-")
+            .tempdir()
+            .context("Failed to create a directory for the recording of rr")?
+            .into_path()
+            .join("trace");
+        let commandline = self.build_record_commandline(&trace_dir, &debuggee_cmd);
+        let recorder = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .logged()
+            .spawn()
+            .context("Failed to launch rr")?;
+        let recorder_pid = Pid::from_raw(recorder.id() as i32);
+        // Ctrl+C stops the recording of the debuggee, not dbgee, which opens the replay after that.
+        // Ignore SIGINT after Command::spawn because spawn inherits the parent's signal handlers.
+        if !self.options.no_sigint_ignore {
+            ignore_sigint()?;
         }
-
-        let pid = launch_debugger_server("dlv", &debugger_args)?;
+        wait_exit_without_reaping(recorder_pid).context("Failed to wait for rr to record")?;
+        log::info!(
+            "rr finished recording to {}. Opening the replay of the recording. \
+            You can replay it again by `rr replay {}`",
+            trace_dir.display(),
+            trace_dir.display()
+        );
+        self.trace_dir = Some(trace_dir);
+        terminal.open(self)?;
+        Ok(recorder_pid)
+    }
+
+    fn set(
+        &mut self,
+        debuggee: &str,
+        start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        set_to_exec_dgeee(
+            debuggee,
+            start_cmd,
+            wrapper_options,
+            &[("debugger", "rr".to_owned())],
+        )
+    }
+
+    fn unset(&mut self, debuggee: &str) -> Result<()> {
+        unset_from_exec_dbgee(debuggee)
+    }
+
+    fn attach(
+        &mut self,
+        _pid: Pid,
+        _debuggee: &str,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!(
+            "rr cannot attach to a running process. Record it from the start by `dbgee run -d rr`"
+        );
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        let trace_dir = self
+            .trace_dir
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] rr has not recorded the debuggee yet"))?;
+        let mut commandline = vec![self.options.debugger_command("rr"), "replay".to_owned()];
+        commandline.extend(self.options.debugger_args.iter().cloned());
+        commandline.push(trace_dir.to_string_lossy().into_owned());
+        Ok(commandline)
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        bail!("VSCode cannot replay the recording of rr. Use a terminal such as tmux, which runs `rr replay`");
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        // rr records only the native binaries on Linux
+        let file_output = get_cached_file_output(debuggee)?;
+        if file_output.contains("ELF") {
+            return Ok(true);
+        }
+        if file_output.contains("shell") && check_if_wrapped(debuggee) {
+            return self.is_debuggee_surely_supported(&get_backup_path_of_wrapper(debuggee));
+        }
+        Ok(false)
+    }
+}
+
+/// Waits for the child `pid` to exit, leaving it unreaped so that `waitpid` can get its exit status later.
+fn wait_exit_without_reaping(pid: Pid) -> Result<()> {
+    loop {
+        let mut info: nix::libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let ret = unsafe {
+            nix::libc::waitid(
+                nix::libc::P_PID,
+                pid.as_raw() as nix::libc::id_t,
+                &mut info,
+                nix::libc::WEXITED | nix::libc::WNOWAIT,
+            )
+        };
+        match nix::errno::Errno::result(ret) {
+            Ok(_) => return Ok(()),
+            Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to wait for pid {}", pid)),
+        }
+    }
+}
+
+/// Default port which debugger servers such as jdb and lldb-dap listen on
+pub const DEFAULT_DEBUGGER_PORT: u16 = 5679;
+/// Default host which debugger servers such as dlv and debugpy listen on
+pub const DEFAULT_LISTEN_HOST: &str = "localhost";
+
+/// Address which debugger servers such as dlv and debugpy listen on.
+///
+/// The precedence is: the environment variables > the flags > the defaults.
+/// `$DBGEE_PORT` and `$DBGEE_LISTEN_HOST` override `--port` and the defaults at runtime, so that the wrapper script
+/// by `set`, which has `--port` baked in, can change them without `set` again.
+/// dlv and debugpy default to a free port instead, so that concurrent debug sessions don't clash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenAddress {
+    pub host: String,
+    pub port: u16,
+}
+
+impl ListenAddress {
+    /// Resolves the address from the environment variables and `port` given by `--port`, falling back to the defaults
+    pub fn resolve(port: Option<u16>) -> Result<ListenAddress> {
+        Self::resolve_with(
+            port,
+            |name| env::var(name).ok(),
+            || Ok(DEFAULT_DEBUGGER_PORT),
+        )
+    }
+
+    /// Resolves the address like `resolve`, but falls back to a free port instead of the default port
+    pub fn resolve_with_free_port(port: Option<u16>) -> Result<ListenAddress> {
+        Self::resolve_with(port, |name| env::var(name).ok(), find_free_port)
+    }
+
+    fn resolve_with<F, D>(port: Option<u16>, get_env: F, default_port: D) -> Result<ListenAddress>
+    where
+        F: Fn(&str) -> Option<String>,
+        D: FnOnce() -> Result<u16>,
+    {
+        let port = match (get_env("DBGEE_PORT"), port) {
+            (Some(port), _) => port
+                .parse()
+                .with_context(|| format!("$DBGEE_PORT is not a valid port: '{}'", port))?,
+            (None, Some(port)) => port,
+            (None, None) => default_port()?,
+        };
+        let host = get_env("DBGEE_LISTEN_HOST").unwrap_or_else(|| DEFAULT_LISTEN_HOST.to_owned());
+        Ok(ListenAddress { host, port })
+    }
+}
+
+impl std::fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+pub struct DelveDebugger {
+    listen_address: Option<ListenAddress>,
+    /// The child process of dlv, which `run` launched
+    debuggee_pid: Option<Pid>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
+}
+
+impl DelveDebugger {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<DelveDebugger> {
+        if options.dlv_path.is_none() && !command_exists("dlv") {
+            bail!("'dlv' is not in PATH. Did you install delve?")
+        }
+        Ok(DelveDebugger {
+            listen_address: None,
+            debuggee_pid: None,
+            options: options.clone(),
+            launcher,
+        })
+    }
+
+    fn dlv_command(&self) -> String {
+        self.options
+            .dlv_path
+            .clone()
+            .unwrap_or_else(|| "dlv".to_owned())
+    }
+
+    fn launch_server(&self, debugger_args: &[&str], listen_port: u16) -> Result<Pid> {
+        let commandline = self.options.wrap_commandline(
+            std::iter::once(self.dlv_command().as_str())
+                .chain(debugger_args.iter().copied())
+                .map(str::to_owned)
+                .collect(),
+        );
+        let commandline: Vec<&str> = commandline.iter().map(String::as_str).collect();
+        self.options.show_debugger_user_warning();
+        self.launcher
+            .launch_server(&commandline, listen_port, &self.options)
+    }
+}
+
+impl Debugger for DelveDebugger {
+    fn name(&self) -> &str {
+        "dlv"
+    }
+
+    fn run(
+        &mut self,
+        debuggee: &str,
+        args: Vec<&str>,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<Pid> {
+        let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
+        let listen_address = ListenAddress::resolve_with_free_port(self.options.listen_port)?;
+        let listen_address_string = listen_address.to_string();
+        let listen_port = listen_address.port;
+        self.listen_address = Some(listen_address);
+        let debugger_args: Vec<&str> = vec![
+            "exec",
+            "--headless",
+            "--log-dest",
+            "/dev/null",
+            "--api-version=2",
+            "--listen",
+            &listen_address_string,
+            &debuggee_abspath,
+            "--",
+        ]
+        .into_iter()
+        .chain(args)
+        .collect();
+
+        if cfg!(target_os = "macos") {
+            show_macos_dlv_log_warning();
+        }
+
+        let pid = self.launch_server(&debugger_args, listen_port)?;
+        // dlv has launched the debuggee by the time it listens
+        self.debuggee_pid = find_leaf_descendant(pid);
+        self.options
+            .run_pre_attach_hook(pid)
+            .map_err(|e| kill_launched_process(pid, e))?;
         terminal.open(self)?;
 
         Ok(pid)
     }
 
+    fn debuggee_pid(&self, launched_pid: Pid) -> Pid {
+        self.debuggee_pid.unwrap_or(launched_pid)
+    }
+
     fn attach(
         &mut self,
         pid: Pid,
         _debuggee: &str,
         terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
-        self.port = Some(5679);
+        let listen_address = ListenAddress::resolve_with_free_port(self.options.listen_port)?;
+        let listen_address_string = listen_address.to_string();
+        let listen_port = listen_address.port;
+        self.listen_address = Some(listen_address);
         let pid_string = pid.as_raw().to_string();
         let debugger_args: Vec<&str> = vec![
             "attach",
@@ -315,7 +1541,7 @@ impl Debugger for DelveDebugger {
             "/dev/null",
             "--api-version=2",
             "--listen",
-            "localhost:5679",
+            &listen_address_string,
             pid_string.as_str(),
         ];
 
@@ -323,7 +1549,9 @@ impl Debugger for DelveDebugger {
             show_macos_dlv_log_warning();
         }
 
-        launch_debugger_server("dlv", &debugger_args).context("Failed to launch dlv")?;
+        self.launch_server(&debugger_args, listen_port)
+            .context("Failed to launch dlv")?;
+        self.options.run_pre_attach_hook(pid)?;
         terminal.open(self).context("Failed to open the terminal")?;
 
         Ok(())
@@ -333,10 +1561,15 @@ impl Debugger for DelveDebugger {
         &mut self,
         debuggee: &str,
         start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
         // terminal is not used since `set_to_exec_dbgee` build the command for `dbgee run` by clap's utility,
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
-        set_to_exec_dgeee(debuggee, start_cmd)
+        let mut resolved_run_args = vec![("debugger", "dlv".to_owned())];
+        if self.options.dlv_path.is_none() {
+            resolved_run_args.extend(find_command_path("dlv").map(|path| ("dlv-path", path)));
+        }
+        set_to_exec_dgeee(debuggee, start_cmd, wrapper_options, &resolved_run_args)
     }
 
     fn unset(&mut self, debuggee: &str) -> Result<()> {
@@ -344,26 +1577,34 @@ impl Debugger for DelveDebugger {
     }
 
     fn build_attach_commandline(&self) -> Result<Vec<String>> {
-        Ok(vec![
-            "dlv".to_owned(),
+        let mut commandline = vec![
+            self.dlv_command(),
             "connect".to_owned(),
-            "localhost:".to_owned()
-                + &self
-                    .port
-                    .ok_or_else(|| anyhow!("[BUG] uninitialized DelveDebugger"))?
-                    .to_string(),
-        ])
+            self.listen_address
+                .as_ref()
+                .ok_or_else(|| anyhow!("[BUG] uninitialized DelveDebugger"))?
+                .to_string(),
+        ];
+        commandline.extend(self.options.debugger_args.iter().cloned());
+        Ok(commandline)
     }
 
     fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        let listen_address = self
+            .listen_address
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] uninitialized DelveDebugger"))?;
         let mut info = HashMap::new();
         info.insert(AttachInformationKey::DebuggerTypeHint, "go".to_owned());
         info.insert(
             AttachInformationKey::DebuggerPort,
-            self.port
-                .ok_or_else(|| anyhow!("[BUG] uninitialized DelveDebugger"))?
-                .to_string(),
+            listen_address.port.to_string(),
+        );
+        info.insert(
+            AttachInformationKey::DebuggerHost,
+            listen_address.host.clone(),
         );
+        self.options.insert_process_context(&mut info);
         Ok(info)
     }
 
@@ -380,27 +1621,57 @@ impl Debugger for DelveDebugger {
             return Ok(true);
         }
         if file_output.contains("shell") && check_if_wrapped(debuggee) {
-            return self.is_debuggee_surely_supported(&get_debuggee_backup_name(debuggee));
+            return self.is_debuggee_surely_supported(&get_backup_path_of_wrapper(debuggee));
         }
         Ok(false)
     }
 }
 
+fn show_macos_debugging_permission_warning() {
+    // Non-root users can debug other processes only when the developer mode is enabled on macOS.
+    if unistd::geteuid().is_root() {
+        return;
+    }
+    let is_developer_mode_enabled = get_cached_command_output(&["DevToolsSecurity", "-status"])
+        .map(|output| output.contains("enabled"))
+        .unwrap_or(true);
+    if !is_developer_mode_enabled {
+        log::warn!(
+            "Debuggers may fail to attach due to lack of permission. Enable the developer mode by \
+            `sudo DevToolsSecurity -enable`, sign the debugger with `com.apple.security.cs.debugger` \
+            entitlement, or give --sudo-debugger option to launch the debugger by sudo."
+        );
+    }
+}
+
 fn show_macos_dlv_log_warning() {
     log::info!(
         "delve outputs logs from lldb-server to stderr on macos, which cannot be suppressed"
     );
 }
 
-pub struct StopAndWritePidDebugger;
+pub struct StopAndWritePidDebugger {
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
+}
 
 impl StopAndWritePidDebugger {
-    pub fn new() -> StopAndWritePidDebugger {
-        StopAndWritePidDebugger {}
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> StopAndWritePidDebugger {
+        StopAndWritePidDebugger {
+            options: options.clone(),
+            launcher,
+        }
     }
 }
 
 impl Debugger for StopAndWritePidDebugger {
+    fn name(&self) -> &str {
+        "stop-and-write-pid"
+    }
+
     fn run(
         &mut self,
         debuggee: &str,
@@ -408,8 +1679,29 @@ impl Debugger for StopAndWritePidDebugger {
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<Pid> {
         let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
-        let debuggee_pid = run_and_stop_dbgee(&debuggee_abspath, args.into_iter())?;
+        let stop_method = self.options.stop_method;
+        if stop_method == StopMethod::PtraceHold && !cfg!(target_os = "linux") {
+            bail!("--stop-method ptrace-hold is supported only on Linux");
+        }
+        let debuggee_cmd: Vec<&str> = std::iter::once(debuggee_abspath.as_str())
+            .chain(args)
+            .collect();
+        let debuggee_pid = self
+            .launcher
+            .run_and_stop(&debuggee_cmd, stop_method, &self.options)?;
+        self.options
+            .run_pre_attach_hook(debuggee_pid)
+            .map_err(|e| kill_launched_process(debuggee_pid, e))?;
         write_pid_file(debuggee_pid).context("Failed to write the pid file")?;
+        if stop_method == StopMethod::PtraceHold {
+            let hold_file = HoldFile::create(debuggee_pid)
+                .map_err(|e| kill_launched_process(debuggee_pid, e))?;
+            log::info!(
+                "Remove {} right before attaching a debugger to take over the debuggee.",
+                hold_file.path().display()
+            );
+            hand_over_held_debuggee(debuggee_pid, &hold_file)?;
+        }
         Ok(debuggee_pid)
     }
 
@@ -417,10 +1709,16 @@ impl Debugger for StopAndWritePidDebugger {
         &mut self,
         debuggee: &str,
         start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
         // terminal is not used since `set_to_exec_dbgee` build the command for `dbgee run` by clap's utility,
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
-        set_to_exec_dgeee(debuggee, start_cmd)
+        set_to_exec_dgeee(
+            debuggee,
+            start_cmd,
+            wrapper_options,
+            &[("debugger", "stop-and-write-pid".to_owned())],
+        )
     }
 
     fn unset(&mut self, debuggee: &str) -> Result<()> {
@@ -433,6 +1731,7 @@ impl Debugger for StopAndWritePidDebugger {
         _debuggee: &str,
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
+        self.options.run_pre_attach_hook(pid)?;
         write_pid_file(pid).context("Failed to write the pid file")?;
         Ok(())
     }
@@ -450,24 +1749,67 @@ impl Debugger for StopAndWritePidDebugger {
     }
 }
 
+/// Quotes `arg` of a command of gdb or lldb, which split the arguments at spaces unless they are double-quoted
+fn quote_debugger_arg(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs `hook`, a shell command given by the `option`, with $DBGEE_PID set to `pid`.
+/// Bails with the output of the hook if it fails.
+pub fn run_attach_hook(option: &str, hook: &str, pid: Pid) -> Result<()> {
+    log::debug!("running {}: {}", option, hook);
+    let output = Command::new("sh")
+        .args(["-c", hook])
+        .env("DBGEE_PID", pid.to_string())
+        .logged()
+        .output()
+        .with_context(|| format!("Failed to run {} '{}'", option, hook))?;
+    if !output.status.success() {
+        bail!(
+            "{} '{}' failed with {}. Output:\n{}{}",
+            option,
+            hook,
+            output.status,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+/// Kills the process which dbgee launched, since nobody debugs it after `error`
+pub(crate) fn kill_launched_process(pid: Pid, error: anyhow::Error) -> anyhow::Error {
+    let _ = signal::kill(pid, signal::SIGKILL);
+    error
+}
+
+/// File where stop-and-write-pid writes the pid of the debuggee
+pub const PID_FILE_PATH: &str = "/tmp/dbgee_pid";
+
 fn write_pid_file(pid: Pid) -> Result<()> {
     log::info!("The debuggee process is paused. Atach a debugger to it by PID.");
     log::info!(
-        "PID: {}. It's also written to /tmp/dbgee_pid as a plain text number.",
-        pid.as_raw()
+        "PID: {}. It's also written to {} as a plain text number.",
+        pid.as_raw(),
+        PID_FILE_PATH
     );
-    let mut pid_file = File::create("/tmp/dbgee_pid")?;
+    let mut pid_file = File::create(PID_FILE_PATH)?;
     write!(pid_file, "{}", pid.as_raw())?;
     Ok(())
 }
 
 pub struct PythonDebugger {
     python_command: String,
-    port: Option<i32>,
+    listen_address: Option<ListenAddress>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
 }
 
 impl PythonDebugger {
-    pub fn new() -> Result<PythonDebugger> {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<PythonDebugger> {
         let python_path;
         if command_exists("python3") {
             python_path = "python3".to_owned();
@@ -477,49 +1819,97 @@ impl PythonDebugger {
             bail!("Neither 'python3' nor 'python' exist. Did you install python?");
         }
 
-        let debugpy_exists = Command::new(&python_path)
-            .args(&["-c", "'import debugpy'"])
-            .stderr(std::process::Stdio::null())
-            .stdout(std::process::Stdio::null())
-            .status();
-        if debugpy_exists.is_err() || !debugpy_exists.unwrap().success() {
-            bail!("'debugpy' module is not installed. Please install debugpy via pip.");
-        }
+        check_debugpy_installed(&python_path, DEBUGPY_CHECK_TIMEOUT)?;
 
         Ok(PythonDebugger {
             python_command: python_path,
-            port: None,
+            listen_address: None,
+            options: options.clone(),
+            launcher,
         })
     }
 }
 
+/// How long to wait for `python -c 'import debugpy'`
+pub(crate) const DEBUGPY_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bails unless `python` can import debugpy
+pub(crate) fn check_debugpy_installed(python: &str, timeout: Duration) -> Result<()> {
+    let mut child = Command::new(python)
+        .args(["-c", "import debugpy"])
+        .logged()
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'. Did you install python?", python))?;
+    let started_at = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if started_at.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "'{} -c \"import debugpy\"' didn't finish in {} seconds",
+                python,
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    if !status.success() {
+        bail!(
+            "'debugpy' module is not installed for '{}'. Please install debugpy via pip.",
+            python
+        );
+    }
+    Ok(())
+}
+
 impl Debugger for PythonDebugger {
+    fn name(&self) -> &str {
+        "debugpy"
+    }
+
     fn run(
         &mut self,
         debuggee: &str,
         args: Vec<&str>,
         terminal: &mut dyn DebuggerTerminal,
     ) -> Result<Pid> {
-        self.port = Some(5679);
-        let debugger_args: Vec<&str> = vec![
+        let listen_address = ListenAddress::resolve_with_free_port(self.options.listen_port)?;
+        let listen_address_string = listen_address.to_string();
+        let listen_port = listen_address.port;
+        self.listen_address = Some(listen_address);
+        let mut debugger_args: Vec<&str> = vec![
             "-m",
             "debugpy",
             "--wait-for-client",
             "--listen",
-            "5679",
-            debuggee,
-        ]
-        .into_iter()
-        .chain(args.into_iter())
-        .collect();
-
-        let pid = launch_debugger_server(&self.python_command, &debugger_args)?;
-        if terminal.name() != "vscode" {
-            log::info!("only `-t vscode` is the supported option for Python.");
+            &listen_address_string,
+        ];
+        // debugpy injects itself into subprocesses and notifies the client to attach them by default.
+        // Give it explicitly either way, so that only the debuggee is debugged without --follow-subprocess
+        let follow_subprocess = if self.options.follow_subprocess {
+            "true"
+        } else {
+            "false"
         };
-        // Ignore the given terminal since Python supports only Vscode
-        let mut vscode = crate::debugger_terminal::VsCode::new();
-        vscode.open(self)?;
+        debugger_args.extend(["--configure-subProcess", follow_subprocess].iter());
+        let commandline: Vec<&str> = std::iter::once(self.python_command.as_str())
+            .chain(debugger_args)
+            .chain(std::iter::once(debuggee))
+            .chain(args)
+            .collect();
+
+        let pid = self
+            .launcher
+            .launch_server(&commandline, listen_port, &self.options)?;
+        self.options
+            .run_pre_attach_hook(pid)
+            .map_err(|e| kill_launched_process(pid, e))?;
+        terminal.open(self)?;
 
         Ok(pid)
     }
@@ -528,6 +1918,7 @@ impl Debugger for PythonDebugger {
         &mut self,
         _debuggee: &str,
         _start_cmd: Vec<&str>,
+        _wrapper_options: &WrapperOptions,
         _terminal: &mut dyn DebuggerTerminal,
     ) -> Result<()> {
         bail!("set is not implemented yet for Python");
@@ -538,19 +1929,23 @@ impl Debugger for PythonDebugger {
     }
 
     fn build_attach_commandline(&self) -> Result<Vec<String>> {
-        bail!("[BUG] build_attach_commandline should not be called for PythonDebugger");
+        bail!("debugpy has no command line client. Map it to vscode by `--terminal-map debugpy=vscode`");
     }
 
     fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        let listen_address = self
+            .listen_address
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] PythonDebugger.listen_address is not initializaed"))?;
         let mut info = HashMap::new();
         info.insert(AttachInformationKey::DebuggerTypeHint, "python".to_owned());
         info.insert(
             AttachInformationKey::DebuggerPort,
-            format!(
-                "{}",
-                self.port
-                    .ok_or_else(|| anyhow!("[BUG] PythonDebugger.port is not initializaed"))?
-            ),
+            listen_address.port.to_string(),
+        );
+        info.insert(
+            AttachInformationKey::DebuggerHost,
+            listen_address.host.clone(),
         );
         Ok(info)
     }
@@ -560,311 +1955,2660 @@ impl Debugger for PythonDebugger {
         if file_output.contains("Python") {
             return Ok(true);
         }
-        Ok(false)
+        // `file` reports scripts with shebangs such as `#!/usr/bin/env -S python3 -X dev` just as text
+        Ok(is_python_script(debuggee))
     }
 }
 
-fn launch_debugger_server(debugger_path: &str, debugger_args: &[&str]) -> Result<Pid> {
-    let debugger = Command::new(debugger_path)
-        .args(debugger_args)
-        .spawn()
-        .with_context(|| {
-            anyhow!(
-                "failed to launch {}. Perhaps is port 5679 being used?",
-                debugger_path
-            )
-        })?;
-    // To wait for the child process, not being signalled by Ctrl+C.
-    // Ignore SIGINT after Command::spawn because spawn inherits the parent's signal handlers.
-    // This makes some gap between the timing when the debugger launched and the timing when the host started to ignore SIGINT,
-    // but I'm doing this just due to laziness
-    ignore_sigint()?;
-
-    // wait for the server to get ready
-    std::thread::sleep(std::time::Duration::from_secs(1));
-    Ok(Pid::from_raw(debugger.id() as i32))
+/// Returns true if the shebang of `debuggee` runs Python, such as python3 or python3.11
+fn is_python_script(debuggee: &str) -> bool {
+    read_shebang(debuggee)
+        .and_then(|shebang| {
+            Path::new(&shebang.interpreter)
+                .file_name()
+                .map(|name| name.to_string_lossy().starts_with("python"))
+        })
+        .unwrap_or(false)
 }
 
-fn run_and_stop_dbgee<'a>(debuggee: &'a str, args: impl Iterator<Item = &'a str>) -> Result<Pid> {
-    let debuggee_cmd: Vec<&str> = vec![debuggee].into_iter().chain(args).collect();
-    // To wait for the child process, not being signalled by Ctrl+C
-    ignore_sigint()?;
-    let debuggee_pid = fork_exec_stop(&debuggee_cmd)?;
-    // Sleeping childs don't respond to SIGINT/SIGTERM. Kill them by SIGKILL for ergonomics
-    kill9_child_by_sigint(debuggee_pid)?;
-    Ok(debuggee_pid)
+/// Debugger which launches lldb-dap (formerly lldb-vscode) as a DAP server, and lets VSCode connect to it
+pub struct LldbDapDebugger {
+    dap_command: String,
+    listen_address: Option<ListenAddress>,
+    debuggee_pid: Option<Pid>,
+    debuggee_path: Option<String>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
 }
 
-fn set_to_exec_dgeee(debuggee: &str, start_cmd: Vec<&str>) -> Result<()> {
-    // Build the `$ dbgee run` command to launch the debugger from the clap's get_matches()
-    let clap_matches = Opts::clap().get_matches();
-    let run_command = build_run_command(&clap_matches)?;
-    wrap_debuggee_binary(debuggee, &run_command)?;
-
-    if start_cmd.is_empty() {
-        return Ok(());
+impl LldbDapDebugger {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<LldbDapDebugger> {
+        // The absolute path is resolved, so that `set` can pin it for the wrapper launched with another PATH
+        let dap_command = match &options.debugger_path {
+            Some(debugger_path) => debugger_path.clone(),
+            None => ["lldb-dap", "lldb-vscode"]
+                .iter()
+                .find_map(|command| find_command_path(command))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Neither 'lldb-dap' nor 'lldb-vscode' is in PATH. Did you install lldb?"
+                    )
+                })?,
+        };
+        Ok(LldbDapDebugger {
+            dap_command,
+            listen_address: None,
+            debuggee_pid: None,
+            debuggee_path: None,
+            options: options.clone(),
+            launcher,
+        })
     }
 
-    let mut child = Command::new(start_cmd[0]).args(&start_cmd[1..]).spawn()?;
-    let _ = child.wait()?;
-
-    unwrap_debuggee_binary(debuggee)
-}
-
-fn unset_from_exec_dbgee(debuggee: &str) -> Result<()> {
-    unwrap_debuggee_binary(debuggee)
-}
-
-fn get_path_of_unset_debuggee(debuggee: &str) -> Result<String> {
-    let abspath = get_abspath(debuggee, "debuggee")?;
-    Ok(match check_if_wrapped(&debuggee) {
-        true => get_debuggee_backup_name(&abspath),
-        false => abspath,
-    })
-}
+    /// Builds the command line to launch the DAP server which listens on `listen_address`
+    fn build_server_commandline(dap_command: &str, listen_address: &ListenAddress) -> Vec<String> {
+        let mut commandline = vec![dap_command.to_owned()];
+        let command_name = Path::new(dap_command)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(dap_command);
+        if command_name.starts_with("lldb-vscode") {
+            // lldb-vscode, the older name of lldb-dap, can listen only on a port of localhost
+            commandline.extend(vec!["--port".to_owned(), listen_address.port.to_string()]);
+        } else {
+            commandline.extend(vec![
+                "--connection".to_owned(),
+                format!("listen://{}", listen_address),
+            ]);
+        }
+        commandline
+    }
 
-fn wrap_debuggee_binary(debuggee: &str, run_command: &str) -> Result<()> {
-    if check_if_wrapped(debuggee) {
-        bail!(
-            "{} is already wrapped by dbgee. Did you set it already?",
-            debuggee
+    fn launch_server_and_open_terminal(
+        &mut self,
+        pid: Pid,
+        debuggee: &str,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        let listen_address = ListenAddress::resolve(self.options.listen_port)?;
+        let commandline = self.options.wrap_commandline(
+            Self::build_server_commandline(&self.dap_command, &listen_address)
+                .into_iter()
+                .chain(self.options.debugger_args.iter().cloned())
+                .collect(),
         );
+        let listen_port = listen_address.port;
+        self.listen_address = Some(listen_address);
+        self.debuggee_pid = Some(pid);
+        self.debuggee_path = Some(debuggee.to_owned());
+        let commandline: Vec<&str> = commandline.iter().map(String::as_str).collect();
+        self.launcher
+            .launch_server(&commandline, listen_port, &self.options)?;
+        self.options.run_pre_attach_hook(pid)?;
+        terminal.open(self)
     }
-
-    let debuggee_path = get_valid_executable_path(Path::new(debuggee), "the debuggee")?;
-
-    let wrapper_sh_template_bytes = include_bytes!("../resources/wrapper.sh");
-    let wrapper_sh_template = str::from_utf8(wrapper_sh_template_bytes).unwrap();
-    let wrapper_sh = wrapper_sh_template
-        .replace("%run_cmd%", run_command)
-        .replace("%debuggee%", &format!("\"{}\"", &debuggee_path));
-
-    let mut debuggee_pathbuf = PathBuf::from_str(&debuggee_path)?;
-    // unwrap should be OK here because debuggee_path is a valid UTF-8 path of an executable file.
-    let debuggee_filename = debuggee_pathbuf
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_owned();
-    debuggee_pathbuf.pop();
-    debuggee_pathbuf.push(get_debuggee_backup_name(&debuggee_filename));
-    let debuggee_backup = debuggee_pathbuf.to_str().unwrap();
-
-    let debuggee_perm = fs::metadata(&debuggee_path)?.permissions();
-    fs::rename(&debuggee_path, &debuggee_backup)?;
-    fs::write(&debuggee_path, wrapper_sh)?;
-    fs::set_permissions(&debuggee_path, debuggee_perm)?;
-
-    Ok(())
 }
 
-fn unwrap_debuggee_binary(debuggee: &str) -> Result<()> {
-    let wrapper_path = get_valid_executable_path(Path::new(debuggee), "the debuggee")?;
+impl Debugger for LldbDapDebugger {
+    fn name(&self) -> &str {
+        "lldb-dap"
+    }
 
-    if !check_if_wrapped(debuggee) {
-        bail!(
-            "{} is not wrapped by dbgee. Did you unset it already?",
-            debuggee
-        );
+    fn run(
+        &mut self,
+        debuggee: &str,
+        args: Vec<&str>,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<Pid> {
+        let debuggee_abspath = get_path_of_unset_debuggee(debuggee)?;
+        let debuggee_cmd: Vec<&str> = std::iter::once(debuggee_abspath.as_str())
+            .chain(args)
+            .collect();
+        let debuggee_pid =
+            self.launcher
+                .run_and_stop(&debuggee_cmd, StopMethod::Sigstop, &self.options)?;
+        self.launch_server_and_open_terminal(debuggee_pid, &debuggee_abspath, terminal)?;
+        Ok(debuggee_pid)
     }
 
-    let mut wrapper_pathbuf = PathBuf::from_str(&wrapper_path)?;
-    // unwrap should be OK here because wrapper_path is a valid UTF-8 path of an executable file.
-    let debuggee_filename = wrapper_pathbuf
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_owned();
-    wrapper_pathbuf.pop();
-    wrapper_pathbuf.push(get_debuggee_backup_name(&debuggee_filename));
-    let debuggee_path = wrapper_pathbuf.to_str().unwrap();
+    fn attach(
+        &mut self,
+        pid: Pid,
+        debuggee: &str,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        self.launch_server_and_open_terminal(pid, debuggee, terminal)
+    }
+
+    fn set(
+        &mut self,
+        debuggee: &str,
+        start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
+        // terminal is not used since `set_to_exec_dbgee` build the command for `dbgee run` by clap's utility,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        // `--debugger-path` given by the user is passed to `dbgee run` as it is
+        let resolved_run_args = match self.options.debugger_path {
+            Some(_) => vec![],
+            None => vec![("debugger-path", self.dap_command.clone())],
+        };
+        set_to_exec_dgeee(debuggee, start_cmd, wrapper_options, &resolved_run_args)
+    }
+
+    fn unset(&mut self, debuggee: &str) -> Result<()> {
+        unset_from_exec_dbgee(debuggee)
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        bail!("lldb-dap has no command line client. Map it to vscode by `--terminal-map lldb-dap=vscode`");
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        let uninitialized = || anyhow!("[BUG] uninitialized LldbDapDebugger");
+        let listen_address = self.listen_address.as_ref().ok_or_else(uninitialized)?;
+        let mut info = HashMap::new();
+        info.insert(
+            AttachInformationKey::DebuggerTypeHint,
+            "lldb-dap".to_owned(),
+        );
+        info.insert(
+            AttachInformationKey::Pid,
+            self.debuggee_pid.ok_or_else(uninitialized)?.to_string(),
+        );
+        let debuggee_path = self.debuggee_path.clone().ok_or_else(uninitialized)?;
+        self.options.insert_debuggee_name(&mut info, &debuggee_path);
+        info.insert(AttachInformationKey::ProgramName, debuggee_path);
+        info.insert(
+            AttachInformationKey::DebuggerPort,
+            listen_address.port.to_string(),
+        );
+        info.insert(
+            AttachInformationKey::DebuggerHost,
+            listen_address.host.clone(),
+        );
+        self.options.insert_source_information(&mut info);
+        self.options.insert_process_context(&mut info);
+        Ok(info)
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        let file_output = get_cached_file_output(debuggee)?;
+        if file_output.contains("ELF") || file_output.contains("Mach-O") {
+            return Ok(true);
+        }
+        if file_output.contains("shell") && check_if_wrapped(debuggee) {
+            return self.is_debuggee_surely_supported(&get_backup_path_of_wrapper(debuggee));
+        }
+        Ok(false)
+    }
+}
+
+pub struct JavaDebugger {
+    listen_address: Option<ListenAddress>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
+}
+
+impl JavaDebugger {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<JavaDebugger> {
+        if !command_exists("java") {
+            bail!("'java' is not in PATH. Did you install JDK?")
+        }
+        Ok(JavaDebugger {
+            listen_address: None,
+            options: options.clone(),
+            launcher,
+        })
+    }
+
+    /// Builds the command line to launch the JVM with the JDWP agent listening on `listen_address`.
+    ///
+    /// `debuggee` is either the `java` command itself, a `.jar` file, or a `.class` file.
+    fn build_java_commandline(
+        debuggee: &str,
+        args: &[&str],
+        listen_address: &ListenAddress,
+    ) -> Result<Vec<String>> {
+        let agent = format!(
+            "-agentlib:jdwp=transport=dt_socket,server=y,suspend=y,address={}",
+            listen_address
+        );
+        let debuggee_path = Path::new(debuggee);
+        let mut commandline = match debuggee_path.extension().and_then(|ext| ext.to_str()) {
+            _ if is_java_command(debuggee) => vec![debuggee.to_owned(), agent],
+            Some("jar") => vec![
+                "java".to_owned(),
+                agent,
+                "-jar".to_owned(),
+                debuggee.to_owned(),
+            ],
+            Some("class") => {
+                let class_path = match debuggee_path.parent() {
+                    Some(dir) if dir != Path::new("") => dir.to_string_lossy().into_owned(),
+                    _ => ".".to_owned(),
+                };
+                let class_name = debuggee_path
+                    .file_stem()
+                    .ok_or_else(|| anyhow!("invalid class file: {}", debuggee))?
+                    .to_string_lossy()
+                    .into_owned();
+                vec![
+                    "java".to_owned(),
+                    agent,
+                    "-cp".to_owned(),
+                    class_path,
+                    class_name,
+                ]
+            }
+            _ => bail!(
+                "'{}' is not a Java debuggee. Give `java`, a .jar file or a .class file.",
+                debuggee
+            ),
+        };
+        commandline.extend(args.iter().map(|arg| (*arg).to_owned()));
+        Ok(commandline)
+    }
+}
+
+/// Returns true if `debuggee` is what `JavaDebugger` launches, that is, the `java` command, a `.jar` file or
+/// a `.class` file. They are not necessarily executable files.
+pub fn is_java_debuggee(debuggee: &str) -> bool {
+    is_java_command(debuggee)
+        || matches!(
+            Path::new(debuggee).extension().and_then(|ext| ext.to_str()),
+            Some("jar") | Some("class")
+        )
+}
+
+fn is_java_command(debuggee: &str) -> bool {
+    Path::new(debuggee).file_name() == Some("java".as_ref())
+}
+
+impl Debugger for JavaDebugger {
+    fn name(&self) -> &str {
+        "jdb"
+    }
+
+    fn run(
+        &mut self,
+        debuggee: &str,
+        args: Vec<&str>,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<Pid> {
+        let listen_address = ListenAddress::resolve(self.options.listen_port)?;
+        let commandline = Self::build_java_commandline(debuggee, &args, &listen_address)?;
+        let commandline: Vec<&str> = commandline.iter().map(String::as_str).collect();
+        let jvm_pid = self
+            .launcher
+            .launch_server(&commandline, listen_address.port, &self.options)
+            .with_context(|| format!("the JVM didn't listen on {}", listen_address))?;
+        self.listen_address = Some(listen_address);
+        self.options
+            .run_pre_attach_hook(jvm_pid)
+            .map_err(|e| kill_launched_process(jvm_pid, e))?;
+        terminal.open(self)?;
+
+        Ok(jvm_pid)
+    }
+
+    fn set(
+        &mut self,
+        _debuggee: &str,
+        _start_cmd: Vec<&str>,
+        _wrapper_options: &WrapperOptions,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!("set is not implemented yet for Java");
+    }
+
+    fn unset(&mut self, _debuggee: &str) -> Result<()> {
+        bail!("unset is not implemented yet for Java");
+    }
+
+    fn attach(
+        &mut self,
+        _pid: Pid,
+        _debuggee: &str,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!("jdb cannot attach to a JVM which is not launched with the JDWP agent. Use `dbgee run` instead.");
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        let listen_address = self
+            .listen_address
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] uninitialized JavaDebugger"))?;
+        let mut commandline = vec![
+            "jdb".to_owned(),
+            "-attach".to_owned(),
+            listen_address.to_string(),
+        ];
+        commandline.extend(self.options.debugger_args.iter().cloned());
+        Ok(self.options.wrap_commandline(commandline))
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        let listen_address = self
+            .listen_address
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] uninitialized JavaDebugger"))?;
+        let mut info = HashMap::new();
+        info.insert(AttachInformationKey::DebuggerTypeHint, "java".to_owned());
+        info.insert(
+            AttachInformationKey::DebuggerPort,
+            listen_address.port.to_string(),
+        );
+        info.insert(
+            AttachInformationKey::DebuggerHost,
+            listen_address.host.clone(),
+        );
+        Ok(info)
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        Ok(is_java_debuggee(debuggee))
+    }
+}
+
+/// Debugger server which runs in the process of the debuggee, such as the inspector of Node.js and rdbg.
+/// The debuggee is launched with the server, which stops it before its first line and waits for the client.
+struct InProcessServer {
+    listen_address: Option<ListenAddress>,
+    options: DebuggerOptions,
+    launcher: Box<dyn ProcessLauncher>,
+}
+
+impl InProcessServer {
+    fn new(options: &DebuggerOptions, launcher: Box<dyn ProcessLauncher>) -> InProcessServer {
+        InProcessServer {
+            listen_address: None,
+            options: options.clone(),
+            launcher,
+        }
+    }
+
+    /// Launches the debuggee by the command line which `build_commandline` builds for the address to listen on,
+    /// and runs the pre-attach hook once the server gets ready. Returns the pid of the debuggee.
+    fn launch(
+        &mut self,
+        build_commandline: impl FnOnce(&ListenAddress) -> Vec<String>,
+    ) -> Result<Pid> {
+        let listen_address = ListenAddress::resolve_with_free_port(self.options.listen_port)?;
+        let commandline = build_commandline(&listen_address);
+        let commandline: Vec<&str> = commandline.iter().map(String::as_str).collect();
+        let pid = self
+            .launcher
+            .launch_server(&commandline, listen_address.port, &self.options)?;
+        self.listen_address = Some(listen_address);
+        self.options
+            .run_pre_attach_hook(pid)
+            .map_err(|e| kill_launched_process(pid, e))?;
+        Ok(pid)
+    }
+
+    fn listen_address(&self) -> Result<&ListenAddress> {
+        self.listen_address
+            .as_ref()
+            .ok_or_else(|| anyhow!("[BUG] the in-process server is not launched"))
+    }
+
+    fn build_attach_information(
+        &self,
+        type_hint: &str,
+    ) -> Result<HashMap<AttachInformationKey, String>> {
+        let listen_address = self.listen_address()?;
+        let mut info = HashMap::new();
+        info.insert(AttachInformationKey::DebuggerTypeHint, type_hint.to_owned());
+        info.insert(
+            AttachInformationKey::DebuggerPort,
+            listen_address.port.to_string(),
+        );
+        info.insert(
+            AttachInformationKey::DebuggerHost,
+            listen_address.host.clone(),
+        );
+        Ok(info)
+    }
+}
+
+/// Debugger which runs Node.js with the inspector, which stops the script before its first line
+/// and lets `node inspect` or VSCode connect to it
+pub struct NodeDebugger {
+    server: InProcessServer,
+}
+
+impl NodeDebugger {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<NodeDebugger> {
+        if !command_exists("node") {
+            bail!("'node' is not in PATH. Did you install Node.js?")
+        }
+        Ok(NodeDebugger {
+            server: InProcessServer::new(options, launcher),
+        })
+    }
+
+    /// Builds the command line to launch Node.js with the inspector listening on `listen_address`,
+    /// which stops the debuggee before its first line.
+    ///
+    /// `debuggee` is either the `node` command itself or a script such as a `.js` or `.mjs` file.
+    fn build_node_commandline(
+        debuggee: &str,
+        args: &[&str],
+        listen_address: &ListenAddress,
+    ) -> Vec<String> {
+        let inspect = format!("--inspect-brk={}", listen_address);
+        let mut commandline = if is_node_command(debuggee) {
+            vec![debuggee.to_owned(), inspect]
+        } else {
+            vec!["node".to_owned(), inspect, debuggee.to_owned()]
+        };
+        commandline.extend(args.iter().map(|arg| (*arg).to_owned()));
+        commandline
+    }
+}
+
+/// Returns true if `debuggee` is what `NodeDebugger` launches without checking the contents, that is,
+/// the `node` command or a `.js`, `.mjs` or `.cjs` file. They are not necessarily executable files.
+pub fn is_node_debuggee(debuggee: &str) -> bool {
+    is_node_command(debuggee)
+        || matches!(
+            Path::new(debuggee).extension().and_then(|ext| ext.to_str()),
+            Some("js") | Some("mjs") | Some("cjs")
+        )
+}
+
+fn is_node_command(debuggee: &str) -> bool {
+    Path::new(debuggee).file_name() == Some("node".as_ref())
+}
+
+impl Debugger for NodeDebugger {
+    fn name(&self) -> &str {
+        "node-inspect"
+    }
+
+    fn run(
+        &mut self,
+        debuggee: &str,
+        args: Vec<&str>,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<Pid> {
+        let node_pid = self
+            .server
+            .launch(|listen_address| Self::build_node_commandline(debuggee, &args, listen_address))
+            .context("the inspector of node didn't get ready")?;
+        terminal.open(self)?;
+
+        Ok(node_pid)
+    }
+
+    fn set(
+        &mut self,
+        debuggee: &str,
+        start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
+        // terminal is not used since `set_to_exec_dbgee` build the command for `dbgee run` by clap's utility,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        // The backup of the script doesn't end with ".js", by which node-inspect would be detected
+        let resolved_run_args = [("debugger", "node-inspect".to_owned())];
+        set_to_exec_dgeee(debuggee, start_cmd, wrapper_options, &resolved_run_args)
+    }
+
+    fn unset(&mut self, debuggee: &str) -> Result<()> {
+        unset_from_exec_dbgee(debuggee)
+    }
+
+    fn attach(
+        &mut self,
+        _pid: Pid,
+        _debuggee: &str,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!("node-inspect cannot attach to a running node process. Use `dbgee run` instead.");
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        let mut commandline = vec![
+            "node".to_owned(),
+            "inspect".to_owned(),
+            self.server.listen_address()?.to_string(),
+        ];
+        commandline.extend(self.server.options.debugger_args.iter().cloned());
+        Ok(commandline)
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        self.server.build_attach_information("node")
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        if is_node_debuggee(debuggee) {
+            return Ok(true);
+        }
+        // `file` reports scripts with shebangs such as `#!/usr/bin/env node` as Node.js scripts
+        let file_output = get_cached_file_output(debuggee)?;
+        Ok(file_output.contains("Node.js script"))
+    }
+}
+
+/// Debugger which runs a Ruby script by rdbg of the debug gem, which stops it before its first line
+/// and lets `rdbg --attach` or VSCode connect to it
+pub struct RubyDebugger {
+    server: InProcessServer,
+}
+
+impl RubyDebugger {
+    pub fn new(
+        options: &DebuggerOptions,
+        launcher: Box<dyn ProcessLauncher>,
+    ) -> Result<RubyDebugger> {
+        if !command_exists("rdbg") {
+            bail!("'rdbg' is not in PATH. Did you install the debug gem by `gem install debug`?")
+        }
+        Ok(RubyDebugger {
+            server: InProcessServer::new(options, launcher),
+        })
+    }
+
+    /// Builds the command line to run the Ruby script `debuggee` by rdbg, which stops it before its first line
+    /// and waits for the client on `listen_address`.
+    fn build_rdbg_commandline(
+        debuggee: &str,
+        args: &[&str],
+        listen_address: &ListenAddress,
+    ) -> Vec<String> {
+        let mut commandline = vec![
+            "rdbg".to_owned(),
+            "--open".to_owned(),
+            "--host".to_owned(),
+            listen_address.host.clone(),
+            "--port".to_owned(),
+            listen_address.port.to_string(),
+            "--".to_owned(),
+            debuggee.to_owned(),
+        ];
+        commandline.extend(args.iter().map(|arg| (*arg).to_owned()));
+        commandline
+    }
+}
+
+/// Returns true if `debuggee` is a `.rb` file, which is not necessarily an executable file
+pub fn is_ruby_debuggee(debuggee: &str) -> bool {
+    Path::new(debuggee).extension() == Some("rb".as_ref())
+}
+
+impl Debugger for RubyDebugger {
+    fn name(&self) -> &str {
+        "rdbg"
+    }
+
+    fn run(
+        &mut self,
+        debuggee: &str,
+        args: Vec<&str>,
+        terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<Pid> {
+        let pid = self.server.launch(|listen_address| {
+            Self::build_rdbg_commandline(debuggee, &args, listen_address)
+        })?;
+        terminal.open(self)?;
+
+        Ok(pid)
+    }
+
+    fn set(
+        &mut self,
+        debuggee: &str,
+        start_cmd: Vec<&str>,
+        wrapper_options: &WrapperOptions,
+        // terminal is not used since `set_to_exec_dbgee` build the command for `dbgee run` by clap's utility,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        // The backup of the script doesn't end with ".rb", by which rdbg would be detected
+        let resolved_run_args = [("debugger", "rdbg".to_owned())];
+        set_to_exec_dgeee(debuggee, start_cmd, wrapper_options, &resolved_run_args)
+    }
+
+    fn unset(&mut self, debuggee: &str) -> Result<()> {
+        unset_from_exec_dbgee(debuggee)
+    }
+
+    fn attach(
+        &mut self,
+        _pid: Pid,
+        _debuggee: &str,
+        _terminal: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!("rdbg cannot attach to a Ruby process which is not launched by rdbg. Use `dbgee run` instead.");
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        let listen_address = self.server.listen_address()?;
+        let mut commandline = vec![
+            "rdbg".to_owned(),
+            "--attach".to_owned(),
+            listen_address.host.clone(),
+            listen_address.port.to_string(),
+        ];
+        commandline.extend(self.server.options.debugger_args.iter().cloned());
+        Ok(commandline)
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        self.server.build_attach_information("rdbg")
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        if is_ruby_debuggee(debuggee) {
+            return Ok(true);
+        }
+        let file_output = get_cached_file_output(debuggee)?;
+        Ok(file_output.contains("Ruby script"))
+    }
+}
+
+/// How long to wait for a debugger server, such as dlv, debugpy or the JVM with JDWP, to start listening,
+/// unless `--server-timeout` is given
+const SERVER_LISTEN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to check if a debugger server got ready
+const SERVER_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many last lines of the stderr of a server are shown when it doesn't get ready
+const SERVER_OUTPUT_TAIL_LINES: usize = 10;
+
+/// Relays the stdout or stderr of a server through a pipe to the one of dbgee while dbgee waits for the server
+/// to get ready, keeping the last lines of it for the error message.
+/// The pipe is handed over to `cat` after that, which keeps relaying the output even after dbgee exits.
+struct ServerOutputRelay {
+    /// The non-blocking read end of the pipe
+    pipe: File,
+    sink: File,
+    /// The last line which doesn't end with a newline yet
+    partial_line: Vec<u8>,
+    last_lines: VecDeque<String>,
+}
+
+impl ServerOutputRelay {
+    /// Creates the relay to the file descriptor `sink_fd` of dbgee, and the write end of its pipe for the server
+    fn new(sink_fd: RawFd) -> Result<(ServerOutputRelay, File)> {
+        let sink = unistd::dup(sink_fd).context("Failed to duplicate the output of dbgee")?;
+        Self::with_sink(unsafe { File::from_raw_fd(sink) })
+    }
+
+    /// Creates the relay to the terminal `sink_fd` of dbgee through a pseudo terminal instead of a pipe,
+    /// so that the server and its debuggee sharing it still write to a terminal, with the same buffering
+    fn new_pty(sink_fd: RawFd) -> Result<(ServerOutputRelay, File)> {
+        let sink = unistd::dup(sink_fd).context("Failed to duplicate the output of dbgee")?;
+        let sink = unsafe { File::from_raw_fd(sink) };
+        let mut winsize: nix::pty::Winsize = unsafe { std::mem::zeroed() };
+        // Safety: TIOCGWINSZ only writes to winsize. The size stays zero if the sink is not a terminal
+        unsafe { nix::libc::ioctl(sink_fd, nix::libc::TIOCGWINSZ, &mut winsize) };
+        let pty = nix::pty::openpty(&winsize, None)
+            .context("Failed to open a pseudo terminal for the output of the server")?;
+        let (master, slave) =
+            unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        for fd in &[master.as_raw_fd(), slave.as_raw_fd()] {
+            nix::fcntl::fcntl(
+                *fd,
+                nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+            )
+            .context("Failed to set close-on-exec to the pseudo terminal")?;
+        }
+        // The terminal of dbgee translates the newlines, so the pseudo terminal passes the output as it is
+        use nix::sys::termios;
+        let mut attrs = termios::tcgetattr(slave.as_raw_fd())
+            .context("Failed to get the attributes of the pseudo terminal")?;
+        attrs.output_flags.remove(termios::OutputFlags::OPOST);
+        termios::tcsetattr(slave.as_raw_fd(), termios::SetArg::TCSANOW, &attrs)
+            .context("Failed to set the attributes of the pseudo terminal")?;
+        Ok((Self::relaying(master, sink)?, slave))
+    }
+
+    fn with_sink(sink: File) -> Result<(ServerOutputRelay, File)> {
+        let (reader, writer) = unistd::pipe2(nix::fcntl::OFlag::O_CLOEXEC)
+            .context("Failed to create a pipe for the output of the server")?;
+        let (pipe, writer) = unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) };
+        Ok((Self::relaying(pipe, sink)?, writer))
+    }
+
+    /// Creates the relay from `pipe`, the read end of a pipe or the master of a pseudo terminal, to `sink`
+    fn relaying(pipe: File, sink: File) -> Result<ServerOutputRelay> {
+        nix::fcntl::fcntl(
+            pipe.as_raw_fd(),
+            nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::O_NONBLOCK),
+        )
+        .context("Failed to make the pipe for the output of the server non-blocking")?;
+        Ok(ServerOutputRelay {
+            pipe,
+            sink,
+            partial_line: vec![],
+            last_lines: VecDeque::new(),
+        })
+    }
+
+    /// Relays the output written so far, and returns the lines completed since the last call
+    fn read_new_lines(&mut self) -> Vec<String> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => {
+                    // dbgee's own output may be closed, which shouldn't stop the server
+                    let _ = self.sink.write_all(&buf[..len]);
+                    self.partial_line.extend_from_slice(&buf[..len]);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                // Nothing more to read for now, or EIO of a pseudo terminal which the server closed
+                Err(_) => break,
+            }
+        }
+        let mut lines: Vec<String> = self
+            .partial_line
+            .split(|&b| b == b'\n')
+            .map(|line| String::from_utf8_lossy(line).into_owned())
+            .collect();
+        let partial_line = lines.pop().unwrap_or_default();
+        self.partial_line = partial_line.into_bytes();
+        for line in &lines {
+            if self.last_lines.len() == SERVER_OUTPUT_TAIL_LINES {
+                self.last_lines.pop_front();
+            }
+            self.last_lines.push_back(line.clone());
+        }
+        lines
+    }
+
+    /// Returns the last lines which the server wrote
+    fn last_lines(&self) -> String {
+        Vec::from(self.last_lines.clone()).join("\n")
+    }
+
+    /// Hands the pipe over to `cat`, which keeps relaying the output until the server closes it
+    fn hand_over(mut self) -> Result<()> {
+        self.read_new_lines();
+        nix::fcntl::fcntl(
+            self.pipe.as_raw_fd(),
+            nix::fcntl::FcntlArg::F_SETFL(nix::fcntl::OFlag::empty()),
+        )
+        .context("Failed to make the pipe for the output of the server blocking")?;
+        Command::new("cat")
+            .stdin(self.pipe)
+            .stdout(self.sink)
+            // A pseudo terminal ends by EIO instead of EOF, which cat reports
+            .stderr(std::process::Stdio::null())
+            .logged()
+            .spawn()
+            .context("Failed to launch cat to relay the output of the server")?;
+        Ok(())
+    }
+}
+
+/// Tells that `port` is in use, and suggests the next port
+fn build_port_in_use_message(port: u16) -> String {
+    format!(
+        "port {} is in use by another process. Stop it, or give another port by --port {}",
+        port,
+        port.checked_add(1).unwrap_or(1024)
+    )
+}
+
+/// Builds the explanation of why the server which should listen on `port` didn't get ready, with a suggested fix.
+/// The port is not in use by another process, since `launch_server` checks it before launching the server.
+fn build_server_not_ready_hint(
+    port: u16,
+    ready_regex: Option<&Regex>,
+    stderr_tail: &str,
+) -> String {
+    let mut hint = if let Some(ready_regex) = ready_regex {
+        format!(
+            "no line of the output of the server matched --ready-regex '{}'. \
+            Check the output of your version of the server",
+            ready_regex
+        )
+    } else {
+        format!(
+            "port {} is not in use. If the server failed to listen on it, give another port by --port {}",
+            port,
+            port.checked_add(1).unwrap_or(1024)
+        )
+    };
+    if stderr_tail.trim().is_empty() {
+        hint.push_str(". The server wrote nothing to stderr");
+    } else {
+        hint.push_str(&format!(
+            ". The last lines of the stderr of the server:\n{}",
+            stderr_tail
+        ));
+    }
+    hint
+}
+
+/// Waits until `server` listens on `port`.
+/// It doesn't try to connect to the port, because servers such as dlv and debugpy take the first connection
+/// as their client.
+/// `outputs` are relayed meanwhile.
+fn wait_until_listening(
+    server: &mut Child,
+    port: u16,
+    outputs: &mut [ServerOutputRelay],
+    timeout: Duration,
+) -> Result<()> {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        outputs.iter_mut().for_each(|output| {
+            output.read_new_lines();
+        });
+        if is_port_listening(port)? {
+            return Ok(());
+        }
+        if let Some(exit_status) = server.try_wait()? {
+            bail!("the server exited before listening. {}", exit_status);
+        }
+        std::thread::sleep(SERVER_POLL_INTERVAL);
+    }
+    bail!(
+        "the server didn't listen on port {} in {:?}. Give a longer --server-timeout if it's slow to start",
+        port,
+        timeout
+    )
+}
+
+/// Waits until `server` writes a line which matches `ready_regex` to any of `outputs`
+fn wait_until_output_matches(
+    server: &mut Child,
+    ready_regex: &Regex,
+    outputs: &mut [ServerOutputRelay],
+    timeout: Duration,
+) -> Result<()> {
+    let started_at = Instant::now();
+    while started_at.elapsed() < timeout {
+        // Check the exit before reading, not to miss the last output
+        let exit_status = server.try_wait()?;
+        if outputs
+            .iter_mut()
+            .flat_map(ServerOutputRelay::read_new_lines)
+            .any(|line| ready_regex.is_match(&line))
+        {
+            return Ok(());
+        }
+        if let Some(exit_status) = exit_status {
+            bail!("the server exited before getting ready. {}", exit_status);
+        }
+        std::thread::sleep(SERVER_POLL_INTERVAL);
+    }
+    bail!(
+        "the server wrote no line which matches '{}' in {:?}. Give a longer --server-timeout if it's slow to start",
+        ready_regex,
+        timeout
+    )
+}
+
+/// Wraps the debuggee by the script which launches `$ dbgee run`.
+/// `resolved_run_args` are the options of `$ dbgee run` which the debugger resolved, such as the absolute path
+/// to the debugger. They are added unless the user gives them.
+fn set_to_exec_dgeee(
+    debuggee: &str,
+    start_cmd: Vec<&str>,
+    wrapper_options: &WrapperOptions,
+    resolved_run_args: &[(&str, String)],
+) -> Result<()> {
+    // Build the `$ dbgee run` command to launch the debugger from the clap's get_matches()
+    let clap_matches = Opts::clap().get_matches();
+    let run_command =
+        build_run_command(&clap_matches, debuggee, wrapper_options, resolved_run_args)?;
+    wrap_debuggee_binary(debuggee, &run_command, wrapper_options)?;
+
+    if start_cmd.is_empty() {
+        return Ok(());
+    }
+
+    let mut child = Command::new(start_cmd[0])
+        .args(&start_cmd[1..])
+        .logged()
+        .spawn()?;
+    let _ = child.wait()?;
+
+    if wrapper_options.once && !check_if_wrapped(debuggee) {
+        // The wrapper has already restored the debuggee
+        return Ok(());
+    }
+    unwrap_debuggee_binary(debuggee)
+}
+
+fn unset_from_exec_dbgee(debuggee: &str) -> Result<()> {
+    unwrap_debuggee_binary(debuggee)
+}
+
+fn get_path_of_unset_debuggee(debuggee: &str) -> Result<String> {
+    let abspath = get_abspath(debuggee, "debuggee")?;
+    Ok(match check_if_wrapped(debuggee) {
+        true => get_backup_path_of_wrapper(&abspath),
+        false => abspath,
+    })
+}
+
+fn wrap_debuggee_binary(
+    debuggee: &str,
+    run_command: &str,
+    wrapper_options: &WrapperOptions,
+) -> Result<()> {
+    validate_backup_suffix(&wrapper_options.backup_suffix)?;
+    let debuggee_path = get_valid_executable_path(Path::new(debuggee), "the debuggee")?;
+    let rewrapping = check_if_wrapped(&debuggee_path);
+    if rewrapping && !wrapper_options.force {
+        bail!(
+            "{} is already wrapped by dbgee. Did you set it already? Use --force to set it again.",
+            debuggee
+        );
+    }
+
+    // Re-wrapping only replaces the wrapper, keeping the existing backup of the true original
+    let backup_suffix = if rewrapping {
+        let recorded_suffix = read_backup_suffix(&debuggee_path);
+        if recorded_suffix != wrapper_options.backup_suffix {
+            log::warn!(
+                "{} is already wrapped with the backup suffix '{}', which is kept.",
+                debuggee,
+                recorded_suffix
+            );
+        }
+        recorded_suffix
+    } else {
+        wrapper_options.backup_suffix.clone()
+    };
+    let backup_suffix = backup_suffix.as_str();
+    let debuggee_backup = get_debuggee_backup_name(&debuggee_path, backup_suffix);
+    if rewrapping && !Path::new(&debuggee_backup).exists() {
+        bail!(
+            "the backup of the wrapped debuggee ({}) is missing. Restore it before setting it again.",
+            debuggee_backup
+        );
+    }
+    if !rewrapping && Path::new(&debuggee_backup).exists() {
+        bail!(
+            "the backup path of the debuggee ({}) already exists. Please choose another suffix.",
+            debuggee_backup
+        );
+    }
+    // The original debuggee, which is the backup if it's already wrapped
+    let original_path = if rewrapping {
+        &debuggee_backup
+    } else {
+        &debuggee_path
+    };
+
+    let running_pids = find_running_instances(Path::new(original_path));
+    if !running_pids.is_empty() {
+        let pids: Vec<String> = running_pids.iter().map(Pid::to_string).collect();
+        if !wrapper_options.allow_running {
+            bail!(
+                "{} is running as pid {}. The running instances keep running the original debuggee \
+                while new launches run the wrapper, which is confusing. Use --allow-running to set it anyway.",
+                original_path,
+                pids.join(", ")
+            );
+        }
+        log::warn!(
+            "{} is running as pid {}. They keep running the original debuggee.",
+            original_path,
+            pids.join(", ")
+        );
+    }
+
+    let wrapper_sh_template_bytes = include_bytes!("../resources/wrapper.sh");
+    let wrapper_sh_template = str::from_utf8(wrapper_sh_template_bytes).unwrap();
+    let wrapper_sh = wrapper_sh_template
+        .replace("%run_cmd%", run_command)
+        .replace("%backup_suffix%", backup_suffix)
+        .replace(
+            "%bypass_conditions%",
+            &build_bypass_conditions(wrapper_options, &debuggee_backup),
+        )
+        .replace(
+            "%unset_once%",
+            &build_unset_once(wrapper_options, &debuggee_backup, &debuggee_path),
+        )
+        .replace("%debuggee%", &format!("\"{}\"", &debuggee_path));
+
+    let debuggee_metadata = fs::metadata(original_path)?;
+    if debuggee_metadata.nlink() > 1 {
+        if !wrapper_options.allow_hardlinked {
+            bail!(
+                "{} has {} hardlinks. The other links would keep pointing to the original debuggee, \
+                and launching it through them would not attach the debugger. \
+                Use --allow-hardlinked to set it anyway.",
+                original_path,
+                debuggee_metadata.nlink()
+            );
+        }
+        log::warn!(
+            "{} has {} hardlinks. The other links keep pointing to the original debuggee.",
+            original_path,
+            debuggee_metadata.nlink()
+        );
+    }
+
+    // Prepare the complete wrapper beside the debuggee first, so that the debuggee path is missing
+    // only between the two renames below and never refers to a partially written wrapper
+    let wrapper = write_wrapper_beside(&debuggee_path, &wrapper_sh, &debuggee_metadata)?;
+    if rewrapping {
+        // rename replaces the old wrapper atomically, so the debuggee is never left unwrapped
+        log::info!("{} is already wrapped. Re-wrapping it.", debuggee);
+        wrapper
+            .persist(&debuggee_path)
+            .map_err(|e| e.error)
+            .context("Failed to replace the wrapper script")?;
+        return Ok(());
+    }
+    fs::rename(&debuggee_path, &debuggee_backup)?;
+    if let Err(e) = wrapper.persist(&debuggee_path) {
+        fs::rename(&debuggee_backup, &debuggee_path)
+            .context("Failed to restore the debuggee from its backup")?;
+        return Err(e.error).context("Failed to put the wrapper script in place of the debuggee");
+    }
+
+    Ok(())
+}
+
+/// Returns the pids of the processes running the executable at `exe_path`.
+/// The processes of other users are found only by root, because their /proc/<pid>/exe are unreadable.
+fn find_running_instances(exe_path: &Path) -> Vec<Pid> {
+    let exe_path = match fs::canonicalize(exe_path) {
+        Ok(exe_path) => exe_path,
+        Err(_) => return vec![],
+    };
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+    sysinfo_system.refresh_processes();
+    let mut pids: Vec<Pid> = sysinfo_system
+        .get_processes()
+        .iter()
+        .filter(|(_, process)| process.exe() == exe_path)
+        .map(|(&pid, _)| Pid::from_raw(pid))
+        .collect();
+    pids.sort();
+    pids
+}
+
+/// Returns the process at the end of the chain of the first children of `pid`, such as the debuggee which
+/// dlv launched under sudo. Returns None if `pid` has no child processes.
+fn find_leaf_descendant(pid: Pid) -> Option<Pid> {
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+    sysinfo_system.refresh_processes();
+    let processes = sysinfo_system.get_processes();
+    let first_child = |parent: Pid| {
+        processes
+            .iter()
+            .filter(|(_, process)| process.parent() == Some(parent.as_raw()))
+            .map(|(&pid, _)| Pid::from_raw(pid))
+            .min()
+    };
+    let mut leaf = first_child(pid)?;
+    while let Some(child) = first_child(leaf) {
+        leaf = child;
+    }
+    Some(leaf)
+}
+
+/// Writes the wrapper script into a temporary file in the same directory as the debuggee, with
+/// the debuggee's permissions and ownership.
+fn write_wrapper_beside(
+    debuggee_path: &str,
+    wrapper_sh: &str,
+    debuggee_metadata: &fs::Metadata,
+) -> Result<tempfile::NamedTempFile> {
+    let dir = Path::new(debuggee_path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut wrapper = tempfile::Builder::new()
+        .prefix(".dbgee-wrapper")
+        .tempfile_in(dir)
+        .with_context(|| format!("Failed to create a temporary file in {}", dir.display()))?;
+    wrapper.write_all(wrapper_sh.as_bytes())?;
+    wrapper.as_file().sync_all()?;
+    fs::set_permissions(wrapper.path(), debuggee_metadata.permissions())?;
+    if let Err(e) = unistd::chown(
+        wrapper.path(),
+        Some(unistd::Uid::from_raw(debuggee_metadata.uid())),
+        Some(unistd::Gid::from_raw(debuggee_metadata.gid())),
+    ) {
+        // Only root can give files to other users. The wrapper still works with the current owner.
+        log::debug!(
+            "Failed to copy the ownership of the debuggee to the wrapper: {}",
+            e
+        );
+    }
+    Ok(wrapper)
+}
+
+/// Builds the shell script lines which execute the original debuggee directly, without the debugger,
+/// when the wrapper script is not activated.
+fn build_bypass_conditions(wrapper_options: &WrapperOptions, debuggee_backup: &str) -> String {
+    let mut conditions = vec![];
+    if let Some(ref activate_env) = wrapper_options.activate_env {
+        conditions.push(activate_env.build_inactive_condition());
+    }
+    if wrapper_options.only_interactive {
+        conditions.push("! [ -t 0 ]".to_owned());
+    }
+    conditions
+        .iter()
+        .map(|condition| {
+            format!(
+                "if {}; then exec '{}'{} \"$@\"; fi",
+                condition,
+                escape_single_quote(debuggee_backup),
+                build_quoted_debuggee_args(wrapper_options)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Builds the command of the wrapper which restores the original debuggee like `unset` for `--once`.
+/// The running wrapper keeps reading itself after the rename, since the shell keeps it open.
+/// If another launch has restored it first, the rename fails, and this launch is debugged as well.
+fn build_unset_once(
+    wrapper_options: &WrapperOptions,
+    debuggee_backup: &str,
+    debuggee_path: &str,
+) -> String {
+    if !wrapper_options.once {
+        return String::new();
+    }
+    format!(
+        "mv -f '{}' '{}' 2>/dev/null",
+        escape_single_quote(debuggee_backup),
+        escape_single_quote(debuggee_path)
+    )
+}
+
+fn unwrap_debuggee_binary(debuggee: &str) -> Result<()> {
+    let wrapper_path = get_valid_executable_path(Path::new(debuggee), "the debuggee")?;
+
+    if !check_if_wrapped(debuggee) {
+        bail!(
+            "{} is not wrapped by dbgee. Did you unset it already?",
+            debuggee
+        );
+    }
+
+    let debuggee_path = get_backup_path_of_wrapper(&wrapper_path);
+
+    // rename replaces the wrapper atomically
+    fs::rename(&debuggee_path, &wrapper_path)?;
+
+    Ok(())
+}
+
+fn check_if_wrapped<P: AsRef<Path>>(path: P) -> bool {
+    let wrapper_sh_template_bytes = include_bytes!("../resources/wrapper.sh");
+    let wrapper_sh_template = str::from_utf8(wrapper_sh_template_bytes).unwrap();
+    let wrapper_signature: String = wrapper_sh_template.lines().take(2).collect();
+
+    let file = File::open(path);
+    if file.is_err() {
+        return false;
+    }
+    let file = file.unwrap();
+
+    let lines = BufReader::new(file).lines();
+    let signature: String = lines
+        .take(2)
+        .map(|lresult| lresult.unwrap_or_default())
+        .collect();
+
+    signature == wrapper_signature
+}
+
+/// Reads the backup suffix recorded in the wrapper script.
+/// Wrappers generated by older versions of dbgee don't record it, so the default suffix is returned for them.
+fn read_backup_suffix<P: AsRef<Path>>(wrapper: P) -> String {
+    const BACKUP_SUFFIX_HEADER: &str = "# backup-suffix: ";
+
+    let suffix = File::open(wrapper).ok().and_then(|file| {
+        BufReader::new(file)
+            .lines()
+            .take(3)
+            .filter_map(|line| line.ok())
+            .find_map(|line| line.strip_prefix(BACKUP_SUFFIX_HEADER).map(str::to_owned))
+    });
+    suffix.unwrap_or_else(|| DEFAULT_BACKUP_SUFFIX.to_owned())
+}
+
+fn validate_backup_suffix(backup_suffix: &str) -> Result<()> {
+    static BACKUP_SUFFIX_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[\w.-]+$").unwrap());
+    if !BACKUP_SUFFIX_RE.is_match(backup_suffix) {
+        bail!(
+            "invalid backup suffix '{}'. Use only alphanumerics, '.', '_' and '-'.",
+            backup_suffix
+        );
+    }
+    Ok(())
+}
+
+fn build_run_command(
+    set_opts: &ArgMatches,
+    debuggee: &str,
+    wrapper_options: &WrapperOptions,
+    resolved_run_args: &[(&str, String)],
+) -> Result<String> {
+    let debuggee_path = get_valid_executable_path(debuggee, "debuggee")?;
+    // With --once, the wrapper has restored the original debuggee to its path before running it
+    let run_path = if wrapper_options.once {
+        debuggee_path
+    } else {
+        get_debuggee_backup_name(&debuggee_path, &wrapper_options.backup_suffix)
+    };
+    let (subcommand_matches, _) = set_subcommand_matches(set_opts)?;
+    let resolved_flags: String = resolved_run_args
+        .iter()
+        .filter(|(key, _)| !subcommand_matches.is_present(key))
+        .map(|(key, value)| format!(" --{} '{}'", key, escape_single_quote(value)))
+        .collect();
+    Ok(format!(
+        "{}{} -- {}{} \"$@\"",
+        build_run_command_prefix(set_opts)?,
+        resolved_flags,
+        run_path,
+        build_quoted_debuggee_args(wrapper_options)
+    ))
+}
+
+/// Builds the arguments baked into the wrapper by `--debuggee-arg`, each of which is single-quoted with a leading space
+fn build_quoted_debuggee_args(wrapper_options: &WrapperOptions) -> String {
+    wrapper_options
+        .debuggee_args
+        .iter()
+        .map(|arg| format!(" '{}'", escape_single_quote(arg)))
+        .collect()
+}
+
+/// Builds `$ dbgee [OPTIONS] run [OPTIONS]` without the debuggee, passing through the global options
+/// and the attach options of the subcommand which sets the debuggee
+pub(crate) fn build_run_command_prefix(opts: &ArgMatches) -> Result<String> {
+    let self_pathbuf = env::current_exe()?;
+    let self_path = get_valid_executable_path(&self_pathbuf, "dbgee")?;
+    let global_opts = reconstruct_flags(opts, &[]);
+    let (subcommand_matches, subcommand_only_args) = set_subcommand_matches(opts)?;
+    let attach_opts = reconstruct_flags(subcommand_matches, &subcommand_only_args);
+    Ok(format!(
+        "{} {} run {}",
+        self_path, &global_opts, &attach_opts
+    ))
+}
+
+/// Returns the matches of the subcommand which sets the debuggee, that is, `set`, `watch` or `systemd`,
+/// and its arguments which must not be passed to `$ dbgee run`
+fn set_subcommand_matches<'a>(
+    opts: &'a ArgMatches,
+) -> Result<(&'a ArgMatches<'a>, Vec<&'static str>)> {
+    match opts.subcommand() {
+        ("set", Some(matches)) | ("watch", Some(matches)) => Ok((
+            matches,
+            [&SETOPTS_POSITIONAL_ARGS[..], &SETOPTS_SET_ONLY_ARGS[..]].concat(),
+        )),
+        ("systemd", Some(matches)) => Ok((matches, SYSTEMDOPTS_ONLY_ARGS.to_vec())),
+        (other, _) => bail!("[BUG] unexpected subcommand for set: {}", other),
+    }
+}
+
+fn get_debuggee_backup_name(debuggee_filename: &str, backup_suffix: &str) -> String {
+    format!("{}{}", debuggee_filename, backup_suffix)
+}
+
+pub(crate) fn get_backup_path_of_wrapper(wrapper: &str) -> String {
+    get_debuggee_backup_name(wrapper, &read_backup_suffix(wrapper))
+}
+
+fn reconstruct_flags(opts: &ArgMatches, positional_args: &[&str]) -> String {
+    let mut command = vec![];
+    for &key in opts.args.keys() {
+        if positional_args.contains(&key) {
+            continue;
+        }
+        let flag = format!("--{}", key.replace("_", "-"));
+        match opts.values_of(key) {
+            // Repeat the flag for each value so that options which take one value per occurrence,
+            // such as `--debugger-arg`, survive the round-trip
+            Some(values) => {
+                for value in values {
+                    command.push(flag.clone());
+                    command.push(format!("'{}'", escape_single_quote(value)));
+                }
+            }
+            None => command.push(flag),
+        }
+    }
+    command.join(" ")
+}
+
+fn escape_single_quote(s: &str) -> String {
+    s.replace("'", "'\"'\"'")
+}
+
+/// Launches the debuggee, and stops it right after exec by `stop_method`
+fn fork_exec_stop<T: AsRef<str>>(
+    debuggee_cmd: &[T],
+    options: &DebuggerOptions,
+    stop_method: StopMethod,
+) -> Result<Pid> {
+    get_valid_executable_path(debuggee_cmd[0].as_ref(), "the debuggee")?;
+    log_commandline(debuggee_cmd);
+    // Open the files for stdio before fork so that the failure is reported by the parent
+    let stdio_files = options.debuggee_stdio.open()?;
+    let limits = options.resource_limits;
+    let cpu_limit_cgroup = match limits.cpu_percent {
+        Some(percent) => Some(create_cpu_limit_cgroup(percent)?),
+        None => None,
+    };
+    let dbgee_pid = unistd::getpid();
+    match unsafe { unistd::fork().with_context(|| "fork failed.")? } {
+        unistd::ForkResult::Child => {
+            if let Some(sig) = options.parent_death_signal {
+                set_parent_death_signal(sig, dbgee_pid)?;
+            }
+            for (file, fd) in &stdio_files {
+                unistd::dup2(file.as_raw_fd(), *fd)
+                    .with_context(|| "Failed to redirect the stdio of the debuggee")?;
+            }
+            if let Some(memory_bytes) = limits.memory_bytes {
+                set_memory_limit(memory_bytes)?;
+            }
+            if let Some(ref cgroup) = cpu_limit_cgroup {
+                enter_cgroup(cgroup)?;
+            }
+            ptrace::traceme()
+                .map_err(add_macos_permission_hint)
+                .with_context(|| "ptrace::traceme failed.")?;
+            let cargs: Vec<CString> = debuggee_cmd
+                .iter()
+                .map(|arg| CString::new(arg.as_ref()).unwrap())
+                .collect();
+            let _ = unistd::execv(&cargs[0], &cargs[0..]);
+            bail!(
+                "exec {} failed. Error: {}",
+                &cargs[0].to_str().unwrap(),
+                nix::Error::last()
+            );
+        }
+        unistd::ForkResult::Parent {
+            child: debuggee_pid,
+        } => {
+            // Wait for the debuggee to be stopped by SIGSTOP, which is triggered by PTRACE_ATTACH
+            match wait::waitpid(debuggee_pid, None)
+                .with_context(|| "Unexpected error. Waiting for SIGTRAP failed.")?
+            {
+                wait::WaitStatus::Stopped(_, signal::SIGTRAP) => {}
+                other => {
+                    log::warn!(
+                        "The observed signal is not SIGTRAP, but dbgee continues. {:?}",
+                        other
+                    );
+                }
+            }
+
+            if stop_method == StopMethod::PtraceHold {
+                // Keep the debuggee in the ptrace-stop. `hand_over_held_debuggee` releases it
+                return Ok(debuggee_pid);
+            }
+
+            // macOS's bug prevents you from delivering SIGSTOP by detach directly.
+            // Thus, send SIGSTOP by kill before detach
+            signal::kill(debuggee_pid, signal::SIGSTOP)
+                .with_context(|| "Unexpected error. Sending a signal failed")?;
+            ptrace::detach(debuggee_pid, None)
+                .map_err(add_macos_permission_hint)
+                .with_context(|| "Unexpected error. Detach and stop failed")?;
+            // SIGSTOP takes effect asynchronously. Wait for it, since the debuggee must look stopped
+            // to the others, e.g. with --no-wait
+            match wait::waitpid(debuggee_pid, Some(wait::WaitPidFlag::WUNTRACED))
+                .with_context(|| "Unexpected error. Waiting for SIGSTOP failed.")?
+            {
+                wait::WaitStatus::Stopped(_, signal::SIGSTOP) => {}
+                other => bail!("The debuggee didn't stop. {:?}", other),
+            }
+
+            Ok(debuggee_pid)
+        }
+    }
+}
+
+/// Limits the virtual memory of this process to `memory_bytes` by RLIMIT_AS
+fn set_memory_limit(memory_bytes: u64) -> Result<()> {
+    let limit = nix::libc::rlimit {
+        rlim_cur: memory_bytes as nix::libc::rlim_t,
+        rlim_max: memory_bytes as nix::libc::rlim_t,
+    };
+    if unsafe { nix::libc::setrlimit(nix::libc::RLIMIT_AS, &limit) } != 0 {
+        bail!(
+            "Failed to set the memory limit of the debuggee: {}",
+            nix::Error::last()
+        );
+    }
+    Ok(())
+}
+
+/// Interval to check if the debugger takes over the held debuggee
+const HAND_OVER_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long to wait for the debugger to attach after dbgee released the held debuggee
+const HAND_OVER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// File in the sessions directory which exists while dbgee holds the debuggee by ptrace.
+/// The debugger taking over the debuggee removes it to request dbgee to release the debuggee.
+pub(crate) struct HoldFile {
+    path: PathBuf,
+}
+
+impl HoldFile {
+    pub(crate) fn create(debuggee_pid: Pid) -> Result<HoldFile> {
+        let path = session::create_sessions_dir()?.join(format!("{}.hold", debuggee_pid));
+        fs::write(&path, format!("{}\n", unistd::getpid()))
+            .with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(HoldFile { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn is_removed(&self) -> bool {
+        !self.path.exists()
+    }
+
+    /// Requests the release of the debuggee on behalf of the debugger
+    fn remove(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for HoldFile {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+/// Builds the command line which takes over the debuggee held by dbgee, and then runs `commandline`.
+///
+/// It removes `hold_file` to request dbgee to release the debuggee, and waits until dbgee is no longer
+/// the tracer of the debuggee, so that the debugger doesn't fail to attach with EPERM.
+fn build_take_over_commandline(
+    hold_file: &Path,
+    debuggee_pid: Pid,
+    commandline: Vec<String>,
+) -> Vec<String> {
+    vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        format!(
+            "rm -- \"$0\" || exit 1; \
+            while grep -qs '^TracerPid:[[:space:]]*[1-9]' /proc/{}/status; do sleep 0.01; done; \
+            exec \"$@\"",
+            debuggee_pid
+        ),
+        hold_file.to_string_lossy().into_owned(),
+    ]
+    .into_iter()
+    .chain(commandline)
+    .collect()
+}
+
+/// Debugger which takes over the debuggee held by dbgee before `debugger` attaches to it
+struct TakingOverDebugger<'a> {
+    debugger: &'a dyn Debugger,
+    hold_file: &'a HoldFile,
+    debuggee_pid: Pid,
+    /// Whether the terminal gave the attach information to the client instead of launching the command line
+    is_information_given: std::cell::Cell<bool>,
+}
+
+impl Debugger for TakingOverDebugger<'_> {
+    fn name(&self) -> &str {
+        self.debugger.name()
+    }
+
+    fn run(&mut self, _: &str, _: Vec<&str>, _: &mut dyn DebuggerTerminal) -> Result<Pid> {
+        bail!("[BUG] TakingOverDebugger only builds how to attach");
+    }
+
+    fn set(
+        &mut self,
+        _: &str,
+        _: Vec<&str>,
+        _: &WrapperOptions,
+        _: &mut dyn DebuggerTerminal,
+    ) -> Result<()> {
+        bail!("[BUG] TakingOverDebugger only builds how to attach");
+    }
+
+    fn unset(&mut self, _: &str) -> Result<()> {
+        bail!("[BUG] TakingOverDebugger only builds how to attach");
+    }
+
+    fn build_attach_commandline(&self) -> Result<Vec<String>> {
+        Ok(build_take_over_commandline(
+            &self.hold_file.path,
+            self.debuggee_pid,
+            self.debugger.build_attach_commandline()?,
+        ))
+    }
+
+    fn build_attach_information(&self) -> Result<HashMap<AttachInformationKey, String>> {
+        self.is_information_given.set(true);
+        self.debugger.build_attach_information()
+    }
+
+    fn is_debuggee_surely_supported(&self, debuggee: &str) -> Result<bool> {
+        self.debugger.is_debuggee_surely_supported(debuggee)
+    }
+}
+
+/// Terminal which opens the debugger so that it takes over the debuggee held by dbgee.
+///
+/// A debugger launched by a command line removes the hold file by itself. For the clients which are given
+/// the attach information instead, such as VSCode, the hold file is removed once they receive it.
+pub(crate) struct TakingOverTerminal<'a> {
+    terminal: &'a mut dyn DebuggerTerminal,
+    hold_file: &'a HoldFile,
+    debuggee_pid: Pid,
+}
+
+impl<'a> TakingOverTerminal<'a> {
+    pub(crate) fn new(
+        terminal: &'a mut dyn DebuggerTerminal,
+        hold_file: &'a HoldFile,
+        debuggee_pid: Pid,
+    ) -> TakingOverTerminal<'a> {
+        TakingOverTerminal {
+            terminal,
+            hold_file,
+            debuggee_pid,
+        }
+    }
+}
+
+impl DebuggerTerminal for TakingOverTerminal<'_> {
+    fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
+        let taking_over = TakingOverDebugger {
+            debugger,
+            hold_file: self.hold_file,
+            debuggee_pid: self.debuggee_pid,
+            is_information_given: std::cell::Cell::new(false),
+        };
+        self.terminal.open(&taking_over)?;
+        if taking_over.is_information_given.get() {
+            self.hold_file.remove();
+        }
+        Ok(())
+    }
+}
 
-    fs::remove_file(&wrapper_path)?;
-    fs::rename(&debuggee_path, &wrapper_path)?;
+/// Waits for a debugger to take over the debuggee held by ptrace, and hands the debuggee over to it.
+///
+/// The debugger requests the debuggee by removing `hold_file`. Then dbgee detaches from the debuggee with SIGSTOP
+/// so that the debugger can attach to it, and sends SIGCONT after the debugger attaches, so that the debuggee
+/// is not left in the group-stop when the debugger resumes it.
+pub(crate) fn hand_over_held_debuggee(debuggee_pid: Pid, hold_file: &HoldFile) -> Result<()> {
+    log::info!(
+        "dbgee holds the debuggee (pid: {}) until a debugger takes it over. Keep dbgee running.",
+        debuggee_pid
+    );
+    while !hold_file.is_removed() {
+        match wait::waitpid(debuggee_pid, Some(wait::WaitPidFlag::WNOHANG)) {
+            Ok(wait::WaitStatus::StillAlive) => {}
+            // The held debuggee can be only killed by SIGKILL
+            other => bail!("the debuggee exited while it was held: {:?}", other),
+        }
+        std::thread::sleep(HAND_OVER_POLL_INTERVAL);
+    }
+    log::info!("Handing the debuggee over to the debugger");
+    ptrace::detach(debuggee_pid, signal::SIGSTOP).context("Failed to release the held debuggee")?;
 
+    let started_at = Instant::now();
+    let mut has_stopped = false;
+    while started_at.elapsed() < HAND_OVER_TIMEOUT {
+        if get_tracer_pid(debuggee_pid)?.is_some() {
+            signal::kill(debuggee_pid, signal::SIGCONT)
+                .context("Failed to clear the stop of the debuggee")?;
+            return Ok(());
+        }
+        match get_process_state(debuggee_pid)? {
+            'T' => has_stopped = true,
+            // The debugger already attached, resumed and detached the debuggee between the polls
+            'Z' => return Ok(()),
+            _ if has_stopped => return Ok(()),
+            _ => (),
+        }
+        std::thread::sleep(HAND_OVER_POLL_INTERVAL);
+    }
+    log::warn!(
+        "The debugger didn't attach to the debuggee in {} seconds. The debuggee is left stopped by SIGSTOP.",
+        HAND_OVER_TIMEOUT.as_secs()
+    );
     Ok(())
 }
 
-fn check_if_wrapped<P: AsRef<Path>>(path: P) -> bool {
-    let wrapper_sh_template_bytes = include_bytes!("../resources/wrapper.sh");
-    let wrapper_sh_template = str::from_utf8(wrapper_sh_template_bytes).unwrap();
-    let wrapper_signature: String = wrapper_sh_template.lines().take(2).collect();
-
-    let file = File::open(path);
-    if file.is_err() {
-        return false;
+/// Adds a hint to resolve a permission error of ptrace on macOS
+fn add_macos_permission_hint(error: nix::Error) -> anyhow::Error {
+    let is_permission_error = matches!(
+        error,
+        nix::Error::Sys(nix::errno::Errno::EPERM) | nix::Error::Sys(nix::errno::Errno::EACCES)
+    );
+    if cfg!(target_os = "macos") && is_permission_error {
+        anyhow!(error).context(
+            "ptrace is not permitted. On macOS, debugging processes requires the developer mode \
+            (`sudo DevToolsSecurity -enable`), `com.apple.security.cs.debugger` entitlement, or sudo.",
+        )
+    } else {
+        anyhow!(error)
     }
-    let file = file.unwrap();
+}
 
-    let lines = BufReader::new(file).lines();
-    let signature: String = lines
-        .take(2)
-        .map(|lresult| lresult.unwrap_or_default())
-        .collect();
+fn ignore_sigint() -> Result<()> {
+    unsafe {
+        signal::signal(signal::Signal::SIGINT, signal::SigHandler::SigIgn)?;
+    }
+    Ok(())
+}
 
-    signature == wrapper_signature
+fn kill9_child_by_sigint(pid: Pid) -> Result<()> {
+    ctrlc::set_handler(move || {
+        let _ = signal::kill(pid, signal::Signal::SIGKILL);
+    })?;
+    Ok(())
 }
 
-fn build_run_command(set_opts: &ArgMatches) -> Result<String> {
-    let self_pathbuf = env::current_exe()?;
-    let self_path = get_valid_executable_path(&self_pathbuf, "dbgee")?;
-    let global_opts = reconstruct_flags(set_opts, &[]);
-    let attach_opts = reconstruct_flags(
-        set_opts.subcommand_matches("set").unwrap(),
-        &SETOPTS_POSITIONAL_ARGS,
-    );
-    let debuggee_path = get_valid_executable_path(
-        &set_opts
-            .subcommand_matches("set")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use std::cell::RefCell;
+    use std::os::unix::fs::PermissionsExt;
+    use std::rc::Rc;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_check_if_wrapperd_by_strings() {
+        let actually_wrapped = indoc! {r#"
+            #!/bin/sh
+            # a wrapper script generated by dbgee
+            some scripts
+        "#};
+        let tmpfile = make_temp_file(actually_wrapped);
+        assert!(check_if_wrapped(tmpfile.path()));
+
+        let not_wrapped = indoc! {r#"
+            #!/bin/sh
+            some scripts in the wild
+        "#};
+        let tmpfile = make_temp_file(not_wrapped);
+        assert!(!check_if_wrapped(tmpfile.path()));
+    }
+
+    #[test]
+    fn test_check_if_wrapperd_by_actually_wrapping() {
+        let tmpfile = make_temp_executable_file("dummy");
+        let tmpfile_path = tmpfile.path().to_str().unwrap();
+        wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -- debuggee",
+            &WrapperOptions::default(),
+        )
+        .unwrap();
+        assert!(check_if_wrapped(tmpfile.path()));
+        unwrap_debuggee_binary(tmpfile_path).unwrap();
+        assert!(!check_if_wrapped(tmpfile.path()));
+    }
+
+    #[test]
+    fn test_double_wrapping() {
+        let tmpfile = make_temp_executable_file("dummy");
+        let tmpfile_path = tmpfile.path().to_str().unwrap();
+        wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -- debuggee",
+            &WrapperOptions::default(),
+        )
+        .unwrap();
+        assert!(wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -- debuggee",
+            &WrapperOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_force_rewrapping() {
+        let tmpfile = make_temp_executable_file("dummy");
+        let tmpfile_path = tmpfile.path().to_str().unwrap();
+        wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -- debuggee",
+            &WrapperOptions::default(),
+        )
+        .unwrap();
+        let backup_path = get_path_of_unset_debuggee(tmpfile_path).unwrap();
+        let backup_ino = fs::metadata(&backup_path).unwrap().ino();
+        // A different suffix doesn't move the existing backup
+        let wrapper_options = WrapperOptions {
+            force: true,
+            backup_suffix: ".other".to_owned(),
+            ..Default::default()
+        };
+        wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -d lldb -- debuggee",
+            &wrapper_options,
+        )
+        .unwrap();
+
+        // the wrapper has the new run_cmd
+        let wrapper = fs::read_to_string(tmpfile.path()).unwrap();
+        assert!(wrapper.contains("exec dummy run -d lldb -- debuggee"));
+        // the true original is still kept as the backup, which is never moved
+        assert_eq!(
+            backup_path,
+            get_path_of_unset_debuggee(tmpfile_path).unwrap()
+        );
+        assert_eq!(backup_ino, fs::metadata(&backup_path).unwrap().ino());
+        assert_eq!("dummy", fs::read_to_string(&backup_path).unwrap());
+
+        unwrap_debuggee_binary(tmpfile_path).unwrap();
+        assert_eq!("dummy", fs::read_to_string(tmpfile.path()).unwrap());
+    }
+
+    #[test]
+    fn test_wrapping_running_debuggee() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let debuggee = tmpdir.path().join("sleep");
+        fs::copy(find_command_path("sleep").unwrap(), &debuggee).unwrap();
+        let mut child = Command::new(&debuggee).arg("10").spawn().unwrap();
+        let child_pid = Pid::from_raw(child.id() as i32);
+        // sysinfo may see the process before it execs the debuggee
+        let start = Instant::now();
+        while !find_running_instances(&debuggee).contains(&child_pid) {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let debuggee = debuggee.to_str().unwrap();
+        assert!(wrap_debuggee_binary(
+            debuggee,
+            "dummy run -- debuggee",
+            &WrapperOptions::default()
+        )
+        .is_err());
+        // --force is only for re-wrapping
+        let wrapper_options = WrapperOptions {
+            force: true,
+            ..Default::default()
+        };
+        assert!(wrap_debuggee_binary(debuggee, "dummy run -- debuggee", &wrapper_options).is_err());
+        let wrapper_options = WrapperOptions {
+            allow_running: true,
+            ..Default::default()
+        };
+        wrap_debuggee_binary(debuggee, "dummy run -- debuggee", &wrapper_options).unwrap();
+        assert!(check_if_wrapped(debuggee));
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_find_leaf_descendant() {
+        // sh forks sleep as its child, like sudo does dlv, and dlv does the debuggee
+        let mut child = Command::new("sh")
+            .args(["-c", "sleep 10; true"])
+            .spawn()
+            .unwrap();
+        let child_pid = Pid::from_raw(child.id() as i32);
+        let start = Instant::now();
+        let leaf = loop {
+            if let Some(leaf) = find_leaf_descendant(child_pid) {
+                break leaf;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        let comm = fs::read_to_string(format!("/proc/{}/comm", leaf)).unwrap();
+        assert_eq!("sleep", comm.trim_end());
+
+        let _ = signal::kill(leaf, signal::SIGKILL);
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn test_wrapping_with_activate_env() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let debuggee = tmpdir.path().join("debuggee");
+        fs::write(&debuggee, "#!/bin/sh\necho original \"$@\"\n").unwrap();
+        fs::set_permissions(&debuggee, fs::Permissions::from_mode(0o755)).unwrap();
+        let debuggee = debuggee.to_str().unwrap();
+
+        let wrapper_options = WrapperOptions {
+            activate_env: Some(ActivationEnv::from_str("DBGEE_ACTIVE=1").unwrap()),
+            ..Default::default()
+        };
+        wrap_debuggee_binary(debuggee, "echo debugger \"$@\"", &wrapper_options).unwrap();
+
+        let run_wrapper = |env_value: Option<&str>| {
+            let mut command = Command::new(debuggee);
+            command.arg("arg0").env_remove("DBGEE_ACTIVE");
+            if let Some(env_value) = env_value {
+                command.env("DBGEE_ACTIVE", env_value);
+            }
+            String::from_utf8(command.output().unwrap().stdout).unwrap()
+        };
+        assert_eq!("original arg0\n", run_wrapper(None));
+        assert_eq!("original arg0\n", run_wrapper(Some("0")));
+        assert_eq!("debugger arg0\n", run_wrapper(Some("1")));
+    }
+
+    #[test]
+    fn test_wrapping_only_interactive() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let debuggee = tmpdir.path().join("debuggee");
+        fs::write(&debuggee, "#!/bin/sh\necho original \"$@\"\n").unwrap();
+        fs::set_permissions(&debuggee, fs::Permissions::from_mode(0o755)).unwrap();
+        let debuggee = debuggee.to_str().unwrap();
+
+        let wrapper_options = WrapperOptions {
+            only_interactive: true,
+            ..Default::default()
+        };
+        wrap_debuggee_binary(debuggee, "echo debugger \"$@\"", &wrapper_options).unwrap();
+        assert!(fs::read_to_string(debuggee)
             .unwrap()
-            .value_of("debuggee")
-            .unwrap(),
-        "debuggee",
-    )?;
+            .contains("if ! [ -t 0 ]; then exec "));
 
-    Ok(format!(
-        "{} {} run {} -- {} \"$@\"",
-        self_path,
-        &global_opts,
-        &attach_opts,
-        &get_debuggee_backup_name(&debuggee_path)
-    ))
-}
+        // Launched non-interactively, as a service is
+        let output = Command::new(debuggee)
+            .arg("arg0")
+            .stdin(std::process::Stdio::null())
+            .output()
+            .unwrap();
+        assert_eq!("original arg0\n", String::from_utf8(output.stdout).unwrap());
+    }
+
+    #[test]
+    fn test_wrapping_once() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let debuggee = tmpdir.path().join("debuggee");
+        let original = "#!/bin/sh\necho original \"$@\"\n";
+        fs::write(&debuggee, original).unwrap();
+        fs::set_permissions(&debuggee, fs::Permissions::from_mode(0o755)).unwrap();
+        let debuggee = debuggee.to_str().unwrap();
+
+        let wrapper_options = WrapperOptions {
+            once: true,
+            ..Default::default()
+        };
+        wrap_debuggee_binary(debuggee, "echo debugger \"$@\"", &wrapper_options).unwrap();
+
+        let run_debuggee = || {
+            String::from_utf8(Command::new(debuggee).arg("arg0").output().unwrap().stdout).unwrap()
+        };
+        assert_eq!("debugger arg0\n", run_debuggee());
+        assert!(!check_if_wrapped(debuggee));
+        assert_eq!(original, fs::read_to_string(debuggee).unwrap());
+        assert_eq!("original arg0\n", run_debuggee());
+    }
+
+    #[test]
+    fn test_parse_activation_env() {
+        assert_eq!(
+            ActivationEnv {
+                name: "DBGEE_ACTIVE".to_owned(),
+                value: None
+            },
+            ActivationEnv::from_str("DBGEE_ACTIVE").unwrap()
+        );
+        assert_eq!(
+            ActivationEnv {
+                name: "A".to_owned(),
+                value: Some("x=y".to_owned())
+            },
+            ActivationEnv::from_str("A=x=y").unwrap()
+        );
+        assert!(ActivationEnv::from_str("1A").is_err());
+        assert!(ActivationEnv::from_str("A;rm").is_err());
+    }
+
+    #[test]
+    fn test_resolve_listen_address() {
+        let default_port = || Ok(DEFAULT_DEBUGGER_PORT);
+        let env: HashMap<&str, &str> = HashMap::new();
+        let get_env = |name: &str| env.get(name).map(|val| val.to_string());
+        assert_eq!(
+            ListenAddress {
+                host: DEFAULT_LISTEN_HOST.to_owned(),
+                port: DEFAULT_DEBUGGER_PORT
+            },
+            ListenAddress::resolve_with(None, get_env, default_port).unwrap()
+        );
+
+        let env: HashMap<&str, &str> = [("DBGEE_PORT", "2345"), ("DBGEE_LISTEN_HOST", "0.0.0.0")]
+            .iter()
+            .copied()
+            .collect();
+        let get_env = |name: &str| env.get(name).map(|val| val.to_string());
+        let listen_address = ListenAddress::resolve_with(None, get_env, default_port).unwrap();
+        assert_eq!("0.0.0.0:2345", listen_address.to_string());
+
+        // $DBGEE_PORT takes precedence over --port
+        let listen_address =
+            ListenAddress::resolve_with(Some(3456), get_env, default_port).unwrap();
+        assert_eq!("0.0.0.0:2345", listen_address.to_string());
+
+        let env: HashMap<&str, &str> = HashMap::new();
+        let get_env = |name: &str| env.get(name).map(|val| val.to_string());
+        let listen_address =
+            ListenAddress::resolve_with(Some(3456), get_env, default_port).unwrap();
+        assert_eq!("localhost:3456", listen_address.to_string());
+
+        let env: HashMap<&str, &str> = [("DBGEE_PORT", "not-a-port")].iter().copied().collect();
+        let get_env = |name: &str| env.get(name).map(|val| val.to_string());
+        assert!(ListenAddress::resolve_with(None, get_env, default_port).is_err());
+    }
+
+    #[test]
+    fn test_build_server_not_ready_hint() {
+        assert_eq!(
+            "port 65535 is not in use. If the server failed to listen on it, give another port by --port 1024. \
+            The last lines of the stderr of the server:\nerror: no module named debugpy",
+            build_server_not_ready_hint(65535, None, "error: no module named debugpy")
+        );
+        assert_eq!(
+            "no line of the output of the server matched --ready-regex 'listening at'. \
+            Check the output of your version of the server. The server wrote nothing to stderr",
+            build_server_not_ready_hint(5679, Some(&Regex::new("listening at").unwrap()), "")
+        );
+    }
+
+    #[test]
+    fn test_server_output_relay_through_pty() {
+        let sink = NamedTempFile::new().unwrap();
+        let (mut relay, mut writer) =
+            ServerOutputRelay::new_pty(sink.as_file().as_raw_fd()).unwrap();
+        // The server writes to a terminal, as the debuggee sharing the stdout would do without dbgee
+        assert!(unistd::isatty(writer.as_raw_fd()).unwrap());
+        writeln!(writer, "API server listening at: 127.0.0.1:5679").unwrap();
+        let started_at = Instant::now();
+        let lines = loop {
+            let lines = relay.read_new_lines();
+            if !lines.is_empty() {
+                break lines;
+            }
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(vec!["API server listening at: 127.0.0.1:5679"], lines);
+
+        relay.hand_over().unwrap();
+        writeln!(writer, "client connected").unwrap();
+        drop(writer);
+        // The newlines are not translated to CRLF
+        let expected = "API server listening at: 127.0.0.1:5679\nclient connected\n";
+        let started_at = Instant::now();
+        while fs::read_to_string(sink.path()).unwrap() != expected {
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_server_output_relay() {
+        let sink = NamedTempFile::new().unwrap();
+        let (mut relay, mut writer) = ServerOutputRelay::with_sink(sink.reopen().unwrap()).unwrap();
+        write!(writer, "Type 'help' for list of commands.\nAPI server ").unwrap();
+        assert_eq!(
+            vec!["Type 'help' for list of commands."],
+            relay.read_new_lines()
+        );
+        writeln!(writer, "listening at: 127.0.0.1:5679").unwrap();
+        assert_eq!(
+            vec!["API server listening at: 127.0.0.1:5679"],
+            relay.read_new_lines()
+        );
+        assert!(relay.read_new_lines().is_empty());
+        for i in 0..SERVER_OUTPUT_TAIL_LINES {
+            writeln!(writer, "{}", i).unwrap();
+        }
+        relay.read_new_lines();
+        assert_eq!("0\n1\n2\n3\n4\n5\n6\n7\n8\n9", relay.last_lines());
+
+        // The output after the hand-over is relayed by cat, until the writer is closed
+        relay.hand_over().unwrap();
+        writeln!(writer, "client connected").unwrap();
+        drop(writer);
+        let expected =
+            "Type 'help' for list of commands.\nAPI server listening at: 127.0.0.1:5679\n\
+            0\n1\n2\n3\n4\n5\n6\n7\n8\n9\nclient connected\n";
+        let started_at = Instant::now();
+        while fs::read_to_string(sink.path()).unwrap() != expected {
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_wait_until_listening() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let listening_port = listener.local_addr().unwrap().port();
+        let mut server = Command::new("sleep").arg("5").spawn().unwrap();
+        wait_until_listening(&mut server, listening_port, &mut [], Duration::from_secs(5)).unwrap();
+
+        let free_port = find_free_port().unwrap();
+        let error =
+            wait_until_listening(&mut server, free_port, &mut [], Duration::from_millis(200))
+                .unwrap_err()
+                .to_string();
+        assert!(error.contains("didn't listen"), "{}", error);
+        server.kill().unwrap();
+        server.wait().unwrap();
+
+        let mut server = Command::new("true").spawn().unwrap();
+        let error = wait_until_listening(&mut server, free_port, &mut [], Duration::from_secs(5))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("exited before listening"), "{}", error);
+    }
+
+    #[test]
+    fn test_wait_until_output_matches() {
+        let ready_regex = Regex::new("^API server listening at: ").unwrap();
+        let (relay, writer) = ServerOutputRelay::with_sink(tempfile::tempfile().unwrap()).unwrap();
+        let mut readers = vec![relay];
+        let mut server = Command::new("sh")
+            .args([
+                "-c",
+                "sleep 0.2; echo 'API server listening at: 127.0.0.1:5679'; sleep 5",
+            ])
+            .stdout(writer.try_clone().unwrap())
+            .spawn()
+            .unwrap();
+        wait_until_output_matches(
+            &mut server,
+            &ready_regex,
+            &mut readers,
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        server.kill().unwrap();
+        server.wait().unwrap();
+
+        let mut server = Command::new("sh")
+            .args(["-c", "echo 'could not launch process'"])
+            .stdout(writer)
+            .spawn()
+            .unwrap();
+        assert!(wait_until_output_matches(
+            &mut server,
+            &ready_regex,
+            &mut readers,
+            Duration::from_secs(5)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_build_lldb_dap_server_commandline() {
+        let listen_address = ListenAddress {
+            host: "localhost".to_owned(),
+            port: 4711,
+        };
+        assert_eq!(
+            vec!["lldb-dap", "--connection", "listen://localhost:4711"],
+            LldbDapDebugger::build_server_commandline("lldb-dap", &listen_address)
+        );
+        assert_eq!(
+            vec!["lldb-vscode", "--port", "4711"],
+            LldbDapDebugger::build_server_commandline("lldb-vscode", &listen_address)
+        );
+        assert_eq!(
+            vec!["/usr/bin/lldb-vscode-14", "--port", "4711"],
+            LldbDapDebugger::build_server_commandline("/usr/bin/lldb-vscode-14", &listen_address)
+        );
+    }
+
+    #[test]
+    fn test_build_take_over_commandline() {
+        let dir = tempfile::tempdir().unwrap();
+        let hold_file = dir.path().join("42.hold");
+        fs::write(&hold_file, "").unwrap();
+        // A process which nobody traces, as the debuggee after dbgee released it
+        let mut debuggee = Command::new("sleep").arg("10").spawn().unwrap();
+        let commandline = build_take_over_commandline(
+            &hold_file,
+            Pid::from_raw(debuggee.id() as i32),
+            vec!["echo".to_owned(), "attached".to_owned()],
+        );
+        let output = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .output()
+            .unwrap();
+        assert_eq!("attached\n", String::from_utf8_lossy(&output.stdout));
+        assert!(!hold_file.exists());
+
+        // The debugger doesn't start if the hold file can't be removed
+        let output = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert_eq!("", String::from_utf8_lossy(&output.stdout));
+        debuggee.kill().unwrap();
+        debuggee.wait().unwrap();
+    }
+
+    #[test]
+    fn test_wrap_commandline() {
+        let commandline = vec!["gdb".to_owned(), "-p".to_owned(), "42".to_owned()];
+        assert_eq!(
+            vec!["gdb", "-p", "42"],
+            DebuggerOptions::default().wrap_commandline(commandline.clone())
+        );
+        let options = DebuggerOptions {
+            sudo: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["sudo", "gdb", "-p", "42"],
+            options.wrap_commandline(commandline.clone())
+        );
+        let options = DebuggerOptions {
+            debugger_user: Some("service".to_owned()),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["sudo", "-u", "service", "gdb", "-p", "42"],
+            options.wrap_commandline(commandline)
+        );
+    }
+
+    #[test]
+    fn test_run_attach_hook() {
+        let pid = Pid::from_raw(42);
+        assert!(run_attach_hook("--pre-attach-hook", r#"[ "$DBGEE_PID" = 42 ]"#, pid).is_ok());
+        let error = run_attach_hook("--pre-attach-hook", "echo not allowed; exit 3", pid)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("not allowed"), "{}", error);
+    }
+
+    #[test]
+    fn test_check_debugpy_installed() {
+        let dir = tempfile::tempdir().unwrap();
+        // Don't use NamedTempFile, whose open fd makes exec fail with ETXTBSY
+        let make_fake_python = |name: &str, script: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, script).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_str().unwrap().to_owned()
+        };
+
+        // A python which fails to import any module
+        let python_without_debugpy = make_fake_python("without_debugpy", "#!/bin/sh\nexit 1\n");
+        let error = check_debugpy_installed(&python_without_debugpy, Duration::from_secs(5))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("'debugpy' module is not installed"),
+            "{}",
+            error
+        );
+
+        let python_with_debugpy = make_fake_python(
+            "with_debugpy",
+            "#!/bin/sh\n[ \"$1\" = -c ] && [ \"$2\" = \"import debugpy\" ]\n",
+        );
+        assert!(check_debugpy_installed(&python_with_debugpy, Duration::from_secs(5)).is_ok());
+
+        let hanging_python = make_fake_python("hanging", "#!/bin/sh\nsleep 10\n");
+        let error = check_debugpy_installed(&hanging_python, Duration::from_millis(100))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("didn't finish"), "{}", error);
+
+        let error = check_debugpy_installed("/nonexistent/python3", Duration::from_secs(5))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Did you install python?"), "{}", error);
+    }
+
+    #[test]
+    fn test_build_capture_core_commandline() {
+        let options = DebuggerOptions {
+            sudo: true,
+            ..Default::default()
+        };
+        let debugger = GdbCompatibleDebugger {
+            debugger_name: "gdb".to_owned(),
+            debuggee_pid: None,
+            debuggee_path: None,
+            commandline_builder: Box::new(|_, _| Ok(vec![])),
+            options,
+            launcher: FakeLauncher::new().0,
+        };
+        assert_eq!(
+            vec![
+                "sudo",
+                "gdb",
+                "--batch",
+                "-p",
+                "42",
+                "-ex",
+                "generate-core-file /tmp/core"
+            ],
+            debugger
+                .build_capture_core_commandline(Pid::from_raw(42), Path::new("/tmp/core"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_gdb_source_dir_args() {
+        assert_eq!(
+            vec!["-ex", "directory /src/a", "-ex", "directory /src/b"],
+            GdbDebugger::build_source_dir_args(
+                &[PathBuf::from("/src/a"), PathBuf::from("/src/b")],
+                "/nonexistent/debuggee"
+            )
+        );
+    }
+
+    #[test]
+    fn test_lldb_source_dir_args() {
+        assert!(LldbDebugger::build_source_dir_args(&[], "/nonexistent/debuggee").is_empty());
+        // The comp_dirs of a debuggee without DWARF are unknown
+        assert_eq!(
+            vec![
+                "-o",
+                r#"settings append target.source-map . "/src/a""#,
+                "-o",
+                r#"settings append target.source-map . "/src/my b""#
+            ],
+            LldbDebugger::build_source_dir_args(
+                &[PathBuf::from("/src/a"), PathBuf::from("/src/my b")],
+                "/nonexistent/debuggee"
+            )
+        );
+    }
+
+    #[test]
+    fn test_cwd_args() {
+        assert!(GdbDebugger::build_cwd_args(&DebuggerOptions::default()).is_empty());
+        let options = DebuggerOptions {
+            debugger_cwd: Some(PathBuf::from("/work/my \"dir\"")),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec!["-ex", "cd /work/my \"dir\""],
+            GdbDebugger::build_cwd_args(&options)
+        );
+        assert_eq!(
+            vec![
+                "-o",
+                r#"settings set target.run-cwd "/work/my \"dir\"""#,
+                "-o",
+                r#"platform settings -w "/work/my \"dir\"""#
+            ],
+            LldbDebugger::build_cwd_args(&options)
+        );
+    }
+
+    #[test]
+    fn test_quote_debugger_arg() {
+        assert_eq!(r#""/src/my dir""#, quote_debugger_arg("/src/my dir"));
+        assert_eq!(
+            r#""C:\\src\"quoted\"""#,
+            quote_debugger_arg(r#"C:\src"quoted""#)
+        );
+    }
+
+    #[test]
+    fn test_build_custom_debugger_commandline() {
+        assert_eq!(
+            vec!["cgdb", "-p", "42", "--", "/bin/prog"],
+            CustomDebugger::build_commandline(
+                "cgdb  -p {pid} -- {program}",
+                Pid::from_raw(42),
+                "/bin/prog"
+            )
+        );
+        assert_eq!(
+            vec!["./attach.sh", "--target=/bin/prog:42"],
+            CustomDebugger::build_commandline(
+                "./attach.sh --target={program}:{pid}",
+                Pid::from_raw(42),
+                "/bin/prog"
+            )
+        );
+    }
+
+    #[test]
+    fn test_rr_commandlines() -> Result<()> {
+        let options = DebuggerOptions {
+            debugger_path: Some("/opt/rr/bin/rr".to_owned()),
+            debugger_args: vec!["-g".to_owned(), "1000".to_owned()],
+            ..DebuggerOptions::default()
+        };
+        let mut debugger = RrDebugger::new(&options)?;
+        let trace_dir = Path::new("/tmp/dbgee-rr-test/trace");
+        assert_eq!(
+            vec![
+                "/opt/rr/bin/rr",
+                "record",
+                "-o",
+                "/tmp/dbgee-rr-test/trace",
+                "/bin/prog",
+                "arg"
+            ],
+            debugger.build_record_commandline(trace_dir, &["/bin/prog", "arg"])
+        );
+        // The replay needs the recording
+        assert!(debugger.build_attach_commandline().is_err());
+        debugger.trace_dir = Some(trace_dir.to_owned());
+        assert_eq!(
+            vec![
+                "/opt/rr/bin/rr",
+                "replay",
+                "-g",
+                "1000",
+                "/tmp/dbgee-rr-test/trace"
+            ],
+            debugger.build_attach_commandline()?
+        );
+
+        let options = DebuggerOptions {
+            debugger_path: Some("/opt/rr/bin/rr".to_owned()),
+            sudo: true,
+            ..DebuggerOptions::default()
+        };
+        assert!(RrDebugger::new(&options).is_err());
+        Ok(())
+    }
 
-fn get_debuggee_backup_name(debuggee_filename: &str) -> String {
-    format!("{}-original", debuggee_filename)
-}
+    #[test]
+    fn test_wait_exit_without_reaping() -> Result<()> {
+        let child = Command::new("sh").args(["-c", "exit 3"]).spawn()?;
+        let pid = Pid::from_raw(child.id() as i32);
+        wait_exit_without_reaping(pid)?;
+        assert_eq!(wait::WaitStatus::Exited(pid, 3), wait::waitpid(pid, None)?);
+        Ok(())
+    }
 
-fn reconstruct_flags(opts: &ArgMatches, positional_args: &[&str]) -> String {
-    let mut command = vec![];
-    for &key in opts.args.keys() {
-        if positional_args.contains(&key) {
-            continue;
-        }
-        command.push(format!("--{}", key.replace("_", "-")));
-        if let Some(values) = opts.values_of(key) {
-            for value in values {
-                command.push(format!("'{}'", escape_single_quote(value)));
-            }
-        }
+    #[test]
+    fn test_is_python_script() {
+        let is_python_script_of = |content: &str| {
+            let mut script = NamedTempFile::new().unwrap();
+            script.write_all(content.as_bytes()).unwrap();
+            is_python_script(script.path().to_str().unwrap())
+        };
+        assert!(is_python_script_of("#!/usr/bin/env -S python3 -X dev\n"));
+        assert!(is_python_script_of("#!/usr/local/bin/python3.11\n"));
+        assert!(!is_python_script_of("#!/bin/bash\npython3 main.py\n"));
+        assert!(!is_python_script_of("print('no shebang')\n"));
     }
-    command.join(" ")
-}
 
-fn escape_single_quote(s: &str) -> String {
-    s.replace("'", "'\"'\"'")
-}
+    #[test]
+    fn test_insert_debuggee_name() {
+        let mut info = HashMap::new();
+        DebuggerOptions::default()
+            .insert_debuggee_name(&mut info, "/tmp/go-build123/b001/exe/main");
+        assert_eq!("main", info[&AttachInformationKey::DebuggeeName]);
+        let options = DebuggerOptions {
+            debuggee_name: Some("server".to_owned()),
+            ..Default::default()
+        };
+        options.insert_debuggee_name(&mut info, "/tmp/go-build123/b001/exe/main");
+        assert_eq!("server", info[&AttachInformationKey::DebuggeeName]);
+    }
 
-fn fork_exec_stop<T: AsRef<str>>(debuggee_cmd: &[T]) -> Result<Pid> {
-    get_valid_executable_path(debuggee_cmd[0].as_ref(), "the debuggee")?;
-    match unsafe { unistd::fork().with_context(|| "fork failed.")? } {
-        unistd::ForkResult::Child => {
-            ptrace::traceme().with_context(|| "ptrace::traceme failed.")?;
-            let cargs: Vec<CString> = debuggee_cmd
-                .iter()
-                .map(|arg| CString::new(arg.as_ref()).unwrap())
-                .collect();
-            let _ = unistd::execv(&cargs[0], &cargs[0..]);
-            bail!(
-                "exec {} failed. Error: {}",
-                &cargs[0].to_str().unwrap(),
-                nix::Error::last()
-            );
-        }
-        unistd::ForkResult::Parent {
-            child: debuggee_pid,
-        } => {
-            // Wait for the debuggee to be stopped by SIGSTOP, which is triggered by PTRACE_ATTACH
-            match wait::waitpid(debuggee_pid, None)
-                .with_context(|| "Unexpected error. Waiting for SIGTRAP failed.")?
-            {
-                wait::WaitStatus::Stopped(_, signal::SIGTRAP) => {}
-                other => {
-                    log::warn!(
-                        "The observed signal is not SIGTRAP, but dbgee continues. {:?}",
-                        other
-                    );
-                }
-            }
+    #[test]
+    fn test_source_map_args() {
+        let options = DebuggerOptions {
+            source_maps: vec![SourceMap {
+                from: "/build/src".to_owned(),
+                to: "/home/me/my src".to_owned(),
+            }],
+            symbols_dir: Some(PathBuf::from("/symbols")),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                "-iex",
+                "set debug-file-directory /symbols:/usr/lib/debug",
+                "-ex",
+                r#"set substitute-path "/build/src" "/home/me/my src""#
+            ],
+            GdbDebugger::build_source_map_args(&options)
+        );
+        assert_eq!(
+            vec![
+                "-O",
+                "settings append target.debug-file-search-paths /symbols",
+                "-o",
+                r#"settings append target.source-map "/build/src" "/home/me/my src""#
+            ],
+            LldbDebugger::build_source_map_args(&options)
+        );
+        assert_eq!(
+            r#"{"/build/src": "/home/me/my src"}"#,
+            options.build_source_map_json()
+        );
+        assert!(GdbDebugger::build_source_map_args(&DebuggerOptions::default()).is_empty());
+    }
 
-            // macOS's bug prevents you from delivering SIGSTOP by detach directly.
-            // Thus, send SIGSTOP by kill before detach
-            signal::kill(debuggee_pid, signal::SIGSTOP)
-                .with_context(|| "Unexpected error. Sending a signal failed")?;
-            ptrace::detach(debuggee_pid, None)
-                .with_context(|| "Unexpected error. Detach and stop failed")?;
+    #[test]
+    fn test_insert_process_context() {
+        let mut info = HashMap::new();
+        DebuggerOptions::default().insert_process_context(&mut info);
+        assert!(info.is_empty());
 
-            Ok(debuggee_pid)
-        }
+        let options = DebuggerOptions {
+            debuggee_cwd: Some(PathBuf::from("/work")),
+            debuggee_env: Some(vec![
+                "HOME=/root".to_owned(),
+                "OPTS=a=\"b\"".to_owned(),
+                "EMPTY=".to_owned(),
+            ]),
+            ..Default::default()
+        };
+        options.insert_process_context(&mut info);
+        assert_eq!("/work", info[&AttachInformationKey::DebuggeeCwd]);
+        assert_eq!(
+            r#"{"HOME": "/root", "OPTS": "a=\"b\"", "EMPTY": ""}"#,
+            info[&AttachInformationKey::DebuggeeEnv]
+        );
     }
-}
 
-fn ignore_sigint() -> Result<()> {
-    unsafe {
-        signal::signal(signal::Signal::SIGINT, signal::SigHandler::SigIgn)?;
+    #[test]
+    fn test_build_node_commandline() {
+        let listen_address = ListenAddress {
+            host: "localhost".to_owned(),
+            port: 5679,
+        };
+        let inspect = "--inspect-brk=localhost:5679";
+        assert_eq!(
+            vec!["/usr/bin/node", inspect, "app.js"],
+            NodeDebugger::build_node_commandline("/usr/bin/node", &["app.js"], &listen_address)
+        );
+        assert_eq!(
+            vec!["node", inspect, "src/app.mjs", "arg0"],
+            NodeDebugger::build_node_commandline("src/app.mjs", &["arg0"], &listen_address)
+        );
+        assert!(is_node_debuggee("node"));
+        assert!(is_node_debuggee("lib/index.cjs"));
+        assert!(!is_node_debuggee("nodemon"));
     }
-    Ok(())
-}
 
-fn kill9_child_by_sigint(pid: Pid) -> Result<()> {
-    ctrlc::set_handler(move || {
-        let _ = signal::kill(pid, signal::Signal::SIGKILL);
-    })?;
-    Ok(())
-}
+    #[test]
+    fn test_build_rdbg_commandline() {
+        let listen_address = ListenAddress {
+            host: "localhost".to_owned(),
+            port: 5679,
+        };
+        assert_eq!(
+            vec![
+                "rdbg",
+                "--open",
+                "--host",
+                "localhost",
+                "--port",
+                "5679",
+                "--",
+                "app.rb",
+                "arg0"
+            ],
+            RubyDebugger::build_rdbg_commandline("app.rb", &["arg0"], &listen_address)
+        );
+        assert!(is_ruby_debuggee("lib/app.rb"));
+        assert!(!is_ruby_debuggee("ruby"));
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use indoc::indoc;
-    use std::os::unix::fs::PermissionsExt;
-    use tempfile::NamedTempFile;
+    #[test]
+    fn test_build_java_commandline() {
+        let listen_address = ListenAddress {
+            host: "localhost".to_owned(),
+            port: 5679,
+        };
+        let agent = "-agentlib:jdwp=transport=dt_socket,server=y,suspend=y,address=localhost:5679";
+        assert_eq!(
+            vec!["/usr/bin/java", agent, "-jar", "app.jar"],
+            JavaDebugger::build_java_commandline(
+                "/usr/bin/java",
+                &["-jar", "app.jar"],
+                &listen_address
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            vec!["java", agent, "-jar", "build/app.jar", "arg0"],
+            JavaDebugger::build_java_commandline("build/app.jar", &["arg0"], &listen_address)
+                .unwrap()
+        );
+        assert_eq!(
+            vec!["java", agent, "-cp", "out", "Main"],
+            JavaDebugger::build_java_commandline("out/Main.class", &[], &listen_address).unwrap()
+        );
+        assert_eq!(
+            vec!["java", agent, "-cp", ".", "Main"],
+            JavaDebugger::build_java_commandline("Main.class", &[], &listen_address).unwrap()
+        );
+        assert!(JavaDebugger::build_java_commandline("main.py", &[], &listen_address).is_err());
+    }
 
     #[test]
-    fn test_check_if_wrapperd_by_strings() {
-        let actually_wrapped = indoc! {r#"
-            #!/bin/sh
-            # a wrapper script generated by dbgee
-            some scripts
-        "#};
-        let tmpfile = make_temp_file(actually_wrapped);
-        assert!(check_if_wrapped(tmpfile.path()));
+    fn test_wrapping_hardlinked_debuggee() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let debuggee = tmpdir.path().join("debuggee");
+        let link = tmpdir.path().join("link");
+        fs::write(&debuggee, "dummy").unwrap();
+        fs::set_permissions(&debuggee, fs::Permissions::from_mode(0o750)).unwrap();
+        fs::hard_link(&debuggee, &link).unwrap();
+        let debuggee = debuggee.to_str().unwrap();
 
-        let not_wrapped = indoc! {r#"
-            #!/bin/sh
-            some scripts in the wild
-        "#};
-        let tmpfile = make_temp_file(not_wrapped);
-        assert!(!check_if_wrapped(tmpfile.path()));
+        assert!(wrap_debuggee_binary(
+            debuggee,
+            "dummy run -- debuggee",
+            &WrapperOptions::default()
+        )
+        .is_err());
+        assert!(!check_if_wrapped(debuggee));
+        assert_eq!(2, fs::metadata(debuggee).unwrap().nlink());
+
+        let wrapper_options = WrapperOptions {
+            allow_hardlinked: true,
+            ..Default::default()
+        };
+        wrap_debuggee_binary(debuggee, "dummy run -- debuggee", &wrapper_options).unwrap();
+        assert!(check_if_wrapped(debuggee));
+        assert_eq!(
+            0o750,
+            fs::metadata(debuggee).unwrap().permissions().mode() & 0o777
+        );
+        // the other link still refers to the original debuggee, which is now the backup
+        assert_eq!("dummy", fs::read_to_string(&link).unwrap());
+        let backup_path = get_path_of_unset_debuggee(debuggee).unwrap();
+        assert_eq!(2, fs::metadata(backup_path).unwrap().nlink());
+        // no temporary file is left behind
+        assert_eq!(3, fs::read_dir(tmpdir.path()).unwrap().count());
+
+        unwrap_debuggee_binary(debuggee).unwrap();
+        assert_eq!(2, fs::metadata(debuggee).unwrap().nlink());
     }
 
     #[test]
-    fn test_check_if_wrapperd_by_actually_wrapping() {
+    fn test_double_unwrapping() {
         let tmpfile = make_temp_executable_file("dummy");
         let tmpfile_path = tmpfile.path().to_str().unwrap();
-        wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee").unwrap();
-        assert!(check_if_wrapped(tmpfile.path()));
+        wrap_debuggee_binary(
+            tmpfile_path,
+            "dummy run -- debuggee",
+            &WrapperOptions::default(),
+        )
+        .unwrap();
         unwrap_debuggee_binary(tmpfile_path).unwrap();
-        assert!(!check_if_wrapped(tmpfile.path()));
+        assert!(unwrap_debuggee_binary(tmpfile_path).is_err());
     }
 
     #[test]
-    fn test_double_wrapping() {
+    fn test_wrapping_with_custom_backup_suffix() {
         let tmpfile = make_temp_executable_file("dummy");
         let tmpfile_path = tmpfile.path().to_str().unwrap();
-        wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee").unwrap();
-        assert!(wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee").is_err());
+        let backup_path = format!("{}.dbgee-bak", tmpfile_path);
+        let wrapper_options = WrapperOptions {
+            backup_suffix: ".dbgee-bak".to_owned(),
+            ..Default::default()
+        };
+        wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee", &wrapper_options).unwrap();
+        assert!(check_if_wrapped(tmpfile.path()));
+        assert_eq!(".dbgee-bak", read_backup_suffix(tmpfile.path()));
+        assert_eq!(
+            backup_path,
+            get_path_of_unset_debuggee(tmpfile_path).unwrap()
+        );
+        assert_eq!("dummy", fs::read_to_string(&backup_path).unwrap());
+
+        unwrap_debuggee_binary(tmpfile_path).unwrap();
+        assert!(!check_if_wrapped(tmpfile.path()));
+        assert!(!Path::new(&backup_path).exists());
+        assert_eq!("dummy", fs::read_to_string(tmpfile.path()).unwrap());
     }
 
     #[test]
-    fn test_double_unwrapping() {
+    fn test_invalid_backup_suffix() {
         let tmpfile = make_temp_executable_file("dummy");
         let tmpfile_path = tmpfile.path().to_str().unwrap();
-        wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee").unwrap();
-        unwrap_debuggee_binary(tmpfile_path).unwrap();
-        assert!(unwrap_debuggee_binary(tmpfile_path).is_err());
+        for backup_suffix in ["/../x", ""] {
+            let wrapper_options = WrapperOptions {
+                backup_suffix: backup_suffix.to_owned(),
+                ..Default::default()
+            };
+            assert!(
+                wrap_debuggee_binary(tmpfile_path, "dummy run -- debuggee", &wrapper_options)
+                    .is_err()
+            );
+        }
+        assert!(!check_if_wrapped(tmpfile.path()));
     }
 
     #[test]
@@ -876,7 +4620,7 @@ mod tests {
         let current_exe_pathbuf = std::env::current_exe().unwrap();
         let current_exe = current_exe_pathbuf.to_str().unwrap();
 
-        let command = vec![
+        let command = [
             current_exe,
             "set",
             debuggee,
@@ -888,8 +4632,98 @@ mod tests {
         ];
         let clap_matches = Opts::clap().get_matches_from(command.iter());
 
-        let constructed_run_command: Vec<String> = build_run_command(&clap_matches)
-            .unwrap()
+        let constructed_run_command: Vec<String> =
+            build_run_command(&clap_matches, debuggee, &WrapperOptions::default(), &[])
+                .unwrap()
+                .split(' ')
+                .map(|s| strip_quote(s).to_owned())
+                .filter(|s| !s.is_empty())
+                .collect();
+        let constructed_clap_matches =
+            Opts::clap().get_matches_from(constructed_run_command.iter());
+
+        let expected = [current_exe, "run", "-t", "tmuxw", "--", debuggee];
+        let expected_clap_matches = Opts::clap().get_matches_from(expected.iter());
+
+        assert!(compare_argmatches(
+            &expected_clap_matches,
+            &constructed_clap_matches
+        ));
+    }
+
+    #[test]
+    fn test_build_run_command_with_debuggee_args() {
+        let debuggee_file = make_temp_executable_file("dummy");
+        let debuggee = debuggee_file.as_ref().to_str().unwrap();
+        let current_exe_pathbuf = std::env::current_exe().unwrap();
+        let current_exe = current_exe_pathbuf.to_str().unwrap();
+
+        let command = [
+            current_exe,
+            "set",
+            debuggee,
+            "--debuggee-arg",
+            "--config",
+            "--debuggee-arg",
+            "it's fixed",
+        ];
+        let clap_matches = Opts::clap().get_matches_from(command.iter());
+        let wrapper_options = WrapperOptions {
+            debuggee_args: vec!["--config".to_owned(), "it's fixed".to_owned()],
+            ..Default::default()
+        };
+        let run_command =
+            build_run_command(&clap_matches, debuggee, &wrapper_options, &[]).unwrap();
+        assert!(!run_command.contains("run --debuggee-arg"));
+        assert!(run_command.ends_with(&format!(
+            " -- {}-original '--config' 'it'\"'\"'s fixed' \"$@\"",
+            debuggee
+        )));
+
+        // The shell gives the baked arguments to the debuggee as they are, followed by the ones of the launch
+        let output = Command::new("sh")
+            .args([
+                "-c",
+                &format!(
+                    "printf '%s\\n' {} \"$@\"",
+                    build_quoted_debuggee_args(&wrapper_options)
+                ),
+                "sh",
+                "launch arg",
+            ])
+            .output()
+            .unwrap();
+        assert_eq!(
+            "--config\nit's fixed\nlaunch arg\n",
+            String::from_utf8(output.stdout).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_build_run_command_with_resolved_args() {
+        let debuggee_file = make_temp_executable_file("dummy");
+        let debuggee = debuggee_file.as_ref().to_str().unwrap();
+        let gdb_file = make_temp_executable_file("dummy");
+        let gdb = gdb_file.as_ref().to_str().unwrap();
+        let current_exe_pathbuf = std::env::current_exe().unwrap();
+        let current_exe = current_exe_pathbuf.to_str().unwrap();
+
+        let command = [current_exe, "set", debuggee, "-d", "gdb"];
+        let clap_matches = Opts::clap().get_matches_from(command.iter());
+
+        // `--debugger` is given by the user, so only `--debugger-path` is added
+        let resolved_run_args = [
+            ("debugger", "gdb".to_owned()),
+            ("debugger-path", gdb.to_owned()),
+        ];
+        let run_command = build_run_command(
+            &clap_matches,
+            debuggee,
+            &WrapperOptions::default(),
+            &resolved_run_args,
+        )
+        .unwrap();
+        let constructed_run_command: Vec<String> = run_command
             .split(' ')
             .map(|s| strip_quote(s).to_owned())
             .filter(|s| !s.is_empty())
@@ -897,7 +4731,62 @@ mod tests {
         let constructed_clap_matches =
             Opts::clap().get_matches_from(constructed_run_command.iter());
 
-        let expected = vec![current_exe, "run", "-t", "tmuxw", "--", debuggee];
+        let expected = [
+            current_exe,
+            "run",
+            "-d",
+            "gdb",
+            "--debugger-path",
+            gdb,
+            "--",
+            debuggee,
+        ];
+        let expected_clap_matches = Opts::clap().get_matches_from(expected.iter());
+
+        assert!(compare_argmatches(
+            &expected_clap_matches,
+            &constructed_clap_matches
+        ));
+    }
+
+    #[test]
+    fn test_build_run_command_with_debugger_args() {
+        let debuggee_file = make_temp_executable_file("dummy");
+        let debuggee = debuggee_file.as_ref().to_str().unwrap();
+        let current_exe_pathbuf = std::env::current_exe().unwrap();
+        let current_exe = current_exe_pathbuf.to_str().unwrap();
+
+        let command = [
+            current_exe,
+            "set",
+            debuggee,
+            "--debugger-arg",
+            "-ex",
+            "--debugger-arg",
+            "bt",
+        ];
+        let clap_matches = Opts::clap().get_matches_from(command.iter());
+
+        let constructed_run_command: Vec<String> =
+            build_run_command(&clap_matches, debuggee, &WrapperOptions::default(), &[])
+                .unwrap()
+                .split(' ')
+                .map(|s| strip_quote(s).to_owned())
+                .filter(|s| !s.is_empty())
+                .collect();
+        let constructed_clap_matches =
+            Opts::clap().get_matches_from(constructed_run_command.iter());
+
+        let expected = [
+            current_exe,
+            "run",
+            "--debugger-arg",
+            "-ex",
+            "--debugger-arg",
+            "bt",
+            "--",
+            debuggee,
+        ];
         let expected_clap_matches = Opts::clap().get_matches_from(expected.iter());
 
         assert!(compare_argmatches(
@@ -906,6 +4795,135 @@ mod tests {
         ));
     }
 
+    type Commandlines = Rc<RefCell<Vec<Vec<String>>>>;
+
+    /// `ProcessLauncher` which records the command lines instead of launching them
+    struct FakeLauncher {
+        commandlines: Commandlines,
+    }
+
+    impl FakeLauncher {
+        const PID: i32 = 4242;
+
+        fn new() -> (Box<FakeLauncher>, Commandlines) {
+            let commandlines = Rc::new(RefCell::new(vec![]));
+            let launcher = FakeLauncher {
+                commandlines: commandlines.clone(),
+            };
+            (Box::new(launcher), commandlines)
+        }
+
+        fn record(&self, commandline: &[&str]) -> Result<Pid> {
+            self.commandlines
+                .borrow_mut()
+                .push(commandline.iter().map(|arg| (*arg).to_owned()).collect());
+            Ok(Pid::from_raw(Self::PID))
+        }
+    }
+
+    impl ProcessLauncher for FakeLauncher {
+        fn run_and_stop(
+            &self,
+            commandline: &[&str],
+            _stop_method: StopMethod,
+            _options: &DebuggerOptions,
+        ) -> Result<Pid> {
+            self.record(commandline)
+        }
+
+        fn launch_server(
+            &self,
+            commandline: &[&str],
+            _listen_port: u16,
+            _options: &DebuggerOptions,
+        ) -> Result<Pid> {
+            self.record(commandline)
+        }
+    }
+
+    /// `DebuggerTerminal` which records the command line to attach the debugger
+    #[derive(Default)]
+    struct FakeTerminal {
+        attach_commandline: Vec<String>,
+    }
+
+    impl DebuggerTerminal for FakeTerminal {
+        fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
+            self.attach_commandline = debugger.build_attach_commandline()?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_gdb_run_by_fake_launcher() {
+        let debuggee_file = make_temp_executable_file("dummy");
+        let debuggee = get_abspath(debuggee_file.path(), "debuggee").unwrap();
+        let options = DebuggerOptions {
+            debugger_path: Some("/opt/gdb".to_owned()),
+            debugger_args: vec!["-ex".to_owned(), "bt".to_owned()],
+            ..Default::default()
+        };
+        let (launcher, commandlines) = FakeLauncher::new();
+        let mut debugger = GdbDebugger::build(&options, launcher).unwrap();
+        let mut terminal = FakeTerminal::default();
+
+        let pid = debugger
+            .run(&debuggee, vec!["arg0", "arg1"], &mut terminal)
+            .unwrap();
+
+        assert_eq!(Pid::from_raw(FakeLauncher::PID), pid);
+        assert_eq!(
+            vec![vec![debuggee.clone(), "arg0".to_owned(), "arg1".to_owned()]],
+            *commandlines.borrow()
+        );
+        assert_eq!(
+            vec!["/opt/gdb", "-tui", "-p", "4242", "-ex", "bt"],
+            terminal.attach_commandline
+        );
+    }
+
+    #[test]
+    fn test_dlv_run_by_fake_launcher() {
+        let debuggee_file = make_temp_executable_file("dummy");
+        let debuggee = get_abspath(debuggee_file.path(), "debuggee").unwrap();
+        let options = DebuggerOptions {
+            dlv_path: Some("/opt/dlv".to_owned()),
+            sudo: true,
+            listen_port: Some(2345),
+            ..Default::default()
+        };
+        let (launcher, commandlines) = FakeLauncher::new();
+        let mut debugger = DelveDebugger::new(&options, launcher).unwrap();
+        let mut terminal = FakeTerminal::default();
+
+        debugger
+            .run(&debuggee, vec!["arg0"], &mut terminal)
+            .unwrap();
+
+        let listen_address = ListenAddress::resolve(Some(2345)).unwrap().to_string();
+        assert_eq!(
+            vec![vec![
+                "sudo",
+                "/opt/dlv",
+                "exec",
+                "--headless",
+                "--log-dest",
+                "/dev/null",
+                "--api-version=2",
+                "--listen",
+                &listen_address,
+                &debuggee,
+                "--",
+                "arg0"
+            ]],
+            *commandlines.borrow()
+        );
+        assert_eq!(
+            vec!["/opt/dlv", "connect", &listen_address],
+            terminal.attach_commandline
+        );
+    }
+
     fn strip_quote(s: &str) -> &str {
         if s.starts_with('\'') {
             &s[1..s.len() - 1]
@@ -1,13 +1,25 @@
 use crate::debugger::{AttachInformationKey, Debugger};
+use crate::file_helper::{find_command_path, quote_shell_arg, LoggedCommand};
 
 use anyhow::{anyhow, bail, Context, Result};
-use nix::unistd;
-use once_cell::sync::OnceCell;
+use nix::{fcntl, sys::signal, unistd};
+use once_cell::sync::{Lazy, OnceCell};
 use std::{
-    fs::File,
-    io::Write,
+    collections::HashMap,
+    ffi::CString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::{
+        fs::{FileTypeExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
     path::{Path, PathBuf},
     process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 /// Prefix of the fifo path to communicate with VScode.
@@ -15,14 +27,29 @@ const VSCODE_COMMUNICATION_FIFO_PATH_PREFIX: &str = "/tmp/dbgee-vscode";
 /// Override the prefix of the fifo path to communicate with VScode.
 /// Used to override the path for test.
 static VSCODE_COMMUNICATION_FIFO_PATH_PREFIX_OVERRIDE: OnceCell<String> = OnceCell::<String>::new();
+/// Format of the JSON sent to VSCode. Compact without a trailing newline unless it is set.
+static VSCODE_JSON_FORMAT: OnceCell<JsonFormat> = OnceCell::new();
+/// Environment variable to override how long dbgee waits for VSCode to open the FIFOs, in seconds
+const VSCODE_FIFO_TIMEOUT_ENV: &str = "DBGEE_VSCODE_TIMEOUT";
+const DEFAULT_VSCODE_FIFO_TIMEOUT: Duration = Duration::from_secs(300);
+const FIFO_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// FIFOs which dbgee created, to remove them when dbgee exits. CString so that the signal handler can unlink them.
+static CREATED_FIFOS: Lazy<Mutex<Vec<CString>>> = Lazy::new(|| Mutex::new(vec![]));
+/// Whether to remove the created FIFOs when dbgee exits. Disabled by `--no-fifo-cleanup`.
+static FIFO_CLEANUP: AtomicBool = AtomicBool::new(true);
 
 pub trait DebuggerTerminal {
-    fn name(&self) -> &str;
     fn open(&mut self, debugger: &dyn Debugger) -> Result<()>;
 }
 
 pub struct Tmux {
     layout: TmuxLayout,
+    /// File which the debugger reads from stdin
+    debugger_script: Option<PathBuf>,
+    /// Session where the debugger is opened, which is created if it doesn't exist
+    session: Option<String>,
+    /// Name of the window which the debugger is opened in, instead of the name of the running command
+    window_name: Option<String>,
 }
 
 pub enum TmuxLayout {
@@ -31,8 +58,86 @@ pub enum TmuxLayout {
 }
 
 impl Tmux {
-    pub fn new(layout: TmuxLayout) -> Tmux {
-        Tmux { layout }
+    pub fn new(
+        layout: TmuxLayout,
+        debugger_script: Option<PathBuf>,
+        session: Option<String>,
+        window_name: Option<String>,
+    ) -> Tmux {
+        Tmux {
+            layout,
+            debugger_script,
+            session,
+            window_name,
+        }
+    }
+
+    /// Returns the arguments of `new-window` and `new-session` to name the window
+    fn build_window_name_args(&self) -> Vec<&str> {
+        match self.window_name {
+            Some(ref name) => vec!["-n", name],
+            None => vec![],
+        }
+    }
+
+    /// Returns the arguments to open the debugger in a new window or pane, without the debugger command
+    fn build_layout_args(&self) -> Vec<&str> {
+        let mut args = self.layout.to_command();
+        if let TmuxLayout::NewWindow = self.layout {
+            args.extend(self.build_window_name_args());
+        }
+        args
+    }
+
+    /// Opens the debugger in `session`, creating the session in the background if it doesn't exist
+    fn open_in_session(
+        &self,
+        tmux_command: &[&str],
+        session: &str,
+        debugger_cmd: &[String],
+    ) -> Result<()> {
+        // '=' lets tmux match the session name exactly, instead of by prefix
+        let target = format!("={}", session);
+        let window_target = format!("{}:", target);
+        let session_exists = Command::new(tmux_command[0])
+            .args(&tmux_command[1..])
+            .args(["has-session", "-t", &target])
+            .logged()
+            .stderr(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .status()
+            .with_context(|| "Failed to launch tmux. Is tmux installed?")?;
+
+        let (mut args, action) = if session_exists.success() {
+            let mut args = self.build_layout_args();
+            args.extend(["-t", &window_target]);
+            (args, "window")
+        } else {
+            let mut args = vec!["new-session", "-d", "-s", session];
+            args.extend(self.build_window_name_args());
+            (args, "session")
+        };
+        args.extend(debugger_cmd.iter().map(|s| s.as_str()));
+        let status = Command::new(tmux_command[0])
+            .args(&tmux_command[1..])
+            .args(&args)
+            .logged()
+            .status()
+            .with_context(|| {
+                format!(
+                    "Failed to open a new tmux {} for an unexpected reason.",
+                    action
+                )
+            })?;
+        if !status.success() {
+            bail!("tmux failed to open the debugger in session '{}'", session);
+        }
+        log::info!(
+            "the debugger has launched in tmux session '{}'. Try `tmux a -t {}` to attach.",
+            session,
+            session
+        );
+        Ok(())
     }
 }
 
@@ -46,10 +151,6 @@ impl TmuxLayout {
 }
 
 impl DebuggerTerminal for Tmux {
-    fn name(&self) -> &str {
-        "tmux"
-    }
-
     fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
         let sudo_user = std::env::var("SUDO_USER");
         let tmux_command = match sudo_user {
@@ -63,38 +164,55 @@ impl DebuggerTerminal for Tmux {
             _ => vec!["tmux"],
         };
 
-        let is_tmux_active = Command::new(&tmux_command[0])
+        if let Some(session) = &self.session {
+            let debugger_cmd = build_commandline_reading_script(
+                debugger.build_attach_commandline()?,
+                &self.debugger_script,
+            );
+            return self.open_in_session(&tmux_command, session, &debugger_cmd);
+        }
+
+        let is_tmux_active = Command::new(tmux_command[0])
             .args(
                 tmux_command[1..tmux_command.len()]
                     .iter()
                     .chain(["ls"].iter()),
             )
+            .logged()
             .stderr(std::process::Stdio::null())
             .stdout(std::process::Stdio::null())
             .status()
             .with_context(|| "Failed to launch tmux. Is tmux installed?")?;
 
-        let debugger_cmd = debugger.build_attach_commandline()?;
+        // tmux runs a command of multiple arguments without a shell since tmux 3.0,
+        // which keeps each argument such as `--debugger-arg 'break main'` as it is
+        let debugger_cmd = build_commandline_reading_script(
+            debugger.build_attach_commandline()?,
+            &self.debugger_script,
+        );
         if is_tmux_active.success() {
-            let mut args = self.layout.to_command();
+            let mut args = self.build_layout_args();
             args.extend(debugger_cmd.iter().map(|s| s.as_str()));
-            Command::new(&tmux_command[0])
+            Command::new(tmux_command[0])
                 .args(
                     tmux_command[1..tmux_command.len()]
                         .iter()
                         .chain(args.iter()),
                 )
+                .logged()
                 .status()
                 .with_context(|| "Failed to open a new tmux window for an unexpected reason.")?;
         } else {
             let mut args = vec!["new-session"];
+            args.extend(self.build_window_name_args());
             args.extend(debugger_cmd.iter().map(|s| s.as_str()));
-            Command::new(&tmux_command[0])
+            Command::new(tmux_command[0])
                 .args(
                     tmux_command[1..tmux_command.len()]
                         .iter()
                         .chain(args.iter()),
                 )
+                .logged()
                 .spawn()
                 .with_context(|| "Failed to open a new tmux session for an unexpected reason.")?;
             log::info!("the debugger has launched in a new tmux session. Try `tmux a` to attach.",);
@@ -104,6 +222,16 @@ impl DebuggerTerminal for Tmux {
     }
 }
 
+/// Debug adapter which VSCode uses to attach to native debuggees
+pub enum VsCodeAdapter {
+    /// Prefer CodeLLDB
+    Auto,
+    /// CodeLLDB (`lldb`)
+    CodeLldb,
+    /// Microsoft's C/C++ extension (`cppdbg`)
+    Cpptools,
+}
+
 /// struct for VSCode that implements `DebuggerTerminal` trait.
 pub struct VsCode {
     /// Path to a FIFO which a VSCode instance will connect if the user manually starts a debug session with dbgee
@@ -113,35 +241,96 @@ pub struct VsCode {
     attach_request_fifo_path: Option<String>,
     /// Version of the message protocol in a semantic version format
     protocol_version: &'static str,
+    /// Debug adapter to attach to native debuggees
+    adapter: VsCodeAdapter,
+    /// How long to wait for VSCode to open the FIFOs
+    fifo_timeout: Duration,
+    /// Format of the JSON sent to the FIFOs
+    json_format: JsonFormat,
+}
+
+/// Format of the JSON which dbgee sends to VSCode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JsonFormat {
+    /// Break lines and indent the fields
+    pub pretty: bool,
+    /// End the JSON with a newline, e.g. for readers of line-delimited JSON
+    pub trailing_newline: bool,
+}
+
+impl JsonFormat {
+    /// Serializes `fields` into a JSON object. The values of `fields` must be JSON values.
+    fn serialize(&self, fields: &[(String, String)]) -> String {
+        let fields = fields
+            .iter()
+            .map(|(key, val)| format!(r#""{}": {}"#, escape_json_string(key), val));
+        let mut json = if self.pretty {
+            format!(
+                "{{\n{}\n}}",
+                fields
+                    .map(|field| format!("  {}", field))
+                    .collect::<Vec<String>>()
+                    .join(",\n")
+            )
+        } else {
+            format!("{{{}}}", fields.collect::<Vec<String>>().join(", "))
+        };
+        if self.trailing_newline {
+            json.push('\n');
+        }
+        json
+    }
 }
 
 impl VsCode {
-    pub fn new() -> VsCode {
+    pub fn new(adapter: VsCodeAdapter) -> VsCode {
         VsCode {
             attach_information_fifo_path: build_attach_information_fifo_path(),
             attach_request_fifo_path: build_attach_request_fifo_path(),
-            protocol_version: "1.1.0",
+            protocol_version: "1.4.0",
+            adapter,
+            fifo_timeout: resolve_fifo_timeout(|name| std::env::var(name).ok()),
+            json_format: VSCODE_JSON_FORMAT.get().copied().unwrap_or_default(),
         }
     }
 
+    /// Sends `json` to the FIFO in the background. `prepared_fifo` is given if dbgee prepared the FIFO.
+    /// The FIFO which dbgee created is removed if VSCode doesn't read it in time.
     fn send_json_to_vscode(
         &self,
         json: String,
         fifo_path: String,
+        prepared_fifo: Option<PreparedFifo>,
         log_after_sent: Option<String>,
     ) -> Result<()> {
         log::debug!("sending json to vscode");
         log::trace!("json: {}", json);
-        match unistd::mkfifo(fifo_path.as_str(), nix::sys::stat::Mode::S_IRWXU) {
-            Err(nix::Error::Sys(nix::errno::Errno::EEXIST)) => Ok(()),
-            other => other,
-        }?;
+        let timeout = self.fifo_timeout;
+        let (fifo_created, opened_fifo) = match prepared_fifo {
+            Some(PreparedFifo::Created) => (true, None),
+            Some(PreparedFifo::Reading(fifo)) => (false, Some(fifo)),
+            None => (false, None),
+        };
         std::thread::spawn(move || {
-            if let Ok(mut fifo) = File::create(fifo_path) {
-                let _ = fifo.write_all(json.as_bytes());
-                if let Some(log) = log_after_sent {
-                    log::info!("{}", log);
+            match write_to_fifo_with_timeout(&fifo_path, opened_fifo, json.as_bytes(), timeout) {
+                Ok(true) => {
+                    if let Some(log) = log_after_sent {
+                        log::info!("{}", log);
+                    }
+                }
+                Ok(false) => {
+                    log::info!(
+                        "No VSCode connected to {} in {} seconds. Gave up sending the attach information. \
+                        Change the timeout by ${}.",
+                        fifo_path,
+                        timeout.as_secs(),
+                        VSCODE_FIFO_TIMEOUT_ENV
+                    );
+                    if fifo_created {
+                        let _ = std::fs::remove_file(&fifo_path);
+                    }
                 }
+                Err(e) => log::debug!("Failed to write to {}. {:?}", fifo_path, e),
             }
         });
 
@@ -152,32 +341,38 @@ impl VsCode {
         let attach_information_keys = [
             AttachInformationKey::Pid,
             AttachInformationKey::ProgramName,
+            AttachInformationKey::DebuggeeName,
             AttachInformationKey::DebuggerPort,
+            AttachInformationKey::DebuggerHost,
+            AttachInformationKey::SourceMap,
+            AttachInformationKey::SymbolSearchPath,
+            AttachInformationKey::DebuggeeCwd,
+            AttachInformationKey::DebuggeeEnv,
+            AttachInformationKey::CorePath,
         ];
-        let json = format!(
-            "{{{}}}",
-            debugger
-                .build_attach_information()?
-                .into_iter()
-                .filter_map(move |(key, val)| {
-                    if attach_information_keys.contains(&key) {
-                        Some(format!("\"{}\": \"{}\"", key, val))
-                    } else {
-                        None
-                    }
-                })
-                .chain(
-                    std::iter::once(format!(r#""protocolVersion": "{}""#, self.protocol_version))
-                        .into_iter()
-                )
-                .collect::<Vec<String>>()
-                .join(", ")
-        );
+        let fields: Vec<(String, String)> = debugger
+            .build_attach_information()?
+            .into_iter()
+            .filter_map(move |(key, val)| {
+                if attach_information_keys.contains(&key) {
+                    Some((key.to_string(), format_json_value(&key, &val)))
+                } else {
+                    None
+                }
+            })
+            .chain(std::iter::once((
+                "protocolVersion".to_owned(),
+                format!(r#""{}""#, self.protocol_version),
+            )))
+            .collect();
+        let json = self.json_format.serialize(&fields);
 
         let fifo_path = self.attach_information_fifo_path.clone();
+        let prepared_fifo = prepare_fifo(&fifo_path)?;
         self.send_json_to_vscode(
             json,
             fifo_path,
+            Some(prepared_fifo),
             Some("VSCode has attached to the debuggee".to_owned()),
         )
     }
@@ -199,21 +394,258 @@ impl VsCode {
         let debugger_type_hint = attach_request
             .get(&AttachInformationKey::DebuggerTypeHint)
             .ok_or_else(|| anyhow!("[BUG] debugger has no DebuggerTypeHint"))?;
-        let debugger_type = match debugger_type_hint.as_str() {
-            "gdb" => "lldb", // use CodeLLDB to attach to gdb
-            other => other,
+        let mut fields = vec![];
+        let debugger_type = match (debugger_type_hint.as_str(), &self.adapter) {
+            ("gdb", VsCodeAdapter::Cpptools) | ("lldb", VsCodeAdapter::Cpptools) => {
+                if let Some(program) = attach_request.get(&AttachInformationKey::ProgramName) {
+                    fields.push(("program", program.clone()));
+                }
+                if debugger_type_hint == "gdb" {
+                    if let Some(gdb_path) = find_command_path("gdb") {
+                        fields.push(("miDebuggerPath", gdb_path));
+                    }
+                }
+                "cppdbg"
+            }
+            ("gdb", _) => "lldb", // use CodeLLDB to attach to gdb
+            (other, _) => other,
         };
-        let json = format!(
-            r#"{{"protocolVersion": "{}", "debuggerType": "{}"}}"#,
-            self.protocol_version, debugger_type
+        let mut json_fields = vec![
+            (
+                "protocolVersion".to_owned(),
+                format!(r#""{}""#, self.protocol_version),
+            ),
+            ("debuggerType".to_owned(), format!(r#""{}""#, debugger_type)),
+        ];
+        json_fields.extend(
+            fields
+                .into_iter()
+                .map(|(key, val)| (key.to_owned(), format!(r#""{}""#, escape_json_string(&val)))),
         );
+        // The extension names the debug session by DebuggeeName, and translates the others
+        // into the fields of each debug adapter
+        for key in [
+            AttachInformationKey::DebuggeeName,
+            AttachInformationKey::SourceMap,
+            AttachInformationKey::SymbolSearchPath,
+            AttachInformationKey::DebuggeeCwd,
+            AttachInformationKey::DebuggeeEnv,
+            AttachInformationKey::CorePath,
+        ] {
+            if let Some(val) = attach_request.get(&key) {
+                json_fields.push((key.to_string(), format_json_value(&key, val)));
+            }
+        }
+        let json = self.json_format.serialize(&json_fields);
         log::debug!("json: {}", json);
 
         log::info!("Requesting VSCode to attach. You can also manually attach by starting debug with \"Dbgee:\" launch configs.");
-        self.send_json_to_vscode(json, fifo_path, None)
+        // The extension creates and removes this FIFO by itself
+        self.send_json_to_vscode(json, fifo_path, None, None)
+    }
+}
+
+/// FIFO which `prepare_fifo` prepared
+#[derive(Debug)]
+enum PreparedFifo {
+    /// dbgee created the FIFO
+    Created,
+    /// The existing FIFO is being read. The file is the FIFO opened for writing.
+    Reading(File),
+}
+
+/// Creates the FIFO at `fifo_path` unless a FIFO which VSCode is reading exists there.
+///
+/// A leftover FIFO without a reader, or a leftover file which is not a FIFO, is recreated.
+fn prepare_fifo(fifo_path: &str) -> Result<PreparedFifo> {
+    let mode = nix::sys::stat::Mode::S_IRWXU;
+    match unistd::mkfifo(fifo_path, mode) {
+        Ok(()) => (),
+        Err(nix::Error::Sys(nix::errno::Errno::EEXIST)) => {
+            if let Some(fifo) = open_fifo_being_read(fifo_path) {
+                return Ok(PreparedFifo::Reading(fifo));
+            }
+            log::debug!("recreating {}, which nobody is reading", fifo_path);
+            fs::remove_file(fifo_path)
+                .with_context(|| format!("Failed to remove the leftover {}", fifo_path))?;
+            unistd::mkfifo(fifo_path, mode)
+                .with_context(|| format!("Failed to create {}", fifo_path))?;
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {}", fifo_path)),
+    }
+    track_created_fifo(fifo_path);
+    Ok(PreparedFifo::Created)
+}
+
+/// Opens `fifo_path` for writing if it is a FIFO which any process has opened for reading.
+/// The opened FIFO must be written to, because closing it without writing gives the reader EOF.
+fn open_fifo_being_read(fifo_path: &str) -> Option<File> {
+    match fs::metadata(fifo_path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => (),
+        _ => return None,
+    }
+    // Opening a FIFO for writing without blocking fails with ENXIO while there's no reader
+    OpenOptions::new()
+        .write(true)
+        .custom_flags(nix::libc::O_NONBLOCK)
+        .open(fifo_path)
+        .ok()
+}
+
+fn track_created_fifo(fifo_path: &str) {
+    if !FIFO_CLEANUP.load(Ordering::SeqCst) {
+        return;
+    }
+    let fifo_path = match CString::new(fifo_path) {
+        Ok(fifo_path) => fifo_path,
+        Err(_) => return,
+    };
+    let mut fifos = CREATED_FIFOS.lock().unwrap_or_else(|e| e.into_inner());
+    if fifos.contains(&fifo_path) {
+        return;
+    }
+    if fifos.is_empty() {
+        if let Err(e) = install_fifo_cleanup_handler() {
+            log::debug!(
+                "Failed to set the signal handlers to remove the FIFOs: {}",
+                e
+            );
+        }
+    }
+    fifos.push(fifo_path);
+}
+
+/// Removes the FIFOs on SIGTERM and SIGHUP, and dies by the signal as it would without the handler.
+/// SIGINT is left to the debuggers, which dbgee lets take Ctrl+C.
+fn install_fifo_cleanup_handler() -> nix::Result<()> {
+    extern "C" fn remove_fifos_and_reraise(signum: nix::libc::c_int) {
+        // Best-effort. Don't wait for the lock, which the interrupted thread may hold
+        if let Ok(fifos) = CREATED_FIFOS.try_lock() {
+            for fifo in fifos.iter() {
+                unsafe { nix::libc::unlink(fifo.as_ptr()) };
+            }
+        }
+        unsafe {
+            nix::libc::signal(signum, nix::libc::SIG_DFL);
+            nix::libc::raise(signum);
+        }
+    }
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(remove_fifos_and_reraise),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+    for sig in [signal::SIGTERM, signal::SIGHUP] {
+        unsafe { signal::sigaction(sig, &action) }?;
+    }
+    Ok(())
+}
+
+/// Removes the FIFOs which dbgee created. Call it before dbgee exits.
+pub fn remove_created_fifos() {
+    let fifos = CREATED_FIFOS.lock().unwrap_or_else(|e| e.into_inner());
+    for fifo in fifos.iter() {
+        let fifo = fifo.to_string_lossy();
+        match fs::remove_file(fifo.as_ref()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                log::debug!("Failed to remove {}: {}", fifo, e)
+            }
+            _ => log::debug!("removed {}", fifo),
+        }
     }
 }
 
+/// Enables or disables removing the FIFOs which dbgee created when it exits
+pub fn set_fifo_cleanup(enabled: bool) {
+    FIFO_CLEANUP.store(enabled, Ordering::SeqCst);
+}
+
+/// Resolves the timeout of waiting for VSCode to open the FIFOs, which `$DBGEE_VSCODE_TIMEOUT` overrides in seconds
+fn resolve_fifo_timeout(get_env: impl Fn(&str) -> Option<String>) -> Duration {
+    get_env(VSCODE_FIFO_TIMEOUT_ENV)
+        .and_then(|timeout| match timeout.parse::<u64>() {
+            Ok(secs) => Some(Duration::from_secs(secs)),
+            Err(_) => {
+                log::warn!(
+                    "${} is not a valid number of seconds: '{}'. Using the default.",
+                    VSCODE_FIFO_TIMEOUT_ENV,
+                    timeout
+                );
+                None
+            }
+        })
+        .unwrap_or(DEFAULT_VSCODE_FIFO_TIMEOUT)
+}
+
+/// Writes `content` to the FIFO once a reader opens it. Returns false if no reader opens it within `timeout`.
+fn write_to_fifo_with_timeout(
+    fifo_path: &str,
+    opened_fifo: Option<File>,
+    content: &[u8],
+    timeout: Duration,
+) -> Result<bool> {
+    let started_at = Instant::now();
+    let mut fifo = match opened_fifo {
+        Some(fifo) => fifo,
+        None => loop {
+            // Opening a FIFO for writing without blocking fails with ENXIO while there's no reader
+            match OpenOptions::new()
+                .write(true)
+                .custom_flags(nix::libc::O_NONBLOCK)
+                .open(fifo_path)
+            {
+                Ok(fifo) => break fifo,
+                Err(e) if e.raw_os_error() == Some(nix::libc::ENXIO) => {
+                    if started_at.elapsed() >= timeout {
+                        return Ok(false);
+                    }
+                    std::thread::sleep(FIFO_POLL_INTERVAL);
+                }
+                // Another dbgee which shared the FIFO removed it on exit
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    if let PreparedFifo::Reading(fifo) = prepare_fifo(fifo_path)? {
+                        break fifo;
+                    }
+                }
+                Err(e) => return Err(e).with_context(|| format!("Failed to open {}", fifo_path)),
+            }
+        },
+    };
+    fcntl::fcntl(
+        fifo.as_raw_fd(),
+        fcntl::FcntlArg::F_SETFL(fcntl::OFlag::empty()),
+    )
+    .context("Failed to make the fifo blocking")?;
+    fifo.write_all(content)
+        .with_context(|| format!("Failed to write to {}", fifo_path))?;
+    Ok(true)
+}
+
+/// Formats a value of the attach information as a JSON value. SourceMap and DebuggeeEnv are already JSON objects.
+fn format_json_value(key: &AttachInformationKey, val: &str) -> String {
+    match key {
+        AttachInformationKey::SourceMap | AttachInformationKey::DebuggeeEnv => val.to_owned(),
+        _ => format!(r#""{}""#, escape_json_string(val)),
+    }
+}
+
+/// Escapes `s` to put it in a JSON string. JSON doesn't allow raw control characters in strings.
+pub(crate) fn escape_json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\u{0}'..='\u{1f}' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 fn build_attach_information_fifo_path() -> String {
     format!("{}-debuggees", vscode_communication_path_prefix())
 }
@@ -232,7 +664,7 @@ fn build_attach_request_fifo_path() -> Option<String> {
         return None;
     }
     let mut path = format!("{}-debuggee-for-", vscode_communication_path_prefix());
-    path.extend(sock_name[0..sock_name.len() - 5].chars().into_iter());
+    path.push_str(&sock_name[0..sock_name.len() - 5]);
     Some(path)
 }
 
@@ -244,11 +676,119 @@ fn vscode_communication_path_prefix() -> &'static str {
     prefix
 }
 
-impl DebuggerTerminal for VsCode {
-    fn name(&self) -> &str {
-        "vscode"
+/// Opens the terminal mapped from the name of the debugger, or the default terminal for the other debuggers
+pub struct PerDebuggerTerminal {
+    default: Box<dyn DebuggerTerminal>,
+    terminals: HashMap<String, Box<dyn DebuggerTerminal>>,
+}
+
+impl PerDebuggerTerminal {
+    pub fn new(
+        default: Box<dyn DebuggerTerminal>,
+        terminals: HashMap<String, Box<dyn DebuggerTerminal>>,
+    ) -> PerDebuggerTerminal {
+        PerDebuggerTerminal { default, terminals }
+    }
+}
+
+impl DebuggerTerminal for PerDebuggerTerminal {
+    fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
+        match self.terminals.get_mut(debugger.name()) {
+            Some(terminal) => terminal.open(debugger),
+            None => self.default.open(debugger),
+        }
+    }
+}
+
+/// Builds the command line which runs `commandline` with `debugger_script` as its stdin, if it's given.
+/// tmux runs the command without a shell, so that a shell does the redirection.
+fn build_commandline_reading_script(
+    commandline: Vec<String>,
+    debugger_script: &Option<PathBuf>,
+) -> Vec<String> {
+    let script = match debugger_script {
+        Some(script) => script,
+        None => return commandline,
+    };
+    vec![
+        "sh".to_owned(),
+        "-c".to_owned(),
+        format!(
+            "exec \"$0\" \"$@\" < {}",
+            quote_shell_arg(&script.to_string_lossy())
+        ),
+    ]
+    .into_iter()
+    .chain(commandline)
+    .collect()
+}
+
+/// Opens nothing, but prints the command line to attach the debugger to stdout on one line,
+/// so that the user can paste it into another terminal
+pub struct PrintConnect {
+    /// File which the debugger reads from stdin, which the printed command line redirects
+    debugger_script: Option<PathBuf>,
+}
+
+impl PrintConnect {
+    pub fn new(debugger_script: Option<PathBuf>) -> PrintConnect {
+        PrintConnect { debugger_script }
+    }
+}
+
+impl DebuggerTerminal for PrintConnect {
+    fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
+        let commandline = debugger
+            .build_attach_commandline()
+            .context("The debugger has no command line to attach. Use `-t vscode` instead")?;
+        let redirection = match self.debugger_script {
+            Some(ref script) => format!(" < {}", quote_shell_arg(&script.to_string_lossy())),
+            None => String::new(),
+        };
+        let mut stdout = std::io::stdout();
+        writeln!(
+            stdout,
+            "{}{}",
+            commandline
+                .iter()
+                .map(|arg| quote_shell_arg(arg))
+                .collect::<Vec<String>>()
+                .join(" "),
+            redirection
+        )?;
+        stdout
+            .flush()
+            .context("Failed to print the command line to attach")
+    }
+}
+
+/// Opens nothing and launches no debugger client, but prints the pid of the debuggee to stdout,
+/// or the address of the debugger server for the debuggers which launch their debuggees by themselves
+pub struct NoTerminal;
+
+impl DebuggerTerminal for NoTerminal {
+    fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
+        let info = debugger.build_attach_information()?;
+        // The server is what the user connects to, even if it knows the pid, like lldb-dap
+        let target = match (
+            info.get(&AttachInformationKey::DebuggerHost),
+            info.get(&AttachInformationKey::DebuggerPort),
+            info.get(&AttachInformationKey::Pid),
+        ) {
+            (Some(host), Some(port), _) => format!("{}:{}", host, port),
+            (_, _, Some(pid)) => pid.clone(),
+            _ => bail!(
+                "[BUG] {} has neither a pid nor a port to attach to",
+                debugger.name()
+            ),
+        };
+        let mut stdout = std::io::stdout();
+        writeln!(stdout, "{}", target)?;
+        stdout.flush().context("Failed to print the pid")
     }
+}
 
+impl DebuggerTerminal for VsCode {
     fn open(&mut self, debugger: &dyn Debugger) -> Result<()> {
         self.send_attach_information(debugger)?;
         self.send_attach_request(debugger).or_else(|e| {
@@ -260,8 +800,157 @@ impl DebuggerTerminal for VsCode {
     }
 }
 
+/// Set the format of the JSON sent to VSCode
+pub fn set_vscode_json_format(format: JsonFormat) -> Result<(), JsonFormat> {
+    VSCODE_JSON_FORMAT.set(format)
+}
+
 /// Set the prefix path of the VSCode communication FIFO paths. This is mainly a function for
 /// integration test
 pub fn set_vscode_communication_fifo_path_prefix(prefix: String) -> Result<(), String> {
     VSCODE_COMMUNICATION_FIFO_PATH_PREFIX_OVERRIDE.set(prefix)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{fs::File, io::Read};
+
+    #[test]
+    fn test_resolve_fifo_timeout() {
+        assert_eq!(DEFAULT_VSCODE_FIFO_TIMEOUT, resolve_fifo_timeout(|_| None));
+        assert_eq!(
+            Duration::from_secs(5),
+            resolve_fifo_timeout(|_| Some("5".to_owned()))
+        );
+        assert_eq!(
+            DEFAULT_VSCODE_FIFO_TIMEOUT,
+            resolve_fifo_timeout(|_| Some("five".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_serialize_json() {
+        let fields = vec![
+            ("pid".to_owned(), r#""42""#.to_owned()),
+            ("sourceMap".to_owned(), r#"{"/a": "/b"}"#.to_owned()),
+        ];
+        assert_eq!(
+            r#"{"pid": "42", "sourceMap": {"/a": "/b"}}"#,
+            JsonFormat::default().serialize(&fields)
+        );
+        let format = JsonFormat {
+            pretty: true,
+            trailing_newline: true,
+        };
+        assert_eq!(
+            "{\n  \"pid\": \"42\",\n  \"sourceMap\": {\"/a\": \"/b\"}\n}\n",
+            format.serialize(&fields)
+        );
+    }
+
+    #[test]
+    fn test_escape_json_string() {
+        assert_eq!(r#"C:\\a \"b\""#, escape_json_string(r#"C:\a "b""#));
+        assert_eq!(
+            r#"line1\nline2\r\tend\u0000\u001b"#,
+            escape_json_string("line1\nline2\r\tend\u{0}\u{1b}")
+        );
+        assert_eq!("日本語", escape_json_string("日本語"));
+    }
+
+    #[test]
+    fn test_write_to_fifo_with_timeout() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let fifo_path = tempdir.path().join("fifo");
+        let fifo_path = fifo_path.to_str().unwrap().to_owned();
+        unistd::mkfifo(fifo_path.as_str(), nix::sys::stat::Mode::S_IRWXU).unwrap();
+
+        // No reader
+        assert!(
+            !write_to_fifo_with_timeout(&fifo_path, None, b"{}", Duration::from_millis(200))
+                .unwrap()
+        );
+
+        let reader_path = fifo_path.clone();
+        let reader = std::thread::spawn(move || {
+            let mut content = String::new();
+            File::open(reader_path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        });
+        assert!(
+            write_to_fifo_with_timeout(&fifo_path, None, b"{}", Duration::from_secs(10)).unwrap()
+        );
+        assert_eq!("{}", reader.join().unwrap());
+    }
+
+    #[test]
+    fn test_build_commandline_reading_script() {
+        let commandline = vec!["cat".to_owned(), "-".to_owned()];
+        assert_eq!(
+            commandline,
+            build_commandline_reading_script(commandline.clone(), &None)
+        );
+
+        let tempdir = tempfile::tempdir().unwrap();
+        let script = tempdir.path().join("it's a script");
+        fs::write(&script, "break main\nrun\n").unwrap();
+        let commandline = build_commandline_reading_script(commandline, &Some(script));
+        let output = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .output()
+            .unwrap();
+        assert_eq!(
+            "break main\nrun\n",
+            String::from_utf8(output.stdout).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_prepare_fifo() {
+        set_fifo_cleanup(false);
+        let tempdir = tempfile::tempdir().unwrap();
+        let fifo_path = tempdir.path().join("fifo");
+        let fifo_path = fifo_path.to_str().unwrap();
+
+        assert!(matches!(
+            prepare_fifo(fifo_path).unwrap(),
+            PreparedFifo::Created
+        ));
+        // A leftover FIFO which nobody reads is recreated
+        assert!(matches!(
+            prepare_fifo(fifo_path).unwrap(),
+            PreparedFifo::Created
+        ));
+        // A FIFO which VSCode is reading is kept, and written through the file opened to check the reader
+        let mut reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_NONBLOCK)
+            .open(fifo_path)
+            .unwrap();
+        let writer = match prepare_fifo(fifo_path).unwrap() {
+            PreparedFifo::Reading(writer) => writer,
+            PreparedFifo::Created => panic!("the FIFO being read is recreated"),
+        };
+        assert!(
+            write_to_fifo_with_timeout(fifo_path, Some(writer), b"{}", Duration::from_secs(1))
+                .unwrap()
+        );
+        let mut json = String::new();
+        reader.read_to_string(&mut json).unwrap();
+        assert_eq!("{}", json);
+
+        let file_path = tempdir.path().join("file");
+        let file_path = file_path.to_str().unwrap();
+        fs::write(file_path, "stale").unwrap();
+        assert!(matches!(
+            prepare_fifo(file_path).unwrap(),
+            PreparedFifo::Created
+        ));
+        assert!(fs::metadata(file_path).unwrap().file_type().is_fifo());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{fs::OpenOptions, path::Path};
+
+use nix::unistd;
+
+use crate::debugger::{check_debugpy_installed, DEBUGPY_CHECK_TIMEOUT, PID_FILE_PATH};
+use crate::debugger_terminal::escape_json_string;
+use crate::file_helper::{command_exists, find_command_path};
+use crate::os::get_ptrace_scope;
+
+/// Directory where dbgee creates the FIFOs for VSCode and the pid file of stop-and-write-pid
+const TMP_DIR: &str = "/tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+/// Result of a check of the environment
+#[derive(Debug, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: &str, status: CheckStatus, detail: String) -> Check {
+        Check {
+            name: name.to_owned(),
+            status,
+            detail,
+        }
+    }
+}
+
+/// Runs all the checks of the environment which dbgee depends on
+pub fn run_checks() -> Vec<Check> {
+    let mut checks = vec![check_ptrace_scope(
+        get_ptrace_scope(),
+        unistd::geteuid().is_root(),
+    )];
+    checks.extend(
+        ["tmux", "gdb", "lldb", "dlv"]
+            .iter()
+            .map(|command| check_command(command)),
+    );
+    checks.push(check_debugpy());
+    checks.push(check_vscode_terminal(crate::is_in_vscode_term()));
+    checks.push(check_tmp_writable());
+    checks
+}
+
+fn check_ptrace_scope(ptrace_scope: Option<u32>, is_root: bool) -> Check {
+    let (status, detail) = match ptrace_scope {
+        None => (
+            CheckStatus::Pass,
+            "ptrace is not restricted by Yama".to_owned(),
+        ),
+        Some(0) => (CheckStatus::Pass, "kernel.yama.ptrace_scope is 0".to_owned()),
+        Some(scope) if is_root && scope < 3 => (
+            CheckStatus::Pass,
+            format!(
+                "kernel.yama.ptrace_scope is {}, which root is allowed to bypass",
+                scope
+            ),
+        ),
+        Some(1) => (
+            CheckStatus::Warn,
+            "kernel.yama.ptrace_scope is 1. Debuggers can attach only to their descendants, \
+            so attaching by pid fails. Give --sudo-debugger, or run `sudo sysctl kernel.yama.ptrace_scope=0`"
+                .to_owned(),
+        ),
+        Some(2) => (
+            CheckStatus::Fail,
+            "kernel.yama.ptrace_scope is 2. Only root can attach debuggers. Give --sudo-debugger".to_owned(),
+        ),
+        Some(scope) => (
+            CheckStatus::Fail,
+            format!(
+                "kernel.yama.ptrace_scope is {}. No debugger can attach until reboot",
+                scope
+            ),
+        ),
+    };
+    Check::new("ptrace", status, detail)
+}
+
+fn check_command(command: &str) -> Check {
+    match find_command_path(command) {
+        Some(path) => Check::new(command, CheckStatus::Pass, path),
+        None => Check::new(
+            command,
+            CheckStatus::Warn,
+            format!("{} is not found in PATH", command),
+        ),
+    }
+}
+
+fn check_debugpy() -> Check {
+    let python = match ["python3", "python"]
+        .iter()
+        .find(|python| command_exists(python))
+    {
+        Some(python) => python,
+        None => {
+            return Check::new(
+                "debugpy",
+                CheckStatus::Warn,
+                "neither python3 nor python is found in PATH".to_owned(),
+            )
+        }
+    };
+    match check_debugpy_installed(python, DEBUGPY_CHECK_TIMEOUT) {
+        Ok(()) => Check::new(
+            "debugpy",
+            CheckStatus::Pass,
+            format!("{} can import debugpy", python),
+        ),
+        Err(e) => Check::new("debugpy", CheckStatus::Warn, e.to_string()),
+    }
+}
+
+fn check_vscode_terminal(is_in_vscode_term: bool) -> Check {
+    let detail = if is_in_vscode_term {
+        "running in a VSCode terminal. --terminal defaults to vscode"
+    } else {
+        "not running in a VSCode terminal. --terminal defaults to tmuxp"
+    };
+    Check::new("vscode-terminal", CheckStatus::Pass, detail.to_owned())
+}
+
+fn check_tmp_writable() -> Check {
+    if let Err(e) = tempfile::tempfile_in(TMP_DIR) {
+        return Check::new(
+            "tmp",
+            CheckStatus::Fail,
+            format!(
+                "{} is not writable, where dbgee creates the FIFOs for VSCode: {}",
+                TMP_DIR, e
+            ),
+        );
+    }
+    // The pid file left by another user cannot be overwritten
+    if Path::new(PID_FILE_PATH).exists() {
+        if let Err(e) = OpenOptions::new().write(true).open(PID_FILE_PATH) {
+            return Check::new(
+                "tmp",
+                CheckStatus::Warn,
+                format!(
+                    "{} is not writable, which stop-and-write-pid writes: {}",
+                    PID_FILE_PATH, e
+                ),
+            );
+        }
+    }
+    Check::new("tmp", CheckStatus::Pass, format!("{} is writable", TMP_DIR))
+}
+
+/// Formats the checks as lines such as `[pass] gdb: /usr/bin/gdb`
+pub fn format_text(checks: &[Check]) -> String {
+    checks
+        .iter()
+        .map(|check| {
+            format!(
+                "[{}] {}: {}\n",
+                check.status.as_str(),
+                check.name,
+                check.detail
+            )
+        })
+        .collect()
+}
+
+/// Formats the checks as a JSON array of objects with "name", "status" and "detail"
+pub fn format_json(checks: &[Check]) -> String {
+    let checks: Vec<String> = checks
+        .iter()
+        .map(|check| {
+            format!(
+                r#"{{"name": "{}", "status": "{}", "detail": "{}"}}"#,
+                escape_json_string(&check.name),
+                check.status.as_str(),
+                escape_json_string(&check.detail)
+            )
+        })
+        .collect();
+    format!("[{}]", checks.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_ptrace_scope() {
+        assert_eq!(CheckStatus::Pass, check_ptrace_scope(None, false).status);
+        assert_eq!(CheckStatus::Pass, check_ptrace_scope(Some(0), false).status);
+        assert_eq!(CheckStatus::Warn, check_ptrace_scope(Some(1), false).status);
+        assert_eq!(CheckStatus::Pass, check_ptrace_scope(Some(1), true).status);
+        assert_eq!(CheckStatus::Fail, check_ptrace_scope(Some(2), false).status);
+        assert_eq!(CheckStatus::Pass, check_ptrace_scope(Some(2), true).status);
+        assert_eq!(CheckStatus::Fail, check_ptrace_scope(Some(3), true).status);
+    }
+
+    #[test]
+    fn test_format_checks() {
+        let checks = vec![
+            Check::new("gdb", CheckStatus::Pass, "/usr/bin/gdb".to_owned()),
+            Check::new(
+                "dlv",
+                CheckStatus::Warn,
+                "dlv is not found in \"PATH\"".to_owned(),
+            ),
+        ];
+        assert_eq!(
+            "[pass] gdb: /usr/bin/gdb\n[warn] dlv: dlv is not found in \"PATH\"\n",
+            format_text(&checks)
+        );
+        assert_eq!(
+            r#"[{"name": "gdb", "status": "pass", "detail": "/usr/bin/gdb"}, {"name": "dlv", "status": "warn", "detail": "dlv is not found in \"PATH\""}]"#,
+            format_json(&checks)
+        );
+    }
+}
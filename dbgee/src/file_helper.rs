@@ -1,7 +1,12 @@
 use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpListener;
 use std::path::Path;
 use std::str;
-use std::sync::Mutex;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
 use std::{env, fs};
 use std::{os::unix::fs::PermissionsExt, process::Command};
 
@@ -10,6 +15,63 @@ use once_cell::sync::Lazy;
 
 static CMD_OUTPUT_CACHE: Lazy<Mutex<HashMap<String, String>>> =
     Lazy::new(|| Mutex::new(HashMap::new()));
+/// Log the external commands at info level instead of debug level, for `--show-commands`
+static SHOW_COMMANDS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_show_commands(show_commands: bool) {
+    SHOW_COMMANDS.store(show_commands, Ordering::SeqCst);
+}
+
+/// Logs the command line which dbgee is about to run, quoted like a shell.
+/// It's logged at info level with `--show-commands`, or at debug level otherwise.
+pub fn log_commandline<S: AsRef<str>>(commandline: &[S]) {
+    let commandline = commandline
+        .iter()
+        .map(|arg| quote_shell_arg(arg.as_ref()))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if SHOW_COMMANDS.load(Ordering::SeqCst) {
+        log::info!("Running: {}", commandline);
+    } else {
+        log::debug!("running: {}", commandline);
+    }
+}
+
+/// Quotes `arg` by single quotes unless it consists of only the characters which are safe in a shell
+pub fn quote_shell_arg(arg: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./,:=@%+".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Extension of `Command` to log it by `log_commandline` before running it
+pub trait LoggedCommand {
+    fn logged(&mut self) -> &mut Self;
+}
+
+impl LoggedCommand for Command {
+    fn logged(&mut self) -> &mut Self {
+        // The environment variables which dbgee sets for the command, such as $DBGEE_PID of hooks
+        let envs = self.get_envs().filter_map(|(key, val)| {
+            Some(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                val?.to_string_lossy()
+            ))
+        });
+        let commandline: Vec<String> = envs
+            .chain(
+                std::iter::once(self.get_program())
+                    .chain(self.get_args())
+                    .map(|arg| arg.to_string_lossy().into_owned()),
+            )
+            .collect();
+        log_commandline(&commandline);
+        self
+    }
+}
 
 pub fn get_cached_file_output(file: &str) -> Result<String> {
     // -L to follow symbolic links
@@ -26,24 +88,94 @@ pub fn get_cached_command_output(cmd: &[&str]) -> Result<String> {
         return Ok(cached.clone());
     }
 
-    let cmd_output = Command::new(&cmd[0])
+    let cmd_output = Command::new(cmd[0])
         .args(cmd[1..cmd.len()].iter())
+        .logged()
         .output()?;
     let cmd_output = str::from_utf8(&cmd_output.stdout)?;
     cmd_cache.insert(cache_key, cmd_output.to_owned());
     Ok(cmd_output.to_owned())
 }
 
-pub fn command_exists(command: &str) -> bool {
-    if let Ok(path) = env::var("PATH") {
-        for dir in path.split(':') {
-            let path = format!("{}/{}", dir, command);
-            if is_executable(path) {
-                return true;
+/// Interpreter of a script given by its shebang, with the arguments for the interpreter
+#[derive(Debug, PartialEq, Eq)]
+pub struct Shebang {
+    /// Path or command name of the interpreter, such as "/bin/sh" or "python3"
+    pub interpreter: String,
+    pub args: Vec<String>,
+}
+
+/// Reads the shebang of `script`. The interpreter which `env` runs is returned instead of `env`.
+pub fn read_shebang<P: AsRef<Path>>(script: P) -> Option<Shebang> {
+    let mut first_line = vec![];
+    BufReader::new(fs::File::open(script).ok()?)
+        .take(SHEBANG_MAX_LEN)
+        .read_until(b'\n', &mut first_line)
+        .ok()?;
+    parse_shebang(&String::from_utf8_lossy(&first_line))
+}
+
+/// Linux reads at most 256 bytes of a shebang since 5.1
+const SHEBANG_MAX_LEN: u64 = 256;
+
+/// Parses the first line of a script such as `#!/usr/bin/env -S python3 -X dev`
+fn parse_shebang(first_line: &str) -> Option<Shebang> {
+    let line = first_line.strip_prefix("#!")?.trim();
+    // Like the kernel, split the interpreter from the rest, which is passed as one argument
+    let (interpreter, arg) = match line.split_once(|c: char| c.is_ascii_whitespace()) {
+        Some((interpreter, arg)) => (interpreter, arg.trim()),
+        None => (line, ""),
+    };
+    if interpreter.is_empty() {
+        return None;
+    }
+    if Path::new(interpreter).file_name() != Some("env".as_ref()) || arg.is_empty() {
+        return Some(Shebang {
+            interpreter: interpreter.to_owned(),
+            args: if arg.is_empty() {
+                vec![]
+            } else {
+                vec![arg.to_owned()]
+            },
+        });
+    }
+
+    // `env -S` splits the argument by spaces. Without -S, the argument is usually a command name,
+    // which is split as well since it has no spaces then
+    let mut env_args = arg.split_ascii_whitespace().peekable();
+    while let Some(env_arg) = env_args.peek() {
+        if *env_arg == "-u" || *env_arg == "--unset" {
+            env_args.next();
+        } else if let Some(split) = env_arg.strip_prefix("-S") {
+            if !split.is_empty() {
+                // `-Spython3` has the command in the same argument
+                return Some(Shebang {
+                    interpreter: split.to_owned(),
+                    args: env_args.skip(1).map(str::to_owned).collect(),
+                });
             }
+        } else if !env_arg.starts_with('-') && !env_arg.contains('=') {
+            break;
         }
+        env_args.next();
     }
-    false
+    let interpreter = env_args.next()?.to_owned();
+    Some(Shebang {
+        interpreter,
+        args: env_args.map(str::to_owned).collect(),
+    })
+}
+
+pub fn command_exists(command: &str) -> bool {
+    find_command_path(command).is_some()
+}
+
+/// Returns the path of `command` found in PATH
+pub fn find_command_path(command: &str) -> Option<String> {
+    let path = env::var("PATH").ok()?;
+    path.split(':')
+        .map(|dir| format!("{}/{}", dir, command))
+        .find(|path| is_executable(path))
 }
 
 pub fn get_valid_executable_path<T: AsRef<Path>>(path: T, name: &str) -> Result<String> {
@@ -81,3 +213,111 @@ pub fn is_executable<P: AsRef<Path>>(path: P) -> bool {
     }
     false
 }
+
+/// Returns a port of localhost which no process listens on, which the OS assigns to a listener bound to port 0.
+/// The port is released before returning, so another process may take it in a rare case.
+pub fn find_free_port() -> Result<u16> {
+    let listener =
+        TcpListener::bind(("127.0.0.1", 0)).context("Failed to bind a port to find a free port")?;
+    Ok(listener
+        .local_addr()
+        .context("Failed to get the address of the listener")?
+        .port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_valid_executable_path() {
+        let tempdir = tempfile::tempdir().unwrap();
+
+        let dir = tempdir.path().join("dir");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(get_valid_executable_path(&dir, "dir").is_err());
+
+        let file = tempdir.path().join("file");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(get_valid_executable_path(&file, "file").is_err());
+
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(get_valid_executable_path(&file, "file").is_ok());
+    }
+
+    #[test]
+    fn test_parse_shebang() {
+        let shebang = |interpreter: &str, args: &[&str]| {
+            Some(Shebang {
+                interpreter: interpreter.to_owned(),
+                args: args.iter().map(|arg| arg.to_string()).collect(),
+            })
+        };
+        assert_eq!(shebang("/bin/sh", &[]), parse_shebang("#!/bin/sh\n"));
+        assert_eq!(
+            shebang("/bin/bash", &["-e"]),
+            parse_shebang("#! /bin/bash -e\r\n")
+        );
+        assert_eq!(
+            shebang("/usr/bin/python3", &["-X dev"]),
+            parse_shebang("#!/usr/bin/python3 -X dev")
+        );
+        assert_eq!(
+            shebang("python3", &[]),
+            parse_shebang("#!/usr/bin/env python3\n")
+        );
+        assert_eq!(
+            shebang("python3", &["-X", "dev"]),
+            parse_shebang("#!/usr/bin/env -S python3 -X dev\n")
+        );
+        assert_eq!(
+            shebang("python3", &["-u"]),
+            parse_shebang("#!/usr/bin/env -S -i -u HOME PYTHONPATH=/lib python3 -u")
+        );
+        assert_eq!(
+            shebang("python3", &["-X", "dev"]),
+            parse_shebang("#!/usr/bin/env -Spython3 -X dev")
+        );
+        assert_eq!(
+            shebang("/usr/bin/env", &[]),
+            parse_shebang("#!/usr/bin/env")
+        );
+        assert_eq!(None, parse_shebang("#!"));
+        assert_eq!(None, parse_shebang("#!/usr/bin/env -S"));
+        assert_eq!(None, parse_shebang("import os\n"));
+    }
+
+    #[test]
+    fn test_read_shebang() {
+        let tempdir = tempfile::tempdir().unwrap();
+        let script = tempdir.path().join("script");
+        fs::write(
+            &script,
+            "#!/usr/bin/env -S python3 -X dev\nprint('#!/bin/sh')\n",
+        )
+        .unwrap();
+        assert_eq!("python3", read_shebang(&script).unwrap().interpreter);
+        assert_eq!(None, read_shebang(tempdir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_find_free_port() {
+        let port = find_free_port().unwrap();
+        let _listener = TcpListener::bind(("127.0.0.1", port)).unwrap();
+        let another_port = find_free_port().unwrap();
+        assert_ne!(port, another_port);
+        assert!(TcpListener::bind(("127.0.0.1", another_port)).is_ok());
+    }
+
+    #[test]
+    fn test_quote_shell_arg() {
+        assert_eq!("/usr/bin/gdb", quote_shell_arg("/usr/bin/gdb"));
+        assert_eq!("--port=1234", quote_shell_arg("--port=1234"));
+        assert_eq!("''", quote_shell_arg(""));
+        assert_eq!("'import debugpy'", quote_shell_arg("import debugpy"));
+        assert_eq!("'it'\\''s'", quote_shell_arg("it's"));
+        assert_eq!("'$HOME'", quote_shell_arg("$HOME"));
+    }
+}
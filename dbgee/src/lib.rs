@@ -1,28 +1,65 @@
+mod config;
 mod debugger;
 mod debugger_terminal;
+mod doctor;
 mod file_helper;
 mod os;
+mod process_picker;
+mod sampler;
+mod session;
+mod system_log;
+mod systemd;
+mod table;
 
-use debugger::Debugger;
-use debugger_terminal::{DebuggerTerminal, Tmux, TmuxLayout, VsCode};
-use file_helper::is_executable;
+use debugger::{AttachInformationKey, Debugger};
+use debugger_terminal::{
+    DebuggerTerminal, NoTerminal, PerDebuggerTerminal, PrintConnect, Tmux, TmuxLayout, VsCode,
+    VsCodeAdapter,
+};
+pub use file_helper::set_show_commands;
+use file_helper::{get_abspath, get_cached_file_output, is_executable, LoggedCommand};
 use log::debug;
-use os::{is_any_hook_condition_set, run_hook};
+use os::{
+    get_process_state, get_tracer_pid, is_any_hook_condition_set, is_zig_binary,
+    print_dwarf_source_files, remove_cpu_limit_cgroup, run_hook, run_with_syscall_summary,
+    wait_pid_exit_reattaching_on_exec, wait_pid_reaped_elsewhere,
+};
+pub use system_log::SystemLogger;
+use table::{Cell, ColorWhen, Table};
 
-use std::str;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::{env, fs, io, str};
 
 use anyhow::{anyhow, bail, Context, Result};
-use nix::sys::wait;
-use nix::unistd::Pid;
-use structopt::StructOpt;
-use strum::{EnumString, EnumVariantNames, VariantNames as _};
+use nix::sys::{
+    signal::{self, Signal},
+    wait,
+};
+use nix::unistd::{self, Pid};
+use structopt::{clap::Shell, StructOpt};
+use strum::{Display, EnumString, EnumVariantNames, VariantNames as _};
 use sysinfo::{ProcessExt, SystemExt};
 
 use crate::debugger::{
-    DelveDebugger, GdbDebugger, LldbDebugger, PythonDebugger, StopAndWritePidDebugger,
+    get_backup_path_of_wrapper, is_java_debuggee, is_node_debuggee, is_ruby_debuggee,
+    ActivationEnv, CustomDebugger, DebuggeeStdio, DebuggerOptions, DelveDebugger, GdbDebugger,
+    JavaDebugger, LldbDapDebugger, LldbDebugger, NodeDebugger, PythonDebugger, ResourceLimits,
+    RrDebugger, RubyDebugger, SourceMap, StopAndWritePidDebugger, StopMethod,
+    SystemProcessLauncher, WrapperOptions, DEFAULT_BACKUP_SUFFIX,
 };
 
-pub use debugger_terminal::set_vscode_communication_fifo_path_prefix;
+pub use debugger_terminal::{
+    remove_created_fifos, set_fifo_cleanup, set_vscode_communication_fifo_path_prefix,
+    set_vscode_json_format, JsonFormat,
+};
 
 #[derive(Debug, StructOpt)]
 /// The zero-configuration debuggee for debuggers.
@@ -39,10 +76,32 @@ pub struct Opts {
     #[structopt(short, long)]
     pub log_level: Option<LogLevel>,
 
+    /// Where dbgee writes its messages. syslog and journald are for dbgee running in services,
+    /// such as the debuggees wrapped by `set` under systemd, whose stderr is hard to see
+    #[structopt(long, default_value = "stderr", possible_values(LogTarget::VARIANTS))]
+    pub log_target: LogTarget,
+
     /// Prefix to override the path of VSCode communication FIFO paths. Mainly for integration tests.
     #[structopt(long, hidden = true)]
     pub vscode_fifo_prefix: Option<String>,
 
+    /// Pretty-print the JSON which dbgee sends to VSCode, mainly for debugging
+    #[structopt(long)]
+    pub vscode_json_pretty: bool,
+
+    /// End the JSON which dbgee sends to VSCode with a newline, for tools reading line-delimited JSON
+    #[structopt(long)]
+    pub vscode_json_newline: bool,
+
+    /// Print the command lines of the external commands, such as debuggers and tmux, before running them
+    #[structopt(long)]
+    pub show_commands: bool,
+
+    /// Leave the FIFOs which dbgee creates in /tmp to send the attach information to VSCode,
+    /// instead of removing them when dbgee exits
+    #[structopt(long)]
+    pub no_fifo_cleanup: bool,
+
     #[structopt(subcommand)]
     pub command: Subcommand,
 }
@@ -58,11 +117,30 @@ pub enum LogLevel {
     Trace,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, EnumString, EnumVariantNames, Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum LogTarget {
+    Stderr,
+    Syslog,
+    Journald,
+}
+
 #[derive(Debug, StructOpt)]
+// Parsed only once from the command line, so the size of the largest options doesn't matter
+#[allow(clippy::large_enum_variant)]
 pub enum Subcommand {
     Run(RunOpts),
     Set(SetOpts),
     Unset(UnsetOpts),
+    Watch(WatchOpts),
+    Attach(AttachCmdOpts),
+    Sources(SourcesOpts),
+    Sample(SampleOpts),
+    Reattach(ReattachOpts),
+    Sessions(SessionsOpts),
+    Systemd(SystemdOpts),
+    Completions(CompletionsOpts),
+    Doctor(DoctorOpts),
 }
 
 /// Launches the debuggee, and attaches the specified debugger to it.
@@ -74,12 +152,122 @@ pub enum Subcommand {
 pub struct RunOpts {
     /// Path to the process to launch. The debugger attaches to this <command>
     /// unless any hook conditions are given.
-    #[structopt()]
-    pub command: String,
+    ///
+    /// With `--cargo`, <command> and <args> are the arguments for `cargo build`, followed by `--` and
+    /// the arguments for the built binary. With `--go-build`, they are the arguments for the built binary.
+    #[structopt(required_unless_one = &["cargo", "go-build"])]
+    pub command: Option<String>,
 
     #[structopt(name = "args")]
     pub command_args: Vec<String>,
 
+    /// Attach a debugger again when the debuggee re-executes itself with another executable file,
+    /// for example, to update itself. (Linux only)
+    ///
+    /// This takes effect for debuggers attaching by PID, and only when the previous debugger
+    /// doesn't follow the new executable. While no debugger traces the debuggee, dbgee traces it
+    /// to catch its exec.
+    #[cfg_attr(target_os = "linux", structopt(long))]
+    #[cfg_attr(not(target_os = "linux"), structopt(skip))]
+    pub reattach_on_exec: bool,
+
+    /// Exit as soon as the debugger is launched, without waiting for the debuggee to exit.
+    ///
+    /// By default, dbgee waits for the debuggee and exits with its exit code.
+    /// With this option, dbgee exits with 0 and leaves the debuggee and the debugger running.
+    /// The debuggee is not reaped by dbgee, and is re-parented to init or the nearest subreaper.
+    #[structopt(long, conflicts_with = "reattach-on-exec")]
+    pub no_wait: bool,
+
+    /// Print the attach information to stdout as shell export lines, such as `export DBGEE_PID='123'`,
+    /// after launching the debugger. Nothing else is printed to stdout by dbgee.
+    ///
+    /// The variables are DBGEE_PID, DBGEE_DEBUGGER, DBGEE_PORT, DBGEE_HOST, DBGEE_PROGRAM and DBGEE_CORE, as far as the
+    /// debugger has them. For `eval "$(dbgee run --print-env ...)"`, give `--no-wait` and `--quiet-debuggee`
+    /// or `--debuggee-log` too, because the command substitution waits until the debuggee closes stdout.
+    #[structopt(long, conflicts_with_all = &["strace-summary", "on-crash"])]
+    pub print_env: bool,
+
+    /// Print how the debuggee ended to stdout as a JSON line after it ends, such as
+    /// `{"pid": 123, "reason": "signaled", "signal": "SIGSEGV", "exit_code": 130}`.
+    ///
+    /// "reason" is one of "exited", which comes with "code", "signaled", which comes with "signal", and "detached".
+    /// "exit_code" is the exit code of dbgee.
+    #[structopt(long, conflicts_with_all = &["no-wait", "strace-summary", "on-crash"])]
+    pub exit_json: bool,
+
+    /// Count syscalls of the debuggee by ptrace, and print the summary like `strace -c`
+    /// when the debuggee exits or gets a signal of --handoff-signal, SIGINT (Ctrl-C) by default.
+    /// (Linux x86_64 only)
+    ///
+    /// On the signal, the debugger attaches to the debuggee after printing the summary.
+    /// Only the main thread of the debuggee is traced.
+    #[structopt(long, conflicts_with_all = &["reattach-on-exec", "no-wait"])]
+    pub strace_summary: bool,
+
+    /// Don't attach the debugger with `--strace-summary`, but just print the summary when the debuggee exits.
+    #[structopt(long, requires = "strace-summary")]
+    pub trace_only: bool,
+
+    /// Hand off the debuggee to the debugger when it gets this signal with `--strace-summary`, such as SIGSEGV
+    /// to debug a crash, instead of SIGINT. Can be given multiple times. dbgee ignores these signals itself, so that the ones sent
+    /// to the process group, such as SIGINT by Ctrl-C, don't terminate it. Ignored with --trace-only.
+    ///
+    /// The signal is not delivered to the debuggee. A fault such as SIGSEGV recurs when the debugger continues it.
+    #[structopt(
+        long,
+        value_name = "signal",
+        number_of_values = 1,
+        requires = "strace-summary",
+        parse(try_from_str = parse_handoff_signal)
+    )]
+    pub handoff_signal: Vec<Signal>,
+
+    /// Run the debuggee under gdb or lldb in batch mode instead of attaching a debugger to it,
+    /// and run the given debugger command when it crashes. e.g. `--on-crash 'bt full'`
+    ///
+    /// This option can be given multiple times to run multiple commands.
+    /// dbgee exits with the exit code of the debuggee, or 130 if it crashes by a signal as it does without this option.
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["reattach-on-exec", "no-wait", "strace-summary"],
+    )]
+    pub on_crash: Vec<String>,
+
+    /// Build the debuggee by `cargo build` with the given arguments, and debug the built binary.
+    /// e.g. `dbgee run --cargo -- --bin myapp -- args-for-myapp`
+    #[structopt(long, conflicts_with = "go-build")]
+    pub cargo: bool,
+
+    /// Build the given Go package by `go build` with optimizations disabled, and debug the built binary.
+    /// e.g. `dbgee run --go-build ./cmd/app -- args-for-app`
+    #[structopt(long, value_name = "package")]
+    pub go_build: Option<String>,
+
+    /// Load hook conditions from the profile of this name in `[hook_profiles.<name>]` of .dbgee.toml,
+    /// which is looked up from the current directory up to the root. --hook-* options override the profile.
+    #[structopt(long, value_name = "name")]
+    pub hook_profile: Option<String>,
+
+    /// Attach to exactly <command>, even if it is a wrapper or a launcher of the program to debug,
+    /// such as a shell script. Unless `--debugger` is given, the native debugger (gdb, or lldb on macOS)
+    /// attaches to it instead of the one detected from its file type, and no exec or fork is followed.
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "reattach-on-exec",
+            "hook-profile",
+            "hook-executable",
+            "hook-source",
+            "hook-source-dir",
+            "hook-build-id",
+            "hook-library",
+            "follow-subprocess",
+        ],
+    )]
+    pub attach_self: bool,
+
     #[structopt(flatten)]
     attach_opts: AttachOpts,
 
@@ -90,6 +278,20 @@ pub struct RunOpts {
 // Positional arguments of SetOpts. `debugger::set_exec_to_dbgee` needs this constants
 // in order to construct `$ dbgee run` command to launch a debugger
 const SETOPTS_POSITIONAL_ARGS: [&str; 2] = ["debuggee", "start-cmd"];
+// Options of SetOpts which are consumed by `set` itself, and must not be passed to `$ dbgee run`
+const SETOPTS_SET_ONLY_ARGS: [&str; 9] = [
+    "backup-suffix",
+    "once",
+    "force",
+    "allow-running",
+    "activate-env",
+    "only-interactive",
+    "yes",
+    "allow-hardlinked",
+    "debuggee-arg",
+];
+// Options of SystemdOpts which must not be passed to `$ dbgee run`
+const SYSTEMDOPTS_ONLY_ARGS: [&str; 6] = ["unit", "set", "undo", "user", "force", "yes"];
 
 /// Replaces the debuggee with a wrapper script, so that the debugger will be attached to it whenever
 /// it is launched by any processes from now on.
@@ -111,6 +313,61 @@ pub struct SetOpts {
     #[structopt()]
     pub start_cmd: Vec<String>,
 
+    /// Suffix of the file name to which the original debuggee is moved. Default is '-original'.
+    /// The suffix is recorded in the wrapper script so that "unset" can find the original debuggee.
+    #[structopt(long)]
+    pub backup_suffix: Option<String>,
+
+    /// Set the debuggee again even if it is already set, replacing the wrapper script with a new one.
+    /// The original debuggee is kept as it is, with the backup suffix it was set with.
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Set the debuggee even if it is running, in which case the running instances keep running the original
+    /// debuggee without the debugger. Without this, `set` refuses a running debuggee.
+    /// Only root can find the running instances of other users, because their /proc/<pid>/exe are unreadable.
+    /// `watch` and `systemd --set` always allow it, only warning.
+    #[structopt(long)]
+    pub allow_running: bool,
+
+    /// Attach the debugger only when the given environment variable is set, in the form of
+    /// `VAR` or `VAR=VAL`. Otherwise, the wrapper script runs the original debuggee directly.
+    ///
+    /// With `VAR`, any non-empty value activates the debugger. With `VAR=VAL`, only `VAL` does.
+    #[structopt(long)]
+    pub activate_env: Option<ActivationEnv>,
+
+    /// Attach the debugger only when the debuggee is launched interactively, that is, its stdin is a terminal.
+    /// Otherwise, such as when a service launches it, the wrapper script runs the original debuggee directly.
+    #[structopt(long)]
+    pub only_interactive: bool,
+
+    /// Don't ask for confirmation before replacing a debuggee outside your home directory or under
+    /// system directories such as /usr, /bin and /sbin.
+    #[structopt(short, long)]
+    pub yes: bool,
+
+    /// Set the debuggee even if it has other hardlinks. The other links keep pointing to the original
+    /// debuggee, so launching the debuggee through them does not attach the debugger.
+    #[structopt(long)]
+    pub allow_hardlinked: bool,
+
+    /// Restore the original debuggee as "unset" does when the debuggee is launched for the first time, so that
+    /// only the first launch attaches the debugger. With `--activate-env` or `--only-interactive`, only the activated
+    /// launch counts.
+    #[structopt(long)]
+    pub once: bool,
+
+    /// Argument which the wrapper script always passes to the debuggee, before the arguments the debuggee
+    /// is launched with. Useful for fixed flags to reproduce a bug. Can be given multiple times.
+    #[structopt(
+        long,
+        value_name = "arg",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub debuggee_arg: Vec<String>,
+
     #[structopt(flatten)]
     attach_opts: AttachOpts,
 }
@@ -124,28 +381,259 @@ pub struct UnsetOpts {
     pub debuggee: String,
 
     /// Specify the debugger used for the previous 'set' command, which will be used for 'unset'.
-    /// Default is 'auto'. To explicitly specify it, choose one of 'gdb', 'lldb', 'dlv', 'stop-and-write-pid' and 'python'.
+    /// Default is 'auto'. To explicitly specify it, choose one of the possible values.
+    #[structopt(short, long, possible_values(DebuggerOptValues::VARIANTS))]
+    pub debugger: Option<DebuggerOptValues>,
+}
+
+/// Does "set" for the debuggee, and stays in the foreground while logging each debug session of the debuggee.
+///
+/// Press Ctrl+C to stop watching. The debuggee is automatically restored by "unset" then.
+#[derive(Debug, StructOpt)]
+#[structopt(usage = "dbgee watch [OPTIONS] <debuggee>", rename_all = "kebab")]
+pub struct WatchOpts {
+    #[structopt(flatten)]
+    set_opts: SetOpts,
+}
+
+/// Attaches the debugger to processes which are already running.
+///
+/// Give one of `--pid`, `--children-of` and `--pick` to choose the processes.
+#[derive(Debug, StructOpt)]
+#[structopt(
+    rename_all = "kebab",
+    group = structopt::clap::ArgGroup::with_name("target").required(true)
+)]
+pub struct AttachCmdOpts {
+    /// Attach to the process of this pid
+    #[structopt(long, value_name = "pid", group = "target")]
+    pub pid: Option<i32>,
+
+    /// Attach to all the child processes of this pid, such as the workers of a prefork server.
+    /// A debugger and a terminal are launched for each child process.
+    #[structopt(long, value_name = "pid", group = "target")]
+    pub children_of: Option<i32>,
+
+    /// Pick the process to attach to from the list of running processes interactively.
+    /// fzf is used if it is installed.
+    #[structopt(long, group = "target")]
+    pub pick: bool,
+
+    /// Maximum number of child processes to attach to. The children with smaller pids are chosen.
+    #[structopt(long, default_value = "8")]
+    pub max_children: usize,
+
+    /// Wait for the debugger to detach, and resume the process if the debugger left it stopped though it was
+    /// running before the attach, so that a mistaken attach-then-quit doesn't freeze the process.
+    /// By default, dbgee exits as soon as it launches the debugger.
+    #[structopt(long)]
+    pub restore_state: bool,
+
+    #[structopt(flatten)]
+    attach_opts: AttachOpts,
+}
+
+/// Prints the source files recorded in the DWARF debug information of a binary. (Linux only)
+///
+/// This is useful to find the paths to give to `--hook-source`.
+/// A file recorded as a relative path is resolved with the comp_dir of its compilation unit.
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab")]
+pub struct SourcesOpts {
+    /// Path to the binary
+    #[structopt(name = "binary")]
+    pub binary: PathBuf,
+
+    /// Print the source files in JSON
+    #[structopt(long)]
+    pub json: bool,
+}
+
+/// Samples the backtraces of all the threads of a running process repeatedly by gdb, and prints
+/// how many times each distinct backtrace is observed. This works as a poor man's profiler.
+///
+/// The process is stopped briefly while gdb attaches to it for each sample.
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab")]
+pub struct SampleOpts {
+    /// Pid of the process to sample
+    #[structopt(long, value_name = "pid")]
+    pub pid: i32,
+
+    /// Number of samples
+    #[structopt(long, default_value = "20")]
+    pub count: usize,
+
+    /// Interval between samples, such as "100ms" or "1s"
+    #[structopt(long, default_value = "100ms", parse(try_from_str = sampler::parse_interval))]
+    pub interval: std::time::Duration,
+
+    /// Debugger to sample backtraces. Only "gdb" is supported for now.
     #[structopt(short, long)]
     pub debugger: Option<DebuggerOptValues>,
+
+    /// Launch the debugger with sudo
+    #[structopt(long)]
+    pub sudo_debugger: bool,
+
+    /// Format of the aggregated backtraces. "table" prints the count and the frames of each backtrace.
+    /// "folded" prints each backtrace as `outermost;...;innermost count`, the folded stack format
+    /// which flamegraph.pl and inferno render as a flame graph.
+    #[structopt(
+        long,
+        default_value = "table",
+        possible_values(SampleOutputOptValues::VARIANTS)
+    )]
+    pub output: SampleOutputOptValues,
+}
+
+/// Waits for a debuggee which `run` detached from, bringing it back to the foreground of the terminal.
+///
+/// Send SIGUSR1 to `dbgee run` while it waits for the debuggee, e.g. `kill -USR1 <pid of dbgee>`,
+/// to detach from the debuggee. dbgee records the debuggee to a session file in $XDG_RUNTIME_DIR/dbgee-sessions,
+/// or /tmp/dbgee-sessions-<uid> without $XDG_RUNTIME_DIR, and exits leaving the debuggee and the debugger running.
+/// The directory must be owned by the user and inaccessible to the others.
+#[derive(Debug, StructOpt)]
+pub struct ReattachOpts {
+    /// Session to reattach to, which is the pid of the debuggee. Can be omitted if there's only one session.
+    pub session: Option<String>,
+}
+
+/// Lists the sessions which dbgee detached from by SIGUSR1, which `reattach` can bring back.
+///
+/// A session whose debuggee has exited without being reattached is shown as "exited".
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab")]
+pub struct SessionsOpts {
+    /// When to colorize the list. `auto` colorizes it only for a terminal, unless $NO_COLOR is set
+    #[structopt(long, default_value = "auto", possible_values(ColorWhen::VARIANTS))]
+    pub color: ColorWhen,
+}
+
+/// Debugs the service of a systemd unit. The command is read from ExecStart by `systemctl show`. (Linux only)
+///
+/// By default, dbgee writes a drop-in to /etc/systemd/system/<unit>.d, which prefixes ExecStart with
+/// `dbgee run`. With `--set`, dbgee does `set` for the executable of ExecStart instead.
+/// Restart the unit afterwards to start debugging it. Give `--undo` to revert it.
+///
+/// The arguments of ExecStart are split by spaces, because `systemctl show` doesn't quote them.
+/// Use `--set` if an argument contains spaces.
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab")]
+pub struct SystemdOpts {
+    /// Name of the unit, e.g. "nginx" or "nginx.service"
+    pub unit: String,
+
+    /// Do `set` for the executable of ExecStart instead of writing a drop-in
+    #[structopt(long)]
+    pub set: bool,
+
+    /// Remove the drop-in, or `unset` the executable with `--set`
+    #[structopt(long)]
+    pub undo: bool,
+
+    /// The unit is a user unit of `systemctl --user`
+    #[structopt(long)]
+    pub user: bool,
+
+    /// Set the executable again even if it is already set. Effective with `--set`.
+    /// The running service doesn't prevent `--set`, which only warns that it keeps running the original.
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Don't ask for confirmation before writing or removing the drop-in of a system unit, or replacing
+    /// a system binary with `--set`
+    #[structopt(short, long)]
+    pub yes: bool,
+
+    #[structopt(flatten)]
+    attach_opts: AttachOpts,
+}
+
+/// Checks the environment which dbgee depends on, such as ptrace permissions and the installed debuggers,
+/// and prints the report. Attach it to bug reports.
+///
+/// Exits with 1 if any check fails.
+#[derive(Debug, StructOpt)]
+#[structopt(rename_all = "kebab")]
+pub struct DoctorOpts {
+    /// Format of the report
+    #[structopt(long, default_value = "text", possible_values(OutputFormat::VARIANTS))]
+    pub output_format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Prints the completion script of dbgee for the given shell to stdout.
+///
+/// e.g. `dbgee completions bash > /etc/bash_completion.d/dbgee`, or
+/// `dbgee completions zsh > "${fpath[1]}/_dbgee"`.
+#[derive(Debug, StructOpt)]
+pub struct CompletionsOpts {
+    /// Shell to generate the completion script for
+    #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+    pub shell: Shell,
 }
 
 #[derive(Debug, StructOpt)]
 pub struct AttachOpts {
-    /// Debugger to launch. Choose one of "gdb", "lldb", "dlv", "stop-and-write-pid" and "python".
+    /// Debugger to launch. Choose one of "gdb", "lldb", "dlv", "stop-and-write-pid", "python", "jdb", "rr",
+    /// "node-inspect" and "rdbg".
     ///
     /// stop-and-write-pid: Stops the debuggee, and prints the debuggee's PID.
     /// dbgee writes the PID to /tmp/dbgee_pid. If stderr is a tty,
     /// dbgee outputs the PID to stderr as well.
     /// debugpy: Use 'debugpy' module to debug Python in VSCode. Currently, 'python' ignores -t option and uses
     /// only VSCode.
+    /// jdb: Launch the JVM with the JDWP agent, and attach jdb or VSCode to it. The debuggee is `java` with its
+    /// arguments, a .jar file or a .class file, e.g. `dbgee run -d jdb -- java -jar app.jar`.
+    /// rr: Record the debuggee by `rr record` on Linux, and open `rr replay` of the recording after the debuggee
+    /// exits. The recording is kept in a directory under $TMPDIR, which dbgee prints to replay it again.
+    /// rr is never chosen automatically, and VSCode, --sudo-debugger and --debugger-user are not supported.
+    /// node-inspect: Launch Node.js with `--inspect-brk`, and attach `node inspect` or VSCode to it. The debuggee is
+    /// `node` with its arguments or a script, e.g. `dbgee run -d node-inspect -- app.js`.
+    /// rdbg: Run a Ruby script by `rdbg --open` of the debug gem, and attach `rdbg --attach` or VSCode to it.
     ///
     /// If not given, dbgee tries to automatically detect the right debugger; use dlv if the debuggee
-    /// file is compiled by Go, use gdb (on linux) / lldb (on macOS) for other compiled binary, use
-    /// python if the debuggee is a Python file, and exits with error otherwise.
+    /// file is compiled by Go, use jdb for Java, use node-inspect for JavaScript, use rdbg for Ruby,
+    /// use lldb if it is compiled by Zig, use gdb (on linux) / lldb (on macOS) for other compiled binary,
+    /// use python if the debuggee is a Python file, and exits with error otherwise.
+    ///
+    /// dlv, python, node-inspect and rdbg listen on a free port of localhost, and jdb listens on localhost:5679.
+    /// Environment variables $DBGEE_PORT and $DBGEE_LISTEN_HOST override them when the debugger launches,
+    /// which also works for debuggees wrapped by `set`. The precedence is: the environment variables > `--port` >
+    /// the defaults, so that $DBGEE_PORT can change even the port baked into a wrapper by `set --port`.
+    /// VSCode connects to the host and the port which the server actually listens on.
+    ///
+    /// Before the automatic detection, the rules in `[[detect]]` of .dbgee.toml choose the debugger by the output
+    /// of `file`, e.g. `file_output = "ACME launcher"` and `debugger = "gdb"`, which are tried in order.
+    /// A malformed .dbgee.toml is ignored with a warning.
     ///
     #[structopt(short, long, possible_values(DebuggerOptValues::VARIANTS))]
     pub debugger: Option<DebuggerOptValues>,
 
+    /// Attach these debuggers to the debuggee as well as `--debugger`, each in its own terminal, e.g.
+    /// `-d debugpy --extra-debugger gdb` to debug both the Python code and the native extensions of a process.
+    /// Give multiple debuggers separated by commas. Effective for `run` and `set`.
+    ///
+    /// The debuggee is launched only once, by python or jdb if given, and the others attach to its PID.
+    /// At most one of gdb, lldb and stop-and-write-pid can be given, because they trace the debuggee by ptrace,
+    /// which a process allows only one tracer to do. dlv and rr cannot be combined for the same reason.
+    #[structopt(
+        long,
+        value_name = "debugger",
+        use_delimiter = true,
+        require_delimiter = true,
+        requires = "debugger",
+        possible_values(DebuggerOptValues::VARIANTS)
+    )]
+    pub extra_debugger: Vec<DebuggerOptValues>,
+
     /// Terminal to launch the debugger in.
     ///
     /// If not given, the default values is 'vscode' if dbgee is running in an integrated terminal,
@@ -158,21 +646,478 @@ pub struct AttachOpts {
     ///
     /// tmuxp: Opens a new tmux pane in last active tmux session.
     ///
-    /// vscode: Open nothing in the terminal, and wait for VSCode to connect to the debugger
+    /// vscode: Open nothing in the terminal, and wait for VSCode to connect to the debugger.
+    /// dbgee gives up waiting in 300 seconds, which $DBGEE_VSCODE_TIMEOUT overrides in seconds.
+    ///
+    /// none: Launch no debugger client, but print the pid of the stopped debuggee to stdout.
+    /// Debuggers running as a server, such as dlv and debugpy, print their `host:port` instead.
+    /// Give `--no-wait` too to get the shell back.
     ///
     #[structopt(short, long, possible_values(TerminalOptValues::VARIANTS))]
     pub terminal: Option<TerminalOptValues>,
+
+    /// Open the windows and panes of tmuxw and tmuxp in the tmux session of this name, such as "dbgee",
+    /// instead of the last active session. The session is created in the background if it doesn't exist,
+    /// so that all the debuggers are collected in one place, which `tmux a -t <name>` attaches to.
+    #[structopt(long, value_name = "name", parse(try_from_str = parse_tmux_session))]
+    pub tmux_session: Option<String>,
+
+    /// Name of the debuggee to show, such as in the title of the debug session of VSCode and the name of
+    /// the tmux window, instead of the basename of its path. Useful for temporary binaries such as the ones
+    /// of `go run`. The debuggee and the path given to the debuggers are unchanged.
+    #[structopt(long, value_name = "name")]
+    pub debuggee_name: Option<String>,
+
+    /// Launch no debugger, but print the command to attach it to stdout on one line, such as
+    /// `gdb -tui -p 12345` or `dlv connect localhost:5679`, to paste into another terminal.
+    /// Nothing else is printed to stdout by dbgee. Not supported by the debuggers whose client is VSCode.
+    ///
+    /// The debuggee stays stopped until the debugger attaches. Give `--no-wait` too to get the shell back.
+    #[structopt(long, conflicts_with = "terminal")]
+    pub print_connect: bool,
+
+    /// Use another terminal for some debuggers, given as comma-separated `<debugger>=<terminal>`,
+    /// such as `debugpy=vscode,dlv=tmuxp`. It takes precedence over `--terminal` for the given debuggers.
+    /// <debugger> is a name of `--debugger`, or "lldb-dap" for `--dap`.
+    ///
+    /// debugpy and lldb-dap are mapped to vscode by default, since VSCode is their only client.
+    #[structopt(
+        long,
+        value_name = "debugger=terminal",
+        use_delimiter = true,
+        parse(try_from_str = parse_terminal_map_entry)
+    )]
+    pub terminal_map: Vec<(String, TerminalOptValues)>,
+
+    /// Debug adapter which VSCode uses to attach to native debuggees of gdb and lldb.
+    /// Choose one of "auto", "codelldb" and "cpptools". Effective only for `-t vscode`.
+    ///
+    /// codelldb: Use CodeLLDB. cpptools: Use Microsoft's C/C++ extension (cppdbg).
+    /// auto: Use CodeLLDB.
+    #[structopt(
+        long,
+        default_value = "auto",
+        possible_values(VsCodeAdapterOptValues::VARIANTS)
+    )]
+    pub vscode_adapter: VsCodeAdapterOptValues,
+
+    /// Launch the debugger by sudo. Effective for gdb, lldb and dlv.
+    ///
+    /// On macOS, debuggers need the developer mode, a special entitlement, or root privilege
+    /// to attach to a process. This is useful when the first two are not available.
+    #[structopt(long)]
+    pub sudo_debugger: bool,
+
+    /// Launch the debugger as this user by `sudo -u`, e.g. to debug a process owned by a service account.
+    /// Effective for gdb, lldb, dlv and jdb.
+    ///
+    /// Note that the debugger can attach only to processes of the same user, and that
+    /// kernel.yama.ptrace_scope >= 1 further requires CAP_SYS_PTRACE on Linux.
+    #[structopt(long, value_name = "user", parse(try_from_str = parse_debugger_user), conflicts_with = "sudo-debugger")]
+    pub debugger_user: Option<String>,
+
+    /// Debug child processes of a Python debuggee as well, such as ones by `multiprocessing`. Effective for python.
+    ///
+    /// debugpy injects itself into the child processes, and VSCode attaches to them automatically.
+    /// Without this, dbgee turns off the subprocess debugging of debugpy, which is on by default in debugpy,
+    /// so that only the debuggee is debugged.
+    #[structopt(long)]
+    pub follow_subprocess: bool,
+
+    /// Capture a core dump of the debuggee to the given path when the debuggee is stopped,
+    /// before the debugger attaches. Effective for gdb and lldb.
+    ///
+    /// The path is given as `corePath` of the attach information for VSCode, and as $DBGEE_CORE by `--print-env`.
+    #[structopt(long, value_name = "path")]
+    pub capture_core: Option<PathBuf>,
+
+    /// Let the debuggee continue after `--capture-core`, without attaching the debugger,
+    /// to take a snapshot of a stuck process rather than an interactive session.
+    #[structopt(long, requires = "capture-core")]
+    pub capture_core_only: bool,
+
+    /// Directory to search for the sources of the debuggee, which is useful when the debuggee was built on
+    /// another machine or directory. Effective for gdb and lldb. This option can be given multiple times.
+    ///
+    /// For lldb, the build directories of the debuggee which don't exist on this machine are mapped to
+    /// the given directories by `target.source-map`. If there are none, the sources recorded as relative paths
+    /// are mapped to them.
+    #[structopt(long, value_name = "dir", number_of_values = 1)]
+    pub source_dir: Vec<PathBuf>,
+
+    /// Map the source paths which start with `old` at build time to `new` on this machine, e.g.
+    /// `--source-map /build/src=/home/me/src`. Effective for gdb, lldb and VSCode.
+    /// This option can be given multiple times.
+    ///
+    /// VSCode receives it as `sourceFileMap` for cppdbg and `sourceMap` for CodeLLDB and lldb-dap.
+    #[structopt(long, value_name = "old=new", number_of_values = 1, parse(try_from_str = parse_source_map))]
+    pub source_map: Vec<SourceMap>,
+
+    /// Directory to search for the separate debug symbols of the debuggee, such as the ones by
+    /// `objcopy --only-keep-debug`. Effective for gdb, lldb and VSCode.
+    #[structopt(long, value_name = "dir")]
+    pub symbols: Option<PathBuf>,
+
+    /// Argument passed verbatim to the debugger, appended after the arguments which dbgee gives.
+    /// Effective for the debuggers which run in terminals, that is, all but python, stop-and-write-pid and
+    /// lldb-dap, and for `--debugger-command`. This option can be given multiple times, one argument each,
+    /// e.g. `--debugger-arg -ex --debugger-arg 'break main'`. tmux keeps the arguments with spaces as they are
+    /// since tmux 3.0.
+    #[structopt(
+        long,
+        value_name = "arg",
+        number_of_values = 1,
+        allow_hyphen_values = true
+    )]
+    pub debugger_arg: Vec<String>,
+
+    /// Give the contents of this file to the stdin of the debugger which dbgee launches in the terminal,
+    /// for debugger front-ends which read a command script from stdin. Effective for tmuxw, tmuxp and
+    /// `--print-connect`, which prints the command line with the redirection.
+    #[structopt(long, value_name = "file", parse(try_from_str = parse_debugger_script))]
+    pub debugger_script: Option<PathBuf>,
+
+    /// Don't ignore Ctrl+C (SIGINT) while the debugger runs, so that it tears down dbgee together with
+    /// the rest of the foreground process group.
+    ///
+    /// By default, dbgee ignores SIGINT so that Ctrl+C in the debugger doesn't kill dbgee, and kills the
+    /// stopped debuggee by SIGKILL. With this option, Ctrl+C may kill the debuggee, and a debuggee
+    /// stopped before the debugger attaches may be left stopped.
+    #[structopt(long)]
+    pub no_sigint_ignore: bool,
+
+    /// Start `record full` of gdb right after it attaches, so that you can execute the debuggee in reverse
+    /// by such as `reverse-step` and `reverse-continue`, without rr. Effective for gdb, except with `-t vscode`.
+    ///
+    /// The debuggee runs hundreds of times slower while recording, and `record full` supports only native
+    /// processes on Linux of some architectures such as x86-64 and AArch64. dbgee rejects the other platforms,
+    /// but gdb may still fail to record such as a debuggee using AVX instructions, which gdb reports right after
+    /// dbgee's hint when it attaches.
+    #[structopt(long)]
+    pub record: bool,
+
+    /// Regex of the line which a debugger server writes to its stdout or stderr when it gets ready,
+    /// such as 'API server listening at' of dlv. dbgee waits for it instead of the server listening on the port,
+    /// and gives up after `--server-timeout`. Effective for dlv, debugpy, lldb-dap and the JVM of jdb.
+    /// It's not only for dlv, because any of them may listen on the port before it accepts the client,
+    /// and both of the stdout and stderr are scanned, because dlv and the JVM print the ready lines to the stdout.
+    ///
+    /// The stdout of the server, which the debuggee of dlv shares, is relayed by dbgee then, through a pseudo
+    /// terminal if it's a terminal so that the debuggee still writes to a terminal.
+    #[structopt(long, value_name = "regex")]
+    pub ready_regex: Option<regex::Regex>,
+
+    /// How long to wait for a debugger server to get ready, such as "500ms" or "60s". Default is 30 seconds.
+    /// A number without a unit is in milliseconds. Effective for dlv, debugpy, lldb-dap and the JVM of jdb.
+    #[structopt(long, value_name = "duration", parse(try_from_str = sampler::parse_interval))]
+    pub server_timeout: Option<std::time::Duration>,
+
+    /// Command line of the debugger to launch instead of `--debugger`, such as `cgdb -p {pid}` or a wrapper script.
+    /// `{pid}` and `{program}` are replaced with the pid and the path of the debuggee.
+    /// The template is split by whitespace, and quotes are not supported. VSCode is not supported.
+    #[structopt(
+        long,
+        value_name = "template",
+        parse(try_from_str = parse_debugger_command),
+        conflicts_with_all = &["debugger", "dap"]
+    )]
+    pub debugger_command: Option<String>,
+
+    /// Path to the gdb, lldb or rr executable, such as `/opt/gcc-arm/bin/arm-none-eabi-gdb`, or lldb-dap with `--dap`.
+    /// By default, the one in PATH is used. Give `--debugger gdb` or `--debugger lldb` as well to tell
+    /// which kind of debugger it is. Other debuggers reject it; use `--dlv-path` for dlv.
+    #[structopt(long, value_name = "path", parse(try_from_str = parse_debugger_path))]
+    pub debugger_path: Option<String>,
+
+    /// Silence the stdout and stderr of the debuggee by redirecting them to /dev/null, so that they
+    /// don't interleave with your shell while the debuggee runs in the background.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, conflicts_with_all = &["debuggee-log", "stdout", "stderr"])]
+    pub quiet_debuggee: bool,
+
+    /// Run this shell command by `sh -c` right before the debugger opens, e.g. to relax ptrace restrictions.
+    /// $DBGEE_PID is set to the pid to attach to. dbgee aborts with the output of the command if it fails.
+    #[structopt(long, value_name = "command")]
+    pub pre_attach_hook: Option<String>,
+
+    /// Run this shell command by `sh -c` after the debuggee exits, e.g. to revert what --pre-attach-hook did.
+    /// $DBGEE_PID is set to the pid of the debuggee, even when the debugger launches it such as dlv.
+    /// Effective only when `run` waits for the debuggee to exit. It's not run with --no-wait, when dbgee
+    /// detaches from the debuggee by SIGUSR1, nor by `attach`, because the debug session outlives dbgee.
+    #[structopt(long, value_name = "command")]
+    pub post_detach_hook: Option<String>,
+
+    /// Limit the virtual memory of the debuggee to this size by setrlimit(RLIMIT_AS), e.g. to reproduce
+    /// out-of-memory bugs. Accepts a number of bytes with an optional suffix K, M or G, such as "512M".
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "bytes", parse(try_from_str = parse_memory_limit))]
+    pub memory_limit: Option<u64>,
+
+    /// Limit the CPU usage of the debuggee to this percentage of one CPU, such as "50". (Linux only)
+    ///
+    /// The debuggee is put in a new child cgroup v2 of the one of dbgee, whose `cgroup.subtree_control`
+    /// must enable the cpu controller. This usually needs root privilege.
+    /// The cgroup is removed after the debuggee exits unless `--no-wait` is given.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "percent", parse(try_from_str = parse_cpu_limit))]
+    pub cpu_limit: Option<u32>,
+
+    /// Send this signal to the debuggee when dbgee exits, even if dbgee is killed abruptly, so that a stopped
+    /// or backgrounded debuggee is not left orphaned. A name such as "SIGKILL" or "KILL", or a number.
+    /// Set by prctl(PR_SET_PDEATHSIG). Don't combine it with `--no-wait`, with which dbgee exits soon. (Linux only)
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "signal", parse(try_from_str = parse_signal))]
+    pub pdeathsig: Option<Signal>,
+
+    /// Append the stdout and stderr of the debuggee to this file instead of the terminal.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "path", conflicts_with_all = &["stdout", "stderr"])]
+    pub debuggee_log: Option<PathBuf>,
+
+    /// Let the debuggee read stdin from this file, e.g. to debug a parser with the same input every time.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "path")]
+    pub stdin: Option<PathBuf>,
+
+    /// Append the stdout of the debuggee to this file instead of the terminal.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "path")]
+    pub stdout: Option<PathBuf>,
+
+    /// Append the stderr of the debuggee to this file instead of the terminal.
+    /// Effective for gdb, lldb and stop-and-write-pid.
+    #[structopt(long, value_name = "path")]
+    pub stderr: Option<PathBuf>,
+
+    /// How stop-and-write-pid keeps the debuggee stopped until a debugger attaches.
+    /// Choose one of "sigstop" (default) and "ptrace-hold".
+    ///
+    /// sigstop: Stops the debuggee by SIGSTOP. Some debuggers fail to resume a process stopped by SIGSTOP
+    /// without an extra SIGCONT.
+    ///
+    /// ptrace-hold: dbgee keeps holding the debuggee by ptrace until it's taken over. dbgee prints the path of
+    /// a hold file, <sessions dir>/<pid>.hold. Remove it right before attaching, and dbgee hands the debuggee over
+    /// and sends SIGCONT after the debugger attaches. dbgee must keep running until then, and a debugger which
+    /// attaches before dbgee logs the hand-over fails with EPERM. See `reattach` for the sessions dir. (Linux only)
+    #[structopt(long, value_name = "method", default_value = "sigstop")]
+    pub stop_method: StopMethod,
+
+    /// Debug native debuggees via lldb-dap (or lldb-vscode), the Debug Adapter Protocol server of lldb,
+    /// instead of the interactive debugger. dbgee launches lldb-dap listening on $DBGEE_PORT,
+    /// and lets VSCode connect to it. Requires the LLDB DAP extension of VSCode.
+    #[structopt(long)]
+    pub dap: bool,
+
+    /// Port which dlv, python, jdb, node-inspect, rdbg and lldb-dap listen on, instead of the default.
+    /// $DBGEE_PORT overrides it. dlv, python, node-inspect and rdbg listen on a free port by default,
+    /// and jdb and lldb-dap on 5679.
+    #[structopt(long, value_name = "port")]
+    pub port: Option<u16>,
+
+    /// Path to the dlv executable. By default, the one in PATH is used.
+    #[structopt(long, value_name = "path", parse(try_from_str = parse_debugger_path))]
+    pub dlv_path: Option<String>,
+}
+
+fn parse_debugger_path(path: &str) -> Result<String> {
+    file_helper::get_valid_executable_path(path, "the debugger")
+}
+
+fn parse_debugger_command(template: &str) -> Result<String> {
+    let command = match template.split_whitespace().next() {
+        Some(command) => command,
+        None => bail!("the debugger command should not be empty"),
+    };
+    if !template.contains("{pid}") {
+        bail!(
+            "the debugger command '{}' should contain {{pid}}, which is replaced with the pid of the debuggee",
+            template
+        );
+    }
+    if command.contains('/') {
+        file_helper::get_valid_executable_path(command, "the debugger")?;
+    }
+    Ok(template.to_owned())
+}
+
+fn parse_source_map(source_map: &str) -> Result<SourceMap> {
+    match source_map.split_once('=') {
+        Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(SourceMap {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        }),
+        _ => bail!(
+            "invalid source map '{}'. Use such as '/build/src=/home/me/src'",
+            source_map
+        ),
+    }
+}
+
+/// Parses a signal given as a name such as "SIGALRM" or "alrm", or as a number such as "14"
+pub(crate) fn parse_signal(sig: &str) -> Result<Signal> {
+    if let Ok(signum) = sig.parse::<i32>() {
+        return Signal::try_from(signum).map_err(|_| anyhow!("invalid signal number '{}'", sig));
+    }
+    let name = sig.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse().map_err(|_| {
+        anyhow!(
+            "unknown signal '{}'. Give a name such as SIGALRM or a number",
+            sig
+        )
+    })
+}
+
+/// Parses a signal of `--handoff-signal`, which dbgee can ignore without breaking itself
+fn parse_handoff_signal(sig: &str) -> Result<Signal> {
+    let sig = parse_signal(sig)?;
+    match sig {
+        Signal::SIGKILL | Signal::SIGSTOP | Signal::SIGCHLD => {
+            bail!("{} can't be a signal to hand off the debuggee", sig)
+        }
+        sig => Ok(sig),
+    }
+}
+
+fn parse_memory_limit(memory_limit: &str) -> Result<u64> {
+    let (number, unit) = match memory_limit.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&memory_limit[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&memory_limit[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&memory_limit[..i], 1 << 30),
+        _ => (memory_limit, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .filter(|bytes| *bytes > 0)
+        .ok_or_else(|| {
+            anyhow!(
+                "invalid memory limit '{}'. Give a positive number of bytes such as '1048576' or '512M'",
+                memory_limit
+            )
+        })
+}
+
+fn parse_cpu_limit(cpu_limit: &str) -> Result<u32> {
+    let max_percent = 100
+        * std::thread::available_parallelism()
+            .map(|cpus| cpus.get() as u32)
+            .unwrap_or(1);
+    match cpu_limit.trim_end_matches('%').parse::<u32>() {
+        Ok(percent) if (1..=max_percent).contains(&percent) => Ok(percent),
+        _ => bail!(
+            "invalid CPU limit '{}'. Give a percentage of one CPU from 1 to {}",
+            cpu_limit,
+            max_percent
+        ),
+    }
+}
+
+/// tmux replaces ':' and '.' in session names, which then can't be found by the given name
+fn parse_tmux_session(session: &str) -> Result<String> {
+    if session.is_empty() || session.contains([':', '.']) {
+        bail!(
+            "invalid tmux session name '{}'. Give a non-empty name without ':' and '.'",
+            session
+        );
+    }
+    Ok(session.to_owned())
+}
+
+/// Resolves the script to the absolute path, since the debugger runs in another directory in the terminal
+fn parse_debugger_script(script: &str) -> Result<PathBuf> {
+    fs::canonicalize(script)
+        .with_context(|| format!("Failed to find the debugger script {}", script))
+}
+
+fn parse_debugger_user(user: &str) -> Result<String> {
+    match unistd::User::from_name(user) {
+        Ok(Some(_)) => Ok(user.to_owned()),
+        Ok(None) => bail!("user '{}' does not exist", user),
+        Err(e) => Err(e).with_context(|| format!("Failed to look up user '{}'", user)),
+    }
+}
+
+impl AttachOpts {
+    fn build_debugger_options(&self) -> DebuggerOptions {
+        DebuggerOptions {
+            sudo: self.sudo_debugger,
+            debugger_user: self.debugger_user.clone(),
+            follow_subprocess: self.follow_subprocess,
+            capture_core: self.capture_core.clone(),
+            capture_core_only: self.capture_core_only,
+            source_dirs: self.source_dir.clone(),
+            debugger_args: self.debugger_arg.clone(),
+            no_sigint_ignore: self.no_sigint_ignore,
+            debugger_path: self.debugger_path.clone(),
+            dlv_path: self.dlv_path.clone(),
+            dap: self.dap,
+            stop_method: self.stop_method,
+            source_maps: self.source_map.clone(),
+            symbols_dir: self.symbols.clone(),
+            pre_attach_hook: self.pre_attach_hook.clone(),
+            resource_limits: ResourceLimits {
+                memory_bytes: self.memory_limit,
+                cpu_percent: self.cpu_limit,
+            },
+            debuggee_stdio: self.build_debuggee_stdio(),
+            debugger_cwd: None,
+            debuggee_cwd: None,
+            debuggee_env: None,
+            parent_death_signal: self.pdeathsig,
+            debuggee_name: self.debuggee_name.clone(),
+            record: self.record,
+            server_ready_regex: self.ready_regex.clone(),
+            debugger_command_template: self.debugger_command.clone(),
+            listen_port: self.port,
+            server_timeout: self.server_timeout,
+        }
+    }
+
+    fn build_debuggee_stdio(&self) -> DebuggeeStdio {
+        let output = if self.quiet_debuggee {
+            Some(PathBuf::from("/dev/null"))
+        } else {
+            self.debuggee_log.clone()
+        };
+        DebuggeeStdio {
+            stdin: self.stdin.clone(),
+            stdout: self.stdout.clone().or_else(|| output.clone()),
+            stderr: self.stderr.clone().or(output),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum SampleOutputOptValues {
+    Table,
+    Folded,
 }
 
 #[derive(Debug, EnumString, EnumVariantNames)]
 #[strum(serialize_all = "kebab-case")]
+pub enum VsCodeAdapterOptValues {
+    Auto,
+    Codelldb,
+    Cpptools,
+}
+
+#[derive(Debug, Clone, Copy, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
 pub enum TerminalOptValues {
     Tmuxw,
     Tmuxp,
     Vscode,
+    None,
 }
 
-#[derive(Debug, Clone, Copy, EnumString, EnumVariantNames)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumString, EnumVariantNames)]
 #[strum(serialize_all = "kebab-case")]
 pub enum DebuggerOptValues {
     Gdb,
@@ -180,57 +1125,794 @@ pub enum DebuggerOptValues {
     Dlv,
     StopAndWritePid,
     Debugpy,
+    Jdb,
+    Rr,
+    NodeInspect,
+    Rdbg,
+}
+
+/// How the debuggee of `run` ended, or how the other subcommands ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// Exited with the exit code
+    Exited(i32),
+    /// Killed by the signal
+    Signaled(Signal),
+    /// dbgee detached from the debuggee by SIGUSR1, leaving it running
+    Detached,
+}
+
+impl ExitReason {
+    /// Returns the exit code for dbgee. It's 130 if the debuggee is killed by a signal, whichever it is.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ExitReason::Exited(code) => *code,
+            ExitReason::Signaled(_) => 130,
+            ExitReason::Detached => 0,
+        }
+    }
+
+    fn to_json(self, pid: Pid) -> String {
+        let details = match self {
+            ExitReason::Exited(code) => format!(r#""reason": "exited", "code": {}"#, code),
+            ExitReason::Signaled(signal) => {
+                format!(r#""reason": "signaled", "signal": "{}""#, signal.as_str())
+            }
+            ExitReason::Detached => r#""reason": "detached""#.to_owned(),
+        };
+        format!(
+            r#"{{"pid": {}, {}, "exit_code": {}}}"#,
+            pid,
+            details,
+            self.exit_code()
+        )
+    }
 }
 
-pub fn run(opts: Opts) -> Result<i32> {
+/// Run dbgee with the given options and returns how it ended. `ExitReason::exit_code` is the exit code for dbgee.
+///
+/// For `run`, it's how the debuggee ended, which dbgee reaps by `waitpid`.
+/// When dbgee is embedded in another program, don't reap the debuggee elsewhere, e.g. by `wait` or by
+/// ignoring SIGCHLD. If it is reaped elsewhere, dbgee waits for the process to disappear and returns an error
+/// because its exit status is unknown. With `--no-wait`, dbgee never reaps the debuggee, so the caller is
+/// responsible for it.
+pub fn run(opts: Opts) -> Result<ExitReason> {
     match opts.command {
-        Subcommand::Run(run_opts) => {
-            bail_if_not_executable(&run_opts.command)?;
+        Subcommand::Run(mut run_opts) => {
+            // Check it before building the debuggee, which can take long
+            bail_if_dbgee_is_traced()?;
+            // Keeps the binary built by --go-build until the debuggee exits
+            let mut build_dir = None;
+            let (command, command_args) = build_debuggee_command(&run_opts, &mut build_dir)?;
+            // `java` and `node` are looked up in PATH, and .jar, .class, .js and .rb files are not executable
+            if !is_java_debuggee(&command)
+                && !is_node_debuggee(&command)
+                && !is_ruby_debuggee(&command)
+            {
+                bail_if_not_executable(&command)?;
+            }
 
+            if let Some(ref profile_name) = run_opts.hook_profile {
+                let profile = config::load_hook_profile(profile_name)?;
+                run_opts.hook_opts.apply_profile(profile);
+            }
+            if !run_opts.reattach_on_exec
+                && !run_opts.attach_self
+                && !is_any_hook_condition_set(&run_opts.hook_opts)
+            {
+                if let Some(kind) = detect_self_extracting(&command) {
+                    log::warn!("{}", build_self_extracting_guidance(&command, kind));
+                }
+            }
             if is_any_hook_condition_set(&run_opts.hook_opts) {
                 run_hook(
-                    run_opts.command,
-                    run_opts.command_args,
+                    command,
+                    command_args,
                     run_opts.hook_opts,
                     run_opts.attach_opts,
                 )
                 .context("Running with hook conditions failed")?;
-                return Ok(0);
+                return Ok(ExitReason::Exited(0));
+            }
+
+            if run_opts.strace_summary {
+                let handoff_signals: &[Signal] = if run_opts.trace_only {
+                    &[]
+                } else if run_opts.handoff_signal.is_empty() {
+                    &[Signal::SIGINT]
+                } else {
+                    &run_opts.handoff_signal
+                };
+                return run_with_syscall_summary(
+                    command,
+                    command_args,
+                    handoff_signals,
+                    &run_opts.attach_opts,
+                );
+            }
+            if !run_opts.on_crash.is_empty() {
+                return run_under_debugger_on_crash(&command, &command_args, &run_opts);
             }
 
-            let mut debugger = build_debugger(&run_opts.attach_opts.debugger, &run_opts.command)?;
-            let mut debugger_terminal = build_debugger_terminal(&run_opts.attach_opts.terminal);
+            let (mut launching_debugger, attaching_debuggers) =
+                order_debuggers(&run_opts.attach_opts)?;
+            if run_opts.attach_self && launching_debugger.is_none() {
+                launching_debugger = Some(native_debugger());
+            }
+            let mut debugger = build_debugger(
+                &launching_debugger,
+                &run_opts.attach_opts.build_debugger_options(),
+                &command,
+            )?;
+            let mut debugger_terminal = build_debugger_terminal(&run_opts.attach_opts);
             let pid = debugger.run(
-                &run_opts.command,
-                run_opts.command_args.iter().map(String::as_str).collect(),
+                &command,
+                command_args.iter().map(String::as_str).collect(),
                 debugger_terminal.as_mut(),
             )?;
-            Ok(wait_pid_exit(pid)?)
+            attach_extra_debuggers(
+                pid,
+                &attaching_debuggers,
+                &run_opts.attach_opts,
+                debugger_terminal.as_mut(),
+            )
+            .map_err(|e| debugger::kill_launched_process(pid, e))?;
+            if run_opts.print_env {
+                print_attach_env(debugger.as_ref(), pid)?;
+            }
+            let debuggee_pid = debugger.debuggee_pid(pid);
+            if run_opts.no_wait {
+                log::debug!("exiting without waiting for pid({}) to exit", pid);
+                return Ok(ExitReason::Exited(0));
+            }
+            let exit_reason = if run_opts.reattach_on_exec {
+                wait_pid_exit_reattaching_on_exec(
+                    pid,
+                    &run_opts.attach_opts,
+                    debugger_terminal.as_mut(),
+                )
+            } else {
+                session::install_detach_handler(&command, &command_args)?;
+                wait_pid_exit(pid)
+            };
+            if run_opts.attach_opts.cpu_limit.is_some() {
+                remove_cpu_limit_cgroup();
+            }
+            match (&run_opts.attach_opts.post_detach_hook, &exit_reason) {
+                // The debug session goes on without dbgee
+                (_, Ok(ExitReason::Detached)) | (None, _) => (),
+                (Some(hook), _) => {
+                    // The debug session is over. Don't let the hook change the exit code of the debuggee
+                    if let Err(e) =
+                        debugger::run_attach_hook("--post-detach-hook", hook, debuggee_pid)
+                    {
+                        log::warn!("{:#}", e);
+                    }
+                }
+            }
+            let exit_reason = exit_reason?;
+            if run_opts.exit_json {
+                println!("{}", exit_reason.to_json(pid));
+            }
+            Ok(exit_reason)
         }
 
         Subcommand::Set(set_opts) => {
-            bail_if_not_executable(&set_opts.debuggee)?;
-
-            let mut debugger = build_debugger(&set_opts.attach_opts.debugger, &set_opts.debuggee)?;
-            let mut debugger_terminal = build_debugger_terminal(&set_opts.attach_opts.terminal);
-            debugger.set(
-                &set_opts.debuggee,
-                set_opts.start_cmd.iter().map(String::as_str).collect(),
-                debugger_terminal.as_mut(),
-            )?;
-            Ok(0)
-        }
+            set_debuggee(&set_opts, set_opts.allow_running)?;
+            Ok(ExitReason::Exited(0))
+        }
 
         Subcommand::Unset(unset_opts) => {
             bail_if_not_executable(&unset_opts.debuggee)?;
 
-            let mut debugger = build_debugger(&unset_opts.debugger, &unset_opts.debuggee)?;
+            let mut debugger = build_debugger(
+                &unset_opts.debugger,
+                &DebuggerOptions::default(),
+                &unset_opts.debuggee,
+            )?;
             debugger.unset(&unset_opts.debuggee)?;
-            Ok(0)
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Watch(watch_opts) => {
+            watch_debuggee(&watch_opts.set_opts)?;
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Attach(attach_cmd_opts) => {
+            let attach_opts = &attach_cmd_opts.attach_opts;
+            let attached_processes = if let Some(parent) = attach_cmd_opts.children_of {
+                attach_to_children(
+                    Pid::from_raw(parent),
+                    attach_cmd_opts.max_children,
+                    attach_opts,
+                )?
+            } else if let Some(pid) = attach_cmd_opts.pid {
+                let pid = Pid::from_raw(pid);
+                vec![attach_to_process(pid, &get_exe_path_of(pid)?, attach_opts)?]
+            } else {
+                let picked = process_picker::pick_process()?;
+                vec![attach_to_process(
+                    picked.pid,
+                    &picked.exe_path,
+                    attach_opts,
+                )?]
+            };
+            if attach_cmd_opts.restore_state {
+                for attached_process in attached_processes.iter().flatten() {
+                    attached_process.wait_detach()?;
+                    attached_process.restore_running()?;
+                }
+            }
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Sources(sources_opts) => {
+            print_dwarf_source_files(&sources_opts.binary, sources_opts.json)?;
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Sample(sample_opts) => {
+            match sample_opts.debugger {
+                None | Some(DebuggerOptValues::Gdb) => (),
+                Some(other) => bail!("sample supports only gdb, but {:?} is given", other),
+            }
+            let debugger_options = DebuggerOptions {
+                sudo: sample_opts.sudo_debugger,
+                ..Default::default()
+            };
+            sampler::sample_backtraces(
+                Pid::from_raw(sample_opts.pid),
+                sample_opts.count,
+                sample_opts.interval,
+                sample_opts.output,
+                &debugger_options,
+            )?;
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Reattach(reattach_opts) => {
+            session::reattach(reattach_opts.session.as_deref()).map(ExitReason::Exited)
+        }
+
+        Subcommand::Sessions(sessions_opts) => {
+            let sessions = session::read_sessions()?;
+            if sessions.is_empty() {
+                log::info!(
+                    "No detached sessions are found in {:?}",
+                    session::sessions_dir()
+                );
+                return Ok(ExitReason::Exited(0));
+            }
+            let colorize = sessions_opts.color.should_colorize();
+            // `colored` decides by itself from the environment variables otherwise
+            colored::control::set_override(colorize);
+            print!(
+                "{}",
+                build_sessions_table(&sessions).render(table::terminal_width(), colorize)
+            );
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Systemd(systemd_opts) => {
+            debug_systemd_unit(systemd_opts)?;
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Completions(completions_opts) => {
+            let mut stdout = io::stdout();
+            write_completions(completions_opts.shell, &mut stdout);
+            stdout
+                .flush()
+                .context("Failed to print the completion script")?;
+            Ok(ExitReason::Exited(0))
+        }
+
+        Subcommand::Doctor(doctor_opts) => {
+            let checks = doctor::run_checks();
+            match doctor_opts.output_format {
+                OutputFormat::Text => print!("{}", doctor::format_text(&checks)),
+                OutputFormat::Json => println!("{}", doctor::format_json(&checks)),
+            }
+            let failed = checks
+                .iter()
+                .any(|check| check.status == doctor::CheckStatus::Fail);
+            Ok(ExitReason::Exited(if failed { 1 } else { 0 }))
         }
     }
 }
 
+fn write_completions<W: Write>(shell: Shell, out: &mut W) {
+    Opts::clap().gen_completions_to("dbgee", shell, out);
+}
+
+/// Prints the attach information of `debugger` as shell export lines for `--print-env`
+fn print_attach_env(debugger: &dyn Debugger, pid: Pid) -> Result<()> {
+    // Some debuggers, such as stop-and-write-pid, have no attach information
+    let attach_information = debugger.build_attach_information().unwrap_or_default();
+    let mut stdout = io::stdout();
+    for line in build_attach_env_lines(&attach_information, pid) {
+        writeln!(stdout, "{}", line)?;
+    }
+    stdout
+        .flush()
+        .context("Failed to print the attach information")
+}
+
+/// Builds the table of `dbgee sessions`
+fn build_sessions_table(sessions: &[session::Session]) -> Table {
+    let mut table = Table::new(vec!["PID", "STATE", "COMMAND"]);
+    for session in sessions {
+        let state = if session.is_alive() {
+            Cell::colored("running", colored::Color::Green)
+        } else {
+            Cell::colored("exited", colored::Color::Red)
+        };
+        table.push_row(vec![
+            Cell::new(session.pid.to_string()),
+            state,
+            Cell::new(session.command.clone()),
+        ]);
+    }
+    table
+}
+
+fn build_attach_env_lines(
+    attach_information: &HashMap<AttachInformationKey, String>,
+    pid: Pid,
+) -> Vec<String> {
+    use AttachInformationKey::*;
+    // The pid of the debuggee if the debugger knows it, or the pid which dbgee waits for
+    let pid = attach_information
+        .get(&Pid)
+        .cloned()
+        .unwrap_or_else(|| pid.to_string());
+    std::iter::once(("DBGEE_PID", Some(&pid)))
+        .chain(
+            [
+                ("DBGEE_DEBUGGER", DebuggerTypeHint),
+                ("DBGEE_PORT", DebuggerPort),
+                ("DBGEE_HOST", DebuggerHost),
+                ("DBGEE_PROGRAM", ProgramName),
+                ("DBGEE_CORE", CorePath),
+            ]
+            .iter()
+            .map(|(name, key)| (*name, attach_information.get(key))),
+        )
+        .filter_map(|(name, val)| Some(format!("export {}={}", name, shell_quote(val?))))
+        .collect()
+}
+
+/// Quotes `s` by single quotes for POSIX shells
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// Sets the debuggee. With `allow_running`, a running debuggee is set only with a warning.
+fn set_debuggee(set_opts: &SetOpts, allow_running: bool) -> Result<()> {
+    bail_if_not_executable(&set_opts.debuggee)?;
+    if !set_opts.yes {
+        confirm_replacing_system_binary(&set_opts.debuggee)?;
+    }
+
+    let mut debugger = build_debugger(
+        &set_opts.attach_opts.debugger,
+        &set_opts.attach_opts.build_debugger_options(),
+        &set_opts.debuggee,
+    )?;
+    let mut debugger_terminal = build_debugger_terminal(&set_opts.attach_opts);
+    let wrapper_options = WrapperOptions {
+        backup_suffix: set_opts
+            .backup_suffix
+            .clone()
+            .unwrap_or_else(|| DEFAULT_BACKUP_SUFFIX.to_owned()),
+        force: set_opts.force,
+        allow_running,
+        activate_env: set_opts.activate_env.clone(),
+        only_interactive: set_opts.only_interactive,
+        allow_hardlinked: set_opts.allow_hardlinked,
+        once: set_opts.once,
+        debuggee_args: set_opts.debuggee_arg.clone(),
+    };
+    debugger.set(
+        &set_opts.debuggee,
+        set_opts.start_cmd.iter().map(String::as_str).collect(),
+        &wrapper_options,
+        debugger_terminal.as_mut(),
+    )
+}
+
+fn debug_systemd_unit(systemd_opts: SystemdOpts) -> Result<()> {
+    let unit = systemd::Unit::new(&systemd_opts.unit, systemd_opts.user);
+    let exec_start = unit.read_exec_start()?;
+    match (systemd_opts.set, systemd_opts.undo) {
+        (false, false) => {
+            let clap_matches = Opts::clap().get_matches();
+            let run_command_prefix = debugger::build_run_command_prefix(&clap_matches)?;
+            if !systemd_opts.yes && !systemd_opts.user {
+                ask_confirmation(
+                    &format!(
+                        "{:?} will be written to run ExecStart of the system unit {} by `{}`.",
+                        unit.build_dropin_path()?,
+                        unit.name,
+                        run_command_prefix
+                    ),
+                    "write the drop-in",
+                )?;
+            }
+            let dropin_path = unit.write_override(&exec_start, &run_command_prefix)?;
+            log::info!("Wrote {:?} to debug {}.", dropin_path, exec_start.path);
+        }
+        (false, true) => {
+            let dropin_path = unit.build_dropin_path()?;
+            // remove_override tells if there is no drop-in, without asking
+            if !systemd_opts.yes && !systemd_opts.user && dropin_path.exists() {
+                ask_confirmation(
+                    &format!(
+                        "{:?} will be removed from the system unit {}.",
+                        dropin_path, unit.name
+                    ),
+                    "remove the drop-in",
+                )?;
+            }
+            let dropin_path = unit.remove_override()?;
+            log::info!("Removed {:?}.", dropin_path);
+        }
+        // The service is usually running while it's set, and restarted afterwards
+        (true, false) => set_debuggee(
+            &SetOpts {
+                debuggee: exec_start.path,
+                start_cmd: vec![],
+                backup_suffix: None,
+                force: systemd_opts.force,
+                allow_running: true,
+                activate_env: None,
+                only_interactive: false,
+                yes: systemd_opts.yes,
+                allow_hardlinked: false,
+                once: false,
+                debuggee_arg: vec![],
+                attach_opts: systemd_opts.attach_opts,
+            },
+            true,
+        )?,
+        (true, true) => {
+            let mut debugger = build_debugger(
+                &systemd_opts.attach_opts.debugger,
+                &DebuggerOptions::default(),
+                &exec_start.path,
+            )?;
+            debugger.unset(&exec_start.path)?;
+        }
+    }
+    eprintln!(
+        "Run `{}` to apply it to the running service.",
+        unit.build_restart_command()
+    );
+    Ok(())
+}
+
+/// Sets the debuggee, and logs the debug sessions of it until Ctrl+C. Then, unsets the debuggee.
+fn watch_debuggee(set_opts: &SetOpts) -> Result<()> {
+    if !set_opts.start_cmd.is_empty() {
+        bail!("watch doesn't take start_cmd. Use set instead.");
+    }
+    if set_opts.once {
+        bail!("watch doesn't take --once. Use set instead.");
+    }
+    // The running instances are what `watch` is often about to replace
+    set_debuggee(set_opts, true)?;
+
+    let stopped = Arc::new(AtomicBool::new(false));
+    let stopped_by_handler = stopped.clone();
+    let watch_result = ctrlc::set_handler(move || stopped_by_handler.store(true, Ordering::SeqCst))
+        .context("Failed to set the Ctrl+C handler")
+        .and_then(|_| {
+            log::info!(
+                "Watching {}. Press Ctrl+C to unset it and exit.",
+                &set_opts.debuggee
+            );
+            watch_debug_sessions(&set_opts.debuggee, &stopped)
+        });
+
+    // Unset even if watching failed, not to leave the wrapper script
+    let mut debugger = build_debugger(
+        &set_opts.attach_opts.debugger,
+        &set_opts.attach_opts.build_debugger_options(),
+        &set_opts.debuggee,
+    )?;
+    debugger.unset(&set_opts.debuggee)?;
+    log::info!("Unset {}", &set_opts.debuggee);
+    watch_result
+}
+
+/// Logs the start and the end of each debug session, that is, each `dbgee run` process which the wrapper script
+/// of `debuggee` launched, until `stopped` becomes true.
+fn watch_debug_sessions(debuggee: &str, stopped: &AtomicBool) -> Result<()> {
+    let debuggee_abspath = get_abspath(debuggee, "debuggee")?;
+    let backup_path = get_backup_path_of_wrapper(&debuggee_abspath);
+    let self_path = env::current_exe().context("Failed to get the path of dbgee")?;
+    let mut sessions: HashSet<sysinfo::Pid> = HashSet::new();
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+
+    while !stopped.load(Ordering::SeqCst) {
+        sysinfo_system.refresh_processes();
+        let current_sessions: HashSet<sysinfo::Pid> = sysinfo_system
+            .get_processes()
+            .iter()
+            .filter(|(_, process)| {
+                process.exe() == self_path && process.cmd().iter().any(|arg| arg == &backup_path)
+            })
+            .map(|(&pid, _)| pid)
+            .collect();
+        for pid in current_sessions.difference(&sessions) {
+            log::info!(
+                "{} was launched. A debug session started (dbgee pid: {})",
+                debuggee,
+                pid
+            );
+        }
+        for pid in sessions.difference(&current_sessions) {
+            log::info!("The debug session ended (dbgee pid: {})", pid);
+        }
+        sessions = current_sessions;
+        std::thread::sleep(WATCH_POLL_INTERVAL);
+    }
+    Ok(())
+}
+
+/// Interval to look for the debug sessions in `watch`
+const WATCH_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Orders `--debugger` and `--extra-debugger` for `run`. Returns the debugger which launches the debuggee,
+/// and the ones which attach to it afterwards.
+///
+/// python, jdb, node-inspect and rdbg launch the debuggee because their debug servers run in the debuggee's process.
+fn order_debuggers(
+    attach_opts: &AttachOpts,
+) -> Result<(Option<DebuggerOptValues>, Vec<DebuggerOptValues>)> {
+    if attach_opts.extra_debugger.is_empty() {
+        return Ok((attach_opts.debugger, vec![]));
+    }
+    let mut debuggers: Vec<DebuggerOptValues> = attach_opts
+        .debugger
+        .iter()
+        .chain(attach_opts.extra_debugger.iter())
+        .copied()
+        .collect();
+    let mut unique_debuggers = HashSet::new();
+    for debugger in &debuggers {
+        if !unique_debuggers.insert(debugger) {
+            bail!("{:?} is given more than once as a debugger", debugger);
+        }
+    }
+    if debuggers.contains(&DebuggerOptValues::Dlv) {
+        bail!(
+            "dlv cannot be combined with other debuggers, because it traces the debuggee by ptrace"
+        );
+    }
+    if debuggers.contains(&DebuggerOptValues::Rr) {
+        bail!(
+            "rr cannot be combined with other debuggers, because it records the debuggee by ptrace"
+        );
+    }
+    let is_in_process = |debugger: &DebuggerOptValues| {
+        matches!(
+            debugger,
+            DebuggerOptValues::Debugpy
+                | DebuggerOptValues::Jdb
+                | DebuggerOptValues::NodeInspect
+                | DebuggerOptValues::Rdbg
+        )
+    };
+    let (in_process, tracing): (Vec<DebuggerOptValues>, Vec<DebuggerOptValues>) =
+        debuggers.iter().partition(|d| is_in_process(d));
+    if in_process.len() > 1 {
+        bail!(
+            "only one of python, jdb, node-inspect and rdbg can be given, but {:?} are given",
+            in_process
+        );
+    }
+    if tracing.len() > 1 {
+        bail!(
+            "only one of gdb, lldb and stop-and-write-pid can be given, because a process allows only one \
+            tracer, but {:?} are given",
+            tracing
+        );
+    }
+    // Stable, so that the order of the others is kept
+    debuggers.sort_by_key(|debugger| !is_in_process(debugger));
+    let launching_debugger = debuggers.remove(0);
+    Ok((Some(launching_debugger), debuggers))
+}
+
+/// Attaches `debuggers` of `--extra-debugger` to the debuggee `pid`, which the first debugger launched
+fn attach_extra_debuggers(
+    pid: Pid,
+    debuggers: &[DebuggerOptValues],
+    attach_opts: &AttachOpts,
+    terminal: &mut dyn DebuggerTerminal,
+) -> Result<()> {
+    if debuggers.is_empty() {
+        return Ok(());
+    }
+    // The debuggee may be run by an interpreter, such as python or java
+    let exe_path = get_exe_path_of(pid)?;
+    let exe_path = exe_path
+        .to_str()
+        .ok_or_else(|| anyhow!("executable path is not a valid utf-8 str"))?;
+    for debugger_type in debuggers {
+        let mut debugger = build_debugger(
+            &Some(*debugger_type),
+            &attach_opts.build_debugger_options(),
+            exe_path,
+        )?;
+        debugger
+            .attach(pid, exe_path, terminal)
+            .with_context(|| format!("{:?} failed to attach {}", debugger_type, pid))?;
+    }
+    Ok(())
+}
+
+/// Attaches the debugger to each child process of `parent`, up to `max_children`.
+fn attach_to_children(
+    parent: Pid,
+    max_children: usize,
+    attach_opts: &AttachOpts,
+) -> Result<Vec<Option<AttachedProcess>>> {
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+    sysinfo_system.refresh_processes();
+    let mut children: Vec<(Pid, PathBuf)> = sysinfo_system
+        .get_processes()
+        .iter()
+        .filter(|(_, process)| process.parent() == Some(parent.as_raw()))
+        .map(|(&pid, process)| (Pid::from_raw(pid), process.exe().to_path_buf()))
+        .collect();
+    if children.is_empty() {
+        bail!("process {} has no child processes.", parent);
+    }
+    children.sort();
+    if children.len() > max_children {
+        log::warn!(
+            "process {} has {} child processes. Attaching to only {} of them. Use --max-children to change the limit.",
+            parent,
+            children.len(),
+            max_children
+        );
+        children.truncate(max_children);
+    }
+
+    children
+        .into_iter()
+        .map(|(pid, exe_path)| attach_to_process(pid, &exe_path, attach_opts))
+        .collect()
+}
+
+/// Launches the debugger and its terminal for the running process `pid`.
+/// Returns the state of the process before the attach, unless it cannot be captured on this platform.
+fn attach_to_process(
+    pid: Pid,
+    exe_path: &Path,
+    attach_opts: &AttachOpts,
+) -> Result<Option<AttachedProcess>> {
+    let exe_path = exe_path
+        .to_str()
+        .ok_or_else(|| anyhow!("executable path is not a valid utf-8 str"))?;
+    let mut debugger = build_debugger(
+        &attach_opts.debugger,
+        &attach_opts.build_debugger_options(),
+        exe_path,
+    )?;
+    let mut debugger_terminal = build_debugger_terminal(attach_opts);
+    let attached_process = AttachedProcess::capture(pid);
+    if let Err(e) = debugger.attach(pid, exe_path, debugger_terminal.as_mut()) {
+        // Such as --capture-core, which stops the process to dump it, may fail in the middle
+        if let Some(ref attached_process) = attached_process {
+            attached_process.restore_running().debug_log_error();
+        }
+        return Err(e).with_context(|| format!("debugger failed to attach {}", pid));
+    }
+    Ok(attached_process)
+}
+
+/// How long `attach` waits for the debugger to start tracing the process
+const DEBUGGER_TRACE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Process which `attach` attaches a debugger to, with its state before the attach.
+/// The running process must not be left stopped after the debugger detaches from it.
+struct AttachedProcess {
+    pid: Pid,
+    was_stopped: bool,
+}
+
+impl AttachedProcess {
+    fn capture(pid: Pid) -> Option<AttachedProcess> {
+        match get_process_state(pid) {
+            Ok(state) => Some(AttachedProcess {
+                pid,
+                was_stopped: is_stopped_state(state),
+            }),
+            Err(e) => {
+                debug!("the state of {} is not captured: {:?}", pid, e);
+                None
+            }
+        }
+    }
+
+    /// Waits for the debugger to start tracing the process and to stop tracing it.
+    /// Gives up waiting if no debugger starts tracing it in `DEBUGGER_TRACE_TIMEOUT`.
+    fn wait_detach(&self) -> Result<()> {
+        let started_at = std::time::Instant::now();
+        let mut has_been_traced = false;
+        loop {
+            if signal::kill(self.pid, None).is_err() {
+                // The process has exited
+                return Ok(());
+            }
+            if get_tracer_pid(self.pid)?.is_some() {
+                has_been_traced = true;
+            } else if has_been_traced {
+                return Ok(());
+            } else if started_at.elapsed() >= DEBUGGER_TRACE_TIMEOUT {
+                debug!("no debugger has started tracing {}", self.pid);
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    /// Resumes the process by SIGCONT if it is left stopped, though it was running before the attach
+    fn restore_running(&self) -> Result<()> {
+        if self.was_stopped || get_tracer_pid(self.pid)?.is_some() {
+            return Ok(());
+        }
+        match get_process_state(self.pid) {
+            Ok(state) if is_stopped_state(state) => {
+                log::info!(
+                    "The debugger left the process {} stopped. Resuming it, as it was running before the attach.",
+                    self.pid
+                );
+                signal::kill(self.pid, Signal::SIGCONT)
+                    .with_context(|| format!("Failed to resume {}", self.pid))
+            }
+            // The process may have exited
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns true if `state` in /proc/<pid>/status is a stop by a signal or ptrace
+fn is_stopped_state(state: char) -> bool {
+    state == 'T' || state == 't'
+}
+
+fn get_exe_path_of(pid: Pid) -> Result<PathBuf> {
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+    sysinfo_system.refresh_process(pid.as_raw());
+    sysinfo_system
+        .get_process(pid.as_raw())
+        .map(|process| process.exe().to_path_buf())
+        .ok_or_else(|| anyhow!("process {} is not found", pid))
+}
+
+/// Bails if dbgee itself is traced, e.g. it's run under gdb or strace. dbgee needs to ptrace the debuggee,
+/// and the nested tracing makes it fail in confusing ways in the middle of the flow.
+fn bail_if_dbgee_is_traced() -> Result<()> {
+    if let Some(tracer_pid) =
+        get_tracer_pid(unistd::getpid()).context("Failed to check if dbgee is traced")?
+    {
+        bail!(
+            "dbgee is traced by another process (pid: {}), such as a debugger or strace. \
+            Running dbgee under a tracer is not supported, because dbgee itself needs to trace the debuggee.",
+            tracer_pid
+        );
+    }
+    Ok(())
+}
+
 fn bail_if_not_executable(debuggee: &str) -> Result<()> {
     if !is_executable(debuggee) {
         bail!(
@@ -241,35 +1923,354 @@ fn bail_if_not_executable(debuggee: &str) -> Result<()> {
     Ok(())
 }
 
+/// Directories whose binaries are considered to belong to the system
+const SYSTEM_BINARY_PREFIXES: [&str; 3] = ["/usr", "/bin", "/sbin"];
+
+/// Asks the user for confirmation if `debuggee` is a system binary, because `set` replaces it with a wrapper script.
+fn confirm_replacing_system_binary(debuggee: &str) -> Result<()> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let debuggee_path = fs::canonicalize(debuggee)
+        .with_context(|| format!("failed to resolve the path of '{}'", debuggee))?;
+    if !is_system_binary(&debuggee_path, home.as_deref()) {
+        return Ok(());
+    }
+    ask_confirmation(
+        &format!(
+            "'{}' is outside your home directory or a system binary. \
+            It will be replaced with a wrapper script.",
+            debuggee_path.display()
+        ),
+        "set the debuggee",
+    )
+}
+
+/// Asks the user for confirmation of the change described by `question`, and fails unless it's accepted.
+/// The confirmation is skipped if the stderr is not a terminal, so that scripts keep working.
+fn ask_confirmation(question: &str, action: &str) -> Result<()> {
+    if !matches!(unistd::isatty(io::stderr().as_raw_fd()), Ok(true)) {
+        return Ok(());
+    }
+    eprint!("{} Continue? [y/N] ", question);
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .context("failed to read the confirmation")?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        bail!("aborted. Give --yes to {} without confirmation.", action);
+    }
+    Ok(())
+}
+
+fn is_system_binary(debuggee_path: &Path, home: Option<&Path>) -> bool {
+    if SYSTEM_BINARY_PREFIXES
+        .iter()
+        .any(|prefix| debuggee_path.starts_with(prefix))
+    {
+        return true;
+    }
+    match home {
+        Some(home) => !debuggee_path.starts_with(home),
+        None => true,
+    }
+}
+
+/// Returns the command and its arguments to debug. If `--cargo` or `--go-build` is given, build the debuggee
+/// in advance and returns the built binary. `build_dir` is set to a temporary directory if the binary is put in it.
+fn build_debuggee_command(
+    run_opts: &RunOpts,
+    build_dir: &mut Option<tempfile::TempDir>,
+) -> Result<(String, Vec<String>)> {
+    let positional_args: Vec<String> = run_opts
+        .command
+        .iter()
+        .chain(run_opts.command_args.iter())
+        .cloned()
+        .collect();
+    if run_opts.cargo {
+        let mut split = positional_args.splitn(2, |arg| arg == "--");
+        let cargo_args = split.next().unwrap_or_default();
+        let debuggee_args = split.next().unwrap_or_default();
+        return Ok((build_with_cargo(cargo_args)?, debuggee_args.to_vec()));
+    }
+    if let Some(ref package) = run_opts.go_build {
+        let build_dir =
+            build_dir.insert(tempfile::tempdir().context("Failed to create a build directory")?);
+        return Ok((build_with_go(package, build_dir.path())?, positional_args));
+    }
+    let mut positional_args = positional_args.into_iter();
+    let command = positional_args
+        .next()
+        .ok_or_else(|| anyhow!("<command> is not given"))?;
+    Ok((command, positional_args.collect()))
+}
+
+/// Run `cargo build` with `cargo_args` and returns the path of the built executable.
+fn build_with_cargo(cargo_args: &[String]) -> Result<String> {
+    let output = std::process::Command::new("cargo")
+        .arg("build")
+        .arg("--message-format=json-render-diagnostics")
+        .args(cargo_args)
+        .logged()
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .context("Failed to run cargo. Is cargo installed?")?;
+    if !output.status.success() {
+        bail!("cargo build failed");
+    }
+    find_cargo_executable(str::from_utf8(&output.stdout)?)
+}
+
+/// Find the executable from the JSON messages of `cargo build --message-format=json`.
+fn find_cargo_executable(messages: &str) -> Result<String> {
+    let mut executables = vec![];
+    for line in messages.lines().filter(|line| line.starts_with('{')) {
+        let message: serde_json::Value = serde_json::from_str(line)
+            .with_context(|| format!("Failed to parse the message of cargo: {}", line))?;
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+        // It's null for the libraries
+        if let Some(executable) = message["executable"].as_str() {
+            executables.push(executable.to_owned());
+        }
+    }
+    executables.dedup();
+    match executables.len() {
+        0 => bail!("cargo built no executable"),
+        1 => Ok(executables.pop().unwrap()),
+        _ => bail!(
+            "cargo built multiple executables: {}. Please specify one, e.g. by --bin",
+            executables.join(", ")
+        ),
+    }
+}
+
+/// Run `go build` for `package` with optimizations disabled, and returns the path of the built executable.
+fn build_with_go(package: &str, build_dir: &Path) -> Result<String> {
+    let name = Path::new(package)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| *name != ".")
+        .unwrap_or("debuggee");
+    let output_path = build_dir.join(name);
+    let output_path = output_path
+        .to_str()
+        .ok_or_else(|| anyhow!("build directory is not a valid utf-8 path"))?;
+    let status = std::process::Command::new("go")
+        .args(["build", "-gcflags=all=-N -l", "-o", output_path, package])
+        .logged()
+        .status()
+        .context("Failed to run go. Is go installed?")?;
+    if !status.success() {
+        bail!("go build failed");
+    }
+    Ok(output_path.to_owned())
+}
+
+/// Run the debuggee under gdb or lldb in batch mode for `--on-crash`, and returns the exit code of the debugger.
+fn run_under_debugger_on_crash(
+    command: &str,
+    command_args: &[String],
+    run_opts: &RunOpts,
+) -> Result<ExitReason> {
+    let debugger_options = run_opts.attach_opts.build_debugger_options();
+    let debugger = match run_opts.attach_opts.debugger {
+        Some(debugger) => debugger,
+        None if is_zig_binary(command) || cfg!(target_os = "macos") => DebuggerOptValues::Lldb,
+        None => DebuggerOptValues::Gdb,
+    };
+    let commandline = match debugger {
+        DebuggerOptValues::Gdb => GdbDebugger::build_on_crash_commandline(
+            &debugger_options,
+            command,
+            command_args,
+            &run_opts.on_crash,
+        )?,
+        DebuggerOptValues::Lldb => LldbDebugger::build_on_crash_commandline(
+            &debugger_options,
+            command,
+            command_args,
+            &run_opts.on_crash,
+        )?,
+        _ => bail!("--on-crash is supported only by gdb and lldb"),
+    };
+    let status = std::process::Command::new(&commandline[0])
+        .args(&commandline[1..])
+        .logged()
+        .status()
+        .with_context(|| format!("Failed to launch {}", commandline[0]))?;
+    Ok(on_crash_exit_reason(status))
+}
+
+/// The debugger of `--on-crash` quits with 128 + the signal number when the debuggee crashes.
+/// Decode it back to `ExitReason::Signaled`, so that `ExitReason::exit_code` applies as in the other runs.
+fn on_crash_exit_reason(status: std::process::ExitStatus) -> ExitReason {
+    use std::os::unix::process::ExitStatusExt;
+    let signum = match status.code() {
+        Some(code) if code > 128 => code - 128,
+        Some(code) => return ExitReason::Exited(code),
+        // The debugger itself is killed
+        None => status.signal().unwrap_or(0),
+    };
+    match Signal::try_from(signum) {
+        Ok(signal) => ExitReason::Signaled(signal),
+        Err(_) => ExitReason::Exited(128 + signum),
+    }
+}
+
 fn build_debugger(
     debugger: &Option<DebuggerOptValues>,
+    debugger_options: &DebuggerOptions,
+    debuggee: &str,
+) -> Result<Box<dyn Debugger>> {
+    let built = select_debugger(debugger, debugger_options, debuggee)?;
+    bail_if_debugger_path_ignored(built.as_ref(), debugger_options)?;
+    Ok(built)
+}
+
+/// Fails if `--debugger-path` is given but `debugger` doesn't launch it, so that it's not silently ignored
+fn bail_if_debugger_path_ignored(
+    debugger: &dyn Debugger,
+    debugger_options: &DebuggerOptions,
+) -> Result<()> {
+    let honors_debugger_path = debugger_options.debugger_command_template.is_some()
+        || matches!(debugger.name(), "gdb" | "lldb" | "rr" | "lldb-dap");
+    if debugger_options.debugger_path.is_some() && !honors_debugger_path {
+        let hint = if debugger.name() == "dlv" {
+            " Use --dlv-path instead."
+        } else {
+            ""
+        };
+        bail!(
+            "--debugger-path is supported only by gdb, lldb, rr and lldb-dap, but {} is used.{}",
+            debugger.name(),
+            hint
+        );
+    }
+    Ok(())
+}
+
+fn select_debugger(
+    debugger: &Option<DebuggerOptValues>,
+    debugger_options: &DebuggerOptions,
     debuggee: &str,
 ) -> Result<Box<dyn Debugger>> {
+    // No debugger of dbgee can debug it, though `file` and `go version` may make it look supported
+    if is_windows_binary(debuggee) {
+        return Err(build_windows_binary_error(debuggee));
+    }
+    if debugger_options.debugger_command_template.is_some() {
+        return Ok(Box::new(CustomDebugger::build(
+            debugger_options,
+            Box::new(SystemProcessLauncher),
+        )?));
+    }
+    if debugger_options.dap {
+        return match debugger {
+            None | Some(DebuggerOptValues::Gdb) | Some(DebuggerOptValues::Lldb) => Ok(Box::new(
+                LldbDapDebugger::new(debugger_options, Box::new(SystemProcessLauncher))?,
+            )),
+            Some(other) => bail!(
+                "--dap supports only native debuggees, but {:?} is given",
+                other
+            ),
+        };
+    }
     match debugger {
-        None => detect_debugger(debuggee).context("Failed to detect the right debugger"),
-        Some(debugger_type) => match *debugger_type {
-            DebuggerOptValues::Gdb => Ok(Box::new(GdbDebugger::build()?)),
-            DebuggerOptValues::Lldb => Ok(Box::new(LldbDebugger::build()?)),
-            DebuggerOptValues::Dlv => Ok(Box::new(DelveDebugger::new()?)),
-            DebuggerOptValues::StopAndWritePid => Ok(Box::new(StopAndWritePidDebugger::new())),
-            DebuggerOptValues::Debugpy => Ok(Box::new(PythonDebugger::new()?)),
-        },
+        None => detect_debugger(debugger_options, debuggee)
+            .context("Failed to detect the right debugger"),
+        Some(debugger_type) => {
+            let launcher = Box::new(SystemProcessLauncher);
+            match *debugger_type {
+                DebuggerOptValues::Gdb => {
+                    Ok(Box::new(GdbDebugger::build(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::Lldb => {
+                    Ok(Box::new(LldbDebugger::build(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::Dlv => {
+                    Ok(Box::new(DelveDebugger::new(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::StopAndWritePid => Ok(Box::new(StopAndWritePidDebugger::new(
+                    debugger_options,
+                    launcher,
+                ))),
+                DebuggerOptValues::Debugpy => {
+                    Ok(Box::new(PythonDebugger::new(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::Jdb => {
+                    Ok(Box::new(JavaDebugger::new(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::Rr => Ok(Box::new(RrDebugger::new(debugger_options)?)),
+                DebuggerOptValues::NodeInspect => {
+                    Ok(Box::new(NodeDebugger::new(debugger_options, launcher)?))
+                }
+                DebuggerOptValues::Rdbg => {
+                    Ok(Box::new(RubyDebugger::new(debugger_options, launcher)?))
+                }
+            }
+        }
     }
 }
 
-fn detect_debugger(debuggee: &str) -> Result<Box<dyn Debugger>> {
+/// Returns the debugger which attaches to any process by pid on this platform
+fn native_debugger() -> DebuggerOptValues {
+    if cfg!(target_os = "linux") {
+        DebuggerOptValues::Gdb
+    } else {
+        DebuggerOptValues::Lldb
+    }
+}
+
+/// Strips "<file>: " from the output of `file <file>`, leaving the description of the file type
+fn strip_file_output_path<'a>(file_output: &'a str, file: &str) -> &'a str {
+    file_output
+        .strip_prefix(file)
+        .and_then(|output| output.strip_prefix(": "))
+        .unwrap_or(file_output)
+}
+
+fn detect_debugger(
+    debugger_options: &DebuggerOptions,
+    debuggee: &str,
+) -> Result<Box<dyn Debugger>> {
     use DebuggerOptValues::*;
 
-    let debuggers = if cfg!(target_os = "linux") {
+    let rules = config::load_detect_rules().unwrap_or_else(|e| {
+        log::warn!("{:#}. The detect rules are ignored", e);
+        vec![]
+    });
+    if !rules.is_empty() {
+        let file_output = get_cached_file_output(debuggee)?;
+        // The path of the debuggee may contain the pattern
+        let file_output = strip_file_output_path(&file_output, debuggee);
+        if let Some(rule) = rules
+            .iter()
+            .find(|rule| file_output.contains(&rule.file_output))
+        {
+            log::debug!(
+                "{:?} is chosen by the detect rule for '{}'",
+                rule.debugger,
+                rule.file_output
+            );
+            return build_debugger(&Some(rule.debugger), debugger_options, debuggee);
+        }
+    }
+
+    let debuggers: &[DebuggerOptValues] = if is_zig_binary(debuggee) {
+        // lldb understands Zig's debug information better since Zig is built on LLVM.
+        // Fall back to gdb if lldb is not installed
+        &[Lldb, Gdb, StopAndWritePid]
+    } else if cfg!(target_os = "linux") {
         // prefer gdb to lldb  in Linux
-        [Dlv, Gdb, Debugpy, StopAndWritePid]
+        &[Dlv, Jdb, NodeInspect, Rdbg, Gdb, Debugpy, StopAndWritePid]
     } else {
         // macOS
         // prefer lldb
-        [Dlv, Lldb, Debugpy, StopAndWritePid]
+        &[Dlv, Jdb, NodeInspect, Rdbg, Lldb, Debugpy, StopAndWritePid]
     };
     for debugger in debuggers.iter() {
-        let candidate = build_debugger(&Some(*debugger), debuggee);
+        let candidate = build_debugger(&Some(*debugger), debugger_options, debuggee);
         if candidate.is_err() {
             continue;
         }
@@ -281,14 +2282,165 @@ fn detect_debugger(debuggee: &str) -> Result<Box<dyn Debugger>> {
     bail!("Could not automatically detect the proper debugger for the given debuggee")
 }
 
-fn build_debugger_terminal(terminal: &Option<TerminalOptValues>) -> Box<dyn DebuggerTerminal> {
+/// Returns true if `debuggee` is a PE file, that is, a Windows binary
+fn is_windows_binary(debuggee: &str) -> bool {
+    let file = match fs::File::open(debuggee) {
+        Ok(file) => file,
+        Err(_) => return false,
+    };
+    matches!(
+        object::FileKind::parse(&object::ReadCache::new(file)),
+        Ok(object::FileKind::Pe32) | Ok(object::FileKind::Pe64)
+    )
+}
+
+/// Builds the error for a Windows binary, with the guidance to debug it by a Windows debugger under WSL
+fn build_windows_binary_error(debuggee: &str) -> anyhow::Error {
+    let is_wsl = env::var_os("WSL_DISTRO_NAME").is_some()
+        || Path::new("/proc/sys/fs/binfmt_misc/WSLInterop").exists();
+    if !is_wsl {
+        return anyhow!(
+            "{} is a Windows binary, which dbgee cannot debug. Debug it on Windows.",
+            debuggee
+        );
+    }
+    let image_name = Path::new(debuggee)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| debuggee.to_owned());
+    anyhow!(
+        "{} is a Windows binary, which runs by WSL interop. dbgee cannot debug Windows processes. \
+        Launch it, and attach your Windows debugger to it, e.g. by `windbg.exe -pn {}` or \
+        \"Attach to Process\" of Visual Studio. `tasklist.exe /FI \"IMAGENAME eq {}\"` shows its Windows PID.",
+        debuggee,
+        image_name,
+        image_name
+    )
+}
+
+/// Executables which extract and run the actual application, to which the debugger should attach instead
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SelfExtractingKind {
+    AppImage,
+    Makeself,
+}
+
+/// Detects an AppImage by its magic "AI" in the padding of the ELF identification, and a self-extracting
+/// archive of makeself by the comment in its header
+fn detect_self_extracting(debuggee: &str) -> Option<SelfExtractingKind> {
+    let mut header = vec![];
+    fs::File::open(debuggee)
+        .and_then(|file| file.take(4096).read_to_end(&mut header))
+        .ok()?;
+    if header.starts_with(b"\x7fELF")
+        && matches!(header.get(8..11), Some(b"AI\x01") | Some(b"AI\x02"))
+    {
+        return Some(SelfExtractingKind::AppImage);
+    }
+    if header.starts_with(b"#!")
+        && String::from_utf8_lossy(&header).contains("This script was generated using Makeself")
+    {
+        return Some(SelfExtractingKind::Makeself);
+    }
+    None
+}
+
+/// Builds the guidance to debug the application in a self-extracting executable, not its launcher
+fn build_self_extracting_guidance(debuggee: &str, kind: SelfExtractingKind) -> String {
+    match kind {
+        SelfExtractingKind::AppImage => format!(
+            "{} is an AppImage, whose runtime mounts the application and executes it. The debugger will attach \
+            to the runtime, not to the application. Extract it by `{} --appimage-extract` and debug \
+            squashfs-root/AppRun or the executable in it, or give `--reattach-on-exec` or `--hook-executable` \
+            to attach to the application.",
+            debuggee, debuggee
+        ),
+        SelfExtractingKind::Makeself => format!(
+            "{} is a self-extracting archive, which extracts the application and executes it. The debugger will \
+            attach to the extracting script, not to the application. Extract it by `{} --noexec --target <dir>` \
+            and debug the executable in <dir>, or give `--hook-executable` to attach to the application.",
+            debuggee, debuggee
+        ),
+    }
+}
+
+/// Parses `<debugger>=<terminal>` of `--terminal-map`
+fn parse_terminal_map_entry(entry: &str) -> Result<(String, TerminalOptValues)> {
+    let (debugger, terminal) = entry
+        .split_once('=')
+        .ok_or_else(|| anyhow!("'{}' is not in the form of <debugger>=<terminal>", entry))?;
+    if !DebuggerOptValues::VARIANTS.contains(&debugger) && debugger != "lldb-dap" {
+        bail!(
+            "unknown debugger '{}'. Choose one of {} and lldb-dap",
+            debugger,
+            DebuggerOptValues::VARIANTS.join(", ")
+        );
+    }
+    let terminal = terminal.parse().map_err(|_| {
+        anyhow!(
+            "unknown terminal '{}'. Choose one of {}",
+            terminal,
+            TerminalOptValues::VARIANTS.join(", ")
+        )
+    })?;
+    Ok((debugger.to_owned(), terminal))
+}
+
+/// Debuggers whose only client is VSCode. `--terminal-map` can override them.
+const VSCODE_ONLY_DEBUGGERS: [&str; 2] = ["debugpy", "lldb-dap"];
+
+fn build_debugger_terminal(attach_opts: &AttachOpts) -> Box<dyn DebuggerTerminal> {
+    let default: Box<dyn DebuggerTerminal> = if attach_opts.print_connect {
+        Box::new(PrintConnect::new(attach_opts.debugger_script.clone()))
+    } else {
+        build_terminal_of(
+            attach_opts
+                .terminal
+                .unwrap_or_else(detect_debugger_terminal),
+            attach_opts,
+        )
+    };
+    // `--terminal none` asks for no client even for the debuggers whose client is only VSCode
+    let vscode_only_debuggers: &[&str] = match attach_opts.terminal {
+        Some(TerminalOptValues::None) => &[],
+        _ => &VSCODE_ONLY_DEBUGGERS,
+    };
+    let terminal_map = vscode_only_debuggers
+        .iter()
+        .map(|debugger| (debugger.to_string(), TerminalOptValues::Vscode))
+        .chain(attach_opts.terminal_map.iter().cloned());
+    let terminals: HashMap<String, Box<dyn DebuggerTerminal>> = terminal_map
+        .map(|(debugger, terminal)| (debugger, build_terminal_of(terminal, attach_opts)))
+        .collect();
+    Box::new(PerDebuggerTerminal::new(default, terminals))
+}
+
+fn build_terminal_of(
+    terminal: TerminalOptValues,
+    attach_opts: &AttachOpts,
+) -> Box<dyn DebuggerTerminal> {
     match terminal {
-        None => build_debugger_terminal(&Some(detect_debugger_terminal())),
-        Some(terminal) => match *terminal {
-            TerminalOptValues::Tmuxw => Box::new(Tmux::new(TmuxLayout::NewWindow)),
-            TerminalOptValues::Tmuxp => Box::new(Tmux::new(TmuxLayout::NewPane)),
-            TerminalOptValues::Vscode => Box::new(VsCode::new()),
-        },
+        TerminalOptValues::Tmuxw => Box::new(Tmux::new(
+            TmuxLayout::NewWindow,
+            attach_opts.debugger_script.clone(),
+            attach_opts.tmux_session.clone(),
+            attach_opts.debuggee_name.clone(),
+        )),
+        TerminalOptValues::Tmuxp => Box::new(Tmux::new(
+            TmuxLayout::NewPane,
+            attach_opts.debugger_script.clone(),
+            attach_opts.tmux_session.clone(),
+            attach_opts.debuggee_name.clone(),
+        )),
+        TerminalOptValues::Vscode => {
+            let adapter = match attach_opts.vscode_adapter {
+                VsCodeAdapterOptValues::Auto => VsCodeAdapter::Auto,
+                VsCodeAdapterOptValues::Codelldb => VsCodeAdapter::CodeLldb,
+                VsCodeAdapterOptValues::Cpptools => VsCodeAdapter::Cpptools,
+            };
+            Box::new(VsCode::new(adapter))
+        }
+        TerminalOptValues::None => Box::new(NoTerminal),
     }
 }
 
@@ -336,19 +2488,23 @@ fn is_in_vscode_term() -> bool {
     inner().unwrap_or(false)
 }
 
-/// wait for pid to exit and returns its exit code
-fn wait_pid_exit(pid: Pid) -> Result<i32> {
-    let exitcode_signaled = 130;
+/// wait for pid to exit and returns how it ended. If dbgee is requested to detach, returns `Detached` without waiting.
+fn wait_pid_exit(pid: Pid) -> Result<ExitReason> {
     loop {
+        // SIGUSR1 interrupts waitpid by EINTR
+        if session::is_detach_requested() {
+            session::detach(pid)?;
+            return Ok(ExitReason::Detached);
+        }
         match wait::waitpid(pid, None) {
             Ok(wait::WaitStatus::Exited(_, exit_status)) => {
-                return Ok(exit_status);
+                return Ok(ExitReason::Exited(exit_status));
             }
-            Ok(wait::WaitStatus::Signaled(_, _, _)) => {
-                return Ok(exitcode_signaled);
+            Ok(wait::WaitStatus::Signaled(_, signal, _)) => {
+                return Ok(ExitReason::Signaled(signal));
             }
             Err(nix::Error::Sys(nix::errno::Errno::ECHILD)) => {
-                return Ok(0);
+                return Err(wait_pid_reaped_elsewhere(pid));
             }
             _ => (),
         }
@@ -366,3 +2522,378 @@ impl<T: std::fmt::Debug> ErrorLogger for Result<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_completions() {
+        let mut completions = vec![];
+        write_completions(Shell::Bash, &mut completions);
+        let completions = String::from_utf8(completions).unwrap();
+        for subcommand in [
+            "run",
+            "set",
+            "unset",
+            "attach",
+            "systemd",
+            "completions",
+            "doctor",
+        ] {
+            assert!(completions.contains(subcommand), "{}", subcommand);
+        }
+        assert!(completions.contains("--show-commands"));
+    }
+
+    #[test]
+    fn test_strip_file_output_path() {
+        assert_eq!(
+            "ELF 64-bit LSB executable\n",
+            strip_file_output_path(
+                "/opt/acme launcher/app: ELF 64-bit LSB executable\n",
+                "/opt/acme launcher/app"
+            )
+        );
+        assert_eq!(
+            "unexpected output",
+            strip_file_output_path("unexpected output", "/opt/app")
+        );
+    }
+
+    #[test]
+    fn test_build_attach_env_lines() {
+        let mut info = HashMap::new();
+        info.insert(AttachInformationKey::DebuggerTypeHint, "gdb".to_owned());
+        info.insert(AttachInformationKey::Pid, "42".to_owned());
+        info.insert(
+            AttachInformationKey::ProgramName,
+            "/tmp/it's a program".to_owned(),
+        );
+        info.insert(AttachInformationKey::CorePath, "/tmp/core.42".to_owned());
+        assert_eq!(
+            vec![
+                "export DBGEE_PID='42'",
+                "export DBGEE_DEBUGGER='gdb'",
+                r#"export DBGEE_PROGRAM='/tmp/it'\''s a program'"#,
+                "export DBGEE_CORE='/tmp/core.42'",
+            ],
+            build_attach_env_lines(&info, Pid::from_raw(1))
+        );
+
+        let mut info = HashMap::new();
+        info.insert(AttachInformationKey::DebuggerPort, "5679".to_owned());
+        info.insert(AttachInformationKey::DebuggerHost, "localhost".to_owned());
+        assert_eq!(
+            vec![
+                "export DBGEE_PID='7'",
+                "export DBGEE_PORT='5679'",
+                "export DBGEE_HOST='localhost'",
+            ],
+            build_attach_env_lines(&info, Pid::from_raw(7))
+        );
+    }
+
+    #[test]
+    fn test_exit_reason() {
+        let pid = Pid::from_raw(42);
+        assert_eq!(3, ExitReason::Exited(3).exit_code());
+        assert_eq!(
+            r#"{"pid": 42, "reason": "exited", "code": 3, "exit_code": 3}"#,
+            ExitReason::Exited(3).to_json(pid)
+        );
+        assert_eq!(130, ExitReason::Signaled(Signal::SIGSEGV).exit_code());
+        assert_eq!(
+            r#"{"pid": 42, "reason": "signaled", "signal": "SIGSEGV", "exit_code": 130}"#,
+            ExitReason::Signaled(Signal::SIGSEGV).to_json(pid)
+        );
+        assert_eq!(0, ExitReason::Detached.exit_code());
+        assert_eq!(
+            r#"{"pid": 42, "reason": "detached", "exit_code": 0}"#,
+            ExitReason::Detached.to_json(pid)
+        );
+    }
+
+    #[test]
+    fn test_on_crash_exit_reason() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::ExitStatus;
+        let exited = |code: i32| on_crash_exit_reason(ExitStatus::from_raw(code << 8));
+        assert_eq!(ExitReason::Exited(3), exited(3));
+        assert_eq!(ExitReason::Signaled(Signal::SIGSEGV), exited(128 + 11));
+        assert_eq!(ExitReason::Exited(200), exited(200));
+        assert_eq!(
+            ExitReason::Signaled(Signal::SIGKILL),
+            on_crash_exit_reason(ExitStatus::from_raw(9))
+        );
+    }
+
+    #[test]
+    fn test_order_debuggers() {
+        let order = |args: &[&str]| {
+            let run_opts =
+                RunOpts::from_iter_safe(["run"].iter().chain(args).chain(["--", "cmd"].iter()))
+                    .unwrap();
+            order_debuggers(&run_opts.attach_opts)
+        };
+        assert_eq!(
+            (Some(DebuggerOptValues::Gdb), vec![]),
+            order(&["-d", "gdb"]).unwrap()
+        );
+        assert_eq!(
+            (
+                Some(DebuggerOptValues::Debugpy),
+                vec![DebuggerOptValues::Gdb]
+            ),
+            order(&["-d", "gdb", "--extra-debugger", "debugpy"]).unwrap()
+        );
+        assert_eq!(
+            (
+                Some(DebuggerOptValues::Jdb),
+                vec![DebuggerOptValues::StopAndWritePid]
+            ),
+            order(&["-d", "jdb", "--extra-debugger", "stop-and-write-pid"]).unwrap()
+        );
+        assert!(order(&["-d", "gdb", "--extra-debugger", "lldb"]).is_err());
+        assert!(order(&["-d", "gdb", "--extra-debugger", "dlv"]).is_err());
+        assert!(order(&["-d", "rr", "--extra-debugger", "debugpy"]).is_err());
+        assert!(order(&["-d", "debugpy", "--extra-debugger", "jdb,gdb"]).is_err());
+        assert!(order(&["-d", "gdb", "--extra-debugger", "gdb"]).is_err());
+    }
+
+    #[test]
+    fn test_order_debuggers_with_rdbg() {
+        let order = |args: &[&str]| {
+            let run_opts =
+                RunOpts::from_iter_safe(["run"].iter().chain(args).chain(["--", "cmd"].iter()))
+                    .unwrap();
+            order_debuggers(&run_opts.attach_opts)
+        };
+        // rdbg launches the debuggee since its debug server runs in the Ruby process
+        assert_eq!(
+            (Some(DebuggerOptValues::Rdbg), vec![DebuggerOptValues::Gdb]),
+            order(&["-d", "rdbg", "--extra-debugger", "gdb"]).unwrap()
+        );
+        assert_eq!(
+            (Some(DebuggerOptValues::Rdbg), vec![DebuggerOptValues::Gdb]),
+            order(&["-d", "gdb", "--extra-debugger", "rdbg"]).unwrap()
+        );
+        assert!(order(&["-d", "rdbg", "--extra-debugger", "debugpy"]).is_err());
+    }
+
+    #[test]
+    fn test_build_debuggee_stdio() {
+        let stdio = |args: &[&str]| {
+            let run_opts =
+                RunOpts::from_iter_safe(["run"].iter().chain(args).chain(["--", "cmd"].iter()))
+                    .unwrap();
+            run_opts.attach_opts.build_debuggee_stdio()
+        };
+        assert_eq!(DebuggeeStdio::default(), stdio(&[]));
+        assert_eq!(
+            DebuggeeStdio {
+                stdin: Some(PathBuf::from("in.txt")),
+                stdout: Some(PathBuf::from("/dev/null")),
+                stderr: Some(PathBuf::from("/dev/null")),
+            },
+            stdio(&["--stdin", "in.txt", "--quiet-debuggee"])
+        );
+        assert_eq!(
+            DebuggeeStdio {
+                stdin: None,
+                stdout: Some(PathBuf::from("out.txt")),
+                stderr: None,
+            },
+            stdio(&["--stdout", "out.txt"])
+        );
+    }
+
+    #[test]
+    fn test_parse_terminal_map_entry() {
+        assert!(matches!(
+            parse_terminal_map_entry("dlv=tmuxp"),
+            Ok((debugger, TerminalOptValues::Tmuxp)) if debugger == "dlv"
+        ));
+        assert!(matches!(
+            parse_terminal_map_entry("lldb-dap=vscode"),
+            Ok((debugger, TerminalOptValues::Vscode)) if debugger == "lldb-dap"
+        ));
+        assert!(parse_terminal_map_entry("dlv").is_err());
+        assert!(parse_terminal_map_entry("vim=tmuxp").is_err());
+        assert!(parse_terminal_map_entry("gdb=xterm").is_err());
+
+        let run_opts = RunOpts::from_iter_safe(&[
+            "run",
+            "--terminal-map",
+            "debugpy=tmuxw,gdb=vscode",
+            "--",
+            "cmd",
+        ])
+        .unwrap();
+        assert_eq!(2, run_opts.attach_opts.terminal_map.len());
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(Signal::SIGALRM, parse_signal("SIGALRM").unwrap());
+        assert_eq!(Signal::SIGALRM, parse_signal("alrm").unwrap());
+        assert_eq!(Signal::SIGUSR1, parse_signal("10").unwrap());
+        assert!(parse_signal("SIGFOO").is_err());
+        assert!(parse_signal("0").is_err());
+        assert_eq!(Signal::SIGSEGV, parse_handoff_signal("SEGV").unwrap());
+        assert!(parse_handoff_signal("KILL").is_err());
+        assert!(parse_handoff_signal("SIGCHLD").is_err());
+    }
+
+    #[test]
+    fn test_parse_memory_limit() {
+        assert_eq!(1048576, parse_memory_limit("1048576").unwrap());
+        assert_eq!(512 << 20, parse_memory_limit("512M").unwrap());
+        assert_eq!(2 << 30, parse_memory_limit("2g").unwrap());
+        assert!(parse_memory_limit("0").is_err());
+        assert!(parse_memory_limit("M").is_err());
+        assert!(parse_memory_limit("1T").is_err());
+        assert!(parse_memory_limit("99999999999G").is_err());
+    }
+
+    #[test]
+    fn test_parse_tmux_session() {
+        assert_eq!("dbgee", parse_tmux_session("dbgee").unwrap());
+        assert!(parse_tmux_session("").is_err());
+        assert!(parse_tmux_session("dbgee:0").is_err());
+        assert!(parse_tmux_session("my.session").is_err());
+    }
+
+    #[test]
+    fn test_parse_debugger_command() {
+        assert_eq!(
+            "cgdb -p {pid}",
+            parse_debugger_command("cgdb -p {pid}").unwrap()
+        );
+        assert!(parse_debugger_command(" ").is_err());
+        assert!(parse_debugger_command("cgdb {program}").is_err());
+        assert!(parse_debugger_command("/dev/null -p {pid}").is_err());
+    }
+
+    #[test]
+    fn test_parse_cpu_limit() {
+        assert_eq!(50, parse_cpu_limit("50").unwrap());
+        assert_eq!(50, parse_cpu_limit("50%").unwrap());
+        assert!(parse_cpu_limit("0").is_err());
+        assert!(parse_cpu_limit("-1").is_err());
+        assert!(parse_cpu_limit("100000000").is_err());
+    }
+
+    #[test]
+    fn test_is_windows_binary() {
+        // The smallest headers which look like a PE32+ file
+        let mut pe = vec![0u8; 512];
+        pe[0..2].copy_from_slice(b"MZ");
+        pe[0x3c] = 0x40;
+        pe[0x40..0x44].copy_from_slice(b"PE\0\0");
+        pe[0x58..0x5a].copy_from_slice(&0x20bu16.to_le_bytes());
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("app.exe");
+        fs::write(&exe, &pe).unwrap();
+        assert!(is_windows_binary(exe.to_str().unwrap()));
+
+        // An MS-DOS executable without the PE header
+        pe[0x40..0x44].copy_from_slice(b"NE\0\0");
+        fs::write(&exe, &pe).unwrap();
+        assert!(!is_windows_binary(exe.to_str().unwrap()));
+
+        assert!(!is_windows_binary("/bin/sh"));
+        assert!(!is_windows_binary("/nonexistent"));
+    }
+
+    #[test]
+    fn test_detect_self_extracting() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("MyApp.AppImage");
+        let mut elf = vec![0u8; 64];
+        elf[0..4].copy_from_slice(b"\x7fELF");
+        elf[8..11].copy_from_slice(b"AI\x02");
+        fs::write(&exe, &elf).unwrap();
+        let exe = exe.to_str().unwrap();
+        assert_eq!(
+            Some(SelfExtractingKind::AppImage),
+            detect_self_extracting(exe)
+        );
+        assert_eq!(
+            format!(
+                "{} is an AppImage, whose runtime mounts the application and executes it. The debugger will \
+                attach to the runtime, not to the application. Extract it by `{} --appimage-extract` and debug \
+                squashfs-root/AppRun or the executable in it, or give `--reattach-on-exec` or \
+                `--hook-executable` to attach to the application.",
+                exe, exe
+            ),
+            build_self_extracting_guidance(exe, SelfExtractingKind::AppImage)
+        );
+
+        let installer = dir.path().join("installer.run");
+        fs::write(
+            &installer,
+            "#!/bin/sh\n# This script was generated using Makeself 2.4.0\n",
+        )
+        .unwrap();
+        let installer = installer.to_str().unwrap();
+        assert_eq!(
+            Some(SelfExtractingKind::Makeself),
+            detect_self_extracting(installer)
+        );
+        assert_eq!(
+            format!(
+                "{} is a self-extracting archive, which extracts the application and executes it. \
+                The debugger will attach to the extracting script, not to the application. \
+                Extract it by `{} --noexec --target <dir>` and debug the executable in <dir>, \
+                or give `--hook-executable` to attach to the application.",
+                installer, installer
+            ),
+            build_self_extracting_guidance(installer, SelfExtractingKind::Makeself)
+        );
+
+        assert_eq!(None, detect_self_extracting("/bin/sh"));
+        assert_eq!(None, detect_self_extracting("/nonexistent"));
+    }
+
+    #[test]
+    fn test_is_system_binary() {
+        let home = Path::new("/home/user");
+        assert!(is_system_binary(Path::new("/usr/bin/service"), Some(home)));
+        assert!(is_system_binary(Path::new("/sbin/init"), Some(home)));
+        assert!(is_system_binary(Path::new("/opt/app/bin"), Some(home)));
+        assert!(!is_system_binary(
+            Path::new("/home/user/project/target/debug/app"),
+            Some(home)
+        ));
+        assert!(is_system_binary(Path::new("/home/user/app"), None));
+    }
+
+    #[test]
+    fn test_find_cargo_executable() {
+        let messages = indoc::indoc! {r#"
+            {"reason":"compiler-artifact","package_id":"lib","target":{"kind":["lib"]},"executable":null,"fresh":true}
+            {"reason":"compiler-artifact","package_id":"hello","target":{"kind":["bin"]},"executable":"/path/to/target/debug/hello","fresh":false}
+            {"reason":"build-finished","success":true}
+        "#};
+        assert_eq!(
+            "/path/to/target/debug/hello",
+            find_cargo_executable(messages).unwrap()
+        );
+
+        let messages = indoc::indoc! {r#"
+            {"reason":"compiler-artifact","executable":"/target/debug/a"}
+            {"reason":"compiler-artifact","executable":"/target/debug/b"}
+        "#};
+        assert!(find_cargo_executable(messages).is_err());
+        assert!(find_cargo_executable("").is_err());
+
+        // The path is unescaped as JSON
+        let messages =
+            r#"{"reason":"compiler-artifact","executable":"/target/debug/a \"b\"\\c\u00e9"}"#;
+        assert_eq!(
+            "/target/debug/a \"b\"\\cé",
+            find_cargo_executable(messages).unwrap()
+        );
+    }
+}
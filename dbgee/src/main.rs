@@ -1,5 +1,9 @@
 use colored::*;
-use dbgee::{run, set_vscode_communication_fifo_path_prefix, LogLevel, Opts};
+use dbgee::{
+    remove_created_fifos, run, set_fifo_cleanup, set_show_commands,
+    set_vscode_communication_fifo_path_prefix, set_vscode_json_format, JsonFormat, LogLevel,
+    LogTarget, Opts, SystemLogger,
+};
 use nix::unistd;
 use structopt::StructOpt;
 
@@ -12,17 +16,26 @@ use std::{
 
 fn main() {
     let opts = Opts::from_args();
-    init_logger(&opts.log_level);
+    init_logger(&opts.log_level, opts.log_target);
+    set_show_commands(opts.show_commands);
+    set_fifo_cleanup(!opts.no_fifo_cleanup);
 
     if let Some(ref fifo_prefix) = opts.vscode_fifo_prefix {
         set_vscode_communication_fifo_path_prefix(fifo_prefix.clone())
             .expect("Failed to set the VSCode fifo prefix");
     }
+    set_vscode_json_format(JsonFormat {
+        pretty: opts.vscode_json_pretty,
+        trailing_newline: opts.vscode_json_newline,
+    })
+    .expect("Failed to set the VSCode JSON format");
 
-    match run(opts) {
-        Ok(exit_status) => {
-            log::debug!("exiting with {}", exit_status);
-            std::process::exit(exit_status);
+    let result = run(opts);
+    remove_created_fifos();
+    match result {
+        Ok(exit_reason) => {
+            log::debug!("exiting by {:?}", exit_reason);
+            std::process::exit(exit_reason.exit_code());
         }
         Err(e) => {
             log::error!("{:?}", e);
@@ -31,18 +44,30 @@ fn main() {
     }
 }
 
-fn init_logger(log_level: &Option<LogLevel>) {
+fn to_level_filter(level: LogLevel) -> log::LevelFilter {
+    log::LevelFilter::from_str(<LogLevel as strum::VariantNames>::VARIANTS[level as usize]).unwrap()
+}
+
+fn init_logger(log_level: &Option<LogLevel>, log_target: LogTarget) {
+    if log_target != LogTarget::Stderr {
+        // Nobody watches syslog and journald as a tty, so that info messages are not suppressed by default
+        let level = log_level.map_or(log::LevelFilter::Info, to_level_filter);
+        match SystemLogger::new(log_target, level).and_then(SystemLogger::init) {
+            Ok(()) => return,
+            Err(e) => eprintln!(
+                "{} {:#}. Writing the messages to stderr instead.",
+                "[Dbgee]".bright_green(),
+                e
+            ),
+        }
+    }
+
     let mut env_logger_builder = env_logger::Builder::new();
 
     let should_show_info_suppression_notice;
     if let Some(ref level) = log_level {
         should_show_info_suppression_notice = false;
-        env_logger_builder.filter_level(
-            log::LevelFilter::from_str(
-                <LogLevel as strum::VariantNames>::VARIANTS[*level as usize],
-            )
-            .unwrap(),
-        );
+        env_logger_builder.filter_level(to_level_filter(*level));
     } else if let Ok(true) = unistd::isatty(std::io::stderr().as_raw_fd()) {
         should_show_info_suppression_notice = true;
         env_logger_builder.filter_level(log::LevelFilter::Info);
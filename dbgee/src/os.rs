@@ -1,4 +1,6 @@
+use anyhow::{anyhow, bail, Result};
 use cfg_if::cfg_if;
+use nix::{errno::Errno, sys::signal, unistd::Pid};
 
 cfg_if! {
     if #[cfg(target_os = "linux")] {
@@ -10,4 +12,33 @@ cfg_if! {
     }
 }
 
-pub use os::{is_any_hook_condition_set, run_hook, HookOpts};
+pub use os::{
+    create_cpu_limit_cgroup, enter_cgroup, find_missing_comp_dirs, get_process_state,
+    get_ptrace_scope, get_tracer_pid, is_any_hook_condition_set, is_port_listening, is_zig_binary,
+    print_dwarf_source_files, remove_cpu_limit_cgroup, run_hook, run_with_syscall_summary,
+    set_parent_death_signal, wait_non_child_pid_exit, wait_pid_exit_reattaching_on_exec, HookOpts,
+};
+
+/// Handles ECHILD of waitpid for the debuggee `pid`, which means that the process was reaped by another waiter
+/// or is not a child of dbgee. Waits for the process to disappear and returns the error to report.
+pub fn wait_pid_reaped_elsewhere(pid: Pid) -> anyhow::Error {
+    if let Err(e) = wait_non_child_pid_exit(pid) {
+        return e.context(format!("Failed to wait for the debuggee (pid: {})", pid));
+    }
+    anyhow!(
+        "The debuggee (pid: {}) was reaped by another waiter. Its exit status is unknown",
+        pid
+    )
+}
+
+/// Waits for `pid`, which is not a child of this process, to exit by polling its existence.
+/// The platforms use it where they can't be notified of the exit.
+fn poll_non_child_pid_exit(pid: Pid) -> Result<()> {
+    loop {
+        match signal::kill(pid, None) {
+            Err(nix::Error::Sys(Errno::ESRCH)) => return Ok(()),
+            Err(e) => bail!("Failed to check if pid {} exists: {}", pid, e),
+            Ok(_) => std::thread::sleep(std::time::Duration::from_millis(100)),
+        }
+    }
+}
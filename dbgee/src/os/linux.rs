@@ -1,19 +1,27 @@
 use core::slice;
 use std::{
     borrow::Cow,
-    collections::HashSet,
+    collections::{HashMap, HashSet, VecDeque},
     fs::{self, File},
-    os::unix::prelude::{AsRawFd, CommandExt},
+    os::unix::{
+        fs::MetadataExt,
+        prelude::{AsRawFd, CommandExt},
+    },
     path::{Path, PathBuf},
     process::Command,
     ptr::null_mut,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, bail, Context, Result};
 use log::{debug, info, trace};
 use nix::{
     errno::Errno,
-    libc::{PTRACE_EVENT_CLONE, PTRACE_EVENT_FORK, PTRACE_EVENT_VFORK},
+    libc::{
+        PTRACE_EVENT_CLONE, PTRACE_EVENT_EXEC, PTRACE_EVENT_FORK, PTRACE_EVENT_STOP,
+        PTRACE_EVENT_VFORK,
+    },
+    poll,
     sys::{
         mman::{mmap, MapFlags, ProtFlags},
         ptrace, signal, wait,
@@ -21,28 +29,128 @@ use nix::{
     unistd::Pid,
 };
 use object::{Object, ObjectSection};
+use regex::Regex;
 use structopt::StructOpt;
+use strum::{EnumString, EnumVariantNames, VariantNames as _};
 
 use crate::{
-    build_debugger, build_debugger_terminal, file_helper::get_abspath, AttachOpts, ErrorLogger,
+    build_debugger, build_debugger_terminal,
+    config::HookProfile,
+    debugger::{hand_over_held_debuggee, HoldFile, TakingOverTerminal},
+    debugger_terminal::escape_json_string,
+    file_helper::{get_abspath, get_cached_file_output, LoggedCommand},
+    parse_signal, wait_pid_exit, AttachOpts, DebuggerTerminal, ErrorLogger, ExitReason,
 };
 
+mod syscall_names;
+
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab")]
 pub struct HookOpts {
     #[structopt(short = "e", long)]
     /// Attach not to <command> itself, but to a descendant process whose executable file is the specified path.
+    /// A script matches it as well when an interpreter such as python or bash runs it.
     hook_executable: Option<PathBuf>,
 
     #[structopt(short = "s", long)]
     /// Attach not to <command> itself, but to a descendant process which is built from any of the given source files.
     /// A process binary must include DWARF debug information, which compilers usually emit for a debug build.
+    /// `@<file>` reads newline-separated source files from <file>, e.g. the output of `git diff --name-only`.
+    /// Any compiled language works, such as `.c`, `.rs` and `.zig`.
     hook_source: Option<Vec<String>>,
 
     #[structopt(short = "i", long)]
     /// Attach not to <command> itself, but to a descendant process which is built from any files under the given directory.
     /// A process binary must include DWARF debug information, which compilers usually emit for a debug build.
     hook_source_dir: Option<PathBuf>,
+
+    #[structopt(long, value_name = "dir-or-glob", number_of_values = 1)]
+    /// Don't let the source files under this directory trigger `--hook-source-dir`, e.g. vendored sources or
+    /// dependencies such as `~/.cargo`. A glob such as `**/vendor` matches the path of a source file or
+    /// any of its parent directories. Can be given multiple times.
+    hook_source_exclude: Vec<String>,
+
+    #[structopt(long, value_name = "id")]
+    /// Attach not to <command> itself, but to a descendant process whose executable has the given GNU build-id,
+    /// in hex such as the output of `readelf -n` or `file`. Useful to attach only to the exact artifact of a build.
+    hook_build_id: Option<String>,
+
+    #[structopt(long, value_name = "name")]
+    /// Attach not to <command> itself, but to a descendant process which loads the given shared library,
+    /// e.g. a plugin loaded by `dlopen`. <name> is a file name such as `libfoo.so` or `libfoo`, or a path.
+    /// dbgee polls the memory maps of the processes, so the debugger attaches shortly after the library is loaded.
+    hook_library: Option<String>,
+
+    #[structopt(long)]
+    /// Print the tree of the processes which dbgee observed while waiting for a hook condition, with their
+    /// executable paths. Useful to find the right hook condition.
+    trace_fork_tree: bool,
+
+    #[structopt(long, value_name = "signal", number_of_values = 1, parse(try_from_str = parse_signal))]
+    /// Don't re-deliver this signal to the processes which dbgee traces while waiting for a hook condition,
+    /// e.g. a noisy `SIGALRM`. A name such as `SIGALRM` or `ALRM`, or a number. Can be given multiple times.
+    hook_suppress_signal: Vec<signal::Signal>,
+
+    #[structopt(long, value_name = "signal", number_of_values = 1, parse(try_from_str = parse_signal))]
+    /// Send this signal to the hooked process when dbgee detaches from it to hand it off to the debugger.
+    /// The process is stopped by SIGSTOP as usual, and the debugger sees the signal after attaching to it.
+    /// Can be given multiple times.
+    hook_detach_signal: Vec<signal::Signal>,
+
+    #[structopt(long)]
+    /// Keep the hooked process in the ptrace-stop of dbgee until the debugger takes it over, instead of detaching
+    /// from it before launching the debugger. This closes the window where the stopped process can be resumed
+    /// by others before the debugger attaches. The debugger launched in tmux takes it over right before attaching,
+    /// and VSCode or `--terminal none` does once it receives the attach information. For the debuggers which
+    /// dbgee doesn't launch, such as stop-and-write-pid, remove the hold file which dbgee prints. (Linux only)
+    keep_stopped: bool,
+
+    #[structopt(long, default_value = "first", possible_values(HookSelect::VARIANTS))]
+    /// Which process to attach to when multiple processes trigger the hook conditions around the same time.
+    /// "first" attaches to the first one dbgee finds. "newest" and "oldest" collect the processes triggering
+    /// the conditions for a short while after the first one, and attach to the one with the latest or
+    /// earliest start time. The others continue without the debugger.
+    hook_select: HookSelect,
+
+    #[structopt(long, value_name = "duration", parse(try_from_str = crate::sampler::parse_interval))]
+    /// Attach only to a process which keeps running for this duration after triggering the hook conditions,
+    /// and still triggers them then, such as "500ms" or "2s". A number without a unit is in milliseconds.
+    /// The processes which exit earlier are ignored, which skips the transient children of fork-heavy launchers.
+    /// The process runs during the duration, so the debugger attaches to it a bit later than usual.
+    hook_settle: Option<Duration>,
+
+    #[structopt(long)]
+    /// Log the environment variables of the hooked process, to see how it was invoked, and pass them to VSCode
+    /// as `debuggeeEnv` of the attach information. Opt-in, because they may contain secrets.
+    /// Its working directory is always logged and passed as `debuggeeCwd`.
+    hook_show_env: bool,
+
+    #[structopt(long)]
+    /// Launch the debugger in the working directory of the hooked process, so that relative paths such as
+    /// the ones to the sources resolve as the process sees them. Effective for gdb and lldb.
+    hook_debugger_cwd: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+enum HookSelect {
+    First,
+    Newest,
+    Oldest,
+}
+
+impl HookOpts {
+    /// Fills the hook conditions which are not given by the command line with `profile`
+    pub fn apply_profile(&mut self, profile: HookProfile) {
+        self.hook_executable = self.hook_executable.take().or(profile.hook_executable);
+        self.hook_source = self.hook_source.take().or(profile.hook_source);
+        self.hook_source_dir = self.hook_source_dir.take().or(profile.hook_source_dir);
+        self.hook_library = self.hook_library.take().or(profile.hook_library);
+        if self.hook_source_exclude.is_empty() {
+            self.hook_source_exclude = profile.hook_source_exclude;
+        }
+        self.hook_build_id = self.hook_build_id.take().or(profile.hook_build_id);
+    }
 }
 
 pub fn is_any_hook_condition_set(hook_opts: &HookOpts) -> bool {
@@ -50,11 +158,24 @@ pub fn is_any_hook_condition_set(hook_opts: &HookOpts) -> bool {
         hook_executable,
         hook_source,
         hook_source_dir,
+        hook_source_exclude: _,
+        hook_build_id,
+        hook_library,
+        trace_fork_tree: _,
+        hook_suppress_signal: _,
+        hook_detach_signal: _,
+        keep_stopped: _,
+        hook_select: _,
+        hook_settle: _,
+        hook_show_env: _,
+        hook_debugger_cwd: _,
     } = hook_opts;
     [
         hook_executable.is_some(),
         hook_source.is_some(),
         hook_source_dir.is_some(),
+        hook_build_id.is_some(),
+        hook_library.is_some(),
     ]
     .iter()
     .any(|cond| *cond)
@@ -67,30 +188,115 @@ pub fn run_hook(
     hook_opts: HookOpts,
     attach_opts: AttachOpts,
 ) -> Result<()> {
-    let terminal = &mut build_debugger_terminal(&attach_opts.terminal);
+    if hook_opts.keep_stopped && attach_opts.capture_core_only {
+        bail!("--keep-stopped holds the hooked process for the debugger, which --capture-core-only doesn't attach");
+    }
+    let terminal = &mut build_debugger_terminal(&attach_opts);
 
     let hook_conditions = build_hook_conditions(
         hook_opts.hook_executable,
         hook_opts.hook_source,
         hook_opts.hook_source_dir,
+        hook_opts.hook_source_exclude,
+        hook_opts.hook_build_id,
     )
     .context("failed to build hook conditions")?;
+    // Libraries can be loaded any time after exec. Check them not only on exec but also periodically
+    let library_condition = hook_opts
+        .hook_library
+        .map(build_hook_library_condition)
+        .transpose()
+        .context("Failed to build hook library condition")?;
 
     let start_command_pid = spawn_traced_command(command, command_args)
         .context("Failed to spawn the traced command")?;
+    let mut fork_tree = ForkTree::new(start_command_pid);
+    let mut hook_selection = HookSelection::new(hook_opts.hook_select);
+    let mut hook_settling = hook_opts.hook_settle.map(HookSettling::new);
+    let conditions: Vec<&dyn HookCondition> = hook_conditions
+        .iter()
+        .map(|cond| cond.as_ref())
+        .chain(
+            library_condition
+                .iter()
+                .map(|cond| cond as &dyn HookCondition),
+        )
+        .collect();
 
     // wait for a process triggering the hook condition
-    let (hooked_command_pid, hooked_command_path) = loop {
-        debug!("waiting for a SIGTRAP, that is, a new process");
-        let pid = match wait_sigtrap().context("Failed to wait until next SIGTRAP")? {
-            Some(pid) => pid,
+    let (hooked_command_pid, hooked_command_path) = 'wait: loop {
+        if let Some(selected) = hook_selection.select_if_settled() {
+            break selected;
+        }
+        if let Some(hook_settling) = hook_settling.as_mut() {
+            for pid in hook_settling.take_settled(Instant::now()) {
+                if !confirm_settled_process(
+                    &mut fork_tree,
+                    pid,
+                    &conditions,
+                    &hook_opts.hook_suppress_signal,
+                )? {
+                    continue;
+                }
+                let exe_path = get_exe_path(pid).context("Failed to get an executable path")?;
+                if let Some(selected) = hook_selection.on_match(pid, exe_path)? {
+                    break 'wait selected;
+                }
+            }
+        }
+        let is_settling = hook_settling
+            .as_ref()
+            .is_some_and(HookSettling::is_settling);
+        trace!("waiting for a SIGTRAP, that is, a new process");
+        let pid = match wait_sigtrap(
+            &mut fork_tree,
+            library_condition.is_some() || hook_selection.is_settling() || is_settling,
+            &hook_opts.hook_suppress_signal,
+        )
+        .context("Failed to wait until next SIGTRAP")?
+        {
+            SigtrapWaitResult::Trapped(pid) => pid,
+            SigtrapWaitResult::NotYet => {
+                if let Some(library_condition) = library_condition.as_ref() {
+                    let mut matched_pids = hook_selection.matched_pids();
+                    matched_pids.extend(hook_settling.iter().flat_map(HookSettling::pids));
+                    if let Some(pid) = find_process_loading_library(
+                        &mut fork_tree,
+                        library_condition,
+                        &conditions,
+                        &matched_pids,
+                        &hook_opts.hook_suppress_signal,
+                    )? {
+                        if let Some(hook_settling) = hook_settling.as_mut() {
+                            hook_settling.start(pid, Instant::now());
+                            continue;
+                        }
+                        let exe_path =
+                            get_exe_path(pid).context("Failed to get an executable path")?;
+                        debug!("hooking exe_path: {:?}", &exe_path);
+                        if let Some(selected) = hook_selection.on_match(pid, exe_path)? {
+                            break selected;
+                        }
+                        continue;
+                    }
+                }
+                std::thread::sleep(LIBRARY_POLL_INTERVAL);
+                continue;
+            }
             // All ancestor processes exited without triggering the condition.
-            None => {
+            SigtrapWaitResult::NoChildren => {
                 info!("No process triggered the hook condition");
+                if hook_opts.trace_fork_tree {
+                    eprint!("{}", fork_tree.format_tree());
+                }
                 return Ok(());
             }
         };
         debug!("a new process({}) is trapped", pid);
+        // A settling process which exec-ed another executable is checked again as a new process
+        if let Some(hook_settling) = hook_settling.as_mut() {
+            hook_settling.forget(pid);
+        }
 
         // ptrace all ancestor processes to find any process which meets the hook condition
         if pid == start_command_pid {
@@ -103,65 +309,889 @@ pub fn run_hook(
             .context("Failed to set a ptrace option")?;
         }
 
-        if hook_conditions
-            .iter()
-            .map(|cond| cond.hooks(pid))
-            .collect::<Result<Vec<bool>>>()
-            .context("Failed to check a hook condition")?
-            .iter()
-            .any(|b| *b)
-        {
+        if triggers_hook_conditions(&conditions, pid)? {
             let exe_path = get_exe_path(pid).context("Failed to get an executable path")?;
             debug!("hooking exe_path: {:?}", &exe_path);
-            break (pid, exe_path);
+            fork_tree.on_exec(pid, Some(exe_path.clone()), false);
+            if let Some(hook_settling) = hook_settling.as_mut() {
+                hook_settling.start(pid, Instant::now());
+                continue;
+            }
+            if let Some(selected) = hook_selection.on_match(pid, exe_path)? {
+                break selected;
+            }
+            continue;
+        }
+        if hook_opts.trace_fork_tree {
+            fork_tree.on_exec(pid, get_exe_path(pid).ok(), false);
         }
 
         // This executable is not the target one, let it continue
         ptrace::cont(pid, None).with_context(|| format!("Failed to ptrace::continue {}", pid))?;
     };
+    fork_tree.on_hook(hooked_command_pid);
+    // The processes which completed execve while the hooked one was being stopped are no longer checked
+    for pid in fork_tree.pending_traps.drain(..) {
+        ptrace::detach(pid, None)
+            .context("Failed to detach from a process which completed execve")
+            .debug_log_error();
+    }
+    let (hooked_cwd, hooked_env) =
+        show_hooked_process_context(hooked_command_pid, hook_opts.hook_show_env);
 
     // Detach from the hooked process so that the debugger can attach it.
-    ptrace::detach(hooked_command_pid, signal::SIGSTOP).with_context(|| {
-        format!(
-            "Failed to detach from the hooked process pid:{} path: {:?}",
-            hooked_command_pid, &hooked_command_path
-        )
-    })?;
+    // With --keep-stopped, it's held until the debugger starts instead.
+    if !hook_opts.keep_stopped {
+        ptrace::detach(hooked_command_pid, signal::SIGSTOP).with_context(|| {
+            format!(
+                "Failed to detach from the hooked process pid:{} path: {:?}",
+                hooked_command_pid, &hooked_command_path
+            )
+        })?;
+        send_detach_signals(hooked_command_pid, &hook_opts.hook_detach_signal)?;
+    }
+    let hold_file = if hook_opts.keep_stopped {
+        Some(HoldFile::create(hooked_command_pid)?)
+    } else {
+        None
+    };
+    let mut debugger_options = attach_opts.build_debugger_options();
+    if hook_opts.hook_debugger_cwd {
+        debugger_options.debugger_cwd = hooked_cwd.clone();
+    }
+    debugger_options.debuggee_cwd = hooked_cwd;
+    debugger_options.debuggee_env = hooked_env;
     let mut debugger = build_debugger(
         &attach_opts.debugger,
+        &debugger_options,
         hooked_command_path
             .to_str()
             .ok_or_else(|| anyhow!("executable path is not a valid utf-8 str"))?,
     )?;
+    let mut taking_over_terminal;
+    let attaching_terminal: &mut dyn DebuggerTerminal = match hold_file {
+        Some(ref hold_file) => {
+            taking_over_terminal =
+                TakingOverTerminal::new(terminal.as_mut(), hold_file, hooked_command_pid);
+            &mut taking_over_terminal
+        }
+        None => terminal.as_mut(),
+    };
     debugger
         .attach(
             hooked_command_pid,
             hooked_command_path
                 .to_str()
                 .ok_or_else(|| anyhow!("exe_path is not a valid utf-8 path"))?,
-            terminal.as_mut(),
+            attaching_terminal,
         )
         .with_context(|| format!("debugger failed to attach {}", hooked_command_pid))?;
+    if let Some(ref hold_file) = hold_file {
+        info!(
+            "To attach a debugger which dbgee didn't launch, remove {} right before attaching it.",
+            hold_file.path().display()
+        );
+        hand_over_held_debuggee(hooked_command_pid, hold_file).with_context(|| {
+            format!(
+                "Failed to hand over the hooked process pid:{} to the debugger",
+                hooked_command_pid
+            )
+        })?;
+        send_detach_signals(hooked_command_pid, &hook_opts.hook_detach_signal)?;
+    }
 
     // wait until the start command exits, while detaching from any other processes
-    wait_pid_exit_and_detach_other(start_command_pid)
+    wait_pid_exit_and_detach_other(start_command_pid, &hook_opts.hook_suppress_signal)
         .context("Failed to wait for pid to exit while detaching other")?;
+    if hook_opts.trace_fork_tree {
+        eprint!("{}", fork_tree.format_tree());
+    }
+
+    Ok(())
+}
+
+/// How long `--hook-select newest|oldest` keeps collecting the processes triggering the hook conditions
+/// after the first one
+const HOOK_SELECT_SETTLE_WINDOW: Duration = Duration::from_millis(200);
+
+/// A process which triggered the hook conditions, kept stopped until `HookSelection` selects one
+#[derive(Debug)]
+struct HookMatch {
+    pid: Pid,
+    exe_path: PathBuf,
+    start_time: u64,
+}
+
+/// Selects the process to attach to from the processes triggering the hook conditions by `--hook-select`
+struct HookSelection {
+    select: HookSelect,
+    matches: Vec<HookMatch>,
+    settles_at: Option<Instant>,
+}
+
+impl HookSelection {
+    fn new(select: HookSelect) -> Self {
+        HookSelection {
+            select,
+            matches: vec![],
+            settles_at: None,
+        }
+    }
+
+    /// Records the stopped process `pid` which triggered the hook conditions.
+    /// Returns the process to attach to if it's selected without waiting for the others.
+    fn on_match(&mut self, pid: Pid, exe_path: PathBuf) -> Result<Option<(Pid, PathBuf)>> {
+        if self.select == HookSelect::First {
+            return Ok(Some((pid, exe_path)));
+        }
+        let start_time = get_process_start_time(pid)?;
+        debug!("pid {} (start time: {}) matched", pid, start_time);
+        self.matches.push(HookMatch {
+            pid,
+            exe_path,
+            start_time,
+        });
+        self.settles_at
+            .get_or_insert_with(|| Instant::now() + HOOK_SELECT_SETTLE_WINDOW);
+        Ok(None)
+    }
+
+    fn is_settling(&self) -> bool {
+        self.settles_at.is_some()
+    }
+
+    fn matched_pids(&self) -> Vec<Pid> {
+        self.matches.iter().map(|m| m.pid).collect()
+    }
+
+    /// Returns the selected process once the settle window has passed, letting the other matched processes
+    /// continue by detaching from them
+    fn select_if_settled(&mut self) -> Option<(Pid, PathBuf)> {
+        match self.settles_at {
+            Some(settles_at) if Instant::now() >= settles_at => {}
+            _ => return None,
+        }
+        let selected = self
+            .matches
+            .swap_remove(select_hook_match(&self.matches, self.select));
+        for other in self.matches.drain(..) {
+            debug!("detaching from pid {}, which is not selected", other.pid);
+            ptrace::detach(other.pid, None)
+                .context("Failed to detach from a process which is not selected")
+                .debug_log_error();
+        }
+        Some((selected.pid, selected.exe_path))
+    }
+}
+
+/// Processes which triggered the hook conditions and keep running for `--hook-settle` until they are confirmed
+struct HookSettling {
+    settle: Duration,
+    /// Pids with the time when their settle duration ends
+    pending: Vec<(Pid, Instant)>,
+}
+
+impl HookSettling {
+    fn new(settle: Duration) -> Self {
+        HookSettling {
+            settle,
+            pending: vec![],
+        }
+    }
+
+    /// Lets the stopped process `pid`, which triggered the hook conditions at `now`, continue until it settles
+    fn start(&mut self, pid: Pid, now: Instant) {
+        debug!("pid {} matched. Waiting for it to settle", pid);
+        self.forget(pid);
+        self.pending.push((pid, now + self.settle));
+        ptrace::cont(pid, None)
+            .context("Failed to do PTRACE_CONT for a settling process")
+            .debug_log_error();
+    }
+
+    fn forget(&mut self, pid: Pid) {
+        self.pending.retain(|(pending, _)| *pending != pid);
+    }
+
+    fn is_settling(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    fn pids(&self) -> Vec<Pid> {
+        self.pending.iter().map(|(pid, _)| *pid).collect()
+    }
+
+    /// Removes and returns the processes whose settle duration has ended by `now`, in the order of their matches
+    fn take_settled(&mut self, now: Instant) -> Vec<Pid> {
+        let (settled, pending) = self
+            .pending
+            .iter()
+            .partition(|(_, settles_at)| *settles_at <= now);
+        self.pending = pending;
+        settled.into_iter().map(|(pid, _)| pid).collect()
+    }
+}
+
+/// Stops the process `pid` which has kept running for `--hook-settle`, and returns true if it still triggers
+/// `conditions`. Returns false if it has exited, or lets it continue if it no longer triggers them.
+fn confirm_settled_process(
+    fork_tree: &mut ForkTree,
+    pid: Pid,
+    conditions: &[&dyn HookCondition],
+    suppressed_signals: &[signal::Signal],
+) -> Result<bool> {
+    // Exited processes are reaped by `wait_sigtrap`, and their pids may have been reused by processes
+    // which are not traced
+    let is_traced =
+        matches!(get_tracer_pid(pid), Ok(Some(tracer)) if tracer == nix::unistd::getpid());
+    if !is_traced
+        || signal::kill(pid, signal::SIGSTOP).is_err()
+        || !wait_signal_stop(fork_tree, pid, signal::SIGSTOP, suppressed_signals)?
+    {
+        debug!("pid {} exited before it settled", pid);
+        return Ok(false);
+    }
+    if triggers_hook_conditions(conditions, pid)? {
+        return Ok(true);
+    }
+    debug!("pid {} no longer triggers the hook conditions", pid);
+    ptrace::cont(pid, None)
+        .context(
+            "Failed to do PTRACE_CONT for a process which no longer triggers the hook conditions",
+        )
+        .debug_log_error();
+    Ok(false)
+}
+
+/// Returns true if the process `pid` triggers any of `conditions`
+fn triggers_hook_conditions(conditions: &[&dyn HookCondition], pid: Pid) -> Result<bool> {
+    Ok(conditions
+        .iter()
+        .map(|cond| cond.hooks(pid))
+        .collect::<Result<Vec<bool>>>()
+        .context("Failed to check a hook condition")?
+        .iter()
+        .any(|b| *b))
+}
+
+/// Returns the index of the match which `select` selects. Ties of the start times are broken by the pids.
+fn select_hook_match(matches: &[HookMatch], select: HookSelect) -> usize {
+    let key = |i: &usize| (matches[*i].start_time, matches[*i].pid.as_raw());
+    let indices = 0..matches.len();
+    match select {
+        HookSelect::First => 0,
+        HookSelect::Newest => indices.max_by_key(key).unwrap(),
+        HookSelect::Oldest => indices.min_by_key(key).unwrap(),
+    }
+}
+
+/// Returns the start time of `pid` in clock ticks after the system boot
+fn get_process_start_time(pid: Pid) -> Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid.as_raw()))
+        .with_context(|| format!("Failed to read /proc/{}/stat", pid.as_raw()))?;
+    parse_start_time(&stat).ok_or_else(|| {
+        anyhow!(
+            "Failed to parse the start time in /proc/{}/stat",
+            pid.as_raw()
+        )
+    })
+}
+
+/// Parses starttime, the 22nd field of /proc/<pid>/stat. The fields are counted after the command name,
+/// the 2nd field in parentheses, since it may contain spaces and parentheses.
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(22 - 3)?.parse().ok()
+}
+
+/// Sends the signals of `--hook-detach-signal` to the hooked process.
+/// The signals stay pending while the process is stopped, and the debugger catches them.
+fn send_detach_signals(pid: Pid, signals: &[signal::Signal]) -> Result<()> {
+    for &sig in signals {
+        signal::kill(pid, sig)
+            .with_context(|| format!("Failed to send {} to the hooked process pid:{}", sig, pid))?;
+    }
+    Ok(())
+}
+
+/// Tree of the processes which `run_hook` observed, built from fork and exec events
+#[derive(Debug)]
+struct ForkTree {
+    root: Pid,
+    nodes: HashMap<Pid, ForkTreeNode>,
+    /// Processes which completed execve while dbgee waited for another process, left for `wait_sigtrap`
+    pending_traps: VecDeque<Pid>,
+}
+
+#[derive(Debug, Default)]
+struct ForkTreeNode {
+    children: Vec<Pid>,
+    /// Executable paths which the process exec-ed, in order. None if it's unknown
+    exe_paths: Vec<Option<PathBuf>>,
+    hooked: bool,
+}
+
+impl ForkTree {
+    fn new(root: Pid) -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(root, ForkTreeNode::default());
+        ForkTree {
+            root,
+            nodes,
+            pending_traps: VecDeque::new(),
+        }
+    }
+
+    fn on_fork(&mut self, parent: Pid, child: Pid) {
+        self.nodes.entry(parent).or_default().children.push(child);
+        self.nodes.entry(child).or_default();
+    }
+
+    fn on_exec(&mut self, pid: Pid, exe_path: Option<PathBuf>, hooked: bool) {
+        let node = self.nodes.entry(pid).or_default();
+        node.exe_paths.push(exe_path);
+        node.hooked |= hooked;
+    }
+
+    /// Marks the process hooked not on exec, e.g. on loading a library
+    fn on_hook(&mut self, pid: Pid) {
+        self.nodes.entry(pid).or_default().hooked = true;
+    }
+
+    /// Format the tree with indentation, one process per line
+    fn format_tree(&self) -> String {
+        let mut tree = String::from("Process tree observed by dbgee:\n");
+        let mut stack = vec![(self.root, 0)];
+        while let Some((pid, depth)) = stack.pop() {
+            let node = match self.nodes.get(&pid) {
+                Some(node) => node,
+                None => continue,
+            };
+            let exe_paths = match node.exe_paths.len() {
+                0 => "(no exec)".to_owned(),
+                _ => node
+                    .exe_paths
+                    .iter()
+                    .map(|exe_path| match exe_path {
+                        Some(exe_path) => exe_path.to_string_lossy().into_owned(),
+                        None => "(unknown)".to_owned(),
+                    })
+                    .collect::<Vec<String>>()
+                    .join(" -> "),
+            };
+            tree.push_str(&format!(
+                "{}{} {}{}\n",
+                "  ".repeat(depth),
+                pid,
+                exe_paths,
+                if node.hooked { " [hooked]" } else { "" }
+            ));
+            stack.extend(node.children.iter().rev().map(|child| (*child, depth + 1)));
+        }
+        tree
+    }
+}
+
+/// Run the command while counting its syscalls, and print the summary like `strace -c` when the command exits or
+/// gets any of `handoff_signals`. On the signal, the debugger attaches to the command.
+///
+/// Only the main thread of the command is traced.
+pub fn run_with_syscall_summary(
+    command: String,
+    command_args: Vec<String>,
+    handoff_signals: &[signal::Signal],
+    attach_opts: &AttachOpts,
+) -> Result<ExitReason> {
+    // The registers of syscalls are read only for x86_64. Fail before the command runs
+    if !cfg!(target_arch = "x86_64") {
+        bail!("--strace-summary is supported only on x86_64");
+    }
+    let mut terminal = build_debugger_terminal(attach_opts);
+    // Ignore the signals so that dbgee can print the summary after Ctrl-C, for example
+    for &sig in handoff_signals {
+        unsafe { signal::signal(sig, signal::SigHandler::SigIgn) }
+            .with_context(|| format!("Failed to ignore {}", sig))?;
+    }
+
+    let pid = spawn_traced_command(command, command_args)
+        .context("Failed to spawn the traced command")?;
+    let (summary, exit_status) =
+        trace_syscalls(pid, handoff_signals).context("Failed to trace syscalls")?;
+    eprint!("{}", summary.format_table());
+    if let Some(exit_status) = exit_status {
+        return Ok(ExitReason::Exited(exit_status));
+    }
+
+    // Hand off the command to the debugger
+    let exe_path = get_exe_path(pid).context("Failed to get an executable path")?;
+    let exe_path = exe_path
+        .to_str()
+        .ok_or_else(|| anyhow!("exe_path is not a valid utf-8 path"))?;
+    ptrace::detach(pid, signal::SIGSTOP)
+        .with_context(|| format!("Failed to detach from the traced command pid:{}", pid))?;
+    let mut debugger = build_debugger(
+        &attach_opts.debugger,
+        &attach_opts.build_debugger_options(),
+        exe_path,
+    )?;
+    debugger
+        .attach(pid, exe_path, terminal.as_mut())
+        .with_context(|| format!("debugger failed to attach {}", pid))?;
+    wait_pid_exit(pid)
+}
+
+/// Count syscalls of the traced process `pid` until it exits, or it gets any of `stop_signals`.
+/// Returns the summary and the exit code, which is None if it stopped by the signal.
+fn trace_syscalls(
+    pid: Pid,
+    stop_signals: &[signal::Signal],
+) -> Result<(SyscallSummary, Option<i32>)> {
+    let exitcode_signaled = 130;
+    // wait for SIGTRAP by execve
+    wait::waitpid(pid, None).context("Failed to wait for the command to start")?;
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACESYSGOOD | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+    .context("Failed to set a ptrace option")?;
+
+    let mut summary = SyscallSummary::default();
+    let mut signal_to_deliver = None;
+    loop {
+        ptrace::syscall(pid, signal_to_deliver)
+            .with_context(|| format!("Failed to do PTRACE_SYSCALL for {}", pid))?;
+        signal_to_deliver = None;
+        match wait::waitpid(pid, None).context("Failed to wait for the traced command")? {
+            wait::WaitStatus::PtraceSyscall(_) => summary.on_syscall_stop(pid)?,
+            wait::WaitStatus::Stopped(_, sig) if stop_signals.contains(&sig) => {
+                debug!("pid {} got {}", pid, sig);
+                return Ok((summary, None));
+            }
+            wait::WaitStatus::Stopped(_, sig) => signal_to_deliver = Some(sig),
+            wait::WaitStatus::Exited(_, exit_status) => return Ok((summary, Some(exit_status))),
+            wait::WaitStatus::Signaled(_, _, _) => return Ok((summary, Some(exitcode_signaled))),
+            other => trace!("other wait event: {:#?}", other),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct SyscallStat {
+    calls: u64,
+    errors: u64,
+    time: Duration,
+}
+
+/// Statistics of syscalls per syscall number
+#[derive(Debug, Default)]
+struct SyscallSummary {
+    stats: HashMap<u64, SyscallStat>,
+    /// Number of the syscall which the process is in, and the time when it entered the syscall
+    current_syscall: Option<(u64, Instant)>,
+}
+
+impl SyscallSummary {
+    /// Update the statistics at a syscall-enter-stop or syscall-exit-stop
+    fn on_syscall_stop(&mut self, pid: Pid) -> Result<()> {
+        let (number, return_value) = read_syscall_registers(pid)?;
+        match self.current_syscall.take() {
+            None => self.current_syscall = Some((number, Instant::now())),
+            Some((number, entered_at)) => {
+                let stat = self.stats.entry(number).or_default();
+                stat.calls += 1;
+                stat.time += entered_at.elapsed();
+                // syscalls return -errno on errors
+                if (-4095..0).contains(&return_value) {
+                    stat.errors += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Format the statistics as a table like `strace -c`
+    fn format_table(&self) -> String {
+        let mut stats: HashMap<u64, SyscallStat> = self.stats.clone();
+        // The process exited in the last syscall, e.g. exit_group
+        if let Some((number, _)) = self.current_syscall {
+            stats.entry(number).or_default().calls += 1;
+        }
+        let mut stats: Vec<(u64, SyscallStat)> = stats.into_iter().collect();
+        stats.sort_by(|(n1, s1), (n2, s2)| s2.time.cmp(&s1.time).then(n1.cmp(n2)));
+
+        let total_time: Duration = stats.iter().map(|(_, stat)| stat.time).sum();
+        let total_calls: u64 = stats.iter().map(|(_, stat)| stat.calls).sum();
+        let total_errors: u64 = stats.iter().map(|(_, stat)| stat.errors).sum();
+        let separator = "------ ----------- ----------- --------- --------- ----------------\n";
+        let mut table = format!(
+            "{:>6} {:>11} {:>11} {:>9} {:>9} {}\n{}",
+            "% time", "seconds", "usecs/call", "calls", "errors", "syscall", separator
+        );
+        let percent = |time: Duration| {
+            if total_time.is_zero() {
+                0.0
+            } else {
+                time.as_secs_f64() * 100.0 / total_time.as_secs_f64()
+            }
+        };
+        let format_errors = |errors: u64| match errors {
+            0 => "".to_owned(),
+            errors => errors.to_string(),
+        };
+        for (number, stat) in stats.iter() {
+            let name = syscall_names::syscall_name(*number)
+                .map(Cow::Borrowed)
+                .unwrap_or_else(|| Cow::Owned(format!("syscall_{}", number)));
+            table.push_str(&format!(
+                "{:>6.2} {:>11.6} {:>11} {:>9} {:>9} {}\n",
+                percent(stat.time),
+                stat.time.as_secs_f64(),
+                stat.time.as_micros() as u64 / stat.calls,
+                stat.calls,
+                format_errors(stat.errors),
+                name
+            ));
+        }
+        table.push_str(separator);
+        table.push_str(&format!(
+            "{:>6.2} {:>11.6} {:>11} {:>9} {:>9} total\n",
+            100.0,
+            total_time.as_secs_f64(),
+            "",
+            total_calls,
+            format_errors(total_errors)
+        ));
+        table
+    }
+}
+
+/// Returns the syscall number and the return value of the traced process `pid` at a syscall stop
+#[cfg(target_arch = "x86_64")]
+fn read_syscall_registers(pid: Pid) -> Result<(u64, i64)> {
+    let regs =
+        ptrace::getregs(pid).with_context(|| format!("Failed to get registers of {}", pid))?;
+    Ok((regs.orig_rax, regs.rax as i64))
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn read_syscall_registers(_pid: Pid) -> Result<(u64, i64)> {
+    bail!("syscall tracing is supported only on x86_64");
+}
+
+/// Wait for `pid` to exit and returns how it ended, like `wait_pid_exit`.
+/// In addition, whenever `pid` re-executes itself and no debugger is tracing it anymore,
+/// this re-runs the detection of the debugger and attaches a new one to it.
+///
+/// While no debugger traces `pid`, dbgee traces it with `PTRACE_O_TRACEEXEC` to catch `PTRACE_EVENT_EXEC`.
+/// There is no event for a debugger attaching or detaching, so dbgee checks it at an interval while
+/// another tracer traces `pid`, or `pid` is stopped and waits for a debugger.
+pub fn wait_pid_exit_reattaching_on_exec(
+    pid: Pid,
+    attach_opts: &AttachOpts,
+    terminal: &mut dyn DebuggerTerminal,
+) -> Result<ExitReason> {
+    let mut exe_id = get_exe_id(pid).context("Failed to get the executable of the debuggee")?;
+    loop {
+        match wait::waitpid(pid, Some(wait::WaitPidFlag::WNOHANG)) {
+            Ok(wait::WaitStatus::Exited(_, exit_status)) => {
+                return Ok(ExitReason::Exited(exit_status))
+            }
+            Ok(wait::WaitStatus::Signaled(_, signal, _)) => {
+                return Ok(ExitReason::Signaled(signal))
+            }
+            Err(nix::Error::Sys(Errno::ECHILD)) => {
+                return Err(super::wait_pid_reaped_elsewhere(pid));
+            }
+            _ => (),
+        }
+
+        // A stopped process without a tracer waits for a debugger to attach
+        let is_free = matches!(get_tracer_pid(pid), Ok(None))
+            && matches!(get_process_state(pid), Ok(state) if state != 'T');
+        if !is_free {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            continue;
+        }
+        // The debuggee may have re-executed itself after the last debugger detached
+        if matches!(get_exe_id(pid), Ok(new_exe_id) if new_exe_id != exe_id) {
+            signal::kill(pid, signal::SIGSTOP)
+                .context("Failed to stop the re-executed debuggee")?;
+        } else {
+            if ptrace::seize(pid, ptrace::Options::PTRACE_O_TRACEEXEC).is_err() {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                continue;
+            }
+            debug!("tracing pid({}) for PTRACE_EVENT_EXEC", pid);
+            match wait_exec_event(pid)? {
+                ExecEvent::Ended(exit_reason) => return Ok(exit_reason),
+                ExecEvent::Stopped => continue,
+                ExecEvent::Executed => (),
+            }
+        }
+        exe_id = get_exe_id(pid).context("Failed to get the executable of the debuggee")?;
+        reattach_on_exec(pid, attach_opts, terminal).debug_log_error();
+    }
+}
+
+/// What ended `wait_exec_event`
+enum ExecEvent {
+    /// The process re-executed itself, and is left stopped by SIGSTOP without a tracer
+    Executed,
+    /// The process got stopped by a signal, and is left stopped without a tracer so that a debugger can attach
+    Stopped,
+    /// The process ended
+    Ended(ExitReason),
+}
+
+/// Waits for `pid`, which this process seized with `PTRACE_O_TRACEEXEC`, to re-execute itself, to stop or to end.
+/// Signals to `pid` are delivered as they are.
+fn wait_exec_event(pid: Pid) -> Result<ExecEvent> {
+    loop {
+        match wait::waitpid(pid, None).with_context(|| format!("Failed to wait for {}", pid))? {
+            wait::WaitStatus::Exited(_, exit_status) => {
+                return Ok(ExecEvent::Ended(ExitReason::Exited(exit_status)))
+            }
+            wait::WaitStatus::Signaled(_, signal, _) => {
+                return Ok(ExecEvent::Ended(ExitReason::Signaled(signal)))
+            }
+            // The signal to deliver is ignored by detach at ptrace-event-stops. Queue SIGSTOP beforehand
+            wait::WaitStatus::PtraceEvent(_, _, PTRACE_EVENT_EXEC) => {
+                signal::kill(pid, signal::SIGSTOP)
+                    .context("Failed to stop the re-executed debuggee")?;
+                ptrace::detach(pid, None)
+                    .with_context(|| format!("Failed to detach from pid {}", pid))?;
+                return Ok(ExecEvent::Executed);
+            }
+            // group-stop of a seized process. It stays stopped after detach
+            wait::WaitStatus::PtraceEvent(_, _, PTRACE_EVENT_STOP) => {
+                ptrace::detach(pid, None)
+                    .with_context(|| format!("Failed to detach from pid {}", pid))?;
+                return Ok(ExecEvent::Stopped);
+            }
+            wait::WaitStatus::Stopped(_, sig) => ptrace::cont(pid, sig)
+                .with_context(|| format!("Failed to ptrace::continue {}", pid))?,
+            other => trace!("other wait event: {:#?}", other),
+        }
+    }
+}
+
+/// Wait for `pid`, which is not a child of this process, to exit.
+///
+/// pidfd is used to wait for the process without races against pid reuse. If pidfd is not available
+/// (Linux < 5.3), fall back to polling the existence of the pid.
+pub fn wait_non_child_pid_exit(pid: Pid) -> Result<()> {
+    let pidfd = unsafe { nix::libc::syscall(nix::libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    if pidfd < 0 {
+        match Errno::last() {
+            Errno::ESRCH => return Ok(()),
+            errno => debug!("pidfd_open failed ({}). Fall back to polling", errno),
+        }
+        return super::poll_non_child_pid_exit(pid);
+    }
 
+    let pidfd = pidfd as i32;
+    let mut poll_fds = [poll::PollFd::new(pidfd, poll::PollFlags::POLLIN)];
+    let result = loop {
+        match poll::poll(&mut poll_fds, -1) {
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            other => break other,
+        }
+    };
+    nix::unistd::close(pidfd)
+        .context("Failed to close pidfd")
+        .debug_log_error();
+    result.with_context(|| format!("Failed to wait for pid {} by pidfd", pid))?;
     Ok(())
 }
 
+fn reattach_on_exec(
+    pid: Pid,
+    attach_opts: &AttachOpts,
+    terminal: &mut dyn DebuggerTerminal,
+) -> Result<()> {
+    let exe_path = get_exe_path(pid).context("Failed to get an executable path")?;
+    info!("The debuggee re-executed itself as {:?}", &exe_path);
+    let exe_path = exe_path
+        .to_str()
+        .ok_or_else(|| anyhow!("executable path is not a valid utf-8 str"))?;
+    let mut debugger = build_debugger(
+        &attach_opts.debugger,
+        &attach_opts.build_debugger_options(),
+        exe_path,
+    )?;
+    debugger
+        .attach(pid, exe_path, terminal)
+        .with_context(|| format!("debugger failed to attach {}", pid))
+}
+
+/// Returns true if any TCP socket is listening on `port`.
+///
+/// This reads /proc/net/tcp{,6} instead of connecting to the port, because some servers such as the JDWP
+/// agent complain about connections without their handshakes.
+pub fn is_port_listening(port: u16) -> Result<bool> {
+    const TCP_LISTEN: &str = "0A";
+    for table_path in ["/proc/net/tcp", "/proc/net/tcp6"].iter() {
+        let table = match fs::read_to_string(table_path) {
+            Ok(table) => table,
+            // IPv6 may be disabled
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", table_path)),
+        };
+        let listening = table.lines().skip(1).any(|line| {
+            let mut columns = line.split_whitespace().skip(1);
+            let local_port = columns
+                .next()
+                .and_then(|local_address| local_address.rsplit(':').next())
+                .and_then(|local_port| u16::from_str_radix(local_port, 16).ok());
+            let state = columns.nth(1);
+            local_port == Some(port) && state == Some(TCP_LISTEN)
+        });
+        if listening {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the cgroup which `--cpu-limit` creates for the debuggee of this dbgee.
+/// It's a child of the cgroup v2 of this dbgee, as systemd and containers delegate only their own subtrees.
+fn build_cpu_limit_cgroup_path() -> Result<PathBuf> {
+    let proc_cgroup =
+        fs::read_to_string("/proc/self/cgroup").context("Failed to read /proc/self/cgroup")?;
+    let own_cgroup = parse_cgroup_v2_path(&proc_cgroup).ok_or_else(|| {
+        anyhow!("--cpu-limit needs cgroup v2, but this system has only cgroup v1")
+    })?;
+    Ok(Path::new("/sys/fs/cgroup")
+        .join(own_cgroup.trim_start_matches('/'))
+        .join(format!("dbgee-{}", nix::unistd::getpid())))
+}
+
+/// Returns the path of the cgroup v2 in the content of /proc/<pid>/cgroup, whose line is "0::<path>"
+fn parse_cgroup_v2_path(proc_cgroup: &str) -> Option<&str> {
+    proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+}
+
+/// The cgroup created by `create_cpu_limit_cgroup`.
+///
+/// Dropping it removes the cgroup unless a process is in it, so that it doesn't leak if launching the debuggee
+/// fails. Once the debuggee entered it, it remains until `remove_cpu_limit_cgroup` after the debuggee exits.
+pub struct CpuLimitCgroup {
+    path: PathBuf,
+}
+
+impl Drop for CpuLimitCgroup {
+    fn drop(&mut self) {
+        match fs::remove_dir(&self.path) {
+            Err(e) if e.raw_os_error() == Some(nix::libc::EBUSY) => (),
+            result => result
+                .with_context(|| format!("Failed to remove cgroup {:?}", self.path))
+                .debug_log_error(),
+        }
+    }
+}
+
+/// Creates a cgroup v2 which limits the CPU usage to `percent` of one CPU.
+///
+/// The cgroup is created under the cgroup of this dbgee, whose `cgroup.subtree_control` must enable
+/// the cpu controller. This usually needs root privilege.
+pub fn create_cpu_limit_cgroup(percent: u32) -> Result<CpuLimitCgroup> {
+    let path = build_cpu_limit_cgroup_path()?;
+    fs::create_dir(&path).with_context(|| {
+        format!(
+            "Failed to create cgroup {:?}. --cpu-limit needs root privilege or a delegated cgroup",
+            path
+        )
+    })?;
+    let cgroup = CpuLimitCgroup { path };
+    const PERIOD_MICROS: u64 = 100_000;
+    let quota_micros = PERIOD_MICROS * percent as u64 / 100;
+    fs::write(
+        cgroup.path.join("cpu.max"),
+        format!("{} {}", quota_micros, PERIOD_MICROS),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to set cpu.max of {:?}. Is the cpu controller enabled in {:?}?",
+            cgroup.path,
+            cgroup.path.with_file_name("cgroup.subtree_control")
+        )
+    })?;
+    Ok(cgroup)
+}
+
+/// Moves the calling process into `cgroup`
+pub fn enter_cgroup(cgroup: &CpuLimitCgroup) -> Result<()> {
+    // "0" means the writing process itself
+    fs::write(cgroup.path.join("cgroup.procs"), "0")
+        .with_context(|| format!("Failed to enter cgroup {:?}", cgroup.path))
+}
+
+/// Removes the cgroup of `--cpu-limit` if this dbgee created it. It remains while any process is in it.
+pub fn remove_cpu_limit_cgroup() {
+    let cgroup = match build_cpu_limit_cgroup_path() {
+        Ok(cgroup) => cgroup,
+        Err(_) => return,
+    };
+    if cgroup.exists() {
+        fs::remove_dir(&cgroup)
+            .with_context(|| format!("Failed to remove cgroup {:?}", cgroup))
+            .debug_log_error();
+    }
+}
+
+/// Returns kernel.yama.ptrace_scope, or None if Yama is not enabled.
+pub fn get_ptrace_scope() -> Option<u32> {
+    fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns the pid of the process tracing `pid`, or None if `pid` is not traced.
+pub fn get_tracer_pid(pid: Pid) -> Result<Option<Pid>> {
+    let status_path = format!("/proc/{}/status", pid.as_raw());
+    let status = fs::read_to_string(&status_path)
+        .with_context(|| format!("Failed to read {}", &status_path))?;
+    let tracer_pid = status
+        .lines()
+        .find_map(|line| line.strip_prefix("TracerPid:"))
+        .ok_or_else(|| anyhow!("TracerPid is not found in {}", &status_path))?
+        .trim()
+        .parse::<i32>()
+        .with_context(|| format!("Failed to parse TracerPid in {}", &status_path))?;
+    Ok(match tracer_pid {
+        0 => None,
+        tracer_pid => Some(Pid::from_raw(tracer_pid)),
+    })
+}
+
+/// Returns the state of `pid` in /proc/<pid>/status, such as 'R' (running), 'T' (stopped by a signal),
+/// 't' (tracing stop) and 'Z' (zombie).
+pub fn get_process_state(pid: Pid) -> Result<char> {
+    let status_path = format!("/proc/{}/status", pid.as_raw());
+    let status = fs::read_to_string(&status_path)
+        .with_context(|| format!("Failed to read {}", &status_path))?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("State:")?.trim_start().chars().next())
+        .ok_or_else(|| anyhow!("State is not found in {}", &status_path))
+}
+
 // Spawn the command, and ptrace it with the given ptrace option
 fn spawn_traced_command(command: String, args: Vec<String>) -> Result<Pid> {
     let mut command = Command::new(command);
-    command.args(args);
+    command.args(args).logged();
     // Safety: safe because we don't have any other threads.
     unsafe {
         command.pre_exec(|| {
             ptrace::traceme().map_err(|e| {
-                e.as_errno().map_or(
-                    std::io::Error::new(std::io::ErrorKind::Other, "ptrace::traceme failed"),
-                    |e| e.into(),
-                )
+                e.as_errno()
+                    .map_or(std::io::Error::other("ptrace::traceme failed"), |e| {
+                        e.into()
+                    })
             })
         });
     }
@@ -171,70 +1201,116 @@ fn spawn_traced_command(command: String, args: Vec<String>) -> Result<Pid> {
     Ok(Pid::from_raw(child.id() as i32)) // u32 to nix::Pid
 }
 
-/// Do wait loop until it finds SIGTRAP
-/// On success, it returns Some(Pid) if it finds, or None if all children exited.
-fn wait_sigtrap() -> Result<Option<Pid>> {
+enum SigtrapWaitResult {
+    /// A process completed execve and threw SIGTRAP
+    Trapped(Pid),
+    /// No process has thrown SIGTRAP yet, in the non-blocking mode
+    NotYet,
+    /// All children exited
+    NoChildren,
+}
+
+/// Do wait loop until it finds SIGTRAP.
+/// If `nonblocking` is true, it returns `NotYet` instead of blocking when there are no wait events.
+/// The signals in `suppressed_signals` are not re-delivered to the tracees.
+fn wait_sigtrap(
+    fork_tree: &mut ForkTree,
+    nonblocking: bool,
+    suppressed_signals: &[signal::Signal],
+) -> Result<SigtrapWaitResult> {
+    if let Some(pid) = fork_tree.pending_traps.pop_front() {
+        trace!("pending trapped pid({})", pid);
+        return Ok(SigtrapWaitResult::Trapped(pid));
+    }
     loop {
-        let wait_result = wait::wait();
+        let wait_result = if nonblocking {
+            wait::waitpid(None, Some(wait::WaitPidFlag::WNOHANG))
+        } else {
+            wait::wait()
+        };
         if matches!(wait_result, Err(nix::Error::Sys(Errno::ECHILD))) {
             // There's no child processes
-            return Ok(None);
+            return Ok(SigtrapWaitResult::NoChildren);
         }
 
-        // Note: `ptrace::cont` can fail if the process always exited, thus don't return when
-        // they fail, but just logs them by `debug_log_error` instead.
         match wait_result.with_context(|| "Unexpected error. Waiting for SIGTRAP failed.")? {
             // A new process completed execve and threw SIGTRAP. Return its pid.
             wait::WaitStatus::Stopped(pid, signal::SIGTRAP) => {
                 trace!("trapped pid({})", pid);
-                return Ok(Some(pid));
-            }
-            // A tracee forked. Let both of the parent and the child continue.
-            wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_FORK)
-            | wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_CLONE)
-            | wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_VFORK) => {
-                trace!("forked: {}", pid);
-                let child_pid = ptrace::getevent(pid)
-                    .with_context(|| anyhow!("Failed to get event of pid {}", pid))?;
-                trace!("child_pid: {}", child_pid);
-                ptrace::cont(pid, None)
-                    .context("Failed to do PTRACE_CONT for the parent process after fork")
-                    .debug_log_error();
-                ptrace::cont(Pid::from_raw(child_pid as i32), None)
-                    .context("Failed to do PTRACE_CONT for the child process after fork")
-                    .debug_log_error();
-            }
-            // Some tracee got a signal. Let it see the given signal.
-            wait::WaitStatus::Stopped(pid, sig) => {
-                trace!("stopped: pid({}) sig({})", pid, sig);
-                ptrace::cont(pid, sig)
-                    .context("Failed to do PTRACE_CONT after stop signal")
-                    .debug_log_error();
-            }
-            // Some tracee exited. Do nothing.
-            wait::WaitStatus::Exited(pid, exitcode) => {
-                trace!("exited: pid({}) sig({})", pid, exitcode);
-            }
-            // Some tracee is terminated by a signal. Do nothing.
-            wait::WaitStatus::Signaled(pid, sig, _) => {
-                trace!("signaled: pid({}) sig({})", pid, sig);
-            }
-            other => {
-                trace!("other wait event: {:#?}", other);
-                ptrace::cont(other.pid().unwrap(), None)
-                    .context("Failed to do PTRACE_CONT after other wait event")
-                    .debug_log_error();
+                return Ok(SigtrapWaitResult::Trapped(pid));
             }
+            wait::WaitStatus::StillAlive => return Ok(SigtrapWaitResult::NotYet),
+            status => handle_tracee_event(fork_tree, status, suppressed_signals)?,
         };
     }
 }
 
+/// Handles the wait event `status` of a tracee other than SIGTRAP, which its caller handles.
+/// Forks are recorded to `fork_tree`, and the tracees continue.
+fn handle_tracee_event(
+    fork_tree: &mut ForkTree,
+    status: wait::WaitStatus,
+    suppressed_signals: &[signal::Signal],
+) -> Result<()> {
+    // Note: `ptrace::cont` can fail if the process always exited, thus don't return when
+    // they fail, but just logs them by `debug_log_error` instead.
+    match status {
+        // A tracee forked. Let both of the parent and the child continue.
+        wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_FORK)
+        | wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_CLONE)
+        | wait::WaitStatus::PtraceEvent(pid, _, PTRACE_EVENT_VFORK) => {
+            trace!("forked: {}", pid);
+            let child_pid = ptrace::getevent(pid)
+                .with_context(|| anyhow!("Failed to get event of pid {}", pid))?;
+            trace!("child_pid: {}", child_pid);
+            let child_pid = Pid::from_raw(child_pid as i32);
+            fork_tree.on_fork(pid, child_pid);
+            ptrace::cont(pid, None)
+                .context("Failed to do PTRACE_CONT for the parent process after fork")
+                .debug_log_error();
+            // The child starts with a SIGSTOP of ptrace, which must not be re-delivered to it.
+            // Otherwise it stays stopped once dbgee detaches from it, blocking its parent.
+            wait::waitpid(child_pid, Some(wait::WaitPidFlag::__WALL))
+                .context("Failed to wait for the child process to stop after fork")
+                .debug_log_error();
+            ptrace::cont(child_pid, None)
+                .context("Failed to do PTRACE_CONT for the child process after fork")
+                .debug_log_error();
+        }
+        // Some tracee got a signal. Let it see the given signal.
+        wait::WaitStatus::Stopped(pid, sig) => {
+            trace!("stopped: pid({}) sig({})", pid, sig);
+            ptrace::cont(pid, signal_to_forward(sig, suppressed_signals))
+                .context("Failed to do PTRACE_CONT after stop signal")
+                .debug_log_error();
+        }
+        // Some tracee exited. Do nothing.
+        wait::WaitStatus::Exited(pid, exitcode) => {
+            trace!("exited: pid({}) sig({})", pid, exitcode);
+        }
+        // Some tracee is terminated by a signal. Do nothing.
+        wait::WaitStatus::Signaled(pid, sig, _) => {
+            trace!("signaled: pid({}) sig({})", pid, sig);
+        }
+        other => {
+            trace!("other wait event: {:#?}", other);
+            ptrace::cont(other.pid().unwrap(), None)
+                .context("Failed to do PTRACE_CONT after other wait event")
+                .debug_log_error();
+        }
+    }
+    Ok(())
+}
+
 /// Wait for pid to exit, while detaching from any processes with other pids which
-/// are caught by wait
-fn wait_pid_exit_and_detach_other(pid_to_wait: Pid) -> Result<()> {
+/// are caught by wait. The signals in `suppressed_signals` are not re-delivered to them.
+fn wait_pid_exit_and_detach_other(
+    pid_to_wait: Pid,
+    suppressed_signals: &[signal::Signal],
+) -> Result<()> {
     loop {
         let wait_result = wait::wait();
-        if matches!(wait_result, Err(nix::Error::Sys(Errno::ESRCH))) {
+        if matches!(wait_result, Err(nix::Error::Sys(Errno::ECHILD))) {
             // There's no child processes, which means `pid_to_wait` also exited.
             return Ok(());
         }
@@ -265,7 +1341,7 @@ fn wait_pid_exit_and_detach_other(pid_to_wait: Pid) -> Result<()> {
             // Some tracee got a signal. Let it see the given signal, detaching from them.
             wait::WaitStatus::Stopped(pid, sig) => {
                 trace!("detach from a stopped process: pid({}) sig({})", pid, sig);
-                ptrace::detach(pid, sig)
+                ptrace::detach(pid, signal_to_forward(sig, suppressed_signals))
                     .context("Failed to detach from a process stopped")
                     .debug_log_error();
             }
@@ -279,6 +1355,18 @@ fn wait_pid_exit_and_detach_other(pid_to_wait: Pid) -> Result<()> {
     }
 }
 
+/// Returns the signal to re-deliver to a tracee which stopped by `sig`, or None if it's suppressed
+fn signal_to_forward(
+    sig: signal::Signal,
+    suppressed_signals: &[signal::Signal],
+) -> Option<signal::Signal> {
+    if suppressed_signals.contains(&sig) {
+        trace!("suppressed {}", sig);
+        return None;
+    }
+    Some(sig)
+}
+
 trait HookCondition {
     fn hooks(&self, pid: Pid) -> Result<bool>;
 }
@@ -290,11 +1378,15 @@ trait HookCondition {
 /// * `hook_executable` - Attach to a process with the specified path
 /// * `hook_source` - Attach to a process which is built from any of the given comma-separated source files.
 /// * `hook_source_dir` - Attach to a process which is built from any files under the given directory.
+/// * `hook_source_exclude` - Directories or globs of the source files which `hook_source_dir` ignores.
+/// * `hook_build_id` - Attach to a process whose executable has the given GNU build-id in hex.
 ///
 fn build_hook_conditions(
     hook_executable: Option<PathBuf>,
     hook_source: Option<Vec<String>>,
     hook_source_dir: Option<PathBuf>,
+    hook_source_exclude: Vec<String>,
+    hook_build_id: Option<String>,
 ) -> Result<Vec<Box<dyn HookCondition>>> {
     let mut conditions: Vec<Box<dyn HookCondition>> = vec![];
     if let Some(path) = hook_executable {
@@ -309,15 +1401,156 @@ fn build_hook_conditions(
                 .context("Failed to build hook source condition")?,
         ));
     }
-    if let Some(source_dir) = hook_source_dir {
-        conditions.push(Box::new(
-            build_hook_source_dir_condition(source_dir)
+    match hook_source_dir {
+        Some(source_dir) => conditions.push(Box::new(
+            build_hook_source_dir_condition(source_dir, hook_source_exclude)
                 .context("Failed to build hook source directory condition")?,
+        )),
+        None if !hook_source_exclude.is_empty() => {
+            bail!("--hook-source-exclude requires --hook-source-dir")
+        }
+        None => {}
+    }
+    if let Some(build_id) = hook_build_id {
+        conditions.push(Box::new(
+            build_hook_build_id_condition(&build_id)
+                .context("Failed to build hook build-id condition")?,
         ));
     }
     Ok(conditions)
 }
 
+/// Interval to check if the traced processes have loaded the library given by `--hook-library`
+const LIBRARY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct HookLibraryCondition {
+    library: String,
+}
+
+fn build_hook_library_condition(library: String) -> Result<HookLibraryCondition> {
+    // A path is compared as the canonicalized path, and a file name is compared as it is
+    let library = if library.contains('/') {
+        get_abspath(&library, "hook_library")?
+    } else {
+        library
+    };
+    Ok(HookLibraryCondition { library })
+}
+
+impl HookCondition for HookLibraryCondition {
+    fn hooks(&self, pid: Pid) -> Result<bool> {
+        let maps_path = format!("/proc/{}/maps", pid.as_raw());
+        let maps = fs::read_to_string(&maps_path)
+            .with_context(|| format!("Failed to read {}", maps_path))?;
+        Ok(maps_contain_library(&maps, &self.library))
+    }
+}
+
+/// Returns true if any file mapped in `maps`, the content of /proc/<pid>/maps, is `library`.
+/// A file name `library` also matches the versioned file names, e.g. "libfoo.so" matches "libfoo.so.1".
+fn maps_contain_library(maps: &str, library: &str) -> bool {
+    maps.lines()
+        // The path is the 6th field, which may contain spaces
+        .filter_map(|line| line.splitn(6, char::is_whitespace).nth(5))
+        .map(str::trim)
+        .filter(|path| path.starts_with('/'))
+        .any(|path| {
+            if library.starts_with('/') {
+                return path == library;
+            }
+            let file_name = path.rsplit('/').next().unwrap_or(path);
+            match file_name.strip_prefix(library) {
+                Some(suffix) => suffix.is_empty() || suffix.starts_with('.'),
+                None => false,
+            }
+        })
+}
+
+/// Finds a process in `fork_tree` which has loaded the library, and stops it by SIGSTOP.
+/// `stopped` are the processes which have already been found and stopped.
+///
+/// The process is checked against `conditions` again once it stops, since it may have exec-ed meanwhile.
+fn find_process_loading_library(
+    fork_tree: &mut ForkTree,
+    library_condition: &HookLibraryCondition,
+    conditions: &[&dyn HookCondition],
+    stopped: &[Pid],
+    suppressed_signals: &[signal::Signal],
+) -> Result<Option<Pid>> {
+    let self_pid = nix::unistd::getpid();
+    let pids: Vec<Pid> = fork_tree.nodes.keys().copied().collect();
+    for pid in pids {
+        if stopped.contains(&pid) {
+            continue;
+        }
+        // Skip exited processes, whose pids may have been reused by processes which are not traced
+        if !matches!(get_tracer_pid(pid), Ok(Some(tracer)) if tracer == self_pid) {
+            continue;
+        }
+        if !library_condition.hooks(pid).unwrap_or(false) {
+            continue;
+        }
+        debug!("pid {} has loaded {}", pid, library_condition.library);
+        signal::kill(pid, signal::SIGSTOP).context("Failed to stop the process")?;
+        if !wait_signal_stop(fork_tree, pid, signal::SIGSTOP, suppressed_signals)? {
+            debug!("pid {} exited before it stopped", pid);
+            continue;
+        }
+        if !triggers_hook_conditions(conditions, pid)? {
+            debug!("pid {} no longer triggers the hook conditions", pid);
+            ptrace::cont(pid, None)
+                .context("Failed to do PTRACE_CONT for a process which no longer triggers the hook conditions")
+                .debug_log_error();
+            continue;
+        }
+        return Ok(Some(pid));
+    }
+    Ok(None)
+}
+
+/// Waits for the tracee `pid` to stop by `sig`, blocking. Returns false if it exits before it stops.
+///
+/// The events of all the tracees are handled as `wait_sigtrap` does, so that the forks are recorded to
+/// `fork_tree` and a vfork child doesn't block its parent forever. The other processes which complete execve
+/// are left stopped in `fork_tree` for `wait_sigtrap`. If `pid` does, it's recorded, and the caller should
+/// check it as a new process once it stops.
+fn wait_signal_stop(
+    fork_tree: &mut ForkTree,
+    pid: Pid,
+    sig: signal::Signal,
+    suppressed_signals: &[signal::Signal],
+) -> Result<bool> {
+    loop {
+        let wait_result = wait::wait();
+        if matches!(wait_result, Err(nix::Error::Sys(Errno::ECHILD))) {
+            return Ok(false);
+        }
+        match wait_result.context("Failed to wait for the process to stop")? {
+            wait::WaitStatus::Stopped(stopped, stop_sig) if stopped == pid && stop_sig == sig => {
+                return Ok(true)
+            }
+            wait::WaitStatus::Exited(exited, _) | wait::WaitStatus::Signaled(exited, ..)
+                if exited == pid =>
+            {
+                return Ok(false)
+            }
+            // It completed execve before `sig` arrived. Let it continue to the pending `sig`
+            wait::WaitStatus::Stopped(trapped, signal::SIGTRAP) if trapped == pid => {
+                trace!("trapped pid({}) while waiting for {}", pid, sig);
+                fork_tree.on_exec(pid, get_exe_path(pid).ok(), false);
+                ptrace::cont(pid, None)
+                    .context("Failed to do PTRACE_CONT after exec")
+                    .debug_log_error();
+            }
+            wait::WaitStatus::Stopped(trapped, signal::SIGTRAP) => {
+                trace!("trapped pid({}) while waiting for pid({})", trapped, pid);
+                fork_tree.pending_traps.push_back(trapped);
+            }
+            status => handle_tracee_event(fork_tree, status, suppressed_signals)?,
+        }
+    }
+}
+
 struct HookExecutableCondition {
     executable_path: PathBuf,
 }
@@ -338,16 +1571,196 @@ impl HookCondition for HookExecutableCondition {
             "checking --hook-executable against exe_path: {:?}",
             &exe_path
         );
+        if self.executable_path == exe_path {
+            return Ok(true);
+        }
+        let interpreter = match interpreter_name(&exe_path) {
+            Some(interpreter) => interpreter,
+            None => return Ok(false),
+        };
+
+        // /proc/<pid>/exe of a script is the interpreter. Check the script in the arguments instead
+        let cmdline = fs::read(format!("/proc/{}/cmdline", pid.as_raw()))
+            .with_context(|| format!("Failed to read /proc/{}/cmdline", pid.as_raw()))?;
+        let cwd = fs::read_link(format!("/proc/{}/cwd", pid.as_raw()))
+            .with_context(|| format!("Failed to read link /proc/{}/cwd", pid.as_raw()))?;
+        let script_path = match find_script_path(interpreter, &cmdline, &cwd) {
+            Some(script_path) => script_path,
+            None => return Ok(false),
+        };
+        debug!(
+            "checking --hook-executable against script_path: {:?}",
+            &script_path
+        );
+        Ok(self.executable_path == script_path)
+    }
+}
+
+struct HookBuildIdCondition {
+    build_id: Vec<u8>,
+}
+
+fn build_hook_build_id_condition(build_id: &str) -> Result<HookBuildIdCondition> {
+    Ok(HookBuildIdCondition {
+        build_id: parse_build_id(build_id)?,
+    })
+}
+
+/// Parses a build-id in hex. Separators such as ":" and spaces are ignored.
+fn parse_build_id(build_id: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u32> = build_id
+        .chars()
+        .filter(|c| !matches!(c, ':' | '-') && !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .ok_or_else(|| anyhow!("'{}' is not a hex build-id", build_id))
+        })
+        .collect::<Result<_>>()?;
+    // An odd number of digits leaves a remainder
+    let pairs = digits.chunks_exact(2);
+    if digits.is_empty() || !pairs.remainder().is_empty() {
+        bail!("'{}' is not a hex build-id", build_id);
+    }
+    Ok(pairs.map(|pair| (pair[0] * 16 + pair[1]) as u8).collect())
+}
+
+/// Returns the GNU build-id in `.note.gnu.build-id` of `exe_path`, or None if it doesn't have one
+fn read_build_id(exe_path: &Path) -> Result<Option<Vec<u8>>> {
+    let mmap = Mmap::new(exe_path).with_context(|| format!("Failed to mmap {:?}", &exe_path))?;
+    let object = object::File::parse(mmap.get())
+        .with_context(|| format!("Failed to parse an object file {:?}", &exe_path))?;
+    Ok(object
+        .build_id()
+        .with_context(|| format!("Failed to read the build-id of {:?}", &exe_path))?
+        .map(<[u8]>::to_vec))
+}
+
+impl HookCondition for HookBuildIdCondition {
+    fn hooks(&self, pid: Pid) -> Result<bool> {
+        // /proc/<pid>/exe is the executable which the process runs even if it's replaced by a rebuild
+        let build_id = read_build_id(Path::new(&format!("/proc/{}/exe", pid.as_raw())))?;
+        debug!("checking --hook-build-id against {:?}", build_id);
+        Ok(build_id.as_deref() == Some(self.build_id.as_slice()))
+    }
+}
+
+/// Interpreters which run the script given as the first non-option argument
+const INTERPRETERS: [&str; 11] = [
+    "python", "perl", "ruby", "node", "php", "lua", "sh", "bash", "dash", "zsh", "ksh",
+];
+
+/// Returns the name of the interpreter of `exe_path` without the version, such as "python" of "python3.11",
+/// or None if it's not an interpreter. Versioned names are interpreters as well.
+fn interpreter_name(exe_path: &Path) -> Option<&'static str> {
+    let file_name = exe_path.file_name()?.to_str()?;
+    let name = file_name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    INTERPRETERS
+        .iter()
+        .find(|interpreter| **interpreter == name)
+        .copied()
+}
+
+/// The options of an interpreter which affect where its script is in the arguments
+#[derive(Default)]
+struct InterpreterOptions {
+    /// Options which take the next argument as the value, such as `-W` of python
+    value_options: &'static [&'static str],
+    /// Options which run something other than a script file, such as `-c`. Its arguments follow them.
+    no_script_options: &'static [&'static str],
+    /// The option whose value is the script, such as `-f` of php
+    script_option: Option<&'static str>,
+    /// Options can start with "+" as well, such as `+o` of the shells
+    plus_options: bool,
+}
 
-        Ok(self.executable_path == exe_path)
+fn get_interpreter_options(interpreter: &str) -> InterpreterOptions {
+    match interpreter {
+        "python" => InterpreterOptions {
+            value_options: &["-W", "-X", "--check-hash-based-pycs"],
+            // `-m` runs a module instead, and the arguments after it are of the module
+            no_script_options: &["-c", "-m"],
+            ..Default::default()
+        },
+        "perl" => InterpreterOptions {
+            no_script_options: &["-e", "-E"],
+            ..Default::default()
+        },
+        "ruby" => InterpreterOptions {
+            value_options: &["-I", "-r", "-C", "-E"],
+            no_script_options: &["-e"],
+            ..Default::default()
+        },
+        "node" => InterpreterOptions {
+            value_options: &[
+                "-r",
+                "--require",
+                "--import",
+                "--loader",
+                "-C",
+                "--conditions",
+            ],
+            no_script_options: &["-e", "--eval", "-p", "--print"],
+            ..Default::default()
+        },
+        "php" => InterpreterOptions {
+            value_options: &["-c", "-d", "-z"],
+            no_script_options: &["-r"],
+            script_option: Some("-f"),
+            ..Default::default()
+        },
+        "lua" => InterpreterOptions {
+            value_options: &["-l"],
+            no_script_options: &["-e"],
+            ..Default::default()
+        },
+        _ => InterpreterOptions {
+            value_options: &["-o", "+o", "-O", "+O"],
+            no_script_options: &["-c"],
+            plus_options: true,
+            ..Default::default()
+        },
     }
 }
 
+/// Returns the canonicalized path of the script in `cmdline`, the content of /proc/<pid>/cmdline of
+/// `interpreter`. The script is the first argument which is neither an option nor the value of an option,
+/// like argv[1] of `python3 ./script.py`. Returns None if the interpreter runs no script file, like `python3 -m`.
+fn find_script_path(interpreter: &str, cmdline: &[u8], cwd: &Path) -> Option<PathBuf> {
+    let options = get_interpreter_options(interpreter);
+    let mut args = cmdline
+        .split(|&byte| byte == 0)
+        // Skip the interpreter itself
+        .skip(1)
+        .filter_map(|arg| std::str::from_utf8(arg).ok())
+        .filter(|arg| !arg.is_empty());
+    let script = loop {
+        let arg = args.next()?;
+        if arg == "--" || Some(arg) == options.script_option {
+            break args.next()?;
+        }
+        if options.no_script_options.contains(&arg) {
+            return None;
+        }
+        if options.value_options.contains(&arg) {
+            args.next();
+            continue;
+        }
+        let is_option = arg.starts_with('-') || (options.plus_options && arg.starts_with('+'));
+        if !is_option {
+            break arg;
+        }
+    };
+    // Relative paths are relative to the cwd of the process, not of dbgee
+    fs::canonicalize(cwd.join(script)).ok()
+}
+
 struct HookSourceCondition {
     source_paths: HashSet<PathBuf>,
 }
 
 fn build_hook_source_condition(source_paths: Vec<String>) -> Result<HookSourceCondition> {
+    let source_paths =
+        expand_list_files(source_paths).context("Failed to expand the list files")?;
     let absolute_paths: Result<HashSet<PathBuf>> = source_paths
         .iter()
         .map(|path| {
@@ -360,6 +1773,28 @@ fn build_hook_source_condition(source_paths: Vec<String>) -> Result<HookSourceCo
     })
 }
 
+/// Expands any `@file` argument into the newline-separated paths listed in the file.
+/// Empty lines are ignored.
+fn expand_list_files(paths: Vec<String>) -> Result<Vec<String>> {
+    let mut expanded = vec![];
+    for path in paths {
+        match path.strip_prefix('@') {
+            Some(list_file) => {
+                let list = fs::read_to_string(list_file)
+                    .with_context(|| format!("Failed to read the list file {:?}", list_file))?;
+                expanded.extend(
+                    list.lines()
+                        .map(str::trim)
+                        .filter(|line| !line.is_empty())
+                        .map(str::to_owned),
+                );
+            }
+            None => expanded.push(path),
+        }
+    }
+    Ok(expanded)
+}
+
 impl HookCondition for HookSourceCondition {
     fn hooks(&self, pid: Pid) -> Result<bool> {
         let exe_path = get_exe_path(pid)
@@ -379,17 +1814,86 @@ impl HookCondition for HookSourceCondition {
 
 struct HookSourceDirCondition {
     source_dir: PathBuf,
+    excludes: Vec<SourceExclude>,
 }
 
-fn build_hook_source_dir_condition(source_dir: PathBuf) -> Result<HookSourceDirCondition> {
+fn build_hook_source_dir_condition(
+    source_dir: PathBuf,
+    excludes: Vec<String>,
+) -> Result<HookSourceDirCondition> {
     let canonicalized = source_dir
         .canonicalize()
         .with_context(|| format!("Failed to canonicalize {:?}", &source_dir))?;
     Ok(HookSourceDirCondition {
         source_dir: canonicalized,
+        excludes: excludes
+            .iter()
+            .map(|exclude| SourceExclude::new(exclude))
+            .collect::<Result<_>>()?,
     })
 }
 
+/// Source files which `--hook-source-exclude` excludes from `--hook-source-dir`
+#[derive(Debug)]
+enum SourceExclude {
+    Dir(PathBuf),
+    Glob(Regex),
+}
+
+impl SourceExclude {
+    fn new(exclude: &str) -> Result<SourceExclude> {
+        if exclude.contains(['*', '?', '[']) {
+            return Ok(SourceExclude::Glob(glob_to_regex(exclude)?));
+        }
+        let dir = Path::new(exclude)
+            .canonicalize()
+            .with_context(|| format!("Failed to canonicalize {:?}", exclude))?;
+        Ok(SourceExclude::Dir(dir))
+    }
+
+    fn excludes(&self, path: &Path) -> bool {
+        match self {
+            SourceExclude::Dir(dir) => path.starts_with(dir),
+            SourceExclude::Glob(glob) => path
+                .ancestors()
+                .filter_map(Path::to_str)
+                .any(|ancestor| glob.is_match(ancestor)),
+        }
+    }
+}
+
+/// Converts a glob to a regex which matches the whole path. `*` and `?` don't match "/", but `**` does.
+/// A relative glob matches the path from any directory, e.g. `vendor/*` matches `/src/vendor/a.c`.
+fn glob_to_regex(glob: &str) -> Result<Regex> {
+    let mut regex = String::from(if glob.starts_with('/') {
+        "^"
+    } else {
+        "^(.*/)?"
+    });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let class: String = chars.by_ref().take_while(|c| *c != ']').collect();
+                let (negation, class) = match class.strip_prefix('!') {
+                    Some(class) => ("^", class),
+                    None => ("", class.as_str()),
+                };
+                regex.push_str(&format!("[{}{}]", negation, class.replace('\\', "\\\\")));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).with_context(|| format!("Invalid glob {:?}", glob))
+}
+
 impl HookCondition for HookSourceDirCondition {
     fn hooks(&self, pid: Pid) -> Result<bool> {
         let exe_path = get_exe_path(pid)
@@ -401,7 +1905,8 @@ impl HookCondition for HookSourceDirCondition {
 
         any_in_dwarf_decl_file(&exe_path, |path| {
             debug!("comparing {:?} with {:?}", &self.source_dir, path);
-            let is_triggered = path.starts_with(&self.source_dir);
+            let is_triggered = path.starts_with(&self.source_dir)
+                && !self.excludes.iter().any(|exclude| exclude.excludes(path));
             debug!("--- result: {}", is_triggered);
             is_triggered
         })
@@ -414,23 +1919,111 @@ impl HookCondition for HookSourceDirCondition {
     }
 }
 
+/// Lets this process receive `sig` when its parent exits, by PR_SET_PDEATHSIG.
+/// `parent` is the expected parent, to send `sig` if it has already exited before this call.
+pub fn set_parent_death_signal(sig: signal::Signal, parent: Pid) -> Result<()> {
+    if unsafe { nix::libc::prctl(nix::libc::PR_SET_PDEATHSIG, sig as nix::libc::c_ulong) } != 0 {
+        bail!("prctl(PR_SET_PDEATHSIG) failed: {}", nix::Error::last());
+    }
+    if nix::unistd::getppid() != parent {
+        signal::raise(sig).context("Failed to raise the parent death signal")?;
+    }
+    Ok(())
+}
+
+/// Shows the working directory of the hooked process, and its environment variables if `show_env` is set.
+/// They tell how the process was invoked, which is hard to recover after the debugger attaches.
+/// Returns the working directory.
+fn show_hooked_process_context(pid: Pid, show_env: bool) -> (Option<PathBuf>, Option<Vec<String>>) {
+    let cwd = match fs::read_link(format!("/proc/{}/cwd", pid.as_raw())) {
+        Ok(cwd) => {
+            info!("The hooked process {} is running in {:?}", pid, &cwd);
+            Some(cwd)
+        }
+        Err(e) => {
+            debug!("Failed to read link /proc/{}/cwd: {}", pid.as_raw(), e);
+            None
+        }
+    };
+    if !show_env {
+        return (cwd, None);
+    }
+    let env = match fs::read(format!("/proc/{}/environ", pid.as_raw())) {
+        Ok(environ) => {
+            let env = parse_environ(&environ);
+            info!(
+                "Environment variables of the hooked process {}:\n{}",
+                pid,
+                env.iter()
+                    .map(|var| format!("  {}", var))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            );
+            Some(env)
+        }
+        Err(e) => {
+            log::warn!(
+                "Failed to read the environment variables of the hooked process {}: {}",
+                pid,
+                e
+            );
+            None
+        }
+    };
+    (cwd, env)
+}
+
+/// Parses the content of /proc/<pid>/environ, which is NUL-separated `KEY=VALUE`s
+fn parse_environ(environ: &[u8]) -> Vec<String> {
+    environ
+        .split(|b| *b == 0)
+        .filter(|var| !var.is_empty())
+        .map(|var| String::from_utf8_lossy(var).into_owned())
+        .collect()
+}
+
+/// Returns (device, inode) of the executable file of `pid`. Unlike the path, this changes
+/// even when the process re-executes a new file which was put at the same path.
+fn get_exe_id(pid: Pid) -> Result<(u64, u64)> {
+    let metadata = fs::metadata(format!("/proc/{}/exe", pid.as_raw()))
+        .with_context(|| format!("Failed to stat /proc/{}/exe", pid.as_raw()))?;
+    Ok((metadata.dev(), metadata.ino()))
+}
+
 fn get_exe_path(pid: Pid) -> Result<PathBuf> {
-    fs::read_link(&format!("/proc/{}/exe", pid.as_raw()))
+    fs::read_link(format!("/proc/{}/exe", pid.as_raw()))
+        .map(strip_deleted_suffix)
         .with_context(|| format!("Failed to read link /proc/{}/exe", pid.as_raw()))
 }
 
-/// Returns true if the dwarf file of `exe_path` contains any sources for which `predicate` returns true.
-/// Note `any_in_dwarf_decl_file` does path comparison, resolving any path to canonicalized paths.
-fn any_in_dwarf_decl_file<F>(exe_path: &Path, mut predicate: F) -> Result<bool>
+/// Strips " (deleted)", which /proc/<pid>/exe has when the executable was removed or replaced after exec,
+/// such as by a rebuild. The path without it is where the executable was.
+fn strip_deleted_suffix(exe_path: PathBuf) -> PathBuf {
+    // A file can really be named "... (deleted)"
+    if exe_path.exists() {
+        return exe_path;
+    }
+    match exe_path
+        .to_str()
+        .and_then(|path| path.strip_suffix(" (deleted)"))
+    {
+        Some(path) => PathBuf::from(path),
+        None => exe_path,
+    }
+}
+
+/// Loads the dwarf of `exe_path`, and calls `f` with it.
+fn with_dwarf<F, R>(exe_path: &Path, f: F) -> Result<R>
 where
-    F: FnMut(&Path) -> bool,
+    F: FnOnce(&gimli::Dwarf<gimli::EndianSlice<gimli::RunTimeEndian>>) -> Result<R>,
 {
-    let mmap = Mmap::new(&exe_path).with_context(|| format!("Failed to mmap {:?}", &exe_path))?;
+    let mmap = Mmap::new(exe_path).with_context(|| format!("Failed to mmap {:?}", &exe_path))?;
     {
         let buf = mmap.get();
         trace!("buf: {:?}", String::from_utf8_lossy(&buf[0..4]));
     }
-    let object = object::File::parse(mmap.get()).unwrap();
+    let object = object::File::parse(mmap.get())
+        .with_context(|| format!("Failed to parse an object file {:?}", &exe_path))?;
 
     let load_section = |id: gimli::SectionId| -> Result<Cow<[u8]>, gimli::Error> {
         match object.section_by_name(id.name()) {
@@ -445,67 +2038,250 @@ where
     let borrow_section: &dyn for<'a> Fn(
         &'a Cow<[u8]>,
     ) -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-        &|section| gimli::EndianSlice::new(&*section, gimli::RunTimeEndian::Little);
+        &|section| gimli::EndianSlice::new(section, gimli::RunTimeEndian::Little);
 
     // Create `EndianSlice`s for all of the sections.
     let dwarf = dwarf_cow.borrow(&borrow_section);
+    f(&dwarf)
+}
 
-    // Iterate over the compilation units.
-    let mut iter = dwarf.units();
-    trace!("iterates dwarf units");
-    while let Some(header) = iter.next().context("Failed to iterate a unit")? {
-        trace!(
-            "Unit at <.debug_info+0x{:x}>",
-            header.offset().as_debug_info_offset().unwrap().0
-        );
-        let unit = dwarf.unit(header)?;
+/// Returns true if the dwarf file of `exe_path` contains any sources for which `predicate` returns true.
+/// Note `any_in_dwarf_decl_file` does path comparison, resolving any path to canonicalized paths.
+fn any_in_dwarf_decl_file<F>(exe_path: &Path, mut predicate: F) -> Result<bool>
+where
+    F: FnMut(&Path) -> bool,
+{
+    find_in_dwarf_source_files(exe_path, false, |source_file| {
+        predicate(source_file.path.as_path())
+    })
+}
 
-        let path_resolver = match DwarfPathResolver::build(&dwarf, &unit)
-            .context("Failed to build DwarfPathResolver")?
-        {
-            Some(path_resolver) => path_resolver,
-            None => continue,
-        };
+/// A source file in the line programs of a dwarf
+#[derive(Debug)]
+pub struct DwarfSourceFile {
+    /// Path resolved with the comp_dir and the directory of the line program
+    pub path: PathBuf,
+    /// File name as it is recorded in the dwarf
+    pub recorded_name: String,
+    /// DW_AT_comp_dir of the compilation unit
+    pub comp_dir: PathBuf,
+}
 
-        let header = match unit.line_program {
-            Some(ref line_program) => line_program.header(),
-            None => continue,
-        };
+impl DwarfSourceFile {
+    /// Returns true if the file name is recorded as a relative path to the comp_dir or the directory
+    pub fn is_relative(&self) -> bool {
+        !self.recorded_name.starts_with('/')
+    }
+}
+
+/// Returns every source file in the line programs of the dwarf of `exe_path`, without duplicates.
+/// Unlike `any_in_dwarf_decl_file`, this includes the sources of compilation units whose comp_dir doesn't exist
+/// on this machine.
+pub fn list_dwarf_source_files(exe_path: &Path) -> Result<Vec<DwarfSourceFile>> {
+    let mut source_files = vec![];
+    let mut found_paths = HashSet::new();
+    find_in_dwarf_source_files(exe_path, true, |source_file| {
+        if found_paths.insert(source_file.path.clone()) {
+            source_files.push(DwarfSourceFile {
+                path: source_file.path.clone(),
+                recorded_name: source_file.recorded_name.clone(),
+                comp_dir: source_file.comp_dir.clone(),
+            });
+        }
+        false
+    })?;
+    Ok(source_files)
+}
+
+/// Iterate over the source files in the dwarf of `exe_path` until `predicate` returns true.
+/// Returns true if `predicate` returned true for any source file.
+fn find_in_dwarf_source_files<F>(
+    exe_path: &Path,
+    include_missing_comp_dir: bool,
+    mut predicate: F,
+) -> Result<bool>
+where
+    F: FnMut(&DwarfSourceFile) -> bool,
+{
+    with_dwarf(exe_path, |dwarf| {
+        // Iterate over the compilation units.
+        let mut iter = dwarf.units();
+        trace!("iterates dwarf units");
+        while let Some(header) = iter.next().context("Failed to iterate a unit")? {
+            trace!(
+                "Unit at <.debug_info+0x{:x}>",
+                header.offset().as_debug_info_offset().unwrap().0
+            );
+            let unit = dwarf.unit(header)?;
 
-        if header.file_names().iter().any(|file_entry| {
-            let mut inner = || -> Result<bool> {
-                let file_path = match path_resolver
-                    .resolve_file(&dwarf, &unit, header, file_entry)
-                    .context("Failed to resolve a file path")?
+            let path_resolver = match DwarfPathResolver::build(dwarf, &unit)
+                .context("Failed to build DwarfPathResolver")?
+            {
+                Some(path_resolver)
+                    if path_resolver.comp_dir_exists || include_missing_comp_dir =>
                 {
-                    Some(file_path) => file_path,
-                    None => return Ok(false),
-                };
-                trace!("-- source file {:?}", &file_path);
-                Ok(predicate(file_path.as_path()))
+                    path_resolver
+                }
+                _ => continue,
             };
-            match inner() {
-                Ok(res) => res,
-                Err(e) => {
-                    debug!("Error occurred during iterating an file name. {:?}", e);
-                    false
+
+            let header = match unit.line_program {
+                Some(ref line_program) => line_program.header(),
+                None => continue,
+            };
+
+            if header.file_names().iter().any(|file_entry| {
+                let mut inner = || -> Result<bool> {
+                    let file_path = match path_resolver
+                        .resolve_file(dwarf, &unit, header, file_entry)
+                        .context("Failed to resolve a file path")?
+                    {
+                        Some(file_path) => file_path,
+                        None => return Ok(false),
+                    };
+                    trace!("-- source file {:?}", &file_path);
+                    let recorded_name = dwarf
+                        .attr_string(&unit, file_entry.path_name())
+                        .context("Failed to get attr_string of a file path_name")?
+                        .to_string_lossy()
+                        .into_owned();
+                    Ok(predicate(&DwarfSourceFile {
+                        path: file_path,
+                        recorded_name,
+                        comp_dir: path_resolver.comp_dir.clone(),
+                    }))
+                };
+                match inner() {
+                    Ok(res) => res,
+                    Err(e) => {
+                        debug!("Error occurred during iterating an file name. {:?}", e);
+                        false
+                    }
                 }
+            }) {
+                return Ok(true);
             }
-        }) {
-            return Ok(true);
+        }
+
+        Ok(false)
+    })
+}
+
+/// Print the source files in the dwarf of `exe_path` for `dbgee sources`
+pub fn print_dwarf_source_files(exe_path: &Path, json: bool) -> Result<()> {
+    let source_files = list_dwarf_source_files(exe_path)
+        .with_context(|| format!("Failed to read the source files of {:?}", exe_path))?;
+    if json {
+        let entries: Vec<String> = source_files
+            .iter()
+            .map(|source_file| {
+                format!(
+                    r#"{{"path": "{}", "recordedName": "{}", "compDir": "{}", "relative": {}}}"#,
+                    escape_json_string(&source_file.path.to_string_lossy()),
+                    escape_json_string(&source_file.recorded_name),
+                    escape_json_string(&source_file.comp_dir.to_string_lossy()),
+                    source_file.is_relative()
+                )
+            })
+            .collect();
+        println!("[{}]", entries.join(", "));
+        return Ok(());
+    }
+    for source_file in source_files.iter() {
+        if source_file.is_relative() {
+            println!(
+                "{} (recorded as {:?}, comp_dir: {:?})",
+                source_file.path.display(),
+                source_file.recorded_name,
+                source_file.comp_dir
+            );
+        } else {
+            println!("{}", source_file.path.display());
         }
     }
+    Ok(())
+}
 
-    Ok(false)
+/// Returns true if `debuggee` is compiled by Zig, judging from DW_AT_producer of its dwarf.
+///
+/// This is asked for every debuggee, so the dwarf is read only if `file` finds an ELF with debug_info.
+pub fn is_zig_binary(debuggee: &str) -> bool {
+    match get_cached_file_output(debuggee) {
+        Ok(file_output) if file_output.contains("ELF") && file_output.contains("debug_info") => (),
+        _ => return false,
+    }
+    match find_dwarf_producers(Path::new(debuggee)) {
+        Ok(producers) => producers
+            .iter()
+            .any(|producer| producer.to_lowercase().starts_with("zig")),
+        Err(e) => {
+            debug!("Failed to read DW_AT_producer of {}. {:?}", debuggee, e);
+            false
+        }
+    }
+}
+
+/// Returns DW_AT_comp_dir of the compilation units in the dwarf of `debuggee` which don't exist on this machine.
+/// This means that the debuggee was built on another machine or directory.
+pub fn find_missing_comp_dirs(debuggee: &str) -> Vec<PathBuf> {
+    let result = with_dwarf(Path::new(debuggee), |dwarf| {
+        let mut comp_dirs = vec![];
+        let mut iter = dwarf.units();
+        while let Some(header) = iter.next().context("Failed to iterate a unit")? {
+            let unit = dwarf.unit(header)?;
+            if let Some(comp_dir) =
+                find_comp_dir(dwarf, &unit).context("Failed to find comp dir")?
+            {
+                let comp_dir = PathBuf::from(comp_dir.to_string_lossy().as_ref());
+                if !comp_dirs.contains(&comp_dir) && !comp_dir.exists() {
+                    comp_dirs.push(comp_dir);
+                }
+            }
+        }
+        Ok(comp_dirs)
+    });
+    result.unwrap_or_else(|e| {
+        debug!("Failed to read DW_AT_comp_dir of {}. {:?}", debuggee, e);
+        vec![]
+    })
+}
+
+/// Returns DW_AT_producer of all the compilation units in the dwarf of `exe_path`.
+fn find_dwarf_producers(exe_path: &Path) -> Result<Vec<String>> {
+    with_dwarf(exe_path, |dwarf| {
+        let mut producers = vec![];
+        let mut iter = dwarf.units();
+        while let Some(header) = iter.next().context("Failed to iterate a unit")? {
+            let unit = dwarf.unit(header)?;
+            let mut entries = unit.entries();
+            let root = match entries.next_dfs().context("Failed to get the root entry")? {
+                Some((_, root)) => root,
+                None => continue,
+            };
+            if let Some(producer) = root
+                .attr_value(gimli::constants::DW_AT_producer)
+                .context("Failed to get DW_AT_producer")?
+            {
+                let producer = dwarf
+                    .attr_string(&unit, producer)
+                    .context("Failed to convert DW_AT_producer to string")?;
+                trace!("-- producer {:?}", producer.to_string_lossy());
+                producers.push(producer.to_string_lossy().into_owned());
+            }
+        }
+        Ok(producers)
+    })
 }
 
 struct DwarfPathResolver {
     comp_dir: PathBuf,
+    /// False if AT_comp_dir contains a path which doesn't exist on the running machine.
+    /// Paths are resolved with the raw AT_comp_dir in that case.
+    comp_dir_exists: bool,
 }
 
 impl DwarfPathResolver {
-    /// Build `DwarfPathResolver`. If the dwarf doesn't have AT_comp_dir or AT_comp_dir contains
-    /// a path which doesn't exit on the running machine, `build` returns `Ok(None)`.
+    /// Build `DwarfPathResolver`. If the dwarf doesn't have AT_comp_dir, `build` returns `Ok(None)`.
     pub fn build(
         dwarf: &gimli::Dwarf<gimli::EndianSlice<gimli::RunTimeEndian>>,
         unit: &gimli::Unit<gimli::EndianSlice<gimli::RunTimeEndian>, usize>,
@@ -515,17 +2291,24 @@ impl DwarfPathResolver {
             Some(comp_dir) => comp_dir,
             None => return Ok(None),
         };
-        let comp_dir = match fs::canonicalize(comp_dir.to_string_lossy().as_ref()) {
-            Ok(absolute_path) => absolute_path,
-            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        let comp_dir_str = comp_dir.to_string_lossy();
+        match fs::canonicalize(comp_dir_str.as_ref()) {
+            Ok(absolute_path) => Ok(Some(DwarfPathResolver {
+                comp_dir: absolute_path,
+                comp_dir_exists: true,
+            })),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Some(DwarfPathResolver {
+                    comp_dir: PathBuf::from(comp_dir_str.as_ref()),
+                    comp_dir_exists: false,
+                }))
+            }
             Err(error) => bail!(
                 "Failed to canonicalize comp_dir({:?}); {:?}",
                 comp_dir,
                 error
             ),
-        };
-
-        Ok(Some(DwarfPathResolver { comp_dir }))
+        }
     }
 
     pub fn resolve_directory(
@@ -572,9 +2355,9 @@ impl DwarfPathResolver {
 
         let file_str = file_slice.to_string_lossy();
         // return if it's an absolute path
-        if file_slice.starts_with(&[b'/']) {
+        if file_slice.starts_with(b"/") {
             trace!("--- a file path is absolute: {:?}", file_str);
-            return Ok(Some(PathBuf::from(file_str.as_ref())));
+            return Ok(Some(normalize_dwarf_path(PathBuf::from(file_str.as_ref()))));
         }
 
         trace!("--- a file path is relative: {:?}", file_str);
@@ -590,8 +2373,29 @@ impl DwarfPathResolver {
             })?;
 
         directory.push(file_str.as_ref());
-        Ok(Some(directory))
+        Ok(Some(normalize_dwarf_path(directory)))
+    }
+}
+
+/// Removes ".." in `path` lexically, so that a source recorded relative to another directory, such as
+/// `../lib/foo.zig`, equals the canonicalized path given by the user.
+fn normalize_dwarf_path(path: PathBuf) -> PathBuf {
+    if !path
+        .components()
+        .any(|c| c == std::path::Component::ParentDir)
+    {
+        return path;
+    }
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
     }
+    normalized
 }
 
 fn find_comp_dir<'a>(
@@ -652,3 +2456,522 @@ impl Mmap {
         unsafe { slice::from_raw_parts(self.mmapped_addr, self.file_size) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_lang_testbin_path(lang: &str) -> String {
+        format!(
+            "{}/tests/lang_projects/{}/hello-{}-linux",
+            env!("CARGO_MANIFEST_DIR"),
+            lang,
+            std::env::consts::ARCH,
+        )
+    }
+
+    #[test]
+    fn test_find_dwarf_producers() {
+        let producers = find_dwarf_producers(Path::new(&get_lang_testbin_path("rust"))).unwrap();
+        assert!(producers.iter().any(|producer| producer.contains("rustc")));
+    }
+
+    #[test]
+    fn test_list_dwarf_source_files() {
+        // the comp_dir of the test binary doesn't exist on this machine
+        let source_files =
+            list_dwarf_source_files(Path::new(&get_lang_testbin_path("rust"))).unwrap();
+        let main_rs = source_files
+            .iter()
+            .find(|source_file| source_file.path.ends_with("lang_projects/rust/src/main.rs"))
+            .unwrap();
+        assert_eq!("src/main.rs", main_rs.recorded_name);
+        assert!(main_rs.is_relative());
+    }
+
+    #[test]
+    fn test_is_port_listening() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(is_port_listening(port).unwrap());
+        drop(listener);
+        assert!(!is_port_listening(port).unwrap());
+    }
+
+    #[test]
+    fn test_apply_hook_profile() {
+        let mut hook_opts =
+            HookOpts::from_iter_safe(["hook", "--hook-source-dir", "cli"].iter()).unwrap();
+        hook_opts.apply_profile(HookProfile {
+            hook_executable: Some(PathBuf::from("/profile/exe")),
+            hook_source: None,
+            hook_source_dir: Some(PathBuf::from("/profile/src")),
+            hook_library: Some("libplugin.so".to_owned()),
+            hook_source_exclude: vec!["/profile/src/vendor".to_owned()],
+            hook_build_id: Some("3f2a9c".to_owned()),
+        });
+        assert_eq!(
+            Some(PathBuf::from("/profile/exe")),
+            hook_opts.hook_executable
+        );
+        assert_eq!(None, hook_opts.hook_source);
+        assert_eq!(Some(PathBuf::from("cli")), hook_opts.hook_source_dir);
+        assert_eq!(Some("libplugin.so".to_owned()), hook_opts.hook_library);
+        assert_eq!(Some("3f2a9c".to_owned()), hook_opts.hook_build_id);
+        assert_eq!(
+            vec!["/profile/src/vendor".to_owned()],
+            hook_opts.hook_source_exclude
+        );
+    }
+
+    #[test]
+    fn test_signal_to_forward() {
+        let suppressed = [signal::SIGALRM];
+        assert_eq!(None, signal_to_forward(signal::SIGALRM, &suppressed));
+        assert_eq!(
+            Some(signal::SIGCHLD),
+            signal_to_forward(signal::SIGCHLD, &suppressed)
+        );
+    }
+
+    #[test]
+    fn test_maps_contain_library() {
+        let maps = "\
+            55d1c0a00000-55d1c0a01000 r--p 00000000 08:01 1234 /usr/bin/host\n\
+            7f2b3c000000-7f2b3c021000 rw-p 00000000 00:00 0 \n\
+            7f2b3d000000-7f2b3d001000 r-xp 00000000 08:01 5678 /opt/my plugins/libplugin.so.1.2\n\
+            7fff5a1f0000-7fff5a211000 rw-p 00000000 00:00 0                          [stack]\n";
+        assert!(maps_contain_library(maps, "libplugin.so"));
+        assert!(maps_contain_library(maps, "libplugin"));
+        assert!(maps_contain_library(maps, "libplugin.so.1.2"));
+        assert!(maps_contain_library(
+            maps,
+            "/opt/my plugins/libplugin.so.1.2"
+        ));
+        assert!(!maps_contain_library(maps, "libplug"));
+        assert!(!maps_contain_library(maps, "/opt/libplugin.so.1.2"));
+        assert!(!maps_contain_library(maps, "stack"));
+    }
+
+    #[test]
+    fn test_interpreter_name() {
+        assert_eq!(
+            Some("python"),
+            interpreter_name(Path::new("/usr/bin/python3"))
+        );
+        assert_eq!(
+            Some("python"),
+            interpreter_name(Path::new("/usr/bin/python3.11"))
+        );
+        assert_eq!(Some("bash"), interpreter_name(Path::new("/bin/bash")));
+        assert_eq!(None, interpreter_name(Path::new("/usr/bin/gdb")));
+        assert_eq!(None, interpreter_name(Path::new("/usr/bin/shred")));
+    }
+
+    #[test]
+    fn test_find_script_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("script.py");
+        fs::write(&script, "print(1)").unwrap();
+        let script = script.canonicalize().unwrap();
+
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path(
+                "python",
+                b"python3\x00-u\x00script.py\x00arg\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path(
+                "python",
+                format!("python3\x00{}\x00", script.display()).as_bytes(),
+                Path::new("/")
+            )
+        );
+        assert_eq!(
+            None,
+            find_script_path("python", b"python3\x00-i\x00", dir.path())
+        );
+        assert_eq!(
+            None,
+            find_script_path("python", b"python3\x00missing.py\x00", dir.path())
+        );
+    }
+
+    #[test]
+    fn test_find_script_path_skips_option_values() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in &["script.py", "ignore", "lib"] {
+            fs::write(dir.path().join(file), "").unwrap();
+        }
+        let script = dir.path().join("script.py").canonicalize().unwrap();
+
+        // "ignore" is the value of -W, though it exists as a file
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path(
+                "python",
+                b"python3\x00-W\x00ignore\x00script.py\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path(
+                "ruby",
+                b"ruby\x00-I\x00lib\x00-r\x00lib\x00script.py\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path(
+                "bash",
+                b"bash\x00+o\x00lib\x00--\x00script.py\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            Some(script.clone()),
+            find_script_path("php", b"php\x00-f\x00script.py\x00", dir.path())
+        );
+    }
+
+    #[test]
+    fn test_find_script_path_without_script() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("script.py"), "").unwrap();
+
+        // The arguments after -m belong to the module, which is not a script file
+        assert_eq!(
+            None,
+            find_script_path(
+                "python",
+                b"python3\x00-m\x00http.server\x00script.py\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            None,
+            find_script_path(
+                "python",
+                b"python3\x00-c\x00print(1)\x00script.py\x00",
+                dir.path()
+            )
+        );
+        assert_eq!(
+            None,
+            find_script_path("bash", b"bash\x00-c\x00true\x00script.py\x00", dir.path())
+        );
+    }
+
+    #[test]
+    fn test_format_fork_tree() {
+        let pid = Pid::from_raw;
+        let mut fork_tree = ForkTree::new(pid(10));
+        fork_tree.on_exec(pid(10), Some(PathBuf::from("/bin/sh")), false);
+        fork_tree.on_fork(pid(10), pid(11));
+        fork_tree.on_fork(pid(10), pid(12));
+        fork_tree.on_fork(pid(11), pid(13));
+        fork_tree.on_exec(pid(11), Some(PathBuf::from("/usr/bin/make")), false);
+        fork_tree.on_exec(pid(13), Some(PathBuf::from("/bin/sh")), false);
+        fork_tree.on_exec(pid(13), Some(PathBuf::from("/work/a.out")), true);
+        fork_tree.on_exec(pid(12), None, false);
+        assert_eq!(
+            "Process tree observed by dbgee:\n\
+            10 /bin/sh\n  \
+            11 /usr/bin/make\n    \
+            13 /bin/sh -> /work/a.out [hooked]\n  \
+            12 (unknown)\n",
+            fork_tree.format_tree()
+        );
+    }
+
+    #[test]
+    fn test_find_missing_comp_dirs() {
+        // the test binary was built on another machine
+        let comp_dirs = find_missing_comp_dirs(&get_lang_testbin_path("rust"));
+        assert!(comp_dirs
+            .iter()
+            .any(|comp_dir| comp_dir.ends_with("lang_projects/rust/target/debug/deps")));
+        assert!(find_missing_comp_dirs("/nonexistent/debuggee").is_empty());
+    }
+
+    #[test]
+    fn test_build_hook_source_condition_with_list_file() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let source_a = source_dir.path().join("a.c");
+        let source_b = source_dir.path().join("b.c");
+        let source_c = source_dir.path().join("c.c");
+        for source in [&source_a, &source_b, &source_c] {
+            File::create(source).unwrap();
+        }
+        let list_file = source_dir.path().join("changed_files");
+        fs::write(
+            &list_file,
+            format!("{}\n\n{}\n", source_a.display(), source_b.display()),
+        )
+        .unwrap();
+
+        let condition = build_hook_source_condition(vec![
+            format!("@{}", list_file.display()),
+            source_c.to_str().unwrap().to_owned(),
+        ])
+        .unwrap();
+        let expected: HashSet<PathBuf> = [source_a, source_b, source_c]
+            .iter()
+            .map(|path| path.canonicalize().unwrap())
+            .collect();
+        assert_eq!(expected, condition.source_paths);
+    }
+
+    #[test]
+    fn test_build_hook_source_condition_with_missing_list_file() {
+        assert!(build_hook_source_condition(vec!["@/nonexistent/list".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn test_hook_build_id_condition() {
+        assert_eq!(vec![0x12, 0xab], parse_build_id("12AB").unwrap());
+        assert_eq!(vec![0x12, 0xab], parse_build_id("12:ab").unwrap());
+        assert!(parse_build_id("12a").is_err());
+        assert!(parse_build_id("xyz0").is_err());
+        assert!(parse_build_id("").is_err());
+
+        let self_pid = nix::unistd::getpid();
+        let build_id = match read_build_id(&get_exe_path(self_pid).unwrap()).unwrap() {
+            Some(build_id) => build_id,
+            // The test binary may be linked without --build-id, which CI must not miss
+            None => {
+                assert!(
+                    std::env::var_os("CI").is_none(),
+                    "the test binary has no build-id in CI"
+                );
+                // Write to stderr directly, since the test harness captures the output of eprintln!
+                use std::io::Write;
+                writeln!(
+                    std::io::stderr(),
+                    "skipped: the test binary has no build-id to hook"
+                )
+                .unwrap();
+                return;
+            }
+        };
+        let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        assert!(build_hook_build_id_condition(&hex)
+            .unwrap()
+            .hooks(self_pid)
+            .unwrap());
+        assert!(!build_hook_build_id_condition("00")
+            .unwrap()
+            .hooks(self_pid)
+            .unwrap());
+    }
+
+    #[test]
+    fn test_select_hook_match() {
+        let hook_match = |pid, start_time| HookMatch {
+            pid: Pid::from_raw(pid),
+            exe_path: PathBuf::from("/bin/true"),
+            start_time,
+        };
+        let matches = vec![
+            hook_match(30, 100),
+            hook_match(10, 300),
+            hook_match(20, 300),
+        ];
+        assert_eq!(0, select_hook_match(&matches, HookSelect::First));
+        assert_eq!(2, select_hook_match(&matches, HookSelect::Newest));
+        assert_eq!(0, select_hook_match(&matches, HookSelect::Oldest));
+    }
+
+    #[test]
+    fn test_hook_settling() {
+        // Pids which don't exist, for which PTRACE_CONT just fails
+        let (pid1, pid2) = (Pid::from_raw(i32::MAX - 1), Pid::from_raw(i32::MAX - 2));
+        let mut hook_settling = HookSettling::new(Duration::from_millis(500));
+        let now = Instant::now();
+        hook_settling.start(pid1, now);
+        hook_settling.start(pid2, now + Duration::from_millis(100));
+        assert!(hook_settling.take_settled(now).is_empty());
+        assert_eq!(
+            vec![pid1],
+            hook_settling.take_settled(now + Duration::from_millis(500))
+        );
+        assert_eq!(vec![pid2], hook_settling.pids());
+
+        // pid2 exec-ed again and matched anew
+        hook_settling.forget(pid2);
+        assert!(!hook_settling.is_settling());
+        hook_settling.start(pid2, now + Duration::from_millis(300));
+        assert!(hook_settling
+            .take_settled(now + Duration::from_millis(600))
+            .is_empty());
+        assert_eq!(
+            vec![pid2],
+            hook_settling.take_settled(now + Duration::from_millis(800))
+        );
+    }
+
+    #[test]
+    fn test_parse_start_time() {
+        let stat =
+            "1234 (a) b) S 1 1234 1234 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 1 0 56789 1000 100";
+        assert_eq!(Some(56789), parse_start_time(stat));
+        assert_eq!(None, parse_start_time("1234 (a) S 1"));
+        assert!(get_process_start_time(nix::unistd::getpid()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_source_exclude() {
+        let dir = tempfile::tempdir().unwrap();
+        let vendor = dir.path().join("vendor");
+        fs::create_dir(&vendor).unwrap();
+        let vendor = vendor.canonicalize().unwrap();
+
+        let exclude = SourceExclude::new(vendor.to_str().unwrap()).unwrap();
+        assert!(exclude.excludes(&vendor.join("lib/a.c")));
+        assert!(!exclude.excludes(&vendor.with_file_name("src/a.c")));
+        assert!(SourceExclude::new("/nonexistent/dbgee/vendor").is_err());
+
+        let exclude = SourceExclude::new("**/.cargo/registry").unwrap();
+        assert!(exclude.excludes(Path::new("/home/u/.cargo/registry/src/x/lib.rs")));
+        assert!(!exclude.excludes(Path::new("/home/u/project/src/lib.rs")));
+
+        let exclude = SourceExclude::new("third_party/*.c").unwrap();
+        assert!(exclude.excludes(Path::new("/work/third_party/zlib.c")));
+        assert!(!exclude.excludes(Path::new("/work/third_party/zlib/inflate.c")));
+        assert!(!exclude.excludes(Path::new("/work/my_third_party/zlib.c")));
+
+        let exclude = SourceExclude::new("/work/gen[!a]?").unwrap();
+        assert!(exclude.excludes(Path::new("/work/genb1/out.c")));
+        assert!(!exclude.excludes(Path::new("/work/gena1/out.c")));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_trace_syscalls() {
+        let pid = spawn_traced_command("echo".to_owned(), vec!["hello".to_owned()]).unwrap();
+        let (summary, exit_status) = trace_syscalls(pid, &[]).unwrap();
+        assert_eq!(Some(0), exit_status);
+
+        let table = summary.format_table();
+        assert!(table.starts_with("% time     seconds  usecs/call     calls    errors syscall\n"));
+        assert!(table.contains(" write\n"));
+        assert!(table.contains(" exit_group\n"));
+        assert!(table.ends_with(" total\n"));
+    }
+
+    #[test]
+    fn test_wait_non_child_pid_exit() {
+        let mut child = Command::new("sleep").arg("0.2").spawn().unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+        let start = std::time::Instant::now();
+        wait_non_child_pid_exit(pid).unwrap();
+        assert!(start.elapsed() >= std::time::Duration::from_millis(200));
+        child.wait().unwrap();
+        // the pid no longer exists
+        wait_non_child_pid_exit(pid).unwrap();
+    }
+
+    #[test]
+    fn test_parse_environ() {
+        assert_eq!(
+            vec!["HOME=/root", "EMPTY="],
+            parse_environ(b"HOME=/root\0EMPTY=\0")
+        );
+    }
+
+    #[test]
+    fn test_strip_deleted_suffix() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let deleted = tmpdir.path().join("hello (deleted)");
+        assert_eq!(
+            tmpdir.path().join("hello"),
+            strip_deleted_suffix(deleted.clone())
+        );
+        fs::write(&deleted, "").unwrap();
+        assert_eq!(deleted, strip_deleted_suffix(deleted.clone()));
+        assert_eq!(
+            tmpdir.path().join("hello"),
+            strip_deleted_suffix(tmpdir.path().join("hello"))
+        );
+    }
+
+    #[test]
+    fn test_hook_executable_condition_with_replaced_executable() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let exe_path = tmpdir.path().join("sleep");
+        let sleep_path = crate::file_helper::find_command_path("sleep").unwrap();
+        fs::copy(&sleep_path, &exe_path).unwrap();
+        let mut child = Command::new(&exe_path).arg("10").spawn().unwrap();
+        let pid = Pid::from_raw(child.id() as i32);
+        // Replace the executable as rebuilds do
+        fs::remove_file(&exe_path).unwrap();
+        fs::copy(&sleep_path, &exe_path).unwrap();
+
+        let condition = build_hook_executable_condition(exe_path).unwrap();
+        let hooks = condition.hooks(pid);
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(hooks.unwrap());
+    }
+
+    #[test]
+    fn test_parse_cgroup_v2_path() {
+        assert_eq!(
+            Some("/user.slice/user-1000.slice/session-2.scope"),
+            parse_cgroup_v2_path("0::/user.slice/user-1000.slice/session-2.scope\n")
+        );
+        assert_eq!(
+            Some("/"),
+            parse_cgroup_v2_path("1:name=systemd:/init.scope\n0::/\n")
+        );
+        assert_eq!(
+            None,
+            parse_cgroup_v2_path("4:memory:/docker\n1:cpu:/docker\n")
+        );
+    }
+
+    #[test]
+    fn test_get_tracer_pid() {
+        let self_pid = nix::unistd::getpid();
+        assert_eq!(None, get_tracer_pid(self_pid).unwrap());
+    }
+
+    #[test]
+    fn test_is_zig_binary() {
+        assert!(!is_zig_binary(&get_lang_testbin_path("c")));
+        assert!(!is_zig_binary(&get_lang_testbin_path("rust")));
+        assert!(!is_zig_binary(&get_lang_testbin_path("python")));
+
+        // Make the rust binary look like a Zig one by rewriting its DW_AT_producer in the same length
+        let dir = tempfile::tempdir().unwrap();
+        let zig_bin = dir.path().join("hello-zig");
+        let binary = fs::read(get_lang_testbin_path("rust")).unwrap();
+        let (from, to) = (b"clang LLVM (", b"zig 0.13.0 (");
+        let pos = binary
+            .windows(from.len())
+            .position(|window| window == from)
+            .unwrap();
+        let mut patched = binary;
+        patched[pos..pos + to.len()].copy_from_slice(to);
+        fs::write(&zig_bin, patched).unwrap();
+        assert!(is_zig_binary(zig_bin.to_str().unwrap()));
+    }
+
+    #[test]
+    fn test_normalize_dwarf_path() {
+        assert_eq!(
+            PathBuf::from("/proj/lib/foo.zig"),
+            normalize_dwarf_path(PathBuf::from("/proj/build/../lib/foo.zig"))
+        );
+        assert_eq!(
+            PathBuf::from("/proj/src/main.zig"),
+            normalize_dwarf_path(PathBuf::from("/proj/src/main.zig"))
+        );
+    }
+}
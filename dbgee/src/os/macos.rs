@@ -1,31 +1,223 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use nix::{sys::signal, unistd::Pid};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
-use crate::AttachOpts;
+use crate::{config::HookProfile, AttachOpts, DebuggerTerminal, ExitReason};
 
 ////
 // macOS does not support Hook option.
+// The options are accepted for the same command line as Linux, and rejected by `run_hook`.
 ///
 
 /// Run a command and attach a debugger to its child process which triggered the specified hook condition.
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab")]
 pub struct HookOpts {
+    #[structopt(short = "e", long, hidden = true)]
+    hook_executable: Option<String>,
+
+    #[structopt(short = "s", long, hidden = true)]
+    hook_source: Option<Vec<String>>,
+
+    #[structopt(short = "i", long, hidden = true)]
+    hook_source_dir: Option<String>,
+
+    #[structopt(long, hidden = true, number_of_values = 1)]
+    hook_source_exclude: Vec<String>,
+
+    #[structopt(long, hidden = true)]
+    hook_build_id: Option<String>,
+
+    #[structopt(long, hidden = true)]
+    hook_library: Option<String>,
+
+    #[structopt(long, hidden = true)]
+    trace_fork_tree: bool,
+
+    #[structopt(long, hidden = true, number_of_values = 1)]
+    hook_suppress_signal: Vec<String>,
+
+    #[structopt(long, hidden = true, number_of_values = 1)]
+    hook_detach_signal: Vec<String>,
+
+    #[structopt(long, hidden = true)]
+    keep_stopped: bool,
+
     #[structopt(long, hidden = true)]
-    _dummy: bool,
+    hook_select: Option<String>,
+
+    #[structopt(long, hidden = true)]
+    hook_settle: Option<String>,
+
+    #[structopt(long, hidden = true)]
+    hook_show_env: bool,
+
+    #[structopt(long, hidden = true)]
+    hook_debugger_cwd: bool,
 }
 
-pub fn is_any_hook_condition_set(_hook_opts: &HookOpts) -> bool {
-    // since macOS does not support any hook conditions
-    false
+impl HookOpts {
+    /// Fills the hook conditions which are not given by the command line with `profile`,
+    /// so that `run_hook` rejects them as well as the ones in the command line
+    pub fn apply_profile(&mut self, profile: HookProfile) {
+        let path_to_string = |path: PathBuf| path.to_string_lossy().into_owned();
+        self.hook_executable = self
+            .hook_executable
+            .take()
+            .or_else(|| profile.hook_executable.map(path_to_string));
+        self.hook_source = self.hook_source.take().or(profile.hook_source);
+        self.hook_source_dir = self
+            .hook_source_dir
+            .take()
+            .or_else(|| profile.hook_source_dir.map(path_to_string));
+        self.hook_library = self.hook_library.take().or(profile.hook_library);
+        if self.hook_source_exclude.is_empty() {
+            self.hook_source_exclude = profile.hook_source_exclude;
+        }
+        self.hook_build_id = self.hook_build_id.take().or(profile.hook_build_id);
+    }
+
+    /// Returns the hook options given in the command line
+    fn given_options(&self) -> Vec<&'static str> {
+        let HookOpts {
+            hook_executable,
+            hook_source,
+            hook_source_dir,
+            hook_source_exclude,
+            hook_build_id,
+            hook_library,
+            trace_fork_tree,
+            hook_suppress_signal,
+            hook_detach_signal,
+            keep_stopped,
+            hook_select,
+            hook_settle,
+            hook_show_env,
+            hook_debugger_cwd,
+        } = self;
+        [
+            ("--hook-executable", hook_executable.is_some()),
+            ("--hook-source", hook_source.is_some()),
+            ("--hook-source-dir", hook_source_dir.is_some()),
+            ("--hook-source-exclude", !hook_source_exclude.is_empty()),
+            ("--hook-build-id", hook_build_id.is_some()),
+            ("--hook-library", hook_library.is_some()),
+            ("--trace-fork-tree", *trace_fork_tree),
+            ("--hook-suppress-signal", !hook_suppress_signal.is_empty()),
+            ("--hook-detach-signal", !hook_detach_signal.is_empty()),
+            ("--keep-stopped", *keep_stopped),
+            ("--hook-select", hook_select.is_some()),
+            ("--hook-settle", hook_settle.is_some()),
+            ("--hook-show-env", *hook_show_env),
+            ("--hook-debugger-cwd", *hook_debugger_cwd),
+        ]
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(name, _)| *name)
+        .collect()
+    }
+}
+
+pub fn is_any_hook_condition_set(hook_opts: &HookOpts) -> bool {
+    // Any hook option lets `run_hook` reject the command line, instead of silently ignoring it
+    !hook_opts.given_options().is_empty()
 }
 
 /// Run the action for subcommand `run` with hook conditions.
 pub fn run_hook(
     _command: String,
     _command_args: Vec<String>,
-    _hook_opts: HookOpts,
+    hook_opts: HookOpts,
     _attach_opts: AttachOpts,
 ) -> Result<()> {
-    unimplemented!("macOS does not support hook conditions");
+    bail!(
+        "{} is given, but hook conditions are only supported on Linux. \
+        Run the debuggee directly with `dbgee run`, or attach to it by `dbgee attach` after it starts.",
+        hook_opts.given_options().join(", ")
+    );
+}
+
+pub fn is_zig_binary(_debuggee: &str) -> bool {
+    // lldb is preferred on macOS regardless of the compiler
+    false
+}
+
+pub fn wait_pid_exit_reattaching_on_exec(
+    _pid: Pid,
+    _attach_opts: &AttachOpts,
+    _terminal: &mut dyn DebuggerTerminal,
+) -> Result<ExitReason> {
+    bail!("--reattach-on-exec is supported only on Linux");
+}
+
+pub fn run_with_syscall_summary(
+    _command: String,
+    _command_args: Vec<String>,
+    _handoff_signals: &[signal::Signal],
+    _attach_opts: &AttachOpts,
+) -> Result<ExitReason> {
+    bail!("--strace-summary is supported only on Linux");
+}
+
+pub fn print_dwarf_source_files(_exe_path: &std::path::Path, _json: bool) -> Result<()> {
+    bail!("`sources` is supported only on Linux");
+}
+
+pub fn find_missing_comp_dirs(_debuggee: &str) -> Vec<std::path::PathBuf> {
+    // Debug information on macOS is usually in a separate dSYM bundle, which is not supported yet
+    vec![]
+}
+
+/// Never created on macOS
+pub enum CpuLimitCgroup {}
+
+pub fn create_cpu_limit_cgroup(_percent: u32) -> Result<CpuLimitCgroup> {
+    bail!("--cpu-limit is supported only on Linux, which has cgroups");
+}
+
+pub fn enter_cgroup(cgroup: &CpuLimitCgroup) -> Result<()> {
+    match *cgroup {}
+}
+
+pub fn remove_cpu_limit_cgroup() {
+    // create_cpu_limit_cgroup never creates it on macOS
+}
+
+pub fn set_parent_death_signal(_sig: signal::Signal, _parent: Pid) -> Result<()> {
+    bail!("--pdeathsig is supported only on Linux, which has PR_SET_PDEATHSIG");
+}
+
+pub fn get_ptrace_scope() -> Option<u32> {
+    // ptrace_scope is a feature of Linux's Yama
+    None
+}
+
+/// Returns the pid of the process tracing `pid`, or None if `pid` is not traced.
+pub fn get_tracer_pid(_pid: Pid) -> Result<Option<Pid>> {
+    // macOS doesn't tell the tracer pid without `kinfo_proc`, which the libc crate doesn't provide yet.
+    // Let the ptrace operations report errors instead.
+    Ok(None)
+}
+
+pub fn get_process_state(_pid: Pid) -> Result<char> {
+    bail!("getting the state of a process is supported only on Linux");
+}
+
+/// Returns true if any TCP socket is listening on `port` of localhost.
+pub fn is_port_listening(port: u16) -> Result<bool> {
+    // Unlike Linux, macOS has no procfs to look up listening sockets. Ask lsof instead of connecting to the port,
+    // because servers such as dlv and debugpy take the first connection as their client.
+    let status = std::process::Command::new("lsof")
+        .args(["-nP", &format!("-iTCP:{}", port), "-sTCP:LISTEN"])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .context("Failed to run lsof")?;
+    Ok(status.success())
+}
+
+/// Wait for `pid`, which is not a child of this process, to exit.
+pub fn wait_non_child_pid_exit(pid: Pid) -> Result<()> {
+    super::poll_non_child_pid_exit(pid)
 }
@@ -0,0 +1,201 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::unistd::{self, Pid};
+use std::{
+    io::{self, BufRead, Write},
+    os::unix::io::AsRawFd,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+use sysinfo::{ProcessExt, SystemExt};
+
+use crate::file_helper::{command_exists, LoggedCommand};
+
+/// Max number of candidates listed at once by the built-in picker
+const MAX_LISTED_CANDIDATES: usize = 30;
+
+/// A running process which can be picked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub pid: Pid,
+    pub name: String,
+    pub cmdline: String,
+    pub exe_path: PathBuf,
+}
+
+impl Candidate {
+    /// One line to show in the picker. The pid comes first so that the selected line can be parsed back.
+    fn to_line(&self) -> String {
+        format!("{}\t{}\t{}", self.pid, self.name, self.cmdline)
+    }
+}
+
+/// Lets the user pick one of the running processes by fzf if it is installed, or by the built-in picker.
+pub fn pick_process() -> Result<Candidate> {
+    if !unistd::isatty(io::stdin().as_raw_fd()).unwrap_or(false) {
+        bail!("--pick needs a terminal to pick a process interactively");
+    }
+    let candidates = list_candidates();
+    if candidates.is_empty() {
+        bail!("no processes to attach to are found");
+    }
+    if command_exists("fzf") {
+        pick_by_fzf(&candidates)
+    } else {
+        pick_by_prompt(&candidates)
+    }
+}
+
+/// Lists the processes except for kernel threads and dbgee itself
+fn list_candidates() -> Vec<Candidate> {
+    let mut sysinfo_system =
+        sysinfo::System::new_with_specifics(sysinfo::RefreshKind::new().with_processes());
+    sysinfo_system.refresh_processes();
+    let self_pid = unistd::getpid().as_raw();
+    let mut candidates: Vec<Candidate> = sysinfo_system
+        .get_processes()
+        .iter()
+        .filter(|(&pid, process)| pid != self_pid && !process.cmd().is_empty())
+        .map(|(&pid, process)| Candidate {
+            pid: Pid::from_raw(pid),
+            name: process.name().to_owned(),
+            cmdline: process.cmd().join(" "),
+            exe_path: process.exe().to_path_buf(),
+        })
+        .collect();
+    // Newer processes first, since the process to debug is likely to be a recent one
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.pid));
+    candidates
+}
+
+fn pick_by_fzf(candidates: &[Candidate]) -> Result<Candidate> {
+    let mut fzf = Command::new("fzf")
+        .args(["--prompt", "attach> "])
+        .logged()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to launch fzf")?;
+    {
+        let mut stdin = fzf.stdin.take().unwrap();
+        for candidate in candidates {
+            // fzf may exit before reading all the lines
+            if writeln!(stdin, "{}", candidate.to_line()).is_err() {
+                break;
+            }
+        }
+    }
+    let output = fzf.wait_with_output().context("Failed to wait for fzf")?;
+    if !output.status.success() {
+        bail!("no process is picked");
+    }
+    let pid = parse_selected_line(&String::from_utf8_lossy(&output.stdout))?;
+    find_candidate(candidates, pid)
+}
+
+/// Parses the pid from the line selected in fzf
+fn parse_selected_line(line: &str) -> Result<Pid> {
+    let pid = line
+        .split('\t')
+        .next()
+        .and_then(|pid| pid.trim().parse().ok())
+        .ok_or_else(|| anyhow!("unexpected line is selected: '{}'", line.trim()))?;
+    Ok(Pid::from_raw(pid))
+}
+
+fn find_candidate(candidates: &[Candidate], pid: Pid) -> Result<Candidate> {
+    candidates
+        .iter()
+        .find(|candidate| candidate.pid == pid)
+        .cloned()
+        .ok_or_else(|| anyhow!("process {} is not found", pid))
+}
+
+/// A minimal picker. It narrows down the candidates by the query the user types, and lets the user
+/// choose one by its number in the list.
+fn pick_by_prompt(candidates: &[Candidate]) -> Result<Candidate> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut matched: Vec<&Candidate> = candidates.iter().collect();
+    loop {
+        for (i, candidate) in matched.iter().take(MAX_LISTED_CANDIDATES).enumerate() {
+            eprintln!("{:>3}) {}", i + 1, candidate.to_line());
+        }
+        if matched.len() > MAX_LISTED_CANDIDATES {
+            eprintln!("  ... and {} more", matched.len() - MAX_LISTED_CANDIDATES);
+        }
+        eprint!("Number to attach, or text to filter the processes: ");
+        io::stderr().flush()?;
+        let input = match lines.next() {
+            Some(line) => line?,
+            None => bail!("no process is picked"),
+        };
+        let input = input.trim();
+        if let Ok(number) = input.parse::<usize>() {
+            if (1..=matched.len().min(MAX_LISTED_CANDIDATES)).contains(&number) {
+                return Ok(matched[number - 1].clone());
+            }
+        }
+        let filtered = filter_candidates(candidates, input);
+        if filtered.is_empty() {
+            eprintln!("No processes match '{}'.", input);
+        } else {
+            matched = filtered;
+        }
+    }
+}
+
+/// Returns the candidates whose line contains all the characters of `query` in order, ignoring case
+fn filter_candidates<'a>(candidates: &'a [Candidate], query: &str) -> Vec<&'a Candidate> {
+    let query = query.to_lowercase();
+    candidates
+        .iter()
+        .filter(|candidate| {
+            let line = candidate.to_line().to_lowercase();
+            let mut line_chars = line.chars();
+            query
+                .chars()
+                .all(|query_char| line_chars.any(|line_char| line_char == query_char))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(pid: i32, name: &str, cmdline: &str) -> Candidate {
+        Candidate {
+            pid: Pid::from_raw(pid),
+            name: name.to_owned(),
+            cmdline: cmdline.to_owned(),
+            exe_path: PathBuf::from("/usr/bin").join(name),
+        }
+    }
+
+    #[test]
+    fn test_filter_candidates() {
+        let candidates = vec![
+            candidate(10, "nginx", "nginx: worker process"),
+            candidate(20, "python3", "python3 manage.py runserver"),
+            candidate(30, "sleep", "sleep 30"),
+        ];
+        assert_eq!(3, filter_candidates(&candidates, "").len());
+        assert_eq!(
+            vec![&candidates[1]],
+            filter_candidates(&candidates, "MNG RUN")
+        );
+        assert_eq!(
+            vec![&candidates[0]],
+            filter_candidates(&candidates, "ngx wrk")
+        );
+        assert_eq!(vec![&candidates[2]], filter_candidates(&candidates, "30"));
+        assert!(filter_candidates(&candidates, "zzz").is_empty());
+    }
+
+    #[test]
+    fn test_parse_selected_line() {
+        let line = format!("{}\n", candidate(42, "sleep", "sleep 30").to_line());
+        assert_eq!(Pid::from_raw(42), parse_selected_line(&line).unwrap());
+        assert!(parse_selected_line("sleep\t30\n").is_err());
+    }
+}
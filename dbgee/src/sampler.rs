@@ -0,0 +1,202 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::unistd::Pid;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::{collections::HashMap, process::Command, time::Duration};
+
+use crate::{
+    debugger::{DebuggerOptions, GdbDebugger},
+    file_helper::LoggedCommand,
+    SampleOutputOptValues,
+};
+
+/// A backtrace of a thread. Frames are ordered from the innermost one.
+type Stack = Vec<String>;
+
+/// Attaches gdb to `pid` `count` times with `interval` in between, and prints how many times each
+/// distinct backtrace is observed, like a poor man's profiler.
+pub fn sample_backtraces(
+    pid: Pid,
+    count: usize,
+    interval: Duration,
+    output_format: SampleOutputOptValues,
+    options: &DebuggerOptions,
+) -> Result<()> {
+    let commandline = GdbDebugger::build_backtrace_commandline(options, pid)?;
+    let mut stack_counts: HashMap<Stack, usize> = HashMap::new();
+    let mut n_samples = 0;
+    for i in 0..count {
+        if i > 0 {
+            std::thread::sleep(interval);
+        }
+        let output = Command::new(&commandline[0])
+            .args(&commandline[1..])
+            .logged()
+            .output()
+            .with_context(|| format!("Failed to launch {}", commandline[0]))?;
+        let stacks = parse_gdb_backtraces(&String::from_utf8_lossy(&output.stdout));
+        if stacks.is_empty() {
+            if n_samples == 0 {
+                bail!(
+                    "gdb printed no backtraces of pid {}. {}",
+                    pid,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            log::info!("pid {} seems to have exited. Stopped sampling.", pid);
+            break;
+        }
+        for stack in stacks {
+            *stack_counts.entry(stack).or_insert(0) += 1;
+        }
+        n_samples += 1;
+    }
+    match output_format {
+        SampleOutputOptValues::Table => print!("{}", format_stack_counts(&stack_counts, n_samples)),
+        SampleOutputOptValues::Folded => print!("{}", format_folded_stacks(&stack_counts)),
+    }
+    Ok(())
+}
+
+/// Parses the output of gdb's `thread apply all bt` into the stack of each thread
+fn parse_gdb_backtraces(output: &str) -> Vec<Stack> {
+    // e.g. "#1  0x0000555555555171 in foo (n=1) at foo.c:3" or "#2  main () at foo.c:8"
+    static FRAME_RE: Lazy<Regex> =
+        Lazy::new(|| Regex::new(r"^#\d+\s+(?:0x[0-9a-f]+ in )?(\S+)").unwrap());
+
+    let mut stacks = vec![];
+    let mut current: Stack = vec![];
+    for line in output.lines() {
+        if let Some(captures) = FRAME_RE.captures(line) {
+            current.push(captures[1].to_owned());
+        } else if line.starts_with("Thread ") && !current.is_empty() {
+            stacks.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        stacks.push(current);
+    }
+    stacks
+}
+
+fn format_stack_counts(stack_counts: &HashMap<Stack, usize>, n_samples: usize) -> String {
+    let mut stack_counts: Vec<(&Stack, &usize)> = stack_counts.iter().collect();
+    // The most frequent stacks first. Sort by the stack as well for a stable output
+    stack_counts.sort_by(|(stack_a, count_a), (stack_b, count_b)| {
+        count_b.cmp(count_a).then_with(|| stack_a.cmp(stack_b))
+    });
+    let mut table = format!("{} samples\n", n_samples);
+    for (stack, count) in stack_counts {
+        table.push_str(&format!("{:>6}  {}\n", count, stack.join(" <- ")));
+    }
+    table
+}
+
+/// Formats the stacks in the folded format of flamegraph.pl, e.g. `main;compute;memcpy 7`,
+/// where the frames are ordered from the outermost one
+fn format_folded_stacks(stack_counts: &HashMap<Stack, usize>) -> String {
+    let mut lines: Vec<String> = stack_counts
+        .iter()
+        .map(|(stack, count)| {
+            // ';' separates the frames. Frames have no spaces, which separate the count
+            let frames: Vec<String> = stack
+                .iter()
+                .rev()
+                .map(|frame| frame.replace(';', ":"))
+                .collect();
+            format!("{} {}\n", frames.join(";"), count)
+        })
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+/// Parses an interval such as "100ms", "2s" or "100". A number without a unit is in milliseconds.
+pub fn parse_interval(interval: &str) -> Result<Duration> {
+    let (number, unit_millis) = if let Some(number) = interval.strip_suffix("ms") {
+        (number, 1)
+    } else if let Some(number) = interval.strip_suffix('s') {
+        (number, 1000)
+    } else {
+        (interval, 1)
+    };
+    let number: u64 = number.parse().map_err(|_| {
+        anyhow!(
+            "invalid interval '{}'. Use such as '100ms' or '1s'",
+            interval
+        )
+    })?;
+    let millis = number
+        .checked_mul(unit_millis)
+        .ok_or_else(|| anyhow!("interval '{}' is too long", interval))?;
+    Ok(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_gdb_backtraces() {
+        let output = "\n\
+            Thread 2 (Thread 0x7ffff7d8a640 (LWP 1235) \"worker\"):\n\
+            #0  0x00007ffff7e5a7fa in __GI___clock_nanosleep (clockid=0, flags=0) at nanosleep.c:78\n\
+            #1  0x0000555555555189 in work (arg=0x0) at main.c:5\n\
+            #2  0x00007ffff7e0cac3 in start_thread (arg=<optimized out>) at pthread_create.c:442\n\
+            \n\
+            Thread 1 (Thread 0x7ffff7d8b740 (LWP 1234) \"main\"):\n\
+            #0  main () at main.c:12\n";
+        assert_eq!(
+            vec![
+                vec!["__GI___clock_nanosleep", "work", "start_thread"],
+                vec!["main"],
+            ],
+            parse_gdb_backtraces(output)
+        );
+        assert!(parse_gdb_backtraces("No such process.\n").is_empty());
+    }
+
+    #[test]
+    fn test_format_stack_counts() {
+        let stack_counts: HashMap<Stack, usize> = [
+            (vec!["poll".to_owned(), "main".to_owned()], 3),
+            (vec!["compute".to_owned(), "main".to_owned()], 7),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        assert_eq!(
+            "10 samples\n     7  compute <- main\n     3  poll <- main\n",
+            format_stack_counts(&stack_counts, 10)
+        );
+    }
+
+    #[test]
+    fn test_format_folded_stacks() {
+        let stack_counts: HashMap<Stack, usize> = [
+            (vec!["poll".to_owned(), "main".to_owned()], 3),
+            (
+                vec!["memcpy".to_owned(), "compute".to_owned(), "main".to_owned()],
+                7,
+            ),
+            (vec!["??".to_owned(), "start_thread".to_owned()], 1),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        assert_eq!(
+            "main;compute;memcpy 7\nmain;poll 3\nstart_thread;?? 1\n",
+            format_folded_stacks(&stack_counts)
+        );
+    }
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(Duration::from_millis(100), parse_interval("100ms").unwrap());
+        assert_eq!(Duration::from_secs(2), parse_interval("2s").unwrap());
+        assert_eq!(Duration::from_millis(50), parse_interval("50").unwrap());
+        assert!(parse_interval("1m").is_err());
+        assert!(parse_interval("ms").is_err());
+        assert!(parse_interval(&format!("{}s", u64::MAX)).is_err());
+    }
+}
@@ -0,0 +1,360 @@
+use anyhow::{anyhow, bail, Context, Result};
+use nix::{
+    sys::signal,
+    unistd::{self, Pid},
+};
+use once_cell::sync::OnceCell;
+use std::{
+    env, fs,
+    os::unix::fs::{DirBuilderExt, MetadataExt},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use toml::Value;
+
+use crate::os::wait_non_child_pid_exit;
+
+/// Name of the directory of the session files in $XDG_RUNTIME_DIR
+const SESSIONS_DIR_NAME: &str = "dbgee-sessions";
+
+/// Set by SIGUSR1 to request dbgee to detach from the debuggee
+static DETACH_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Thread which waits for the debuggee, and has to be interrupted by SIGUSR1
+static WAITING_THREAD: AtomicUsize = AtomicUsize::new(0);
+/// Command line of the debuggee to record in the session file
+static DEBUGGEE_COMMANDLINE: OnceCell<String> = OnceCell::new();
+
+/// A debuggee which dbgee detached from, leaving it and the debugger running
+#[derive(Debug, PartialEq, Eq)]
+pub struct Session {
+    pub pid: Pid,
+    /// Process group of the debuggee, which `reattach` brings to the foreground of the terminal
+    pub pgid: Pid,
+    pub command: String,
+}
+
+impl Session {
+    fn to_toml(&self) -> String {
+        let mut table = toml::map::Map::new();
+        table.insert("pid".to_owned(), Value::Integer(self.pid.as_raw().into()));
+        table.insert("pgid".to_owned(), Value::Integer(self.pgid.as_raw().into()));
+        table.insert("command".to_owned(), Value::String(self.command.clone()));
+        Value::Table(table).to_string()
+    }
+
+    /// Returns true if the debuggee is still running. The session file is left behind if it has exited
+    /// without being reattached.
+    pub fn is_alive(&self) -> bool {
+        signal::kill(self.pid, None).is_ok()
+    }
+
+    fn from_toml(session: &str) -> Result<Session> {
+        let session: Value = session
+            .parse()
+            .context("Failed to parse the session file")?;
+        let get_pid = |key: &str| -> Result<Pid> {
+            session
+                .get(key)
+                .and_then(Value::as_integer)
+                .map(|pid| Pid::from_raw(pid as i32))
+                .ok_or_else(|| anyhow!("'{}' is not found in the session file", key))
+        };
+        Ok(Session {
+            pid: get_pid("pid")?,
+            pgid: get_pid("pgid")?,
+            command: session
+                .get("command")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_owned(),
+        })
+    }
+}
+
+/// Lets SIGUSR1 interrupt the wait for the debuggee so that dbgee can detach from it.
+/// Call this in the thread which waits for the debuggee.
+pub fn install_detach_handler(command: &str, command_args: &[String]) -> Result<()> {
+    let commandline = std::iter::once(command)
+        .chain(command_args.iter().map(String::as_str))
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let _ = DEBUGGEE_COMMANDLINE.set(commandline);
+    extern "C" fn request_detach(_: nix::libc::c_int) {
+        DETACH_REQUESTED.store(true, Ordering::SeqCst);
+        // The signal may be delivered to another thread, such as the one of ctrlc.
+        // Forward it so that waitpid in the waiting thread returns EINTR
+        let waiting_thread = WAITING_THREAD.load(Ordering::SeqCst) as nix::libc::pthread_t;
+        unsafe {
+            if nix::libc::pthread_self() != waiting_thread {
+                nix::libc::pthread_kill(waiting_thread, nix::libc::SIGUSR1);
+            }
+        }
+    }
+    WAITING_THREAD.store(
+        unsafe { nix::libc::pthread_self() } as usize,
+        Ordering::SeqCst,
+    );
+    // Without SA_RESTART, so that waitpid returns EINTR
+    let action = signal::SigAction::new(
+        signal::SigHandler::Handler(request_detach),
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+    unsafe { signal::sigaction(signal::SIGUSR1, &action) }
+        .context("Failed to set the signal handler of SIGUSR1")?;
+    log::debug!(
+        "Send SIGUSR1 to dbgee (pid: {}) to detach from the debuggee",
+        unistd::getpid()
+    );
+    Ok(())
+}
+
+pub fn is_detach_requested() -> bool {
+    DETACH_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Records the debuggee to a session file so that `dbgee reattach` can resume waiting for it
+pub fn detach(pid: Pid) -> Result<()> {
+    let session = Session {
+        pid,
+        pgid: unistd::getpgid(Some(pid))
+            .context("Failed to get the process group of the debuggee")?,
+        command: DEBUGGEE_COMMANDLINE.get().cloned().unwrap_or_default(),
+    };
+    let sessions_dir = create_sessions_dir()?;
+    let session_path = build_session_path(&sessions_dir, &pid.to_string());
+    fs::write(&session_path, session.to_toml())
+        .with_context(|| format!("Failed to write {:?}", session_path))?;
+    log::info!(
+        "Detached from the debuggee (pid: {}). It keeps running with the debugger. \
+        Run `dbgee reattach {}` to bring it back to the foreground.",
+        pid,
+        pid
+    );
+    Ok(())
+}
+
+/// Returns the directory of the session files, each of which records a debuggee that dbgee detached from.
+///
+/// It's $XDG_RUNTIME_DIR/dbgee-sessions, or /tmp/dbgee-sessions-<uid> without $XDG_RUNTIME_DIR,
+/// so that other users can't plant sessions to reattach to.
+pub fn sessions_dir() -> PathBuf {
+    resolve_sessions_dir(
+        env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from),
+        unistd::getuid(),
+    )
+}
+
+fn resolve_sessions_dir(runtime_dir: Option<PathBuf>, uid: unistd::Uid) -> PathBuf {
+    match runtime_dir {
+        Some(runtime_dir) if runtime_dir.is_absolute() => runtime_dir.join(SESSIONS_DIR_NAME),
+        _ => PathBuf::from(format!("/tmp/{}-{}", SESSIONS_DIR_NAME, uid)),
+    }
+}
+
+/// Creates the sessions directory only accessible to the user if it doesn't exist, and returns it
+pub fn create_sessions_dir() -> Result<PathBuf> {
+    let dir = sessions_dir();
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create {:?}", dir))?;
+    verify_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Bails unless `dir` is a directory owned by the user and inaccessible to the others
+fn verify_private_dir(dir: &Path) -> Result<()> {
+    // Don't follow a symlink, which another user may have planted in /tmp
+    let metadata =
+        fs::symlink_metadata(dir).with_context(|| format!("Failed to stat {:?}", dir))?;
+    if !metadata.is_dir() {
+        bail!("{:?} is not a directory", dir);
+    }
+    if metadata.uid() != unistd::getuid().as_raw() {
+        bail!(
+            "{:?} is owned by another user (uid: {}). Remove it to use dbgee's sessions",
+            dir,
+            metadata.uid()
+        );
+    }
+    if metadata.mode() & 0o077 != 0 {
+        bail!(
+            "{:?} is accessible to other users (mode: {:o}). Run `chmod 700 {}`",
+            dir,
+            metadata.mode() & 0o777,
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+fn build_session_path(sessions_dir: &Path, name: &str) -> PathBuf {
+    sessions_dir.join(format!("{}.toml", name))
+}
+
+/// Returns the names of the sessions, which are the pids of the debuggees
+fn list_sessions(sessions_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(sessions_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    match path.extension() {
+                        Some(extension) if extension == "toml" => {
+                            Some(path.file_stem()?.to_str()?.to_owned())
+                        }
+                        _ => None,
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// Returns the sessions which dbgee detached from, sorted by their names
+pub fn read_sessions() -> Result<Vec<Session>> {
+    let sessions_dir = sessions_dir();
+    if !sessions_dir.exists() {
+        return Ok(vec![]);
+    }
+    verify_private_dir(&sessions_dir)?;
+    list_sessions(&sessions_dir)
+        .iter()
+        .map(|name| {
+            let session_path = build_session_path(&sessions_dir, name);
+            fs::read_to_string(&session_path)
+                .with_context(|| format!("Failed to read {:?}", session_path))
+                .and_then(|session| Session::from_toml(&session))
+        })
+        .collect()
+}
+
+/// Brings the debuggee of the session back to the foreground of the terminal like `fg`, and waits for it to exit.
+///
+/// The session is the only one if `name` is not given.
+pub fn reattach(name: Option<&str>) -> Result<i32> {
+    let sessions_dir = sessions_dir();
+    if sessions_dir.exists() {
+        verify_private_dir(&sessions_dir)?;
+    }
+    let name = match name {
+        Some(name) => name.to_owned(),
+        None => match list_sessions(&sessions_dir).as_slice() {
+            [name] => name.clone(),
+            [] => bail!("no detached sessions are found in {:?}", sessions_dir),
+            names => bail!(
+                "multiple sessions are found. Give one of them: {}",
+                names.join(", ")
+            ),
+        },
+    };
+    let session_path = build_session_path(&sessions_dir, &name);
+    let session = fs::read_to_string(&session_path)
+        .with_context(|| format!("session '{}' is not found", name))
+        .and_then(|session| Session::from_toml(&session))?;
+    if signal::kill(session.pid, None).is_err() {
+        fs::remove_file(&session_path).ok();
+        bail!(
+            "the debuggee of session '{}' ({}) has already exited",
+            name,
+            session.command
+        );
+    }
+
+    let original_foreground = give_foreground(&session)?;
+    log::info!(
+        "Reattached to the debuggee (pid: {}). {}",
+        session.pid,
+        session.command
+    );
+    let waited = wait_non_child_pid_exit(session.pid);
+    if let Some(original_foreground) = original_foreground {
+        take_back_foreground(original_foreground)?;
+    }
+    waited?;
+    fs::remove_file(&session_path).ok();
+    // The debuggee is not a child of this dbgee anymore
+    log::info!("The debuggee exited. Its exit status is unknown after reattaching.");
+    Ok(0)
+}
+
+/// Makes the process group of the debuggee the foreground of the terminal, and returns the original one.
+/// Returns None if the terminal cannot be given, e.g. when dbgee is not in the session of the terminal.
+fn give_foreground(session: &Session) -> Result<Option<Pid>> {
+    let stdin = nix::libc::STDIN_FILENO;
+    if !unistd::isatty(stdin).unwrap_or(false) {
+        return Ok(None);
+    }
+    if unistd::getsid(Some(session.pid)).ok() != unistd::getsid(None).ok() {
+        log::warn!(
+            "The debuggee is not in the session of this terminal. Just waiting for it to exit."
+        );
+        return Ok(None);
+    }
+    let original_foreground = unistd::tcgetpgrp(stdin).context("Failed to get the foreground")?;
+    unistd::tcsetpgrp(stdin, session.pgid)
+        .context("Failed to bring the debuggee to the foreground")?;
+    Ok(Some(original_foreground))
+}
+
+fn take_back_foreground(original_foreground: Pid) -> Result<()> {
+    // dbgee is in the background now, and tcsetpgrp from the background raises SIGTTOU
+    unsafe { signal::signal(signal::SIGTTOU, signal::SigHandler::SigIgn) }?;
+    unistd::tcsetpgrp(nix::libc::STDIN_FILENO, original_foreground)
+        .context("Failed to take back the foreground")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_toml() {
+        let session = Session {
+            pid: Pid::from_raw(42),
+            pgid: Pid::from_raw(41),
+            command: "/bin/server \"--port\" 80".to_owned(),
+        };
+        assert_eq!(session, Session::from_toml(&session.to_toml()).unwrap());
+        assert!(Session::from_toml("pid = 42").is_err());
+    }
+
+    #[test]
+    fn test_resolve_sessions_dir() {
+        let uid = unistd::Uid::from_raw(1000);
+        assert_eq!(
+            PathBuf::from("/run/user/1000/dbgee-sessions"),
+            resolve_sessions_dir(Some(PathBuf::from("/run/user/1000")), uid)
+        );
+        assert_eq!(
+            PathBuf::from("/tmp/dbgee-sessions-1000"),
+            resolve_sessions_dir(None, uid)
+        );
+        // A relative path is invalid as $XDG_RUNTIME_DIR
+        assert_eq!(
+            PathBuf::from("/tmp/dbgee-sessions-1000"),
+            resolve_sessions_dir(Some(PathBuf::from("run")), uid)
+        );
+    }
+
+    #[test]
+    fn test_verify_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(verify_private_dir(dir.path()).is_ok());
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(verify_private_dir(dir.path()).is_err());
+
+        let symlink = dir.path().join("symlink");
+        let private_dir = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(private_dir.path(), &symlink).unwrap();
+        assert!(verify_private_dir(&symlink).is_err());
+    }
+}
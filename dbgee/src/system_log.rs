@@ -0,0 +1,148 @@
+use std::os::unix::net::UnixDatagram;
+
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::LogTarget;
+
+/// Socket of syslog, which journald also listens on if it replaces the syslog daemon
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+/// Socket of the native protocol of journald
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+const IDENTIFIER: &str = "dbgee";
+/// Facility "user" of syslog
+const SYSLOG_FACILITY_USER: u8 = 1;
+
+/// Logger which sends the messages to syslog or journald, instead of stderr
+pub struct SystemLogger {
+    target: LogTarget,
+    socket: UnixDatagram,
+    level: LevelFilter,
+}
+
+impl SystemLogger {
+    /// Connects to the socket of `target`, which is either `LogTarget::Syslog` or `LogTarget::Journald`
+    pub fn new(target: LogTarget, level: LevelFilter) -> Result<SystemLogger> {
+        let socket_path = match target {
+            LogTarget::Syslog => SYSLOG_SOCKET_PATH,
+            LogTarget::Journald => JOURNALD_SOCKET_PATH,
+            LogTarget::Stderr => panic!("[BUG] SystemLogger doesn't log to stderr"),
+        };
+        let socket = UnixDatagram::unbound().context("Failed to create a socket")?;
+        socket
+            .connect(socket_path)
+            .with_context(|| format!("Failed to connect to {} of {}", socket_path, target))?;
+        Ok(SystemLogger {
+            target,
+            socket,
+            level,
+        })
+    }
+
+    /// Sets this logger as the logger of the `log` crate
+    pub fn init(self) -> Result<()> {
+        let level = self.level;
+        log::set_boxed_logger(Box::new(self)).context("Failed to set the logger")?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for SystemLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let pid = std::process::id();
+        let datagram = match self.target {
+            LogTarget::Journald => format_journald_datagram(record.level(), pid, &message),
+            _ => format_syslog_datagram(record.level(), pid, &message).into_bytes(),
+        };
+        // There's nowhere to report the failure
+        let _ = self.socket.send(&datagram);
+    }
+
+    fn flush(&self) {}
+}
+
+fn to_syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Formats a message in the format which /dev/log accepts, such as `<14>dbgee[42]: message`
+fn format_syslog_datagram(level: Level, pid: u32, message: &str) -> String {
+    format!(
+        "<{}>{}[{}]: {}",
+        SYSLOG_FACILITY_USER * 8 + to_syslog_severity(level),
+        IDENTIFIER,
+        pid,
+        message
+    )
+}
+
+/// Formats a message in the native protocol of journald, where each field is `KEY=value\n`.
+/// A value with newlines is instead given as `KEY\n`, its length in little endian u64, and `value\n`.
+fn format_journald_datagram(level: Level, pid: u32, message: &str) -> Vec<u8> {
+    let fields = [
+        ("PRIORITY", to_syslog_severity(level).to_string()),
+        ("SYSLOG_IDENTIFIER", IDENTIFIER.to_owned()),
+        ("SYSLOG_PID", pid.to_string()),
+        ("MESSAGE", message.to_owned()),
+    ];
+    let mut datagram = vec![];
+    for (key, val) in fields.iter() {
+        datagram.extend_from_slice(key.as_bytes());
+        if val.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(val.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(val.as_bytes());
+        datagram.push(b'\n');
+    }
+    datagram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_syslog_datagram() {
+        assert_eq!(
+            "<14>dbgee[42]: VSCode has attached to the debuggee",
+            format_syslog_datagram(Level::Info, 42, "VSCode has attached to the debuggee")
+        );
+        assert_eq!(
+            "<11>dbgee[42]: failed",
+            format_syslog_datagram(Level::Error, 42, "failed")
+        );
+    }
+
+    #[test]
+    fn test_format_journald_datagram() {
+        assert_eq!(
+            b"PRIORITY=4\nSYSLOG_IDENTIFIER=dbgee\nSYSLOG_PID=42\nMESSAGE=detached\n".to_vec(),
+            format_journald_datagram(Level::Warn, 42, "detached")
+        );
+        let mut expected =
+            b"PRIORITY=3\nSYSLOG_IDENTIFIER=dbgee\nSYSLOG_PID=42\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&4u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n\n");
+        assert_eq!(
+            expected,
+            format_journald_datagram(Level::Error, 42, "a\nb\n")
+        );
+    }
+}
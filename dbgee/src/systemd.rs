@@ -0,0 +1,259 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::file_helper::{find_command_path, LoggedCommand};
+
+/// Name of the drop-in which `dbgee systemd` writes. "zz-" lets it override ExecStart of other drop-ins.
+const DROPIN_NAME: &str = "zz-dbgee.conf";
+
+/// ExecStart of a systemd unit
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExecStart {
+    /// Absolute path to the executable
+    pub path: String,
+    /// Arguments including argv[0]
+    pub argv: Vec<String>,
+    /// ExecStart is prefixed by "-", which makes systemd ignore its failure
+    pub ignore_errors: bool,
+}
+
+/// systemd unit, which is a system unit unless `user` is set
+pub struct Unit {
+    pub name: String,
+    pub user: bool,
+}
+
+impl Unit {
+    pub fn new(name: &str, user: bool) -> Unit {
+        // systemctl regards a name without a suffix as a service
+        let name = if name.contains('.') {
+            name.to_owned()
+        } else {
+            format!("{}.service", name)
+        };
+        Unit { name, user }
+    }
+
+    /// Reads ExecStart by `systemctl show`
+    pub fn read_exec_start(&self) -> Result<ExecStart> {
+        let show = self.systemctl(&["show", "-p", "ExecStart", &self.name])?;
+        parse_exec_start(&show)
+            .with_context(|| format!("Failed to read ExecStart of {}", self.name))
+    }
+
+    /// Writes the drop-in which replaces ExecStart with `run_command_prefix -- <ExecStart>`
+    pub fn write_override(
+        &self,
+        exec_start: &ExecStart,
+        run_command_prefix: &str,
+    ) -> Result<PathBuf> {
+        let dropin_path = self.build_dropin_path()?;
+        let dropin_dir = dropin_path.parent().unwrap();
+        fs::create_dir_all(dropin_dir)
+            .with_context(|| format!("Failed to create {:?}", dropin_dir))?;
+        fs::write(&dropin_path, build_override(exec_start, run_command_prefix))
+            .with_context(|| format!("Failed to write {:?}", dropin_path))?;
+        self.systemctl(&["daemon-reload"])?;
+        Ok(dropin_path)
+    }
+
+    /// Removes the drop-in which `write_override` wrote
+    pub fn remove_override(&self) -> Result<PathBuf> {
+        let dropin_path = self.build_dropin_path()?;
+        if !dropin_path.exists() {
+            bail!(
+                "{} is not overridden by dbgee. {:?} is not found. \
+                Give --set if you set the binary of the unit.",
+                self.name,
+                dropin_path
+            );
+        }
+        fs::remove_file(&dropin_path)
+            .with_context(|| format!("Failed to remove {:?}", dropin_path))?;
+        // Leave the directory if the user has other drop-ins in it
+        let _ = fs::remove_dir(dropin_path.parent().unwrap());
+        self.systemctl(&["daemon-reload"])?;
+        Ok(dropin_path)
+    }
+
+    /// Returns `systemctl restart` for the unit, which the user runs to apply changes
+    pub fn build_restart_command(&self) -> String {
+        format!(
+            "systemctl {}restart {}",
+            if self.user { "--user " } else { "" },
+            self.name
+        )
+    }
+
+    /// Returns the path of the drop-in which dbgee writes for the unit
+    pub fn build_dropin_path(&self) -> Result<PathBuf> {
+        let units_dir = if self.user {
+            match env::var_os("XDG_CONFIG_HOME") {
+                Some(config_home) => PathBuf::from(config_home),
+                None => Path::new(&env::var_os("HOME").ok_or_else(|| anyhow!("$HOME is not set"))?)
+                    .join(".config"),
+            }
+            .join("systemd/user")
+        } else {
+            PathBuf::from("/etc/systemd/system")
+        };
+        Ok(units_dir.join(format!("{}.d", self.name)).join(DROPIN_NAME))
+    }
+
+    fn systemctl(&self, args: &[&str]) -> Result<String> {
+        let mut command = Command::new("systemctl");
+        if self.user {
+            command.arg("--user");
+        }
+        let output = command
+            .args(args)
+            .logged()
+            .output()
+            .context("Failed to run systemctl. Is systemd available?")?;
+        if !output.status.success() {
+            bail!(
+                "systemctl {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Parses the output of `systemctl show -p ExecStart`, which looks like
+/// `ExecStart={ path=/usr/bin/foo ; argv[]=foo --bar ; ignore_errors=no ; ... }`
+fn parse_exec_start(show: &str) -> Result<ExecStart> {
+    let value = show
+        .lines()
+        .find_map(|line| line.strip_prefix("ExecStart="))
+        .ok_or_else(|| anyhow!("ExecStart is not found"))?
+        .trim();
+    let commands: Vec<&str> = value
+        .split('}')
+        .map(|command| command.trim().trim_start_matches('{').trim())
+        .filter(|command| !command.is_empty())
+        .collect();
+    let command = match commands.as_slice() {
+        [command] => command,
+        [] => bail!("the unit has no ExecStart"),
+        _ => bail!("the unit has multiple ExecStart, which is not supported"),
+    };
+    let mut path = None;
+    let mut argv = vec![];
+    let mut ignore_errors = false;
+    for field in command.split(" ; ") {
+        if let Some(val) = field.strip_prefix("path=") {
+            path = Some(val.to_owned());
+        } else if let Some(val) = field.strip_prefix("argv[]=") {
+            // systemctl doesn't quote the arguments, so arguments with spaces are split
+            argv = val.split_whitespace().map(str::to_owned).collect();
+        } else if let Some(val) = field.strip_prefix("ignore_errors=") {
+            ignore_errors = val == "yes";
+        }
+    }
+    let path = path.ok_or_else(|| anyhow!("the path of ExecStart is not found"))?;
+    Ok(ExecStart {
+        path: resolve_executable(&path)?,
+        argv,
+        ignore_errors,
+    })
+}
+
+/// Resolves the executable of ExecStart. Recent systemd shows the absolute path even if the unit
+/// gives a command name in PATH, but old versions show the name as it is.
+fn resolve_executable(path: &str) -> Result<String> {
+    if Path::new(path).is_absolute() {
+        return Ok(path.to_owned());
+    }
+    find_command_path(path).ok_or_else(|| anyhow!("{} of ExecStart is not found in PATH", path))
+}
+
+fn build_override(exec_start: &ExecStart, run_command_prefix: &str) -> String {
+    // argv[0] may differ from the path by the "@" prefix, but `dbgee run` cannot pass it through
+    let command = std::iter::once(exec_start.path.as_str())
+        .chain(exec_start.argv.iter().skip(1).map(String::as_str))
+        .map(quote_systemd_arg)
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "# Written by `dbgee systemd`. `dbgee systemd --undo` removes this file.\n\
+        [Service]\n\
+        ExecStart=\n\
+        ExecStart={}{} -- {}\n",
+        if exec_start.ignore_errors { "-" } else { "" },
+        run_command_prefix.replace('%', "%%"),
+        command
+    )
+}
+
+/// Quotes an argument for the command lines of systemd units, where "%" is a specifier
+fn quote_systemd_arg(arg: &str) -> String {
+    let arg = arg.replace('%', "%%");
+    if !arg.is_empty()
+        && arg != ";"
+        && !arg
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\')
+    {
+        return arg;
+    }
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_exec_start() {
+        let show = "ExecStart={ path=/usr/sbin/nginx ; argv[]=/usr/sbin/nginx -g daemon on; ; \
+                    ignore_errors=yes ; start_time=[n/a] ; stop_time=[n/a] ; pid=0 ; code=(null) ; status=0/0 }\n";
+        assert_eq!(
+            ExecStart {
+                path: "/usr/sbin/nginx".to_owned(),
+                argv: vec!["/usr/sbin/nginx", "-g", "daemon", "on;"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+                ignore_errors: true,
+            },
+            parse_exec_start(show).unwrap()
+        );
+
+        // A command name in PATH, shown by old systemd
+        let show = "ExecStart={ path=sh ; argv[]=sh -c true ; ignore_errors=no }";
+        let exec_start = parse_exec_start(show).unwrap();
+        assert!(Path::new(&exec_start.path).is_absolute());
+        assert!(exec_start.path.ends_with("/sh"));
+
+        assert!(parse_exec_start("ExecStart=\n").is_err());
+        assert!(parse_exec_start(
+            "ExecStart={ path=/bin/a ; argv[]=/bin/a } { path=/bin/b ; argv[]=/bin/b }"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_build_override() {
+        let exec_start = ExecStart {
+            path: "/usr/bin/server".to_owned(),
+            argv: vec!["server", "--name", "my server", "--rate", "50%"]
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+            ignore_errors: false,
+        };
+        assert_eq!(
+            "# Written by `dbgee systemd`. `dbgee systemd --undo` removes this file.\n\
+            [Service]\n\
+            ExecStart=\n\
+            ExecStart=/bin/dbgee run -t 'tmuxw' -- /usr/bin/server --name \"my server\" --rate 50%%\n",
+            build_override(&exec_start, "/bin/dbgee run -t 'tmuxw'")
+        );
+    }
+}
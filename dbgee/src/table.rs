@@ -0,0 +1,239 @@
+use std::{env, io, os::unix::io::AsRawFd};
+
+use colored::{Color, Colorize};
+use nix::unistd;
+use strum::{EnumString, EnumVariantNames};
+
+/// Separator between the columns
+const COLUMN_SEPARATOR: &str = "  ";
+/// A truncated column keeps at least this width
+const MIN_TRUNCATED_WIDTH: usize = 12;
+const ELLIPSIS: &str = "...";
+
+/// When to colorize the listings, given by `--color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorWhen {
+    /// Returns true if the output to stdout should be colorized.
+    /// `auto` colorizes only a terminal, and respects $NO_COLOR.
+    pub fn should_colorize(self) -> bool {
+        match self {
+            ColorWhen::Always => true,
+            ColorWhen::Never => false,
+            ColorWhen::Auto => {
+                env::var_os("NO_COLOR").is_none()
+                    && unistd::isatty(io::stdout().as_raw_fd()).unwrap_or(false)
+            }
+        }
+    }
+}
+
+/// A cell of a `Table`, which is colored when the table is rendered with colors
+#[derive(Debug)]
+pub struct Cell {
+    text: String,
+    color: Option<Color>,
+}
+
+impl Cell {
+    pub fn new(text: impl Into<String>) -> Cell {
+        Cell {
+            text: text.into(),
+            color: None,
+        }
+    }
+
+    pub fn colored(text: impl Into<String>, color: Color) -> Cell {
+        Cell {
+            text: text.into(),
+            color: Some(color),
+        }
+    }
+}
+
+/// A table for the listings of subcommands such as `sessions`, whose columns are aligned
+#[derive(Debug)]
+pub struct Table {
+    header: Vec<&'static str>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(header: Vec<&'static str>) -> Table {
+        Table {
+            header,
+            rows: vec![],
+        }
+    }
+
+    pub fn push_row(&mut self, row: Vec<Cell>) {
+        debug_assert_eq!(self.header.len(), row.len());
+        self.rows.push(row);
+    }
+
+    /// Renders the table with a header line.
+    ///
+    /// If the table is wider than `width`, the widest column is truncated with an ellipsis. The ellipsis replaces
+    /// the beginning of each cell, because the widest one is usually a path or a command, whose end tells more.
+    pub fn render(&self, width: Option<usize>, colorize: bool) -> String {
+        let widths = self.fit_column_widths(width);
+        let mut rendered = String::new();
+        let header = self.header.iter().map(|title| {
+            let title = title.to_string();
+            let colored_title = if colorize {
+                title.bold().to_string()
+            } else {
+                title.clone()
+            };
+            (title, colored_title)
+        });
+        Self::render_line(&mut rendered, header, &widths);
+        for row in &self.rows {
+            let cells = row.iter().zip(&widths).map(|(cell, width)| {
+                let text = truncate_start(&cell.text, *width);
+                let colored_text = match cell.color {
+                    Some(color) if colorize => text.color(color).to_string(),
+                    _ => text.clone(),
+                };
+                (text, colored_text)
+            });
+            Self::render_line(&mut rendered, cells, &widths);
+        }
+        rendered
+    }
+
+    /// Pads the cells, each of which is the pair of the plain text and the colored one, to `widths`.
+    /// The last cell is not padded not to leave trailing spaces.
+    fn render_line(
+        rendered: &mut String,
+        cells: impl Iterator<Item = (String, String)>,
+        widths: &[usize],
+    ) {
+        let cells: Vec<String> = cells
+            .zip(widths)
+            .map(|((text, colored_text), width)| {
+                let padding = width.saturating_sub(text.chars().count());
+                format!("{}{}", colored_text, " ".repeat(padding))
+            })
+            .collect();
+        rendered.push_str(cells.join(COLUMN_SEPARATOR).trim_end());
+        rendered.push('\n');
+    }
+
+    /// Returns the widths of the columns, shrinking the widest one to fit in `width` as far as possible
+    fn fit_column_widths(&self, width: Option<usize>) -> Vec<usize> {
+        let mut widths: Vec<usize> = self
+            .header
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].text.chars().count())
+                    .chain(std::iter::once(title.len()))
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+        let total_width =
+            widths.iter().sum::<usize>() + COLUMN_SEPARATOR.len() * widths.len().saturating_sub(1);
+        if let Some(width) = width {
+            if total_width > width {
+                if let Some(widest) = widths.iter_mut().max() {
+                    let shrunk = widest.saturating_sub(total_width - width);
+                    *widest = shrunk.max(MIN_TRUNCATED_WIDTH).min(*widest);
+                }
+            }
+        }
+        widths
+    }
+}
+
+/// Truncates `text` to `width` characters, replacing its beginning with an ellipsis
+fn truncate_start(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        return text.to_owned();
+    }
+    let kept = width.saturating_sub(ELLIPSIS.len());
+    let tail: String = text.chars().skip(len - kept).collect();
+    format!("{}{}", ELLIPSIS, tail)
+}
+
+/// Returns the width of the terminal of stdout, or $COLUMNS if it's not available.
+/// Returns None if stdout is not a terminal, so that the output to a pipe is not truncated.
+pub fn terminal_width() -> Option<usize> {
+    let stdout = io::stdout().as_raw_fd();
+    if !unistd::isatty(stdout).unwrap_or(false) {
+        return None;
+    }
+    let mut winsize: nix::libc::winsize = unsafe { std::mem::zeroed() };
+    // Safety: TIOCGWINSZ only writes to winsize
+    let result = unsafe { nix::libc::ioctl(stdout, nix::libc::TIOCGWINSZ, &mut winsize) };
+    if result == 0 && winsize.ws_col > 0 {
+        return Some(winsize.ws_col as usize);
+    }
+    env::var("COLUMNS").ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_table() -> Table {
+        let mut table = Table::new(vec!["PID", "STATE", "COMMAND"]);
+        table.push_row(vec![
+            Cell::new("42"),
+            Cell::colored("running", Color::Green),
+            Cell::new("/usr/local/bin/server --config /etc/server/config.toml"),
+        ]);
+        table.push_row(vec![
+            Cell::new("1234"),
+            Cell::colored("exited", Color::Red),
+            Cell::new("./a.out"),
+        ]);
+        table
+    }
+
+    #[test]
+    fn test_render_table() {
+        assert_eq!(
+            "PID   STATE    COMMAND\n\
+             42    running  /usr/local/bin/server --config /etc/server/config.toml\n\
+             1234  exited   ./a.out\n",
+            build_table().render(None, false)
+        );
+    }
+
+    #[test]
+    fn test_render_table_truncated() {
+        assert_eq!(
+            "PID   STATE    COMMAND\n\
+             42    running  ...config /etc/server/config.toml\n\
+             1234  exited   ./a.out\n",
+            build_table().render(Some(48), false)
+        );
+        // The truncated column keeps the minimum width even in a very narrow terminal
+        assert_eq!(
+            "PID   STATE    COMMAND\n\
+             42    running  ...nfig.toml\n\
+             1234  exited   ./a.out\n",
+            build_table().render(Some(10), false)
+        );
+    }
+
+    #[test]
+    fn test_render_table_colored() {
+        colored::control::set_override(true);
+        let rendered = build_table().render(None, true);
+        colored::control::unset_override();
+        assert!(rendered.contains(&"running".green().to_string()));
+        assert!(rendered.contains(&"exited".red().to_string()));
+    }
+}
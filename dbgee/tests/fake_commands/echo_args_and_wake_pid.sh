@@ -5,6 +5,8 @@ set -e
 pid=
 is_pid=0
 is_user_name=0  # for fake sudo
+listen=
+is_listen=0  # for fake dlv
 for arg in "$@"; do
   if [ "$arg" = -p ]; then
     is_pid=1
@@ -21,9 +23,14 @@ for arg in "$@"; do
     # print <USER> to enable assertion with the output
     printf "'<USER>' "
     continue
+  elif [ "$arg" = --listen ]; then
+    is_listen=1
+  elif [ "$is_listen" = 1 ]; then
+    is_listen=0
+    listen="$arg"
   fi
 
-  printf "'$arg' "
+  printf "'%s' " "$arg"
 done
 
 printf '\n'
@@ -34,3 +41,8 @@ printf '\n'
 kill -s CONT "$pid" || true
 kill -s CONT "$pid" || true
 kill -s CONT "$pid" || true
+
+# Emulate a debugger server such as dlv, which dbgee waits for until it listens on the port
+if [ -n "$listen" ]; then
+  DBGEE_FAKE_LISTEN_ADDRESS="$listen" DBGEE_FAKE_LISTEN_SECONDS=2 "$DBGEE_FAKE_LISTENER_EXE" --exact fake_server_listen >/dev/null 2>&1
+fi
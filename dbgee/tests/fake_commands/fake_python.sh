@@ -14,7 +14,7 @@ for arg in "$@"; do
     continue
   fi
 
-  printf "'$arg' "
+  printf "'%s' " "$arg"
 done
 printf '\n'
 
@@ -26,5 +26,5 @@ for arg in "$@"; do
     fi
 done
 
-# debugpy server should not exit soon
-sleep 10
+# debugpy server should not exit soon, listening on the port as dbgee waits for it
+DBGEE_FAKE_LISTEN_ADDRESS="$port" DBGEE_FAKE_LISTEN_SECONDS=10 "$DBGEE_FAKE_LISTENER_EXE" --exact fake_server_listen >/dev/null 2>&1
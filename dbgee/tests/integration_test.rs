@@ -1,10 +1,11 @@
 use std::{
     env, fs,
-    io::Read,
-    os::unix::process::CommandExt,
-    path::PathBuf,
+    io::{Read, Write},
+    os::unix::{fs::PermissionsExt, process::CommandExt},
+    path::{Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
+    sync::Mutex,
 };
 
 use anyhow::Result;
@@ -13,40 +14,1627 @@ use nix::{sys::signal, unistd};
 /// FIFO prefix path of VSCode instances for test
 static VSCODE_COMMUNICATION_FIFO_PATH_PREFIX_OVERRIDE: &str = "/tmp/dbgee-integration-test";
 
+/// Gets the path to the test binary of the language, or returns from the test as skipped if it's not available
+macro_rules! lang_testbin_or_skip {
+    ($lang:expr) => {
+        if is_lang_testbin_available($lang)? {
+            get_lang_testbin_path($lang)?
+        } else {
+            report_skipped_lang($lang)?;
+            return Ok(());
+        }
+    };
+}
+
+#[test]
+fn test_run_pid_debugger() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let langs = ["c", "rust"];
+
+    for lang in langs.iter() {
+        if !is_lang_testbin_available(lang)? {
+            report_skipped_lang(lang)?;
+            continue;
+        }
+        let lang_bin_path = get_lang_testbin_path(lang)?;
+        let cmd = vec![
+            "run",
+            "-t",
+            "tmuxw",
+            "--",
+            lang_bin_path.as_str(),
+            "arg0",
+            "arg1",
+        ];
+        let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+        assert_eq!(Some(0), output.status.code());
+        if cfg!(target_os = "linux") {
+            assert_eq!(
+                "'new-window' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
+                &String::from_utf8(output.stdout)?
+            );
+        }
+        /* macOS */
+        else {
+            assert_eq!(
+                "'new-window' 'lldb' '-p' '<NUM>' \nhello\n",
+                &String::from_utf8(output.stdout)?
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_on_crash() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-d",
+        "gdb",
+        "--on-crash",
+        "bt full",
+        "--",
+        lang_bin_path.as_str(),
+        "arg0",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        format!(
+            "'--batch' '-ex' 'run' '-ex' 'bt full' '-ex' 'quit $_isvoid($_exitcode) ? 128 + $_siginfo.si_signo : $_exitcode' '--args' '{}' 'arg0' \n",
+            lang_bin_path
+        ),
+        String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn test_run_with_strace_summary() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "--strace-summary",
+        "--trace-only",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!("hello\n", &String::from_utf8(output.stdout)?);
+    let stderr = String::from_utf8(output.stderr)?;
+    assert!(stderr.contains(" write\n"));
+    assert!(stderr.contains(" total\n"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn test_run_with_strace_summary_handoff_signal() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--strace-summary",
+        "--handoff-signal",
+        "TERM",
+        "--",
+        "/bin/sh",
+        "-c",
+        "kill -TERM $$; echo resumed",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    // SIGTERM is not delivered, and the debugger continues the shell
+    let stdout = String::from_utf8(output.stdout)?;
+    let stdout: Vec<&str> = stdout.lines().collect();
+    assert!(stdout[0].starts_with("'new-window' 'gdb' '-tui' '-p' '<NUM>'"));
+    assert_eq!("resumed", stdout[1]);
+    assert!(String::from_utf8(output.stderr)?.contains(" kill\n"));
+
+    Ok(())
+}
+
+#[test]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn test_run_under_tracer() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let dbgee_path = dbgee_pathbuf.to_str().unwrap();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    // Let the outer dbgee trace the inner one
+    let cmd = vec![
+        "run",
+        "--strace-summary",
+        "--trace-only",
+        "--",
+        dbgee_path,
+        "run",
+        "-t",
+        "tmuxw",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_ne!(Some(0), output.status.code());
+    assert_eq!("", &String::from_utf8(output.stdout)?);
+    assert!(String::from_utf8(output.stderr)?.contains("dbgee is traced by another process"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_pid_debugger_with_stop_method() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    // The hold file is written in $XDG_RUNTIME_DIR
+    let runtime_dir = tempfile::tempdir()?;
+
+    for stop_method in ["sigstop", "ptrace-hold"] {
+        let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+            .args([
+                // The PID is logged at info level, which is suppressed when stderr is piped
+                "--log-level",
+                "info",
+                "run",
+                "-d",
+                "stop-and-write-pid",
+                "--stop-method",
+                stop_method,
+                "--",
+                lang_bin_path.as_str(),
+            ])
+            .env("XDG_RUNTIME_DIR", runtime_dir.path())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stderr_lines = BufReader::new(dbgee.stderr.take().unwrap()).lines();
+        let pid: i32 = loop {
+            let line = stderr_lines.next().expect("dbgee didn't print the PID")?;
+            if let Some(pid) = line.split("PID: ").nth(1) {
+                break pid.split('.').next().unwrap().parse()?;
+            }
+        };
+        let pid = unistd::Pid::from_raw(pid);
+
+        if stop_method == "ptrace-hold" {
+            let hold_file = loop {
+                let line = stderr_lines
+                    .next()
+                    .expect("dbgee didn't print the hold file")?;
+                if let Some(path) = line.split("Remove ").nth(1) {
+                    break PathBuf::from(path.split(' ').next().unwrap());
+                }
+            };
+            // Processes which merely refer to the pid must not take over the debuggee
+            let mut unrelated_processes = [
+                Command::new("sh")
+                    .args(["-c", "sleep 10", "gdb", "-p", &pid.to_string()])
+                    .spawn()?,
+                Command::new("tail")
+                    .args([&format!("--pid={}", pid), "-f", "/dev/null"])
+                    .spawn()?,
+            ];
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+            for process in unrelated_processes.iter_mut() {
+                process.kill()?;
+                process.wait()?;
+            }
+            assert!(
+                stat.rsplit(") ").next().unwrap().starts_with('t'),
+                "the debuggee is not held by dbgee. stat: {}",
+                stat
+            );
+            assert!(hold_file.exists());
+            fs::remove_file(&hold_file)?;
+        }
+        attach_and_resume_like_gdb(pid)?;
+
+        for line in stderr_lines {
+            line?;
+        }
+        let status = dbgee.wait()?;
+        let mut stdout = String::new();
+        dbgee.stdout.take().unwrap().read_to_string(&mut stdout)?;
+        assert_eq!(Some(0), status.code(), "stop method: {}", stop_method);
+        assert_eq!("hello\n", stdout, "stop method: {}", stop_method);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_no_sigint_ignore() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::process::ExitStatusExt;
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let runtime_dir = tempfile::tempdir()?;
+
+    let mut dbgee_command = Command::new(dbgee_pathbuf.as_os_str());
+    dbgee_command
+        .args([
+            "--log-level",
+            "info",
+            "run",
+            "-d",
+            "stop-and-write-pid",
+            "--no-sigint-ignore",
+            "--",
+            "/bin/sleep",
+            "30",
+        ])
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .stderr(Stdio::piped());
+    unsafe {
+        // to send SIGINT to dbgee and the debuggee as Ctrl+C does to the foreground process group
+        dbgee_command.pre_exec(|| {
+            unistd::setpgid(unistd::Pid::from_raw(0), unistd::Pid::from_raw(0)).unwrap();
+            Ok(())
+        });
+    }
+    let mut dbgee = dbgee_command.spawn()?;
+    let mut stderr_lines = BufReader::new(dbgee.stderr.take().unwrap()).lines();
+    let pid: i32 = loop {
+        let line = stderr_lines.next().expect("dbgee didn't print the PID")?;
+        if let Some(pid) = line.split("PID: ").nth(1) {
+            break pid.split('.').next().unwrap().parse()?;
+        }
+    };
+    // Resume the debuggee as a debugger would do, and press Ctrl+C
+    signal::kill(unistd::Pid::from_raw(pid), signal::SIGCONT)?;
+    signal::killpg(unistd::Pid::from_raw(dbgee.id() as i32), signal::SIGINT)?;
+
+    let status = dbgee.wait()?;
+    assert_eq!(Some(signal::SIGINT as i32), status.signal());
+    // The debuggee is terminated as well, though its zombie may remain until init reaps it
+    let start = std::time::Instant::now();
+    loop {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat)
+                if !stat
+                    .rsplit(')')
+                    .next()
+                    .unwrap()
+                    .trim_start()
+                    .starts_with('Z') =>
+            {
+                assert!(
+                    start.elapsed() < std::time::Duration::from_secs(5),
+                    "the debuggee is still alive"
+                );
+                std::thread::sleep(std::time::Duration::from_millis(10));
+            }
+            _ => break,
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_detach_and_reattach() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    // The session files are written in $XDG_RUNTIME_DIR
+    let runtime_dir = tempfile::tempdir()?;
+
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "--log-level",
+            "debug",
+            "run",
+            "-d",
+            "stop-and-write-pid",
+            "--",
+            "/bin/sleep",
+            "1",
+        ])
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr_lines = BufReader::new(dbgee.stderr.take().unwrap()).lines();
+    let pid: i32 = loop {
+        let line = stderr_lines.next().expect("dbgee didn't print the PID")?;
+        if let Some(pid) = line.split("PID: ").nth(1) {
+            break pid.split('.').next().unwrap().parse()?;
+        }
+    };
+    // Wait for dbgee to accept SIGUSR1
+    loop {
+        let line = stderr_lines.next().expect("dbgee didn't accept SIGUSR1")?;
+        if line.contains("SIGUSR1") {
+            break;
+        }
+    }
+    // Resume the debuggee as a debugger would do, and detach dbgee from it
+    signal::kill(unistd::Pid::from_raw(pid), signal::SIGCONT)?;
+    signal::kill(unistd::Pid::from_raw(dbgee.id() as i32), signal::SIGUSR1)?;
+    assert_eq!(Some(0), dbgee.wait()?.code());
+    let session_path = runtime_dir
+        .path()
+        .join(format!("dbgee-sessions/{}.toml", pid));
+    assert!(fs::read_to_string(&session_path)?.contains("/bin/sleep 1"));
+
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["sessions", "--color", "never"])
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<Vec<&str>> = stdout
+        .lines()
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(
+        vec![
+            vec!["PID", "STATE", "COMMAND"],
+            vec![&pid.to_string(), "running", "/bin/sleep", "1"],
+        ],
+        lines
+    );
+
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["reattach", &pid.to_string()])
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .stdin(Stdio::null())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert!(!session_path.exists());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_with_reattach_on_exec() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // The fake debugger resumes the shell without tracing it, and dbgee catches the exec of echo
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "--reattach-on-exec",
+            "--",
+            "/bin/sh",
+            "-c",
+            "sleep 0.5; exec /bin/echo re-executed",
+        ])
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n\
+         'new-window' 'gdb' '-tui' '-p' '<NUM>' \n\
+         re-executed\n",
+        &String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_with_pdeathsig() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "--log-level",
+            "info",
+            "run",
+            "-d",
+            "stop-and-write-pid",
+            "--pdeathsig",
+            "KILL",
+            "--",
+            "/bin/sleep",
+            "30",
+        ])
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr_lines = BufReader::new(dbgee.stderr.take().unwrap()).lines();
+    let pid: i32 = loop {
+        let line = stderr_lines.next().expect("dbgee didn't print the PID")?;
+        if let Some(pid) = line.split("PID: ").nth(1) {
+            break pid.split('.').next().unwrap().parse()?;
+        }
+    };
+
+    // Kill dbgee abruptly. The stopped debuggee must not be left orphaned
+    dbgee.kill()?;
+    dbgee.wait()?;
+    let started_at = std::time::Instant::now();
+    loop {
+        match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Err(_) => break,
+            Ok(stat) if stat.rsplit(") ").next().unwrap().starts_with('Z') => break,
+            Ok(stat) => assert!(
+                started_at.elapsed() < std::time::Duration::from_secs(5),
+                "the debuggee is alive. stat: {}",
+                stat
+            ),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+/// Attaches to `pid` by ptrace and resumes it, as `gdb -p <pid>` followed by `detach` does.
+/// Like gdb, this sends SIGCONT since the process is stopped by SIGSTOP.
+#[cfg(target_os = "linux")]
+fn attach_and_resume_like_gdb(pid: unistd::Pid) -> Result<()> {
+    use nix::sys::{ptrace, wait};
+    use std::time::{Duration, Instant};
+
+    // Wait for the SIGSTOP from dbgee to take effect. In ptrace-hold, dbgee holds the process
+    // in a ptrace-stop ('t') until its hold file is removed
+    let started_at = Instant::now();
+    loop {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+        if stat.rsplit(") ").next().unwrap().starts_with('T') {
+            break;
+        }
+        if started_at.elapsed() > Duration::from_secs(5) {
+            anyhow::bail!("pid {} is not stopped by SIGSTOP. stat: {}", pid, stat);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    ptrace::attach(pid)?;
+    wait::waitpid(pid, None)?;
+    signal::kill(pid, signal::SIGCONT)?;
+    ptrace::detach(pid, None)?;
+    Ok(())
+}
+
+#[test]
+fn test_run_with_cargo() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let manifest_path = get_tests_dir()?.join("lang_projects/rust/Cargo.toml");
+    let target_dir = tempfile::tempdir()?;
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--cargo",
+        "--",
+        "--manifest-path",
+        manifest_path.to_str().unwrap(),
+        "--target-dir",
+        target_dir.path().to_str().unwrap(),
+        "--",
+        "arg0",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
+        &String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_attach_children_of() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let mut parent = Command::new("sh")
+        .args(["-c", "sleep 30 & sleep 30 & wait"])
+        .process_group(0)
+        .spawn()?;
+    // Wait for the children to exec sleep
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let parent_pid = parent.id().to_string();
+    let cmd = vec![
+        "attach",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--children-of",
+        parent_pid.as_str(),
+        "--max-children",
+        "1",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output();
+    signal::killpg(unistd::Pid::from_raw(parent.id() as i32), signal::SIGTERM)?;
+    parent.wait()?;
+    let output = output?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n",
+        &String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_attach_pid() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let mut sleep = Command::new("sleep").arg("30").spawn()?;
+    let pid = sleep.id().to_string();
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["attach", "-t", "tmuxw", "-d", "gdb", "--pid", pid.as_str()])
+        .output();
+    // --pick needs a terminal
+    let pick_output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["attach", "-t", "tmuxw", "--pick"])
+        .stdin(Stdio::null())
+        .output();
+    sleep.kill()?;
+    sleep.wait()?;
+    let output = output?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n",
+        &String::from_utf8(output.stdout)?
+    );
+    let pick_output = pick_output?;
+    assert_ne!(Some(0), pick_output.status.code());
+    assert!(String::from_utf8(pick_output.stderr)?.contains("needs a terminal"));
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_attach_restores_running() -> Result<()> {
+    use nix::sys::{ptrace, wait};
+    use std::io::{BufRead, BufReader};
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let mut sleep = Command::new("sleep").arg("30").spawn()?;
+    let pid = unistd::Pid::from_raw(sleep.id() as i32);
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "attach",
+            "-t",
+            "tmuxw",
+            "-d",
+            "gdb",
+            "--restore-state",
+            "--pid",
+        ])
+        .arg(pid.to_string())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // Wait for dbgee to launch the debugger
+    let mut line = String::new();
+    BufReader::new(dbgee.stdout.take().unwrap()).read_line(&mut line)?;
+
+    // Attach and detach immediately as a debugger would do, but leave the process stopped by mistake
+    ptrace::attach(pid)?;
+    wait::waitpid(pid, None)?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    ptrace::detach(pid, Some(signal::SIGSTOP))?;
+
+    let status = dbgee.wait()?;
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    sleep.kill()?;
+    sleep.wait()?;
+    assert_eq!(Some(0), status.code());
+    assert!(
+        stat.rsplit(") ").next().unwrap().starts_with('S'),
+        "stat: {}",
+        stat
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_sudo_debugger() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--sudo-debugger",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    if cfg!(target_os = "linux") {
+        assert_eq!(
+            "'new-window' 'sudo' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
+            &String::from_utf8(output.stdout)?
+        );
+    }
+    /* macOS */
+    else {
+        assert_eq!(
+            "'new-window' 'sudo' 'lldb' '-p' '<NUM>' \nhello\n",
+            &String::from_utf8(output.stdout)?
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_tmux_session() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    // The fake tmux always succeeds, so the session is considered to exist
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxp",
+        "-d",
+        "gdb",
+        "--tmux-session",
+        "dbgee",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'splitw' '-h' '-t' '=dbgee:' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
+        String::from_utf8(output.stdout)?
+    );
+
+    // The window is named by --debuggee-name
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "-d",
+            "gdb",
+            "--tmux-session",
+            "dbgee",
+            "--debuggee-name",
+            "server",
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' '-n' 'server' '-t' '=dbgee:' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
+        String::from_utf8(output.stdout)?
+    );
+
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "--tmux-session",
+            "my.session",
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_record() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--record",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \
+        '-ex' 'echo [dbgee] Starting `record full` for --record. \
+        If gdb reports an error right below, gdb cannot record this debuggee and it runs without recording.\\n' \
+        '-ex' 'record full' \nhello\n",
+        String::from_utf8(output.stdout)?
+    );
+
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "-d",
+            "lldb",
+            "--record",
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_rr() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "rr",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let tmp_dir = tempfile::tempdir()?;
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .env("TMPDIR", tmp_dir.path())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    // The replay of the trace directory opens after the recorded debuggee exits
+    let stdout = String::from_utf8(output.stdout)?;
+    let trace_dir = stdout
+        .strip_prefix("hello\n'new-window' 'rr' 'replay' '")
+        .and_then(|rest| rest.strip_suffix("' \n"))
+        .unwrap_or_else(|| panic!("unexpected output: {}", stdout));
+    let trace_dir = Path::new(trace_dir);
+    assert!(trace_dir.starts_with(tmp_dir.path()));
+    // The fake rr creates the trace directory given by `-o`
+    assert!(trace_dir.is_dir());
+    Ok(())
+}
+
+#[test]
+fn test_run_with_debugger_path() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let fake_gdb = fs::canonicalize(get_tests_dir()?.join("fake_commands/gdb"))?;
+    let fake_gdb = fake_gdb.to_str().unwrap();
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--debugger-path",
+        fake_gdb,
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        format!("'new-window' '{}' '-tui' '-p' '<NUM>' \nhello\n", fake_gdb),
+        String::from_utf8(output.stdout)?
+    );
+
+    // the path must be executable
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "--debugger-path",
+            "/dev/null",
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+
+    // the debuggers which don't launch it don't silently ignore it
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "-d",
+            "dlv",
+            "--debugger-path",
+            fake_gdb,
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+    assert!(String::from_utf8(output.stderr)?.contains("--dlv-path"));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_debugger_arg() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--debugger-arg=-ex",
+        "--debugger-arg",
+        "b main",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    // The argument with a space is given to tmux as one argument
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' '-ex' 'b main' \nhello\n",
+        String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_debugger_command() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--debugger-command",
+        "gdb -p {pid} --args {program}",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        format!(
+            "'new-window' 'gdb' '-p' '<NUM>' '--args' '{}' \nhello\n",
+            fs::canonicalize(&lang_bin_path)?.display()
+        ),
+        String::from_utf8(output.stdout)?
+    );
+
+    // --debugger-command is exclusive with --debugger
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-d",
+            "gdb",
+            "--debugger-command",
+            "cgdb -p {pid}",
+            "--",
+            lang_bin_path.as_str(),
+        ])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_quiet_debuggee() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--quiet-debuggee",
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n",
+        &String::from_utf8(output.stdout)?
+    );
+
+    let tmpdir = tempfile::tempdir()?;
+    let log_path = tmpdir.path().join("debuggee.log");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--debuggee-log",
+        log_path.to_str().unwrap(),
+        "--",
+        lang_bin_path.as_str(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n",
+        &String::from_utf8(output.stdout)?
+    );
+    assert_eq!("hello\n", fs::read_to_string(&log_path)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_stdio_files() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let tmpdir = tempfile::tempdir()?;
+    let stdin_path = tmpdir.path().join("input.txt");
+    let stdout_path = tmpdir.path().join("stdout.txt");
+    let stderr_path = tmpdir.path().join("stderr.txt");
+    fs::write(&stdin_path, "deterministic input\n")?;
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--stdin",
+        stdin_path.to_str().unwrap(),
+        "--stdout",
+        stdout_path.to_str().unwrap(),
+        "--stderr",
+        stderr_path.to_str().unwrap(),
+        "--",
+        "/bin/sh",
+        "-c",
+        "cat; echo error >&2",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        "'new-window' 'gdb' '-tui' '-p' '<NUM>' \n",
+        &String::from_utf8(output.stdout)?
+    );
+    assert_eq!("deterministic input\n", fs::read_to_string(&stdout_path)?);
+    assert_eq!("error\n", fs::read_to_string(&stderr_path)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_attach_self() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // A wrapper script, which would make dbgee choose stop-and-write-pid without --attach-self
+    let tmpdir = tempfile::tempdir()?;
+    let wrapper_path = tmpdir.path().join("wrapper.sh");
+    fs::write(&wrapper_path, "#!/bin/sh\necho wrapper\n")?;
+    fs::set_permissions(&wrapper_path, fs::Permissions::from_mode(0o755))?;
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--attach-self",
+        "--",
+        wrapper_path.to_str().unwrap(),
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    if cfg!(target_os = "linux") {
+        assert_eq!(
+            "'new-window' 'gdb' '-tui' '-p' '<NUM>' \nwrapper\n",
+            &String::from_utf8(output.stdout)?
+        );
+    }
+    /* macOS */
+    else {
+        assert_eq!(
+            "'new-window' 'lldb' '-p' '<NUM>' \nwrapper\n",
+            &String::from_utf8(output.stdout)?
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_no_wait() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // the fake debugpy server keeps running for 10 seconds, but dbgee should exit without waiting for it
+    let lang_testbin = lang_testbin_or_skip!("python");
+    let cmd = vec![
+        "--vscode-fifo-prefix",
+        "/tmp/dbgee-integration-test-no-wait",
+        "run",
+        "-t",
+        "vscode",
+        "--no-wait",
+        "--port",
+        "5680",
+        "--",
+        &lang_testbin,
+    ];
+    let started = std::time::Instant::now();
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_print_env() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-d",
+        "stop-and-write-pid",
+        "--no-wait",
+        "--quiet-debuggee",
+        "--print-env",
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stderr(Stdio::null())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    let pid = stdout
+        .strip_prefix("export DBGEE_PID='")
+        .and_then(|rest| rest.strip_suffix("'\n"))
+        .unwrap_or_else(|| panic!("unexpected output: {:?}", stdout));
+    signal::kill(unistd::Pid::from_raw(pid.parse()?), signal::SIGKILL)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_dap_listen_address() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("c");
+    // $DBGEE_PORT and $DBGEE_LISTEN_HOST take precedence over --port, and the attach information reflects them
+    let cmd = vec![
+        "run",
+        "-t",
+        "none",
+        "--dap",
+        "--port",
+        "5684",
+        "--ready-regex",
+        "^Listening for: ",
+        "--no-wait",
+        "--quiet-debuggee",
+        "--print-env",
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .env("DBGEE_PORT", "5685")
+        .env("DBGEE_LISTEN_HOST", "127.0.0.1")
+        .stderr(Stdio::null())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert!(lines.contains(&"export DBGEE_PORT='5685'"), "{}", stdout);
+    assert!(
+        lines.contains(&"export DBGEE_HOST='127.0.0.1'"),
+        "{}",
+        stdout
+    );
+    let pid = lines
+        .iter()
+        .find_map(|line| line.strip_prefix("export DBGEE_PID='"))
+        .and_then(|rest| rest.strip_suffix('\''))
+        .unwrap_or_else(|| panic!("unexpected output: {:?}", stdout));
+    signal::kill(unistd::Pid::from_raw(pid.parse()?), signal::SIGKILL)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_print_connect() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-d",
+        "gdb",
+        "--print-connect",
+        "--no-wait",
+        "--quiet-debuggee",
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stderr(Stdio::null())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    // The fake gdb is not launched, and the debuggee is left stopped
+    let pid = stdout
+        .strip_prefix("gdb -tui -p ")
+        .and_then(|rest| rest.strip_suffix('\n'))
+        .unwrap_or_else(|| panic!("unexpected output: {:?}", stdout));
+    signal::kill(unistd::Pid::from_raw(pid.parse()?), signal::SIGKILL)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_terminal_none() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "none",
+        "--no-wait",
+        "--quiet-debuggee",
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stderr(Stdio::null())
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    // Only the pid is printed, since no debugger is launched
+    let pid: i32 = stdout
+        .strip_suffix('\n')
+        .and_then(|pid| pid.parse().ok())
+        .unwrap_or_else(|| panic!("unexpected output: {:?}", stdout));
+    if cfg!(target_os = "linux") {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+        let state = stat
+            .rsplit(") ")
+            .next()
+            .and_then(|rest| rest.chars().next());
+        assert_eq!(Some('T'), state);
+    }
+    signal::kill(unistd::Pid::from_raw(pid), signal::SIGKILL)?;
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_hook_with_keep_stopped() -> Result<()> {
+    use std::io::{BufRead, BufReader};
+
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let lang_bin_path = lang_testbin_or_skip!("c");
+    // The hold file is written in $XDG_RUNTIME_DIR
+    let runtime_dir = tempfile::tempdir()?;
+
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "none",
+            "--hook-executable",
+            &lang_bin_path,
+            "--keep-stopped",
+            "--",
+            "/bin/sh",
+            "-c",
+            &lang_bin_path,
+        ])
+        .env("XDG_RUNTIME_DIR", runtime_dir.path())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout_lines = BufReader::new(dbgee.stdout.take().unwrap()).lines();
+    let pid: i32 = stdout_lines
+        .next()
+        .expect("dbgee didn't print the pid")?
+        .parse()?;
+    // `--terminal none` takes over the hooked process once it prints the pid
+    attach_and_resume_like_gdb(unistd::Pid::from_raw(pid))?;
+    let rest = stdout_lines.collect::<Result<Vec<String>, _>>()?;
+    assert_eq!(vec!["hello"], rest);
+    assert_eq!(Some(0), dbgee.wait()?.code());
+    assert!(!runtime_dir
+        .path()
+        .join(format!("dbgee-sessions/{}.hold", pid))
+        .exists());
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_hook_library() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // Either the shell or its children which keep forking loads libc first
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "--hook-library",
+            "libc.so",
+            "--",
+            "/bin/sh",
+            "-c",
+            "i=0; while [ $i -lt 100 ]; do /bin/true; i=$((i+1)); done; echo done",
+        ])
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    let stdout: Vec<&str> = stdout.lines().collect();
+    assert!(stdout[0].starts_with("'new-window' 'gdb' '-tui' '-p' '<NUM>'"));
+    assert_eq!("done", stdout[1]);
+
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_hook_settle_while_forking() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    // The script keeps forking while dbgee stops it to confirm it has settled
+    let tmpdir = tempfile::tempdir()?;
+    let script = tmpdir.path().join("forking.sh");
+    fs::write(
+        &script,
+        "i=0\nwhile [ $i -lt 100 ]; do /bin/true; i=$((i+1)); done\necho done\n",
+    )?;
+    let script = script.to_str().unwrap();
+
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args([
+            "run",
+            "-t",
+            "tmuxw",
+            "--hook-executable",
+            script,
+            "--hook-settle",
+            "100ms",
+            "--trace-fork-tree",
+            "--",
+            "/bin/sh",
+            script,
+        ])
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    let stdout = String::from_utf8(output.stdout)?;
+    let stdout: Vec<&str> = stdout.lines().collect();
+    assert!(stdout[0].starts_with("'new-window' 'gdb' '-tui' '-p' '<NUM>'"));
+    assert_eq!("done", stdout[1]);
+    let fork_tree = String::from_utf8(output.stderr)?;
+    assert!(fork_tree.contains("[hooked]"));
+    assert!(fork_tree.contains("/bin/true"));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_pre_attach_hook() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("c");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--pre-attach-hook",
+        "echo hook for $DBGEE_PID; exit 1",
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_ne!(Some(0), output.status.code());
+    // The debugger is not launched when the hook fails
+    assert!(!String::from_utf8(output.stdout)?.contains("new-window"));
+    assert!(String::from_utf8(output.stderr)?.contains("hook for "));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_python_with_follow_subprocess() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("python");
+    let cmd = vec![
+        "--vscode-fifo-prefix",
+        "/tmp/dbgee-integration-test-follow-subprocess",
+        "run",
+        "-t",
+        "vscode",
+        "--no-wait",
+        "--follow-subprocess",
+        "--port",
+        "5681",
+        "--",
+        &lang_testbin,
+    ];
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Don't wait for EOF since the fake debugpy server keeps stdout open for a while
+    let stdout = std::io::BufReader::new(dbgee.stdout.take().unwrap());
+    let debugpy_line = std::io::BufRead::lines(stdout)
+        .find(|line| matches!(line, Ok(line) if line.contains("'debugpy'")))
+        .unwrap()?;
+    assert_eq!(
+        format!(
+            "'-m' 'debugpy' '--wait-for-client' '--listen' '<NUM>' '--configure-subProcess' 'true' '{}' ",
+            lang_testbin
+        ),
+        debugpy_line
+    );
+    dbgee.wait()?;
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_node_inspect() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // .js files are not executable
+    let tmpdir = tempfile::tempdir()?;
+    let script = tmpdir.path().join("app.js");
+    fs::write(&script, "console.log('hello');\n")?;
+    let script = script.to_str().unwrap();
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "node-inspect",
+        "--port",
+        "5684",
+        "--no-wait",
+        "--",
+        script,
+        "arg0",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        format!(
+            "'--inspect-brk=<NUM>' '{}' 'arg0' \n'new-window' 'node' 'inspect' 'localhost:5684' \n",
+            script
+        ),
+        String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
 #[test]
-fn test_run_pid_debugger() -> Result<()> {
+fn test_set_node_script() -> Result<()> {
     set_fake_commands_path()?;
     let dbgee_pathbuf = get_dbgee_bin_path();
-    let langs = ["c", "rust"];
 
-    for lang in langs.iter() {
-        let lang_bin_path = get_lang_testbin_path(lang)?;
-        let cmd = vec![
-            "run",
-            "-t",
-            "tmuxw",
-            "--",
-            lang_bin_path.as_str(),
-            "arg0",
-            "arg1",
-        ];
-        let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
-        assert_eq!(Some(0), output.status.code());
-        if cfg!(target_os = "linux") {
-            assert_eq!(
-                "'new-window' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
-                &String::from_utf8(output.stdout)?
-            );
-        }
-        /* macOS */
-        else {
-            // commands are wrapped by sudo in macOS
-            assert_eq!(
-                "'new-window' 'lldb' '-p' '<NUM>' \nhello\n",
-                &String::from_utf8(output.stdout)?
-            );
-        }
-    }
+    let tmpdir = tempfile::tempdir()?;
+    let script = tmpdir.path().join("app.js");
+    fs::write(&script, "#!/usr/bin/env node\nconsole.log('hello');\n")?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    let script = script.to_str().unwrap();
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["set", "-t", "tmuxw", "-d", "node-inspect", script])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    let wrapper = fs::read_to_string(script)?;
+    assert!(
+        wrapper.contains(&format!("-- {}-original", script)),
+        "{}",
+        wrapper
+    );
+    assert!(wrapper.contains("node-inspect"), "{}", wrapper);
+
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["unset", script])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    assert!(fs::read_to_string(script)?.contains("console.log('hello');"));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_rdbg() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    // .rb files are not executable
+    let tmpdir = tempfile::tempdir()?;
+    let script = tmpdir.path().join("app.rb");
+    fs::write(&script, "puts 'hello'\n")?;
+    let script = script.to_str().unwrap();
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "rdbg",
+        "--port",
+        "5685",
+        "--no-wait",
+        "--",
+        script,
+        "arg0",
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(
+        format!(
+            "'--open' '--host' 'localhost' '--port' '<NUM>' '--' '{}' 'arg0' \n\
+            'new-window' 'rdbg' '--attach' 'localhost' '5685' \n",
+            script
+        ),
+        String::from_utf8(output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_ruby_script() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let tmpdir = tempfile::tempdir()?;
+    let script = tmpdir.path().join("app.rb");
+    fs::write(&script, "#!/usr/bin/env ruby\nputs 'hello'\n")?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    let script = script.to_str().unwrap();
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["set", "-t", "tmuxw", "-d", "rdbg", script])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    let wrapper = fs::read_to_string(script)?;
+    assert!(
+        wrapper.contains(&format!("-- {}-original", script)),
+        "{}",
+        wrapper
+    );
+    assert!(wrapper.contains("rdbg"), "{}", wrapper);
+
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["unset", script])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    assert!(fs::read_to_string(script)?.contains("puts 'hello'"));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_port_in_use() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("python");
+    let _listener = std::net::TcpListener::bind("localhost:5683")?;
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["run", "-t", "vscode", "--port", "5683", "--", &lang_testbin])
+        .output()?;
+    assert_ne!(Some(0), output.status.code());
+    assert!(String::from_utf8(output.stderr)?.contains(
+        "port 5683 is in use by another process. Stop it, or give another port by --port 5684"
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_run_with_extra_debugger() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+
+    let lang_testbin = lang_testbin_or_skip!("python");
+    let cmd = vec![
+        "--vscode-fifo-prefix",
+        "/tmp/dbgee-integration-test-extra-debugger",
+        "run",
+        "-t",
+        "tmuxw",
+        "-d",
+        "gdb",
+        "--extra-debugger",
+        "debugpy",
+        "--no-wait",
+        "--port",
+        "5682",
+        "--",
+        &lang_testbin,
+    ];
+    let mut dbgee = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // debugpy launches the debuggee, and then gdb attaches to it
+    let stdout = std::io::BufReader::new(dbgee.stdout.take().unwrap());
+    let lines = std::io::BufRead::lines(stdout)
+        .take(2)
+        .collect::<std::io::Result<Vec<String>>>()?;
+    assert!(lines[0].starts_with("'-m' 'debugpy' '--wait-for-client'"));
+    assert_eq!("'new-window' 'gdb' '-tui' '-p' '<NUM>' ", lines[1]);
+    assert_eq!(Some(0), dbgee.wait()?.code());
 
     Ok(())
 }
@@ -56,7 +1644,7 @@ fn test_run_dlv() -> Result<()> {
     set_fake_commands_path()?;
     let dbgee_pathbuf = get_dbgee_bin_path();
 
-    let lang_testbin = get_lang_testbin_path("go")?;
+    let lang_testbin = lang_testbin_or_skip!("go");
     let cmd = vec!["run", "-t", "tmuxw", "--", &lang_testbin, "arg0", "arg1"];
     let output = Command::new(dbgee_pathbuf.as_os_str()).args(cmd).output()?;
     assert_eq!(Some(0), output.status.code());
@@ -71,12 +1659,45 @@ fn test_run_dlv() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_run_dlv_with_post_detach_hook() -> Result<()> {
+    set_fake_commands_path()?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let tmpdir = tempfile::tempdir()?;
+    let pid_file = tmpdir.path().join("debuggee_pid");
+    let hook_output = tmpdir.path().join("hook_output");
+    let hook = format!("printf %s $DBGEE_PID > {}", hook_output.to_str().unwrap());
+
+    let lang_testbin = lang_testbin_or_skip!("go");
+    let cmd = vec![
+        "run",
+        "-t",
+        "tmuxw",
+        "--post-detach-hook",
+        &hook,
+        "--",
+        &lang_testbin,
+    ];
+    let output = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd)
+        .env("DBGEE_FAKE_LISTENER_PID_FILE", &pid_file)
+        .output()?;
+    assert_eq!(Some(0), output.status.code());
+    // $DBGEE_PID is the debuggee which dlv launched, not dlv
+    assert_eq!(
+        fs::read_to_string(&pid_file)?,
+        fs::read_to_string(&hook_output)?
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_set_pid_debugger() -> Result<()> {
     set_fake_commands_path()?;
 
     // copy the hello binary to a temporary file for testing
-    let copied_hello = CopiedExecutable::new(&get_lang_testbin_path("c")?)?;
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
 
     // `set` should succeed
     let dbgee_pathbuf = get_dbgee_bin_path();
@@ -86,21 +1707,29 @@ fn test_set_pid_debugger() -> Result<()> {
         .status()?;
     assert_eq!(Some(0), status.code());
 
-    // Running the copied hello binary now should trigger tmux
+    // Running the copied hello binary now should trigger tmux.
+    // `set` records the absolute path to the debugger found in PATH
     let debuggee_output = Command::new(&copied_hello.path).output()?;
     assert_eq!(Some(0), debuggee_output.status.code());
     if cfg!(target_os = "linux") {
+        let gdb = fs::canonicalize(get_tests_dir()?.join("fake_commands/gdb"))?;
         assert_eq!(
-            "'new-window' 'gdb' '-tui' '-p' '<NUM>' \nhello\n",
-            &String::from_utf8(debuggee_output.stdout)?
+            format!(
+                "'new-window' '{}' '-tui' '-p' '<NUM>' \nhello\n",
+                gdb.to_str().unwrap()
+            ),
+            String::from_utf8(debuggee_output.stdout)?
         );
     }
     /* macOS */
     else {
-        // commands are wrapped by sudo in macOS
+        let lldb = fs::canonicalize(get_tests_dir()?.join("fake_commands/lldb"))?;
         assert_eq!(
-            "'new-window' 'lldb' '-p' '<NUM>' \nhello\n",
-            &String::from_utf8(debuggee_output.stdout)?
+            format!(
+                "'new-window' '{}' '-p' '<NUM>' \nhello\n",
+                lldb.to_str().unwrap()
+            ),
+            String::from_utf8(debuggee_output.stdout)?
         );
     }
 
@@ -122,11 +1751,145 @@ fn test_set_pid_debugger() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_watch() -> Result<()> {
+    set_fake_commands_path()?;
+
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
+
+    // `watch` should set the debuggee, and keep running
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let mut watch = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["watch", "-t", "tmuxw", &copied_hello.path])
+        .spawn()?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    assert!(watch.try_wait()?.is_none());
+
+    // Running the copied hello binary now should trigger tmux
+    let debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(Some(0), debuggee_output.status.code());
+    assert!(String::from_utf8(debuggee_output.stdout)?.starts_with("'new-window'"));
+
+    // Ctrl+C should unset the debuggee
+    signal::kill(unistd::Pid::from_raw(watch.id() as i32), signal::SIGINT)?;
+    assert_eq!(Some(0), watch.wait()?.code());
+    let original_debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(
+        "hello\n",
+        &String::from_utf8(original_debuggee_output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_with_backup_suffix() -> Result<()> {
+    set_fake_commands_path()?;
+
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
+    let backup_path = format!("{}.dbgee-bak", &copied_hello.path);
+
+    // `set` with a custom suffix should move the original debuggee to the custom backup path
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let cmd_to_set = vec![
+        "set",
+        "-t",
+        "tmuxw",
+        "--backup-suffix",
+        ".dbgee-bak",
+        &copied_hello.path,
+    ];
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd_to_set)
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    assert!(PathBuf::from(&backup_path).exists());
+
+    // Running the copied hello binary now should trigger tmux
+    let debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(Some(0), debuggee_output.status.code());
+    assert!(String::from_utf8(debuggee_output.stdout)?.ends_with("\nhello\n"));
+
+    // `unset` should find the original debuggee by the recorded suffix
+    let cmd_to_unset = vec!["unset", &copied_hello.path];
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd_to_unset)
+        .status()?;
+    assert_eq!(Some(0), status.code());
+    assert!(!PathBuf::from(&backup_path).exists());
+
+    let original_debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(Some(0), original_debuggee_output.status.code());
+    assert_eq!(
+        "hello\n",
+        &String::from_utf8(original_debuggee_output.stdout)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_set_with_dap_pins_server_path() -> Result<()> {
+    set_fake_commands_path()?;
+
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["set", "-t", "tmuxw", "--dap", &copied_hello.path])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+
+    // The wrapper launches the lldb-dap found now, even with another PATH
+    let wrapper = fs::read_to_string(&copied_hello.path)?;
+    let fake_lldb_dap = get_tests_dir()?.join("fake_commands/lldb-dap");
+    assert!(wrapper.contains("--debugger-path"), "{}", wrapper);
+    assert!(
+        wrapper.contains(fake_lldb_dap.to_str().unwrap()),
+        "{}",
+        wrapper
+    );
+
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(["unset", &copied_hello.path])
+        .status()?;
+    assert_eq!(Some(0), status.code());
+
+    Ok(())
+}
+
+#[test]
+fn test_set_once() -> Result<()> {
+    set_fake_commands_path()?;
+
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
+    let original = fs::read(&copied_hello.path)?;
+
+    let dbgee_pathbuf = get_dbgee_bin_path();
+    let cmd_to_set = vec!["set", "-t", "tmuxw", "--once", &copied_hello.path];
+    let status = Command::new(dbgee_pathbuf.as_os_str())
+        .args(cmd_to_set)
+        .status()?;
+    assert_eq!(Some(0), status.code());
+
+    // The first launch should trigger tmux
+    let debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(Some(0), debuggee_output.status.code());
+    assert!(String::from_utf8(debuggee_output.stdout)?.ends_with("\nhello\n"));
+
+    // The second launch should run the original unmodified
+    assert_eq!(original, fs::read(&copied_hello.path)?);
+    let debuggee_output = Command::new(&copied_hello.path).output()?;
+    assert_eq!(Some(0), debuggee_output.status.code());
+    assert_eq!("hello\n", &String::from_utf8(debuggee_output.stdout)?);
+
+    Ok(())
+}
+
 #[test]
 fn test_run_debuggee_which_is_set_before() -> Result<()> {
     set_fake_commands_path()?;
 
-    let copied_hello = CopiedExecutable::new(&get_lang_testbin_path("c")?)?;
+    let copied_hello = CopiedExecutable::new(&lang_testbin_or_skip!("c"))?;
 
     // `set` the debuggee first
     let dbgee_pathbuf = get_dbgee_bin_path();
@@ -156,7 +1919,6 @@ fn test_run_debuggee_which_is_set_before() -> Result<()> {
     }
     /* macOS */
     else {
-        // commands are wrapped by sudo in macOS
         assert_eq!(
             "'new-window' 'lldb' '-p' '<NUM>' \nhello\n",
             &String::from_utf8(output.stdout)?
@@ -188,7 +1950,7 @@ fn test_run_for_vscode() -> Result<()> {
     );
 
     // launch `dbgee`
-    let lang_testbin = get_lang_testbin_path("python")?;
+    let lang_testbin = lang_testbin_or_skip!("python");
     let debuggee_args = vec![
         "--vscode-fifo-prefix",
         VSCODE_COMMUNICATION_FIFO_PATH_PREFIX_OVERRIDE,
@@ -212,7 +1974,7 @@ fn test_run_for_vscode() -> Result<()> {
             Ok(())
         });
     }
-    let dbgee = dbgee_command.spawn().unwrap();
+    let mut dbgee = dbgee_command.spawn().unwrap();
 
     // read from the fifo in a thread with timeout because it may block if there's a bug
     let (sender, receiver) = std::sync::mpsc::channel();
@@ -232,10 +1994,16 @@ fn test_run_for_vscode() -> Result<()> {
     // kill dbgee including its child.
     signal::killpg(unistd::Pid::from_raw(dbgee.id() as i32), signal::SIGTERM).unwrap();
     let mut debugpy_args = String::new();
-    let _ = dbgee.stdout.unwrap().read_to_string(&mut debugpy_args);
+    let _ = dbgee
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut debugpy_args);
+    let _ = dbgee.wait();
     assert_eq!(
         format!(
-            "'-m' 'debugpy' '--wait-for-client' '--listen' '<NUM>' '{}' 'arg0' 'arg1' \n",
+            "'-m' 'debugpy' '--wait-for-client' '--listen' '<NUM>' '--configure-subProcess' 'false' \
+            '{}' 'arg0' 'arg1' \n",
             &get_lang_testbin_path("python")?
         ),
         debugpy_args
@@ -254,6 +2022,27 @@ fn set_fake_commands_path() -> Result<()> {
     }
     path.insert_str(0, pathbuf.to_str().unwrap());
     env::set_var("PATH", path);
+    // The fake debugger servers run `fake_server_listen` of this test binary
+    env::set_var("DBGEE_FAKE_LISTENER_EXE", env::current_exe()?);
+    Ok(())
+}
+
+/// Not a test by itself. The fake debugger servers run this test binary only with this test to listen on
+/// $DBGEE_FAKE_LISTEN_ADDRESS for $DBGEE_FAKE_LISTEN_SECONDS, as the real servers do.
+/// It writes its pid to $DBGEE_FAKE_LISTENER_PID_FILE if given, since it stands for the debuggee of dlv.
+#[test]
+fn fake_server_listen() -> Result<()> {
+    let address = match env::var("DBGEE_FAKE_LISTEN_ADDRESS") {
+        Ok(address) if address.contains(':') => address,
+        Ok(port) => format!("localhost:{}", port),
+        Err(_) => return Ok(()),
+    };
+    let seconds = env::var("DBGEE_FAKE_LISTEN_SECONDS")?.parse()?;
+    if let Ok(pid_file) = env::var("DBGEE_FAKE_LISTENER_PID_FILE") {
+        fs::write(pid_file, std::process::id().to_string())?;
+    }
+    let _listener = std::net::TcpListener::bind(address)?;
+    std::thread::sleep(std::time::Duration::from_secs(seconds));
     Ok(())
 }
 
@@ -268,14 +2057,146 @@ fn get_dbgee_bin_path() -> PathBuf {
     pathbuf
 }
 
+/// Returns the test binary of `lang` given by the environment variables, which take precedence over the
+/// `hello-<arch>-<os>` binaries:
+///
+/// - `DBGEE_TEST_BIN_<LANG>`, e.g. `DBGEE_TEST_BIN_RUST`, is the path to the binary of the language
+/// - `DBGEE_TEST_BIN_DIR` is the directory which has the binaries as `<lang>/hello`, e.g. `rust/hello`
+///
+/// The overridden binaries are never built by the tests.
+fn get_lang_testbin_override_path(lang: &str) -> Option<PathBuf> {
+    if let Some(path) = env::var_os(format!("DBGEE_TEST_BIN_{}", lang.to_uppercase())) {
+        return Some(PathBuf::from(path));
+    }
+    env::var_os("DBGEE_TEST_BIN_DIR").map(|dir| Path::new(&dir).join(lang).join("hello"))
+}
+
+/// Returns the paths of the test binary of `lang`, the prebuilt one by `make -C tests/lang_projects`,
+/// and the one built on demand by the tests.
+fn get_lang_testbin_candidate_paths(lang: &str) -> Result<(PathBuf, PathBuf)> {
+    let testbin_name = format!("hello-{}-{}", std::env::consts::ARCH, std::env::consts::OS);
+    let prebuilt_path = get_tests_dir()?
+        .join("lang_projects")
+        .join(lang)
+        .join(&testbin_name);
+    let built_path = PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join("lang_projects")
+        .join(lang)
+        .join(&testbin_name);
+    Ok((prebuilt_path, built_path))
+}
+
+/// Reports that the test for `lang` is skipped since its test binary is not available.
+/// Fails instead under CI, where every toolchain is installed, not to skip tests silently.
+fn report_skipped_lang(lang: &str) -> Result<()> {
+    anyhow::ensure!(
+        env::var_os("CI").is_none(),
+        "the test binary of {} is not available in CI",
+        lang
+    );
+    // Write to stderr directly, since the test harness captures the output of eprintln!
+    writeln!(
+        std::io::stderr(),
+        "skipped: the test binary of {} is not available",
+        lang
+    )?;
+    Ok(())
+}
+
+/// Returns false if the test binary of `lang` is neither built yet nor buildable since its toolchain is not
+/// installed, or if the overridden binary doesn't exist. Tests for such languages are skipped.
+fn is_lang_testbin_available(lang: &str) -> Result<bool> {
+    if let Some(override_path) = get_lang_testbin_override_path(lang) {
+        return Ok(override_path.exists());
+    }
+    let (prebuilt_path, built_path) = get_lang_testbin_candidate_paths(lang)?;
+    if prebuilt_path.exists() || built_path.exists() {
+        return Ok(true);
+    }
+    let toolchain = match lang {
+        "c" => "cc",
+        "go" => "go",
+        "rust" => "cargo",
+        _ => return Ok(true),
+    };
+    Ok(Command::new("sh")
+        .args(["-c", &format!("command -v {}", toolchain)])
+        .stdout(Stdio::null())
+        .status()?
+        .success())
+}
+
+/// Returns the path of the test binary for `lang`, for the running platform.
+/// If the prebuilt binary doesn't exist, e.g. on Apple Silicon, the binary is built from the sources
+/// under the target directory.
 fn get_lang_testbin_path(lang: &str) -> Result<String> {
-    Ok(format!(
-        "{}/lang_projects/{}/hello-{}-{}",
-        get_tests_dir()?.to_str().unwrap(),
-        lang,
-        std::env::consts::ARCH,
-        std::env::consts::OS,
-    ))
+    if let Some(override_path) = get_lang_testbin_override_path(lang) {
+        anyhow::ensure!(
+            override_path.exists(),
+            "the test binary for {} is not found at {:?}",
+            lang,
+            override_path
+        );
+        return Ok(fs::canonicalize(override_path)?
+            .to_str()
+            .unwrap()
+            .to_owned());
+    }
+    let (prebuilt_path, built_path) = get_lang_testbin_candidate_paths(lang)?;
+    if prebuilt_path.exists() {
+        return Ok(prebuilt_path.to_str().unwrap().to_owned());
+    }
+
+    let lang_project_dir = get_tests_dir()?.join("lang_projects").join(lang);
+    // Tests run in parallel. Build each binary only once
+    static BUILD_LOCK: Mutex<()> = Mutex::new(());
+    let _lock = BUILD_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if !built_path.exists() {
+        build_lang_testbin(lang, &lang_project_dir, &built_path)?;
+    }
+    Ok(built_path.to_str().unwrap().to_owned())
+}
+
+fn build_lang_testbin(lang: &str, lang_project_dir: &Path, output_path: &Path) -> Result<()> {
+    fs::create_dir_all(output_path.parent().unwrap())?;
+    let output_dir = output_path.parent().unwrap();
+    let status = match lang {
+        "c" => Command::new("cc")
+            .arg("-g")
+            .arg("-o")
+            .arg(output_path)
+            .arg(lang_project_dir.join("hello.c"))
+            .status()?,
+        "go" => Command::new("go")
+            .args(["build", "-o"])
+            .arg(output_path)
+            .current_dir(lang_project_dir)
+            .status()?,
+        "rust" => {
+            let target_dir = output_dir.join("target");
+            let status = Command::new("cargo")
+                .arg("build")
+                .arg("--manifest-path")
+                .arg(lang_project_dir.join("Cargo.toml"))
+                .arg("--target-dir")
+                .arg(&target_dir)
+                .status()?;
+            anyhow::ensure!(status.success(), "failed to build the test binary for rust");
+            fs::copy(target_dir.join("debug/hello"), output_path)?;
+            status
+        }
+        "python" => {
+            fs::copy(lang_project_dir.join("hello"), output_path)?;
+            return Ok(());
+        }
+        _ => anyhow::bail!("unknown lang: {}", lang),
+    };
+    anyhow::ensure!(
+        status.success(),
+        "failed to build the test binary for {}",
+        lang
+    );
+    Ok(())
 }
 
 fn get_tests_dir() -> Result<PathBuf> {
@@ -291,7 +2212,7 @@ struct CopiedExecutable {
 impl CopiedExecutable {
     fn new(path: &str) -> Result<CopiedExecutable> {
         let copied_path = format!("/tmp/dbgee-copied-debuggee-{}", uuid::Uuid::new_v4());
-        fs::copy(&path, &copied_path)?;
+        fs::copy(path, &copied_path)?;
         Ok(CopiedExecutable { path: copied_path })
     }
 }
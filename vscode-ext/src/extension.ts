@@ -6,7 +6,7 @@ import * as nodeFs from 'fs';
 import * as nodeUtil from 'util';
 import * as net from 'net';
 
-const PROTOCOL_VERSION = "1.1.0";
+const PROTOCOL_VERSION = "1.4.0";
 
 type Deactivate = () => void;
 type RegisterDeactivate = (deactivate: Deactivate) => void;
@@ -20,12 +20,15 @@ export function activate(context: vscode.ExtensionContext) {
 	}
 	const dbgeeConnector = new DbgeeConnector();
 
-	const attachInfoCommandFactory = (information: keyof DbgeeAttachInformation) => (async () => {
+	const attachInfoCommandFactory = (information: keyof DbgeeAttachInformation, defaultValue?: string) => (async () => {
 		logger.trace(`getting attach information of: ${information}`);
 		const info = await dbgeeConnector.getAttachInformation(information);
 		logger.trace(`got attach information of: ${information}`);
 
 		if (!info) {
+			if (defaultValue !== undefined) {
+				return defaultValue;
+			}
 			logger.error(`${information} is not found in the information given by dbgee.`);
 			return '';
 		}
@@ -34,6 +37,8 @@ export function activate(context: vscode.ExtensionContext) {
 	});
 	context.subscriptions.push(vscode.commands.registerCommand('dbgee.getPid', attachInfoCommandFactory("pid")));
 	context.subscriptions.push(vscode.commands.registerCommand('dbgee.getDebuggerPort', attachInfoCommandFactory("debuggerPort")));
+	// Older dbgee doesn't give the host, where the servers always listened on localhost
+	context.subscriptions.push(vscode.commands.registerCommand('dbgee.getDebuggerHost', attachInfoCommandFactory("debuggerHost", "localhost")));
 	context.subscriptions.push(vscode.commands.registerCommand('dbgee.getProgramName', attachInfoCommandFactory("programName")));
 
 	const dbgeeDebuggerConfigurationFactory = new DbgeeDebuggerConfigurationFactory();
@@ -47,12 +52,12 @@ export function activate(context: vscode.ExtensionContext) {
 
 	debugSessionTracker.activate(dbgeeConnector);
 	dbgeeRequestListener.listen();
-	dbgeeDebuggerConfigurationProvider.registerToVsCode();
+	dbgeeDebuggerConfigurationProvider.registerToVsCode(dbgeeConnector);
 }
 
 class DbgeeConnector {
 	private retrievedProperties: Set<string>;
-	private attachInformation: DbgeeAttachInformation | undefined;
+	private attachInformation: (DbgeeAttachInformation & DbgeeSourceSettings) | undefined;
 	private subscribers: ((isWaiting: boolean) => void)[];
 
 	constructor() {
@@ -73,13 +78,21 @@ class DbgeeConnector {
 		return this.attachInformation![key];
 	}
 
+	// Source settings of the debuggee whose attach information is being retrieved
+	get sourceSettings(): DbgeeSourceSettings | undefined {
+		if (!this.attachInformation || this.retrievedProperties.size === 0) {
+			return;
+		}
+		return this.attachInformation;
+	}
+
 	async refreshAttachInformation() {
 		logger.trace(`waiting attach information`);
 		this.subscribers.forEach((subscriber) => subscriber(true));
 		try {
 			const fifoPath = "/tmp/dbgee-vscode-debuggees";
 			await makeFifoUnlessExists(fifoPath);
-			this.attachInformation = JSON.parse(await readFifo(fifoPath, 30_000)) as DbgeeAttachInformation;
+			this.attachInformation = JSON.parse(await readFifo(fifoPath, 30_000)) as DbgeeAttachInformation & DbgeeSourceSettings;
 			if (detectSemVerBreakingChange(PROTOCOL_VERSION, this.attachInformation.protocolVersion)) {
 				throw new Error("incompatible protocol version");
 			}
@@ -115,11 +128,22 @@ class DbgeeRequestListener {
 					throw new Error("incompatible protocol version");
 				}
 				logger.trace(`[${listeningLoop}] got attach request: ${JSON.stringify(request)}`);
-				const config = this.debuggerConfigFactory.getDebuggerConfigurationForRequest(request);
+				let config = this.debuggerConfigFactory.getDebuggerConfigurationForRequest(request);
 				if (!config) {
 					logger.trace(`[${listeningLoop}] no config found for the config`);
 					continue;
 				}
+				// fields which the debug adapter needs in addition to the config. e.g. miDebuggerPath for cppdbg
+				if (request.program) {
+					config = { ...config, program: request.program };
+				}
+				if (request.miDebuggerPath) {
+					config = { ...config, miDebuggerPath: request.miDebuggerPath };
+				}
+				if (request.debuggeeName) {
+					config = { ...config, name: `${config.name} (${request.debuggeeName})` };
+				}
+				config = applySourceSettings(config, request);
 				if (!this.debugSessionTracker.isDebugSessionActive) {
 					logger.trace(`[${listeningLoop}] starting the debug session`);
 					vscode.debug.startDebugging(vscode.workspace.workspaceFolders?.[0], config);
@@ -250,7 +274,17 @@ class DbgeeDebuggerConfigurationProvider {
 		this.factory = factory;
 	}
 
-	registerToVsCode() {
+	registerToVsCode(dbgeeConnector: DbgeeConnector) {
+		// Apply the source settings given by dbgee to the launch configs which retrieved the attach information
+		vscode.debug.registerDebugConfigurationProvider("*", {
+			resolveDebugConfigurationWithSubstitutedVariables: (_folder: WorkspaceFolder | undefined, config: DebugConfiguration, token?: CancellationToken): ProviderResult<DebugConfiguration> => {
+				const sourceSettings = dbgeeConnector.sourceSettings;
+				if (!sourceSettings || !config.name?.includes("Dbgee:")) {
+					return config;
+				}
+				return applySourceSettings(config, sourceSettings);
+			}
+		});
 		for (const debuggerConfig of this.factory.getInitialConfigurations()) {
 			vscode.debug.registerDebugConfigurationProvider(debuggerConfig.type, {
 				resolveDebugConfiguration: (_folder: WorkspaceFolder | undefined, config: DebugConfiguration, token?: CancellationToken): ProviderResult<DebugConfiguration> => {
@@ -269,6 +303,35 @@ class DbgeeDebuggerConfigurationProvider {
 	}
 }
 
+// Translates the source settings given by dbgee into the fields of each debug adapter
+function applySourceSettings(config: DebugConfiguration, settings: DbgeeSourceSettings): DebugConfiguration {
+	const { sourceMap, symbolSearchPath } = settings;
+	const lldbInitCommands = symbolSearchPath ? [`settings append target.debug-file-search-paths ${symbolSearchPath}`] : [];
+	switch (config.type) {
+		case "cppdbg":
+			return {
+				...config,
+				...(sourceMap && { sourceFileMap: { ...config.sourceFileMap, ...sourceMap } }),
+				...(symbolSearchPath && { additionalSOLibSearchPath: [config.additionalSOLibSearchPath, symbolSearchPath].filter(path => path).join(";") }),
+			};
+		case "lldb":
+			// CodeLLDB
+			return {
+				...config,
+				...(sourceMap && { sourceMap: { ...config.sourceMap, ...sourceMap } }),
+				initCommands: [...(config.initCommands || []), ...lldbInitCommands],
+			};
+		case "lldb-dap":
+			return {
+				...config,
+				...(sourceMap && { sourceMap: [...(config.sourceMap || []), ...Object.entries(sourceMap)] }),
+				initCommands: [...(config.initCommands || []), ...lldbInitCommands],
+			};
+		default:
+			return config;
+	}
+}
+
 async function readFifo(path: string, timeout?: number): Promise<string> {
 	return new Promise<string>((resolve, reject) => {
 		nodeFs.open(path, nodeFs.constants.O_RDONLY | nodeFs.constants.O_NONBLOCK, (err, fd) => {
@@ -341,12 +404,23 @@ interface DbgeeAttachInformation {
 	protocolVersion?: string;
 	pid?: string;
 	debuggerPort?: string;
+	debuggerHost?: string;
 	programName?: string;
+	debuggeeName?: string;
+}
+
+interface DbgeeSourceSettings {
+	// source path at build time -> source path on this machine
+	sourceMap?: { [from: string]: string };
+	symbolSearchPath?: string;
 }
 
-interface DbgeeAttachRequest {
+interface DbgeeAttachRequest extends DbgeeSourceSettings {
 	protocolVersion: string;
 	debuggerType: string;
+	program?: string;
+	miDebuggerPath?: string;
+	debuggeeName?: string;
 }
 
 